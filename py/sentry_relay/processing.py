@@ -23,6 +23,8 @@ __all__ = [
     "validate_pii_config",
     "convert_datascrubbing_config",
     "pii_strip_event",
+    "pii_audit_event",
+    "pii_decrypt",
     "pii_selectors_from_event",
     "pii_selector_suggestions_from_event",
     "VALID_PLATFORMS",
@@ -204,6 +206,25 @@ def pii_strip_event(config, event):
     return json.loads(decode_str(raw_rv, free=True))
 
 
+def pii_audit_event(config, event):
+    """
+    Apply a PII config to an event in audit mode and return all matches without modifying it.
+    """
+    raw_config = encode_str(json.dumps(config))
+    raw_event = encode_str(json.dumps(event))
+    raw_rv = rustcall(lib.relay_pii_audit_event, raw_config, raw_event)
+    return json.loads(decode_str(raw_rv, free=True))
+
+
+def pii_decrypt(keys, value):
+    """
+    Decrypt a value that was encrypted during PII scrubbing with one of the given keys.
+    """
+    raw_keys = encode_str(json.dumps(keys))
+    raw_rv = rustcall(lib.relay_pii_decrypt, raw_keys, encode_str(value))
+    return decode_str(raw_rv, free=True)
+
+
 def pii_selectors_from_event(event):
     """
     DEPRECATED: Use relay_pii_selector_suggestions_from_event
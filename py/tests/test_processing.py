@@ -1,4 +1,6 @@
 # coding: utf-8
+import json
+
 import sentry_relay
 
 import pytest
@@ -165,6 +167,22 @@ def test_validate_pii_config():
     with pytest.raises(ValueError):
         sentry_relay.validate_pii_config('{"applications": true}')
 
+    # Encryption rules require a key
+    with pytest.raises(ValueError):
+        sentry_relay.validate_pii_config(
+            json.dumps(
+                {
+                    "rules": {
+                        "encrypt_email": {
+                            "type": "email",
+                            "redaction": {"method": "encrypt"},
+                        }
+                    },
+                    "applications": {"$user.email": ["encrypt_email"]},
+                }
+            )
+        )
+
 
 def test_convert_datascrubbing_config():
     cfg = sentry_relay.convert_datascrubbing_config(
@@ -198,6 +216,34 @@ def test_pii_strip_event():
     assert sentry_relay.pii_strip_event({}, event) == event
 
 
+def test_pii_audit_event():
+    config = {"applications": {"$message": ["@userpath"]}}
+    event = {"logentry": {"formatted": "/Users/jane/file"}}
+    assert sentry_relay.pii_audit_event(config, event) == [
+        {"path": "logentry.formatted", "rule_id": "@userpath", "range": [7, 11]}
+    ]
+
+
+def test_pii_decrypt():
+    key = {"id": "k1", "secret": "YW4gZXhhbXBsZSB2ZXJ5IHZlcnkgc2VjcmV0IGtleS4="}
+    config = {
+        "rules": {
+            "encrypt_email": {"type": "email", "redaction": {"method": "encrypt"}}
+        },
+        "vars": {"encryptionKey": key},
+        "applications": {"$string": ["encrypt_email"]},
+    }
+    event = {"logentry": {"formatted": "jane.doe@example.com"}}
+
+    scrubbed = sentry_relay.pii_strip_event(config, event)
+    encrypted = scrubbed["logentry"]["formatted"]
+    assert encrypted.startswith("enc:k1:")
+    assert sentry_relay.pii_decrypt([key], encrypted) == "jane.doe@example.com"
+
+    with pytest.raises(sentry_relay.DecryptionErrorUnknownKey):
+        sentry_relay.pii_decrypt([{"id": "k2", "secret": key["secret"]}], encrypted)
+
+
 def test_pii_selector_suggestions_from_event():
     event = {"logentry": {"formatted": "hi"}}
     assert set(sentry_relay.pii_selectors_from_event(event)) == {"$message"}
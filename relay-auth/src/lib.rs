@@ -194,6 +194,13 @@ pub struct Registration {
     relay_id: RelayId,
 }
 
+impl Registration {
+    /// Returns the relay ID of the registered relay.
+    pub fn relay_id(&self) -> &RelayId {
+        &self.relay_id
+    }
+}
+
 impl SecretKey {
     /// Signs some data with the secret key and returns the signature.
     ///
@@ -534,6 +541,15 @@ impl RegisterResponse {
     pub fn token(&self) -> &str {
         &self.token
     }
+
+    /// Creates the final registration for this response.
+    ///
+    /// This must only be called after the response has been verified against the challenge.
+    pub fn create_registration(&self) -> Registration {
+        Registration {
+            relay_id: self.relay_id,
+        }
+    }
 }
 
 #[test]
@@ -640,6 +656,10 @@ fn test_registration() {
         .unwrap();
     assert_eq!(reg_resp.relay_id(), &relay_id);
     assert_eq!(reg_resp.token(), challenge.token());
+
+    // finalize the registration
+    let registration = reg_resp.create_registration();
+    assert_eq!(registration.relay_id(), &relay_id);
 }
 
 #[test]
@@ -40,6 +40,18 @@ enum RelayDataCategory {
    * Session updates. Quantity is the number of updates in the batch.
    */
   RELAY_DATA_CATEGORY_SESSION,
+  /**
+   * Custom metrics. Quantity is the number of metric values in the batch.
+   */
+  RELAY_DATA_CATEGORY_METRIC,
+  /**
+   * Profiles of transactions.
+   */
+  RELAY_DATA_CATEGORY_PROFILE,
+  /**
+   * Check-ins of scheduled jobs for monitoring.
+   */
+  RELAY_DATA_CATEGORY_MONITOR,
   /**
    * Any other data category not known by this Relay.
    */
@@ -76,6 +88,9 @@ enum RelayErrorCode {
   RELAY_ERROR_CODE_INVALID_RELEASE_ERROR_TOO_LONG = 3001,
   RELAY_ERROR_CODE_INVALID_RELEASE_ERROR_RESTRICTED_NAME = 3002,
   RELAY_ERROR_CODE_INVALID_RELEASE_ERROR_BAD_CHARACTERS = 3003,
+  RELAY_ERROR_CODE_DECRYPTION_ERROR_INVALID_FORMAT = 4001,
+  RELAY_ERROR_CODE_DECRYPTION_ERROR_UNKNOWN_KEY = 4002,
+  RELAY_ERROR_CODE_DECRYPTION_ERROR_BAD_CIPHERTEXT = 4003,
 };
 typedef uint32_t RelayErrorCode;
 
@@ -352,6 +367,16 @@ bool relay_is_glob_match(const RelayBuf *value,
 
 RelayStr relay_parse_release(const RelayStr *value);
 
+/**
+ * Apply a PII config to an event in audit mode and return all matches without modifying it.
+ */
+RelayStr relay_pii_audit_event(const RelayStr *config, const RelayStr *event);
+
+/**
+ * Decrypt a value that was encrypted during PII scrubbing with one of the given keys.
+ */
+RelayStr relay_pii_decrypt(const RelayStr *keys, const RelayStr *value);
+
 /**
  * Walk through the event and collect selectors that can be applied to it in a PII config. This
  * function is used in the UI to provide auto-completion of selectors.
@@ -10,6 +10,7 @@ use sentry_release_parser::InvalidRelease;
 
 use relay_auth::{KeyParseError, UnpackError};
 use relay_common::Uuid;
+use relay_general::pii::DecryptionError;
 use relay_general::store::GeoIpError;
 use relay_general::types::ProcessingAction;
 
@@ -41,6 +42,11 @@ pub enum RelayErrorCode {
     InvalidReleaseErrorTooLong = 3001,
     InvalidReleaseErrorRestrictedName = 3002,
     InvalidReleaseErrorBadCharacters = 3003,
+
+    // relay_general::pii::DecryptionError
+    DecryptionErrorInvalidFormat = 4001,
+    DecryptionErrorUnknownKey = 4002,
+    DecryptionErrorBadCiphertext = 4003,
 }
 
 impl RelayErrorCode {
@@ -88,6 +94,13 @@ impl RelayErrorCode {
                     }
                 };
             }
+            if let Some(err) = cause.downcast_ref::<DecryptionError>() {
+                return match err {
+                    DecryptionError::InvalidFormat => RelayErrorCode::DecryptionErrorInvalidFormat,
+                    DecryptionError::UnknownKey(_) => RelayErrorCode::DecryptionErrorUnknownKey,
+                    DecryptionError::BadCiphertext => RelayErrorCode::DecryptionErrorBadCiphertext,
+                };
+            }
         }
         RelayErrorCode::Unknown
     }
@@ -9,7 +9,8 @@ use std::slice;
 use json_forensics;
 use relay_common::{glob_match_bytes, GlobOptions};
 use relay_general::pii::{
-    selector_suggestions_from_value, DataScrubbingConfig, PiiConfig, PiiProcessor,
+    decrypt_value, selector_suggestions_from_value, DataScrubbingConfig, EncryptionKey, PiiConfig,
+    PiiProcessor,
 };
 use relay_general::processor::{process_value, split_chunks, ProcessingState};
 use relay_general::protocol::{Event, VALID_PLATFORMS};
@@ -144,8 +145,11 @@ ffi_fn! {
     unsafe fn relay_validate_pii_config(
         value: *const RelayStr
     ) -> Result<RelayStr> {
-        match serde_json::from_str((*value).as_str()) {
-            Ok(PiiConfig { .. }) => Ok(RelayStr::new("")),
+        match serde_json::from_str::<PiiConfig>((*value).as_str()) {
+            Ok(config) => match config.validate() {
+                Ok(()) => Ok(RelayStr::new("")),
+                Err(e) => Ok(RelayStr::from_string(e.to_string())),
+            },
             Err(e) => Ok(RelayStr::from_string(e.to_string()))
         }
     }
@@ -171,6 +175,7 @@ ffi_fn! {
         event: *const RelayStr
     ) -> Result<RelayStr> {
         let config = serde_json::from_str::<PiiConfig>((*config).as_str())?;
+        config.validate()?;
         let compiled = config.compiled();
         let mut processor = PiiProcessor::new(&compiled);
 
@@ -181,6 +186,36 @@ ffi_fn! {
     }
 }
 
+ffi_fn! {
+    /// Apply a PII config to an event in audit mode and return all matches without modifying it.
+    unsafe fn relay_pii_audit_event(
+        config: *const RelayStr,
+        event: *const RelayStr
+    ) -> Result<RelayStr> {
+        let config = serde_json::from_str::<PiiConfig>((*config).as_str())?;
+        config.validate()?;
+        let compiled = config.compiled();
+        let mut processor = PiiProcessor::audit(&compiled);
+
+        let mut event = Annotated::<Event>::from_json((*event).as_str())?;
+        process_value(&mut event, &mut processor, ProcessingState::root())?;
+
+        Ok(RelayStr::from_string(serde_json::to_string(&processor.into_matches())?))
+    }
+}
+
+ffi_fn! {
+    /// Decrypt a value that was encrypted during PII scrubbing with one of the given keys.
+    unsafe fn relay_pii_decrypt(
+        keys: *const RelayStr,
+        value: *const RelayStr
+    ) -> Result<RelayStr> {
+        let keys = serde_json::from_str::<Vec<EncryptionKey>>((*keys).as_str())?;
+        let decrypted = decrypt_value(&keys, (*value).as_str())?;
+        Ok(RelayStr::from_string(decrypted))
+    }
+}
+
 ffi_fn! {
     /// DEPRECATED: Use relay_pii_selector_suggestions_from_event
     unsafe fn relay_pii_selectors_from_event(event: *const RelayStr) -> Result<RelayStr> {
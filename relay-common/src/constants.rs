@@ -89,6 +89,12 @@ pub enum DataCategory {
     Attachment,
     /// Session updates. Quantity is the number of updates in the batch.
     Session,
+    /// Custom metrics. Quantity is the number of metric lines in the batch.
+    Metric,
+    /// Profiles of transactions.
+    Profile,
+    /// Check-ins of scheduled jobs for monitoring.
+    Monitor,
     /// Any other data category not known by this Relay.
     #[serde(other)]
     Unknown = -1,
@@ -104,6 +110,9 @@ impl DataCategory {
             "security" => Self::Security,
             "attachment" => Self::Attachment,
             "session" => Self::Session,
+            "metric" => Self::Metric,
+            "profile" => Self::Profile,
+            "monitor" => Self::Monitor,
             _ => Self::Unknown,
         }
     }
@@ -117,6 +126,9 @@ impl DataCategory {
             Self::Security => "security",
             Self::Attachment => "attachment",
             Self::Session => "session",
+            Self::Metric => "metric",
+            Self::Profile => "profile",
+            Self::Monitor => "monitor",
             Self::Unknown => "unknown",
         }
     }
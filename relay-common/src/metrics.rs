@@ -57,17 +57,36 @@
 //! });
 //! ```
 //!
+//! ## Prometheus
+//!
+//! Instead of or in addition to statsd, metrics can be aggregated in memory with a
+//! [`PrometheusAggregator`]. Use [`configure_prometheus`] to install it, and
+//! [`prometheus_aggregator`] to render the aggregated metrics in the Prometheus text format.
+//!
+//! The prefix and default tags can be changed later with [`reconfigure`], which keeps sending
+//! metrics to the same destination.
+//!
 //! [Metric Types]: https://github.com/statsd/statsd/blob/master/docs/metric_types.md
 //! [`set_client`]: fn.set_client.html
 //! [`configure_statsd`]: fn.configure_statsd.html
+//! [`configure_prometheus`]: fn.configure_prometheus.html
+//! [`prometheus_aggregator`]: fn.prometheus_aggregator.html
+//! [`reconfigure`]: fn.reconfigure.html
+//! [`PrometheusAggregator`]: struct.PrometheusAggregator.html
 //! [`metric!`]: ../macro.metric.html
 
-use std::collections::BTreeMap;
-use std::net::ToSocketAddrs;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use cadence::{Metric, MetricBuilder, StatsdClient};
+use cadence::{Metric, MetricBuilder, MetricSink, StatsdClient, UdpMetricSink};
 use lazy_static::lazy_static;
 use parking_lot::RwLock;
 
@@ -109,12 +128,30 @@ impl MetricsClient {
     }
 }
 
+/// A metric sink shared by all clients created from it.
+#[derive(Clone)]
+struct SharedSink(Arc<dyn MetricSink + Send + Sync + RefUnwindSafe>);
+
+impl MetricSink for SharedSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        self.0.emit(metric)
+    }
+}
+
 lazy_static! {
     static ref METRICS_CLIENT: RwLock<Option<Arc<MetricsClient>>> = RwLock::new(None);
+    static ref METRICS_SINK: RwLock<Option<SharedSink>> = RwLock::new(None);
+    static ref PROMETHEUS_AGGREGATOR: RwLock<Option<Arc<PrometheusAggregator>>> = RwLock::new(None);
 }
 
+/// Incremented whenever the global client changes to refresh the thread local clients.
+static CLIENT_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
 thread_local! {
-    static CURRENT_CLIENT: Option<Arc<MetricsClient>> = METRICS_CLIENT.read().clone();
+    static CURRENT_CLIENT: RefCell<(usize, Option<Arc<MetricsClient>>)> = RefCell::new((
+        CLIENT_GENERATION.load(Ordering::Acquire),
+        METRICS_CLIENT.read().clone(),
+    ));
 }
 
 /// Internal prelude for the macro
@@ -131,11 +168,47 @@ pub mod prelude {
 /// Set a new statsd client.
 pub fn set_client(client: MetricsClient) {
     *METRICS_CLIENT.write() = Some(Arc::new(client));
+    CLIENT_GENERATION.fetch_add(1, Ordering::Release);
 }
 
 /// Disable the client again.
 pub fn disable() {
+    *METRICS_SINK.write() = None;
     *METRICS_CLIENT.write() = None;
+    CLIENT_GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// Installs a new client sending to the given sink.
+fn configure_sink<T>(prefix: &str, sink: T, default_tags: BTreeMap<String, String>)
+where
+    T: MetricSink + Send + Sync + RefUnwindSafe + 'static,
+{
+    let sink = SharedSink(Arc::new(sink));
+    *METRICS_SINK.write() = Some(sink.clone());
+
+    set_client(MetricsClient {
+        statsd_client: StatsdClient::from_sink(prefix, sink),
+        default_tags,
+    });
+}
+
+/// Changes the prefix and default tags of all metrics.
+///
+/// Metrics are still sent to the destination set up with [`configure_statsd`] or
+/// [`configure_prometheus`]. If metrics are not configured, this function does nothing.
+///
+/// [`configure_statsd`]: fn.configure_statsd.html
+/// [`configure_prometheus`]: fn.configure_prometheus.html
+pub fn reconfigure(prefix: &str, default_tags: BTreeMap<String, String>) {
+    let sink = match *METRICS_SINK.read() {
+        Some(ref sink) => sink.clone(),
+        None => return,
+    };
+
+    set_client(MetricsClient {
+        statsd_client: StatsdClient::from_sink(prefix, sink),
+        default_tags,
+    });
 }
 
 /// Tell the metrics system to report to statsd.
@@ -148,11 +221,50 @@ pub fn configure_statsd<A: ToSocketAddrs>(
     if !addrs.is_empty() {
         log::info!("reporting metrics to statsd at {}", addrs[0]);
     }
-    let statsd_client = StatsdClient::from_udp_host(prefix, &addrs[..]).unwrap();
-    set_client(MetricsClient {
-        statsd_client,
-        default_tags,
+
+    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let sink = UdpMetricSink::from(&addrs[..], socket).unwrap();
+    configure_sink(prefix, sink, default_tags);
+}
+
+/// Tell the metrics system to aggregate metrics for Prometheus.
+///
+/// If `statsd_host` is given, metrics are also reported to statsd. The aggregated metrics can be
+/// obtained from [`prometheus_aggregator`].
+///
+/// [`prometheus_aggregator`]: fn.prometheus_aggregator.html
+pub fn configure_prometheus<A: ToSocketAddrs>(
+    prefix: &str,
+    statsd_host: Option<A>,
+    default_tags: BTreeMap<String, String>,
+) {
+    let statsd_sink = statsd_host.map(|host| {
+        let addrs: Vec<_> = host.to_socket_addrs().unwrap().collect();
+        if !addrs.is_empty() {
+            log::info!("reporting metrics to statsd at {}", addrs[0]);
+        }
+
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        UdpMetricSink::from(&addrs[..], socket).unwrap()
     });
+
+    log::info!("aggregating metrics for prometheus");
+    let aggregator = Arc::new(PrometheusAggregator::new());
+    *PROMETHEUS_AGGREGATOR.write() = Some(aggregator.clone());
+
+    let sink = PrometheusSink {
+        aggregator,
+        statsd_sink,
+    };
+
+    configure_sink(prefix, sink, default_tags);
+}
+
+/// Returns the Prometheus aggregator, if configured.
+pub fn prometheus_aggregator() -> Option<Arc<PrometheusAggregator>> {
+    PROMETHEUS_AGGREGATOR.read().clone()
 }
 
 /// Invoke a callback with the current statsd client.
@@ -165,13 +277,343 @@ where
     F: FnOnce(&MetricsClient) -> R,
     R: Default,
 {
-    CURRENT_CLIENT.with(|client| {
-        if let Some(client) = client {
-            f(&*client)
-        } else {
-            R::default()
+    let client = CURRENT_CLIENT.with(|current| {
+        let generation = CLIENT_GENERATION.load(Ordering::Acquire);
+        let mut current = current.borrow_mut();
+        if current.0 != generation {
+            *current = (generation, METRICS_CLIENT.read().clone());
+        }
+        current.1.clone()
+    });
+
+    match client {
+        Some(client) => f(&client),
+        None => R::default(),
+    }
+}
+
+/// Window in which unique values of set metrics are counted.
+const SET_WINDOW: Duration = Duration::from_secs(60);
+
+/// Bucket boundaries in seconds for timer metrics.
+const TIMER_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Bucket boundaries for histogram metrics.
+const HISTOGRAM_BUCKETS: &[f64] = &[
+    1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1e3, 2e3, 5e3, 1e4, 2e4, 5e4, 1e5, 2e5,
+    5e5, 1e6, 2e6, 5e6, 1e7, 2e7, 5e7, 1e8, 2e8, 5e8, 1e9,
+];
+
+/// Aggregated value of a single Prometheus time series.
+#[derive(Debug)]
+enum PrometheusValue {
+    Counter(f64),
+    Gauge(f64),
+    Histogram {
+        bounds: &'static [f64],
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+    Set {
+        values: HashSet<String>,
+        window_start: Instant,
+        previous: usize,
+    },
+}
+
+impl PrometheusValue {
+    fn histogram(bounds: &'static [f64]) -> Self {
+        PrometheusValue::Histogram {
+            bounds,
+            buckets: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn set() -> Self {
+        PrometheusValue::Set {
+            values: HashSet::new(),
+            window_start: Instant::now(),
+            previous: 0,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            PrometheusValue::Counter(_) => "counter",
+            PrometheusValue::Gauge(_) | PrometheusValue::Set { .. } => "gauge",
+            PrometheusValue::Histogram { .. } => "histogram",
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let PrometheusValue::Histogram {
+            bounds,
+            buckets,
+            sum,
+            count,
+        } = self
+        {
+            for (bound, bucket) in bounds.iter().zip(buckets.iter_mut()) {
+                if value <= *bound {
+                    *bucket += 1;
+                }
+            }
+
+            *sum += value;
+            *count += 1;
+        }
+    }
+
+    /// Starts a new window for set metrics if the current one has elapsed.
+    fn roll_window(&mut self, now: Instant) {
+        if let PrometheusValue::Set {
+            values,
+            window_start,
+            previous,
+        } = self
+        {
+            let elapsed = now.duration_since(*window_start);
+            if elapsed >= SET_WINDOW {
+                // If no value was recorded in the last full window, there are no unique values.
+                *previous = if elapsed >= 2 * SET_WINDOW {
+                    0
+                } else {
+                    values.len()
+                };
+                values.clear();
+                *window_start = now;
+            }
         }
-    })
+    }
+}
+
+/// Name and sorted labels of a Prometheus time series.
+type SeriesKey = (String, Vec<(String, String)>);
+
+/// Replaces all characters that are not allowed in Prometheus metric and label names.
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+
+    sanitized
+}
+
+/// Escapes a Prometheus label value.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_labels(output: &mut String, labels: &[(String, String)], le: Option<&str>) {
+    let le = le.map(|le| ("le", le));
+    let mut labels = labels
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .chain(le)
+        .peekable();
+
+    if labels.peek().is_none() {
+        return;
+    }
+
+    output.push('{');
+    for (index, (key, value)) in labels.enumerate() {
+        if index > 0 {
+            output.push(',');
+        }
+        write!(output, "{}=\"{}\"", key, escape_label_value(value)).ok();
+    }
+    output.push('}');
+}
+
+/// Aggregates metrics in memory for exposition in the Prometheus text format.
+///
+/// The aggregator consumes metrics in the statsd line format as they are emitted by the metrics
+/// client. Metric names are converted into valid Prometheus names, and tags are mapped to labels.
+///
+///  - Counters are exposed as counters with a `_total` suffix.
+///  - Gauges are exposed as gauges.
+///  - Timers are exposed as histograms in seconds with a `_seconds` suffix.
+///  - Histograms are exposed as histograms with exponential buckets.
+///  - Sets are exposed as gauges with the number of unique values in the last full minute.
+#[derive(Debug, Default)]
+pub struct PrometheusAggregator {
+    series: Mutex<BTreeMap<SeriesKey, PrometheusValue>>,
+}
+
+impl PrometheusAggregator {
+    /// Creates a new, empty aggregator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a metric in the statsd line format, such as `name:1|c|#tag:value`.
+    ///
+    /// Invalid lines are ignored.
+    pub fn record(&self, line: &str) {
+        let mut parts = line.split('|');
+        let (name, value) = match parts.next().and_then(|m| {
+            let index = m.rfind(':')?;
+            Some((&m[..index], m[index + 1..].parse::<f64>().ok()))
+        }) {
+            Some(metric) => metric,
+            None => return,
+        };
+
+        let ty = match parts.next() {
+            Some(ty) => ty,
+            None => return,
+        };
+
+        let mut labels: Vec<_> = parts
+            .filter(|part| part.starts_with('#'))
+            .flat_map(|part| part[1..].split(','))
+            .filter_map(|tag| {
+                let mut split = tag.splitn(2, ':');
+                let key = sanitize_name(split.next()?);
+                Some((key, split.next().unwrap_or_default().to_owned()))
+            })
+            .collect();
+        labels.sort();
+
+        let name = sanitize_name(name);
+        let mut series = self.series.lock().unwrap();
+
+        match ty {
+            "c" => {
+                let entry = series
+                    .entry((format!("{}_total", name), labels))
+                    .or_insert(PrometheusValue::Counter(0.0));
+                if let (PrometheusValue::Counter(total), Some(value)) = (entry, value) {
+                    *total += value;
+                }
+            }
+            "g" => {
+                let entry = series
+                    .entry((name, labels))
+                    .or_insert(PrometheusValue::Gauge(0.0));
+                if let (PrometheusValue::Gauge(gauge), Some(value)) = (entry, value) {
+                    *gauge = value;
+                }
+            }
+            "ms" => {
+                let entry = series
+                    .entry((format!("{}_seconds", name), labels))
+                    .or_insert_with(|| PrometheusValue::histogram(TIMER_BUCKETS));
+                if let Some(value) = value {
+                    entry.observe(value / 1000.0);
+                }
+            }
+            "h" => {
+                let entry = series
+                    .entry((name, labels))
+                    .or_insert_with(|| PrometheusValue::histogram(HISTOGRAM_BUCKETS));
+                if let Some(value) = value {
+                    entry.observe(value);
+                }
+            }
+            "s" => {
+                // Set values are arbitrary strings and might not parse as number.
+                let raw_value = line.split('|').next().and_then(|m| m.rsplit(':').next());
+                let entry = series
+                    .entry((name, labels))
+                    .or_insert_with(PrometheusValue::set);
+                entry.roll_window(Instant::now());
+                if let (PrometheusValue::Set { values, .. }, Some(raw_value)) = (entry, raw_value) {
+                    values.insert(raw_value.to_owned());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Renders all aggregated metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut series = self.series.lock().unwrap();
+        let mut last_name = None;
+        let now = Instant::now();
+
+        for ((name, labels), value) in series.iter_mut() {
+            if last_name != Some(name.as_str()) {
+                writeln!(output, "# TYPE {} {}", name, value.type_name()).ok();
+                last_name = Some(name.as_str());
+            }
+
+            value.roll_window(now);
+
+            match value {
+                PrometheusValue::Counter(value) | PrometheusValue::Gauge(value) => {
+                    output.push_str(name);
+                    write_labels(&mut output, labels, None);
+                    writeln!(output, " {}", value).ok();
+                }
+                PrometheusValue::Histogram {
+                    bounds,
+                    buckets,
+                    sum,
+                    count,
+                } => {
+                    for (bound, bucket) in bounds.iter().zip(buckets.iter()) {
+                        write!(output, "{}_bucket", name).ok();
+                        write_labels(&mut output, labels, Some(&bound.to_string()));
+                        writeln!(output, " {}", bucket).ok();
+                    }
+
+                    write!(output, "{}_bucket", name).ok();
+                    write_labels(&mut output, labels, Some("+Inf"));
+                    writeln!(output, " {}", count).ok();
+
+                    write!(output, "{}_sum", name).ok();
+                    write_labels(&mut output, labels, None);
+                    writeln!(output, " {}", sum).ok();
+
+                    write!(output, "{}_count", name).ok();
+                    write_labels(&mut output, labels, None);
+                    writeln!(output, " {}", count).ok();
+                }
+                PrometheusValue::Set { previous, .. } => {
+                    output.push_str(name);
+                    write_labels(&mut output, labels, None);
+                    writeln!(output, " {}", previous).ok();
+                }
+            }
+        }
+
+        output
+    }
+}
+
+/// A metric sink that records metrics in a [`PrometheusAggregator`] and forwards them to statsd.
+///
+/// [`PrometheusAggregator`]: struct.PrometheusAggregator.html
+struct PrometheusSink {
+    aggregator: Arc<PrometheusAggregator>,
+    statsd_sink: Option<UdpMetricSink>,
+}
+
+impl MetricSink for PrometheusSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        self.aggregator.record(metric);
+
+        match self.statsd_sink {
+            Some(ref sink) => sink.emit(metric),
+            None => Ok(metric.len()),
+        }
+    }
 }
 
 /// A metric for capturing timings.
@@ -489,3 +931,89 @@ macro_rules! metric {
         rv
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::metrics::prelude::*;
+
+    #[test]
+    fn test_reconfigure() {
+        let count = |key| with_client(|client| client.send_metric(client.count_with_tags(key, 1)));
+
+        configure_prometheus("old", None::<&str>, BTreeMap::new());
+        count("requests");
+
+        let mut default_tags = BTreeMap::new();
+        default_tags.insert("host".to_owned(), "relay1".to_owned());
+        reconfigure("new", default_tags);
+        count("requests");
+
+        let aggregator = prometheus_aggregator().unwrap();
+        disable();
+
+        assert_eq!(
+            aggregator.render(),
+            "# TYPE new_requests_total counter\n\
+             new_requests_total{host=\"relay1\"} 1\n\
+             # TYPE old_requests_total counter\n\
+             old_requests_total 1\n"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_counter() {
+        let aggregator = PrometheusAggregator::new();
+        aggregator.record("sentry.relay.event.accepted:1|c|#version:2");
+        aggregator.record("sentry.relay.event.accepted:2|c|#version:2");
+        aggregator.record("sentry.relay.event.accepted:1|c");
+
+        assert_eq!(
+            aggregator.render(),
+            "# TYPE sentry_relay_event_accepted_total counter\n\
+             sentry_relay_event_accepted_total 1\n\
+             sentry_relay_event_accepted_total{version=\"2\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_timer() {
+        let aggregator = PrometheusAggregator::new();
+        aggregator.record("requests.duration:20|ms|#route:store");
+
+        let output = aggregator.render();
+        assert!(output.contains("# TYPE requests_duration_seconds histogram\n"));
+        assert!(
+            output.contains("requests_duration_seconds_bucket{route=\"store\",le=\"0.01\"} 0\n")
+        );
+        assert!(
+            output.contains("requests_duration_seconds_bucket{route=\"store\",le=\"0.025\"} 1\n")
+        );
+        assert!(
+            output.contains("requests_duration_seconds_bucket{route=\"store\",le=\"+Inf\"} 1\n")
+        );
+        assert!(output.contains("requests_duration_seconds_sum{route=\"store\"} 0.02\n"));
+        assert!(output.contains("requests_duration_seconds_count{route=\"store\"} 1\n"));
+    }
+
+    #[test]
+    fn test_prometheus_gauge_and_labels() {
+        let aggregator = PrometheusAggregator::new();
+        aggregator.record("queue.size:5|g|#a:x\"y,b-c:z");
+        aggregator.record("queue.size:3|g|#a:x\"y,b-c:z");
+
+        assert_eq!(
+            aggregator.render(),
+            "# TYPE queue_size gauge\nqueue_size{a=\"x\\\"y\",b_c=\"z\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_invalid_line() {
+        let aggregator = PrometheusAggregator::new();
+        aggregator.record("garbage");
+        aggregator.record("name:abc|x");
+        assert_eq!(aggregator.render(), "");
+    }
+}
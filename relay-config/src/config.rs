@@ -9,7 +9,7 @@ use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use failure::{Backtrace, Context, Fail};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{de, de::DeserializeOwned, Deserialize, Serialize, Serializer};
 
 use relay_auth::{generate_key_pair, generate_relay_id, PublicKey, RelayId, SecretKey};
 use relay_common::{Dsn, Uuid};
@@ -211,7 +211,7 @@ trait ConfigObject: DeserializeOwned + Serialize {
 
 /// Structure used to hold information about configuration overrides via
 /// CLI parameters or environment variables
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct OverridableConfig {
     /// The upstream relay or sentry instance.
     pub upstream: Option<String>,
@@ -235,6 +235,26 @@ pub struct OverridableConfig {
     pub outcome_source: Option<String>,
 }
 
+/// A key pair that the relay rotates to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyPair {
+    /// The secret key of the key pair.
+    pub secret_key: SecretKey,
+    /// The public key of the key pair.
+    pub public_key: PublicKey,
+}
+
+impl KeyPair {
+    /// Generates a new random key pair.
+    pub fn generate() -> Self {
+        let (secret_key, public_key) = generate_key_pair();
+        KeyPair {
+            secret_key,
+            public_key,
+        }
+    }
+}
+
 /// The relay credentials
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Credentials {
@@ -244,6 +264,12 @@ pub struct Credentials {
     pub public_key: PublicKey,
     /// The globally unique ID of the relay.
     pub id: RelayId,
+    /// The key pair to rotate to.
+    ///
+    /// While set, the relay registers this key pair with the upstream in addition to the current
+    /// one, and switches to signing with it once the upstream has acknowledged it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<KeyPair>,
 }
 
 impl Credentials {
@@ -255,6 +281,30 @@ impl Credentials {
             secret_key: sk,
             public_key: pk,
             id: generate_relay_id(),
+            next: None,
+        }
+    }
+
+    /// Starts a key rotation by generating a new key pair.
+    ///
+    /// Replaces the key pair of a previous rotation, if any.
+    pub fn start_rotation(&mut self) -> &KeyPair {
+        log::info!("generating next relay key pair");
+        self.next = Some(KeyPair::generate());
+        self.next.as_ref().unwrap()
+    }
+
+    /// Completes a key rotation by replacing the current key pair with the next one.
+    ///
+    /// Returns `false` if there is no rotation in progress.
+    pub fn complete_rotation(&mut self) -> bool {
+        match self.next.take() {
+            Some(next) => {
+                self.secret_key = next.secret_key;
+                self.public_key = next.public_key;
+                true
+            }
+            None => false,
         }
     }
 
@@ -336,6 +386,22 @@ fn default_host() -> IpAddr {
     }
 }
 
+/// Selects the upstream for requests if multiple upstreams are configured.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamStrategy {
+    /// Send all requests to the first healthy upstream in the configured order.
+    Failover,
+    /// Distribute requests across all healthy upstreams in turn.
+    RoundRobin,
+}
+
+impl Default for UpstreamStrategy {
+    fn default() -> Self {
+        UpstreamStrategy::Failover
+    }
+}
+
 /// Relay specific configuration values.
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(default)]
@@ -344,6 +410,11 @@ pub struct Relay {
     pub mode: RelayMode,
     /// The upstream relay or sentry instance.
     pub upstream: UpstreamDescriptor<'static>,
+    /// A list of upstreams to fail over between. If set, this replaces `upstream`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub upstreams: Vec<UpstreamDescriptor<'static>>,
+    /// Selects the upstream for requests if multiple upstreams are configured.
+    pub upstream_strategy: UpstreamStrategy,
     /// The host the relay should bind to (network interface).
     pub host: IpAddr,
     /// The port to bind for the unencrypted relay HTTP server.
@@ -361,6 +432,8 @@ impl Default for Relay {
         Relay {
             mode: RelayMode::Managed,
             upstream: "https://sentry.io/".parse().unwrap(),
+            upstreams: Vec::new(),
+            upstream_strategy: UpstreamStrategy::default(),
             host: default_host(),
             port: 3000,
             tls_port: None,
@@ -384,6 +457,131 @@ pub enum LogFormat {
     Json,
 }
 
+/// Matches HTTP status codes of requests written to the access log.
+///
+/// Serialized as a string, either an exact status code such as `"429"` or a class of status codes
+/// such as `"4xx"`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatusCodeFilter {
+    /// Matches exactly the given status code.
+    Exact(u16),
+    /// Matches all status codes of the given class, such as `4` for all client errors.
+    Class(u16),
+}
+
+impl StatusCodeFilter {
+    /// Returns `true` if the given status code matches this filter.
+    pub fn matches(self, status: u16) -> bool {
+        match self {
+            StatusCodeFilter::Exact(code) => status == code,
+            StatusCodeFilter::Class(class) => status / 100 == class,
+        }
+    }
+}
+
+impl std::str::FromStr for StatusCodeFilter {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.len() != 3 || !value.is_ascii() {
+            return Err(());
+        }
+
+        let class = value[..1].parse().map_err(|_| ())?;
+        if !(1..=5).contains(&class) {
+            return Err(());
+        }
+
+        if value[1..].eq_ignore_ascii_case("xx") {
+            Ok(StatusCodeFilter::Class(class))
+        } else {
+            value.parse().map(StatusCodeFilter::Exact).map_err(|_| ())
+        }
+    }
+}
+
+impl fmt::Display for StatusCodeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusCodeFilter::Exact(code) => write!(f, "{}", code),
+            StatusCodeFilter::Class(class) => write!(f, "{}xx", class),
+        }
+    }
+}
+
+impl Serialize for StatusCodeFilter {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusCodeFilter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct V;
+
+        impl<'de> de::Visitor<'de> for V {
+            type Value = StatusCodeFilter;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("status code or class, such as 429 or \"4xx\"")
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<StatusCodeFilter, E>
+            where
+                E: de::Error,
+            {
+                self.visit_str(&value.to_string())
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<StatusCodeFilter, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_any(V)
+    }
+}
+
+/// Controls the access log.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+struct AccessLog {
+    /// If set to true, a structured log record is written for every sampled request.
+    enabled: bool,
+    /// The fraction of matching requests written to the access log, between `0.0` and `1.0`.
+    sample_rate: f64,
+    /// Only log requests with these status codes. Logs all requests if empty.
+    status_codes: Vec<StatusCodeFilter>,
+    /// Only log requests for these project ids. Logs all projects if empty.
+    project_ids: Vec<u64>,
+    /// Only log requests authenticated with these public keys. Logs all keys if empty.
+    public_keys: Vec<String>,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        AccessLog {
+            enabled: false,
+            sample_rate: 1.0,
+            status_codes: Vec::new(),
+            project_ids: Vec::new(),
+            public_keys: Vec::new(),
+        }
+    }
+}
+
 /// Controls the logging system.
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(default)]
@@ -396,6 +594,8 @@ struct Logging {
     format: LogFormat,
     /// When set to true, backtraces are forced on.
     enable_backtraces: bool,
+    /// Controls the access log.
+    access_log: AccessLog,
 }
 
 impl Default for Logging {
@@ -405,6 +605,7 @@ impl Default for Logging {
             log_failed_payloads: false,
             format: LogFormat::Auto,
             enable_backtraces: false,
+            access_log: AccessLog::default(),
         }
     }
 }
@@ -422,6 +623,9 @@ struct Metrics {
     default_tags: BTreeMap<String, String>,
     /// A tag name to report the hostname to, for each metric. Defaults to not sending such a tag.
     hostname_tag: Option<String>,
+    /// If set to true, metrics are aggregated in memory and served on `/metrics` in the
+    /// Prometheus text format.
+    prometheus: bool,
 }
 
 impl Default for Metrics {
@@ -431,10 +635,25 @@ impl Default for Metrics {
             prefix: "sentry.relay".into(),
             default_tags: BTreeMap::new(),
             hostname_tag: None,
+            prometheus: false,
         }
     }
 }
 
+/// Selects the store in which quotas are counted.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimiterKind {
+    /// Use Redis if it is configured, otherwise count quotas in memory.
+    Auto,
+    /// Count quotas in Redis. Requires processing mode and a Redis configuration.
+    Redis,
+    /// Count quotas in memory of this Relay instance.
+    Memory,
+    /// Do not enforce quotas. Rate limits returned by the upstream still apply.
+    Disabled,
+}
+
 /// Controls various limits
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(default)]
@@ -457,6 +676,8 @@ struct Limits {
     max_envelope_size: ByteSize,
     /// The maximum number of session items per envelope.
     max_session_count: usize,
+    /// The maximum payload size for each profile.
+    max_profile_size: ByteSize,
     /// The maximum payload size for general API requests.
     max_api_payload_size: ByteSize,
     /// The maximum payload size for file uploads and chunks.
@@ -480,6 +701,8 @@ struct Limits {
     max_connections: usize,
     /// The maximum number of seconds to wait for pending events after receiving a shutdown signal.
     shutdown_timeout: u64,
+    /// The store in which quotas are counted.
+    rate_limiter: RateLimiterKind,
 }
 
 impl Default for Limits {
@@ -492,6 +715,7 @@ impl Default for Limits {
             max_attachments_size: ByteSize::mebibytes(100),
             max_envelope_size: ByteSize::mebibytes(100),
             max_session_count: 100,
+            max_profile_size: ByteSize::mebibytes(50),
             max_api_payload_size: ByteSize::mebibytes(20),
             max_api_file_upload_size: ByteSize::mebibytes(40),
             max_api_chunk_upload_size: ByteSize::mebibytes(100),
@@ -501,6 +725,7 @@ impl Default for Limits {
             max_pending_connections: 2048,
             max_connections: 25_000,
             shutdown_timeout: 10,
+            rate_limiter: RateLimiterKind::Auto,
         }
     }
 }
@@ -560,7 +785,7 @@ struct Cache {
     ///
     /// `cache.batch_interval` controls how quickly batches are sent, this controls the batch size.
     batch_size: usize,
-    /// Interval for watching local cache override files in seconds.
+    /// Interval for polling local project config files in seconds, if they cannot be watched.
     file_interval: u32,
     /// Interval for evicting outdated project configs from memory.
     eviction_interval: u32,
@@ -583,6 +808,67 @@ impl Default for Cache {
     }
 }
 
+/// Controls persistent buffering of envelopes on disk.
+///
+/// The spool is used whenever the in-memory event buffer is exhausted or the upstream is not
+/// available. Spooling is disabled unless a `path` is configured.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+struct Spool {
+    /// The directory to write spooled envelopes to.
+    path: Option<PathBuf>,
+    /// The maximum combined size of all envelopes in the spool.
+    max_disk_size: ByteSize,
+    /// The maximum time in seconds an envelope is kept in the spool before it is dropped.
+    max_age: u32,
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Spool {
+            path: None,
+            max_disk_size: ByteSize::mebibytes(500),
+            max_age: 24 * 3600, // 1 day
+        }
+    }
+}
+
+/// Controls the aggregation of custom metrics into buckets.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+struct Aggregator {
+    /// The length of the time window of each bucket in seconds.
+    bucket_interval: u64,
+    /// The time in seconds to wait after the end of a bucket's time window before flushing it.
+    ///
+    /// This allows late values to be added to the bucket.
+    flush_delay: u64,
+    /// The maximum length of a metric name in bytes. Longer metrics are dropped.
+    max_name_length: usize,
+    /// The maximum number of distinct tag combinations per metric name and project in a bucket.
+    ///
+    /// Values with new tag combinations beyond this limit are dropped.
+    max_tag_cardinality: usize,
+    /// The maximum age of metric timestamps in seconds. Older metrics are dropped.
+    max_secs_in_past: u64,
+    /// The maximum time in seconds that metric timestamps may lie in the future. Metrics with later
+    /// timestamps are dropped.
+    max_secs_in_future: u64,
+}
+
+impl Default for Aggregator {
+    fn default() -> Self {
+        Aggregator {
+            bucket_interval: 10,
+            flush_delay: 30,
+            max_name_length: 200,
+            max_tag_cardinality: 1000,
+            max_secs_in_past: 5 * 24 * 60 * 60, // 5 days
+            max_secs_in_future: 60,             // 1 minute
+        }
+    }
+}
+
 /// Controls interal reporting to Sentry.
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(default)]
@@ -615,6 +901,14 @@ pub enum KafkaTopic {
     Outcomes,
     /// Session health updates.
     Sessions,
+    /// Aggregated custom metrics.
+    Metrics,
+    /// Aggregated session health updates.
+    SessionAggregates,
+    /// Profiles of transactions.
+    Profiles,
+    /// Check-ins of scheduled jobs.
+    Monitors,
 }
 
 /// Configuration for topics.
@@ -631,6 +925,14 @@ pub struct TopicNames {
     pub outcomes: String,
     /// Session health topic name.
     pub sessions: String,
+    /// Custom metrics topic name.
+    pub metrics: String,
+    /// Aggregated session health topic name.
+    pub session_aggregates: String,
+    /// Profiles topic name.
+    pub profiles: String,
+    /// Monitor check-ins topic name.
+    pub monitors: String,
 }
 
 impl Default for TopicNames {
@@ -641,6 +943,10 @@ impl Default for TopicNames {
             transactions: "ingest-transactions".to_owned(),
             outcomes: "outcomes".to_owned(),
             sessions: "ingest-sessions".to_owned(),
+            metrics: "ingest-metrics".to_owned(),
+            session_aggregates: "ingest-session-aggregates".to_owned(),
+            profiles: "profiles".to_owned(),
+            monitors: "ingest-monitors".to_owned(),
         }
     }
 }
@@ -715,6 +1021,9 @@ pub struct Processing {
     /// Emits flags for rate limited attachments. Disabled by default.
     #[serde(default)]
     pub _attachment_flag: bool,
+    /// Aggregation of session updates before they are written to Kafka.
+    #[serde(default)]
+    pub session_aggregation: SessionAggregation,
 }
 
 impl Default for Processing {
@@ -733,6 +1042,29 @@ impl Default for Processing {
             projectconfig_cache_prefix: default_projectconfig_cache_prefix(),
             max_rate_limit: default_max_rate_limit(),
             _attachment_flag: false,
+            session_aggregation: SessionAggregation::default(),
+        }
+    }
+}
+
+/// Configuration for the aggregation of session updates in processing mode.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct SessionAggregation {
+    /// Aggregate session updates instead of producing one message per update.
+    pub enabled: bool,
+    /// The interval in seconds at which aggregated sessions are flushed.
+    pub flush_interval: u64,
+    /// The maximum number of buckets held in memory before they are flushed early.
+    pub max_buckets: usize,
+}
+
+impl Default for SessionAggregation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            flush_interval: 60,
+            max_buckets: 10_000,
         }
     }
 }
@@ -766,6 +1098,24 @@ impl Default for Outcomes {
     }
 }
 
+/// Configuration for the admin API.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+struct Admin {
+    /// The bearer token required to access the admin API. The API is disabled if not set.
+    token: Option<String>,
+}
+
+/// Controls registration of downstream Relays.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+struct Auth {
+    /// If set to true, downstream Relays register with this Relay instead of its upstream.
+    authority: bool,
+    /// Public keys of downstream Relays that are allowed to register with this Relay.
+    allowed_relays: Vec<PublicKey>,
+}
+
 /// Minimal version of a config for dumping out.
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct MinimalConfig {
@@ -804,6 +1154,10 @@ struct ConfigValues {
     #[serde(default)]
     cache: Cache,
     #[serde(default)]
+    spool: Spool,
+    #[serde(default)]
+    aggregator: Aggregator,
+    #[serde(default)]
     limits: Limits,
     #[serde(default)]
     logging: Logging,
@@ -815,6 +1169,10 @@ struct ConfigValues {
     processing: Processing,
     #[serde(default)]
     outcomes: Outcomes,
+    #[serde(default)]
+    admin: Admin,
+    #[serde(default)]
+    auth: Auth,
 }
 
 impl ConfigObject for ConfigValues {
@@ -831,6 +1189,7 @@ pub struct Config {
     values: ConfigValues,
     credentials: Option<Credentials>,
     path: PathBuf,
+    overrides: Vec<OverridableConfig>,
 }
 
 impl fmt::Debug for Config {
@@ -856,6 +1215,7 @@ impl Config {
                 Err(_) => None,
             },
             path: path.clone(),
+            overrides: Vec::new(),
         };
 
         if cfg!(not(feature = "processing")) && config.processing_enabled() {
@@ -865,18 +1225,34 @@ impl Config {
         Ok(config)
     }
 
+    /// Creates a config from a JSON value.
+    ///
+    /// This is mostly useful for tests.
+    pub fn from_json_value(value: serde_json::Value) -> Result<Config, ConfigError> {
+        Ok(Config {
+            values: serde_json::from_value(value)
+                .map_err(|e| ConfigError::wrap(e, ConfigErrorKind::BadJson))?,
+            ..Config::default()
+        })
+    }
+
     /// Override configuration with values coming from other sources (e.g. env variables or
     /// command line parameters)
     pub fn apply_override(
         &mut self,
         mut overrides: OverridableConfig,
     ) -> Result<&mut Self, ConfigError> {
+        // Remember the overrides, so that they can be applied again when reloading the config.
+        self.overrides.push(overrides.clone());
+
         let relay = &mut self.values.relay;
 
         if let Some(upstream) = overrides.upstream {
             relay.upstream = upstream
                 .parse::<UpstreamDescriptor>()
                 .map_err(|err| ConfigError::for_field(err, "upstream"))?;
+            // An explicit upstream replaces the list of failover upstreams.
+            relay.upstreams.clear();
         }
 
         if let Some(host) = overrides.host {
@@ -968,6 +1344,7 @@ impl Config {
                         id,
                         public_key,
                         secret_key,
+                        next: None,
                     })
                 }
                 (None, None, None) => {
@@ -984,6 +1361,128 @@ impl Config {
         Ok(self)
     }
 
+    /// Loads the config again from the same config folder.
+    ///
+    /// All overrides that have been applied to this config are applied to the reloaded config in
+    /// the same order.
+    pub fn reload(&self) -> Result<Config, ConfigError> {
+        let mut config = Config::from_path(&self.path)?;
+        for overrides in &self.overrides {
+            config.apply_override(overrides.clone())?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the names of options that differ in the given config and cannot be changed at
+    /// runtime.
+    ///
+    /// If the returned list is not empty, Relay has to be restarted to apply the new config
+    /// completely.
+    pub fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+        let mut options = Vec::new();
+        let mut check = |name, changed| {
+            if changed {
+                options.push(name);
+            }
+        };
+
+        let kafka_config = |config: &Config| -> Vec<(String, String)> {
+            config
+                .kafka_config()
+                .iter()
+                .map(|param| (param.name.clone(), param.value.clone()))
+                .collect()
+        };
+
+        check("relay.mode", self.relay_mode() != other.relay_mode());
+        check(
+            "relay.host",
+            self.listen_addr().ip() != other.listen_addr().ip(),
+        );
+        check(
+            "relay.port",
+            self.listen_addr().port() != other.listen_addr().port(),
+        );
+        check(
+            "relay.tls_port",
+            self.tls_listen_addr() != other.tls_listen_addr(),
+        );
+        check(
+            "relay.tls_identity_path",
+            self.tls_identity_path() != other.tls_identity_path(),
+        );
+        check(
+            "relay.tls_identity_password",
+            self.tls_identity_password() != other.tls_identity_password(),
+        );
+        check("spool.path", self.spool_path() != other.spool_path());
+        check(
+            "spool.max_disk_size",
+            self.spool_max_disk_size() != other.spool_max_disk_size(),
+        );
+        check(
+            "spool.max_age",
+            self.spool_max_age() != other.spool_max_age(),
+        );
+        check(
+            "limits.max_concurrent_requests",
+            self.max_concurrent_requests() != other.max_concurrent_requests(),
+        );
+        check(
+            "limits.max_thread_count",
+            self.cpu_concurrency() != other.cpu_concurrency(),
+        );
+        check(
+            "limits.max_connection_rate",
+            self.max_connection_rate() != other.max_connection_rate(),
+        );
+        check(
+            "limits.max_pending_connections",
+            self.max_pending_connections() != other.max_pending_connections(),
+        );
+        check(
+            "limits.max_connections",
+            self.max_connections() != other.max_connections(),
+        );
+        check(
+            "limits.rate_limiter",
+            self.rate_limiter() != other.rate_limiter(),
+        );
+        check("logging.format", self.log_format() != other.log_format());
+        check(
+            "logging.enable_backtraces",
+            self.enable_backtraces() != other.enable_backtraces(),
+        );
+        check(
+            "metrics.statsd",
+            self.values.metrics.statsd != other.values.metrics.statsd,
+        );
+        check(
+            "metrics.prometheus",
+            self.metrics_prometheus() != other.metrics_prometheus(),
+        );
+        check(
+            "sentry",
+            self.sentry_dsn().map(ToString::to_string)
+                != other.sentry_dsn().map(ToString::to_string),
+        );
+        check(
+            "processing.enabled",
+            self.processing_enabled() != other.processing_enabled(),
+        );
+        check("processing.redis", self.redis() != other.redis());
+        check(
+            "processing.kafka_config",
+            kafka_config(self) != kafka_config(other),
+        );
+        check(
+            "processing.geoip_path",
+            self.geoip_path() != other.geoip_path(),
+        );
+
+        options
+    }
+
     /// Checks if the config is already initialized.
     pub fn config_exists<P: AsRef<Path>>(path: P) -> bool {
         fs::metadata(ConfigValues::path(path.as_ref())).is_ok()
@@ -1059,6 +1558,14 @@ impl Config {
         self.credentials.as_ref().map(|x| &x.public_key)
     }
 
+    /// Returns the public key of the key pair to rotate to, if a rotation is in progress.
+    pub fn next_public_key(&self) -> Option<&PublicKey> {
+        self.credentials
+            .as_ref()
+            .and_then(|x| x.next.as_ref())
+            .map(|x| &x.public_key)
+    }
+
     /// Returns the relay ID.
     pub fn relay_id(&self) -> Option<&RelayId> {
         self.credentials.as_ref().map(|x| &x.id)
@@ -1070,8 +1577,27 @@ impl Config {
     }
 
     /// Returns the upstream target as descriptor.
+    ///
+    /// If multiple upstreams are configured, this is the first one.
     pub fn upstream_descriptor(&self) -> &UpstreamDescriptor<'_> {
-        &self.values.relay.upstream
+        &self.upstream_descriptors()[0]
+    }
+
+    /// Returns all upstream targets in the configured order.
+    ///
+    /// This always contains at least one upstream.
+    pub fn upstream_descriptors(&self) -> &[UpstreamDescriptor<'_>] {
+        let relay = &self.values.relay;
+        if relay.upstreams.is_empty() {
+            std::slice::from_ref(&relay.upstream)
+        } else {
+            &relay.upstreams
+        }
+    }
+
+    /// Returns the strategy for selecting an upstream.
+    pub fn upstream_strategy(&self) -> UpstreamStrategy {
+        self.values.relay.upstream_strategy
     }
 
     /// Returns the custom HTTP "Host" header.
@@ -1127,6 +1653,31 @@ impl Config {
         self.values.outcomes.source.as_deref()
     }
 
+    /// Returns the bearer token for the admin API.
+    ///
+    /// If this returns `None`, the admin API is disabled.
+    pub fn admin_token(&self) -> Option<&str> {
+        self.values.admin.token.as_deref()
+    }
+
+    /// Returns `true` if downstream Relays register with this Relay.
+    ///
+    /// In this mode, this Relay verifies downstream Relays against the allowed public keys and
+    /// serves project configs to them, instead of forwarding registration to the upstream.
+    pub fn registration_authority(&self) -> bool {
+        self.values.auth.authority
+    }
+
+    /// Returns the public keys of downstream Relays that are allowed to register.
+    pub fn allowed_relays(&self) -> &[PublicKey] {
+        &self.values.auth.allowed_relays
+    }
+
+    /// Returns `true` if the downstream Relay with this public key may register with this Relay.
+    pub fn is_allowed_relay(&self, public_key: &PublicKey) -> bool {
+        self.registration_authority() && self.allowed_relays().contains(public_key)
+    }
+
     /// Returns the log level.
     pub fn log_level_filter(&self) -> log::LevelFilter {
         self.values.logging.level
@@ -1147,6 +1698,37 @@ impl Config {
         self.values.logging.format
     }
 
+    /// Returns whether the access log is enabled.
+    pub fn access_log_enabled(&self) -> bool {
+        self.values.logging.access_log.enabled
+    }
+
+    /// Returns the fraction of requests written to the access log.
+    pub fn access_log_sample_rate(&self) -> f64 {
+        self.values.logging.access_log.sample_rate
+    }
+
+    /// Returns the status codes of requests written to the access log.
+    ///
+    /// If empty, requests are logged regardless of their status code.
+    pub fn access_log_status_codes(&self) -> &[StatusCodeFilter] {
+        &self.values.logging.access_log.status_codes
+    }
+
+    /// Returns the project ids of requests written to the access log.
+    ///
+    /// If empty, requests are logged regardless of their project.
+    pub fn access_log_project_ids(&self) -> &[u64] {
+        &self.values.logging.access_log.project_ids
+    }
+
+    /// Returns the public keys of requests written to the access log.
+    ///
+    /// If empty, requests are logged regardless of their public key.
+    pub fn access_log_public_keys(&self) -> &[String] {
+        &self.values.logging.access_log.public_keys
+    }
+
     /// Returns the socket addresses for statsd.
     ///
     /// If stats is disabled an empty vector is returned.
@@ -1173,6 +1755,11 @@ impl Config {
         &self.values.metrics.default_tags
     }
 
+    /// Returns whether metrics should be served in the Prometheus text format.
+    pub fn metrics_prometheus(&self) -> bool {
+        self.values.metrics.prometheus
+    }
+
     /// Returns the name of the hostname tag that should be attached to each outgoing metric.
     pub fn metrics_hostname_tag(&self) -> Option<&str> {
         self.values.metrics.hostname_tag.as_deref()
@@ -1213,6 +1800,57 @@ impl Config {
         self.values.cache.event_buffer_size
     }
 
+    /// Returns the directory for the persistent envelope spool, if enabled.
+    ///
+    /// Relative paths are resolved relative to the config directory.
+    pub fn spool_path(&self) -> Option<PathBuf> {
+        self.values
+            .spool
+            .path
+            .as_ref()
+            .map(|path| self.path.join(path))
+    }
+
+    /// Returns the maximum combined size of all spooled envelopes in bytes.
+    pub fn spool_max_disk_size(&self) -> usize {
+        self.values.spool.max_disk_size.as_bytes()
+    }
+
+    /// Returns the maximum time an envelope is kept in the spool before it is dropped.
+    pub fn spool_max_age(&self) -> Duration {
+        Duration::from_secs(self.values.spool.max_age.into())
+    }
+
+    /// Returns the length of the time window of custom metric buckets in seconds.
+    pub fn aggregator_bucket_interval(&self) -> u64 {
+        self.values.aggregator.bucket_interval.max(1)
+    }
+
+    /// Returns the time to wait after the end of a bucket's time window before flushing it.
+    pub fn aggregator_flush_delay(&self) -> Duration {
+        Duration::from_secs(self.values.aggregator.flush_delay)
+    }
+
+    /// Returns the maximum length of a custom metric name in bytes.
+    pub fn max_metric_name_length(&self) -> usize {
+        self.values.aggregator.max_name_length
+    }
+
+    /// Returns the maximum number of distinct tag combinations per metric name in a bucket.
+    pub fn max_metric_tag_cardinality(&self) -> usize {
+        self.values.aggregator.max_tag_cardinality
+    }
+
+    /// Returns the maximum age of custom metric timestamps in seconds.
+    pub fn max_metric_secs_in_past(&self) -> u64 {
+        self.values.aggregator.max_secs_in_past
+    }
+
+    /// Returns the maximum time in seconds that custom metric timestamps may lie in the future.
+    pub fn max_metric_secs_in_future(&self) -> u64 {
+        self.values.aggregator.max_secs_in_future
+    }
+
     /// Returns the expiry timeout for cached misses before trying to refetch.
     pub fn cache_miss_expiry(&self) -> Duration {
         Duration::from_secs(self.values.cache.miss_expiry.into())
@@ -1228,7 +1866,8 @@ impl Config {
     pub fn query_batch_interval(&self) -> Duration {
         Duration::from_millis(self.values.cache.batch_interval.into())
     }
-
+    /// Returns the interval in which local project configurations are polled if they cannot be
+    /// watched for changes.
     /// Returns the interval in seconds in which local project configurations should be reloaded.
     pub fn local_cache_interval(&self) -> Duration {
         Duration::from_secs(self.values.cache.file_interval.into())
@@ -1268,6 +1907,11 @@ impl Config {
         self.values.limits.max_session_count
     }
 
+    /// Returns the maximum size of each profile in bytes.
+    pub fn max_profile_size(&self) -> usize {
+        self.values.limits.max_profile_size.as_bytes()
+    }
+
     /// Returns the maximum payload size for general API requests.
     pub fn max_api_payload_size(&self) -> usize {
         self.values.limits.max_api_payload_size.as_bytes()
@@ -1298,6 +1942,11 @@ impl Config {
         Duration::from_secs(self.values.limits.query_timeout)
     }
 
+    /// Returns the store in which quotas are counted.
+    pub fn rate_limiter(&self) -> RateLimiterKind {
+        self.values.limits.rate_limiter
+    }
+
     /// The maximum number of open connections to Relay.
     pub fn max_connections(&self) -> usize {
         self.values.limits.max_connections
@@ -1369,6 +2018,26 @@ impl Config {
         self.values.processing.max_session_secs_in_past.into()
     }
 
+    /// Returns `true` if session updates are aggregated before they are written to Kafka.
+    pub fn aggregate_sessions(&self) -> bool {
+        self.values.processing.session_aggregation.enabled
+    }
+
+    /// The interval at which aggregated sessions are flushed to Kafka.
+    pub fn session_flush_interval(&self) -> Duration {
+        let interval = self.values.processing.session_aggregation.flush_interval;
+        Duration::from_secs(interval.max(1))
+    }
+
+    /// The maximum number of session buckets held in memory before they are flushed to Kafka.
+    pub fn session_max_buckets(&self) -> usize {
+        self.values
+            .processing
+            .session_aggregation
+            .max_buckets
+            .max(1)
+    }
+
     /// The list of Kafka configuration parameters.
     pub fn kafka_config(&self) -> &[KafkaConfigParam] {
         self.values.processing.kafka_config.as_slice()
@@ -1383,6 +2052,10 @@ impl Config {
             KafkaTopic::Transactions => topics.transactions.as_str(),
             KafkaTopic::Outcomes => topics.outcomes.as_str(),
             KafkaTopic::Sessions => topics.sessions.as_str(),
+            KafkaTopic::Metrics => topics.metrics.as_str(),
+            KafkaTopic::SessionAggregates => topics.session_aggregates.as_str(),
+            KafkaTopic::Profiles => topics.profiles.as_str(),
+            KafkaTopic::Monitors => topics.monitors.as_str(),
         }
     }
 
@@ -1419,6 +2092,115 @@ impl Default for Config {
             values: ConfigValues::default(),
             credentials: None,
             path: PathBuf::new(),
+            overrides: Vec::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_from_yaml(yaml: &str) -> Config {
+        Config {
+            values: serde_yaml::from_str(yaml).unwrap(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_restart_not_required() {
+        let current = config_from_yaml("{}");
+        let reloaded = config_from_yaml(
+            r#"
+logging:
+  level: trace
+metrics:
+  prefix: relay.test
+  default_tags:
+    region: eu
+  hostname_tag: host
+"#,
+        );
+
+        assert!(current.restart_required(&reloaded).is_empty());
+    }
+
+    #[test]
+    fn test_restart_required() {
+        let current = config_from_yaml("{}");
+        let reloaded = config_from_yaml(
+            r#"
+relay:
+  port: 3001
+logging:
+  level: debug
+  format: json
+metrics:
+  prefix: relay.test
+  statsd: "127.0.0.1:8126"
+"#,
+        );
+
+        assert_eq!(
+            current.restart_required(&reloaded),
+            vec!["relay.port", "logging.format", "metrics.statsd"]
+        );
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let mut credentials = Credentials::generate();
+        let public_key = credentials.public_key.clone();
+        assert!(!credentials.complete_rotation());
+        assert_eq!(credentials.public_key, public_key);
+
+        let next_public_key = credentials.start_rotation().public_key.clone();
+        assert_ne!(next_public_key, public_key);
+        assert_eq!(credentials.public_key, public_key);
+
+        // Starting again replaces the next key pair.
+        let next_public_key = credentials.start_rotation().public_key.clone();
+        assert_ne!(next_public_key, public_key);
+
+        assert!(credentials.complete_rotation());
+        assert_eq!(credentials.public_key, next_public_key);
+        assert!(credentials.next.is_none());
+        assert!(!credentials.complete_rotation());
+    }
+
+    #[test]
+    fn test_status_code_filter() {
+        assert_eq!("4xx".parse(), Ok(StatusCodeFilter::Class(4)));
+        assert_eq!("5XX".parse(), Ok(StatusCodeFilter::Class(5)));
+        assert_eq!("429".parse(), Ok(StatusCodeFilter::Exact(429)));
+        assert_eq!(" 200 ".parse(), Ok(StatusCodeFilter::Exact(200)));
+
+        for invalid in &[
+            "", "4", "4x", "4xxx", "0xx", "6xx", "600", "abc", "x29", "é4",
+        ] {
+            assert_eq!(invalid.parse::<StatusCodeFilter>(), Err(()), "{}", invalid);
+        }
+
+        assert!(StatusCodeFilter::Class(4).matches(429));
+        assert!(!StatusCodeFilter::Class(4).matches(500));
+        assert!(StatusCodeFilter::Exact(429).matches(429));
+        assert!(!StatusCodeFilter::Exact(429).matches(400));
+    }
+
+    #[test]
+    fn test_status_code_filter_deserialize() {
+        let filters: Vec<StatusCodeFilter> = serde_yaml::from_str("[4xx, 429, '503']").unwrap();
+        assert_eq!(
+            filters,
+            vec![
+                StatusCodeFilter::Class(4),
+                StatusCodeFilter::Exact(429),
+                StatusCodeFilter::Exact(503),
+            ]
+        );
+
+        assert!(serde_yaml::from_str::<Vec<StatusCodeFilter>>("[42]").is_err());
+        assert!(serde_yaml::from_str::<Vec<StatusCodeFilter>>("[4xy]").is_err());
+    }
+}
@@ -2,7 +2,7 @@ use std::cmp::Ordering;
 use std::collections::BTreeSet;
 
 use crate::pii::builtin::BUILTIN_RULES_MAP;
-use crate::pii::{PiiConfig, Redaction, RuleSpec, RuleType};
+use crate::pii::{PiiConfig, PiiConfigError, Redaction, RuleSpec, RuleType};
 use crate::processor::SelectorSpec;
 
 /// A representation of `PiiConfig` that is more (CPU-)efficient for use in `PiiProcessor`. It is
@@ -11,38 +11,62 @@ use crate::processor::SelectorSpec;
 #[derive(Debug, Clone)]
 pub struct CompiledPiiConfig {
     pub(super) applications: Vec<(SelectorSpec, BTreeSet<RuleRef>)>,
+    /// Errors of rules that were skipped because they cannot be executed.
+    pub(super) errors: Vec<PiiConfigError>,
 }
 
 impl CompiledPiiConfig {
     pub fn new(config: &PiiConfig) -> Self {
         let mut applications = Vec::new();
+        let mut errors = Vec::new();
         for (selector, rules) in &config.applications {
             #[allow(clippy::mutable_key_type)]
             let mut rule_set = BTreeSet::default();
             for rule_id in rules {
-                collect_rules(config, &mut rule_set, &rule_id, None);
+                collect_rules(config, &mut rule_set, &mut errors, &rule_id, None);
             }
             applications.push((selector.clone(), rule_set));
         }
 
-        CompiledPiiConfig { applications }
+        CompiledPiiConfig {
+            applications,
+            errors,
+        }
     }
 }
 
 fn get_rule(config: &PiiConfig, id: &str) -> Option<RuleRef> {
-    if let Some(spec) = config.rules.get(id) {
-        Some(RuleRef::new(id.to_owned(), spec))
+    let mut rule = if let Some(spec) = config.rules.get(id) {
+        RuleRef::new(id.to_owned(), spec)
     } else if let Some(spec) = BUILTIN_RULES_MAP.get(id) {
-        Some(RuleRef::new(id.to_owned(), spec))
+        RuleRef::new(id.to_owned(), spec)
     } else {
-        None
+        return None;
+    };
+
+    // Pseudonyms and encryptions use the default keys unless the rule overrides them.
+    match rule.redaction {
+        Redaction::Pseudonymize(ref mut pseudonymize) => {
+            if pseudonymize.key.is_none() {
+                pseudonymize.key = config.vars.hash_key.clone();
+            }
+        }
+        Redaction::Encrypt(ref mut encrypt) => {
+            if encrypt.key.is_none() {
+                encrypt.key = config.vars.encryption_key.clone();
+            }
+        }
+        _ => (),
     }
+
+    Some(rule)
 }
 
 #[allow(clippy::mutable_key_type)]
 fn collect_rules(
     config: &PiiConfig,
     rules: &mut BTreeSet<RuleRef>,
+    errors: &mut Vec<PiiConfigError>,
     rule_id: &str,
     parent: Option<RuleRef>,
 ) {
@@ -68,7 +92,7 @@ fn collect_rules(
                 None
             };
             for rule_id in &m.rules {
-                collect_rules(config, rules, &rule_id, parent.clone());
+                collect_rules(config, rules, errors, &rule_id, parent.clone());
             }
         }
         RuleType::Alias(ref a) => {
@@ -77,11 +101,28 @@ fn collect_rules(
             } else {
                 None
             };
-            collect_rules(config, rules, &a.rule, parent);
-        }
-        _ => {
-            rules.insert(rule);
+            collect_rules(config, rules, errors, &a.rule, parent);
         }
+        _ => match rule.redaction {
+            // Encrypted values are meant to be recovered, so the rule is skipped instead of falling
+            // back to another redaction method.
+            Redaction::Encrypt(ref encrypt) if encrypt.key.is_none() => {
+                let error = PiiConfigError::MissingEncryptionKey(rule.origin.clone());
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            }
+            // Pseudonyms derived without a secret key can be reversed by hashing candidate values.
+            Redaction::Pseudonymize(ref pseudonymize) if pseudonymize.key.is_none() => {
+                let error = PiiConfigError::MissingHashKey(rule.origin.clone());
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            }
+            _ => {
+                rules.insert(rule);
+            }
+        },
     }
 }
 
@@ -2,6 +2,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::ops::Deref;
 
+use failure::Fail;
 use regex::{Regex, RegexBuilder};
 use relay_common::{LazyCellRef, UpsertingLazyCell};
 use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
@@ -104,6 +105,27 @@ pub struct RedactPairRule {
     pub key_pattern: Pattern,
 }
 
+fn entropy_threshold_default() -> f64 {
+    4.0
+}
+
+/// A rule that matches random-looking strings, such as generic API keys.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HighEntropyRule {
+    /// The minimum Shannon entropy in bits per character for a string to match.
+    #[serde(default = "entropy_threshold_default")]
+    pub threshold: f64,
+}
+
+impl Default for HighEntropyRule {
+    fn default() -> Self {
+        HighEntropyRule {
+            threshold: entropy_threshold_default(),
+        }
+    }
+}
+
 /// Supported stripping rules.
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -132,8 +154,28 @@ pub enum RuleType {
     UrlAuth,
     /// US SSN.
     UsSsn,
+    /// International bank account numbers
+    Iban,
+    /// Phone numbers in international or national format
+    Phone,
+    /// UK National Insurance numbers
+    UkNino,
+    /// German tax identification numbers (Steuer-ID)
+    DeTaxId,
     /// Keys that look like passwords
     Password,
+    /// AWS access key IDs
+    AwsAccessKey,
+    /// GitHub personal access and OAuth tokens
+    GithubToken,
+    /// Slack API tokens
+    SlackToken,
+    /// JSON Web Tokens
+    Jwt,
+    /// Tokens in bearer authorization headers
+    BearerToken,
+    /// Strings that look random, such as generic API keys
+    HighEntropy(HighEntropyRule),
     /// When a regex matches a key, a value is removed
     #[serde(alias = "redactPair")]
     RedactPair(RedactPairRule),
@@ -152,6 +194,49 @@ pub struct RuleSpec {
     pub redaction: Redaction,
 }
 
+/// The length of encryption keys in bytes.
+const ENCRYPTION_KEY_LENGTH: usize = 32;
+
+fn deserialize_encryption_secret<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<[u8; ENCRYPTION_KEY_LENGTH], D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    let decoded = base64::decode(&raw).map_err(Error::custom)?;
+    if decoded.len() != ENCRYPTION_KEY_LENGTH {
+        return Err(Error::invalid_length(
+            decoded.len(),
+            &"a base64 encoded key of 32 bytes",
+        ));
+    }
+
+    let mut secret = [0; ENCRYPTION_KEY_LENGTH];
+    secret.copy_from_slice(&decoded);
+    Ok(secret)
+}
+
+/// A named secret key for encrypting values.
+///
+/// The secret is never serialized, so that it does not leak into configs that are served to
+/// downstream Relays or returned from admin endpoints.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptionKey {
+    /// The identifier of the key, which is prefixed to all encrypted values.
+    pub id: String,
+    /// The 256-bit secret key, encoded in base64.
+    #[serde(skip_serializing, deserialize_with = "deserialize_encryption_secret")]
+    pub secret: [u8; ENCRYPTION_KEY_LENGTH],
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the secret.
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
 /// Configuration for rule parameters.
 #[derive(Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -159,6 +244,25 @@ pub struct Vars {
     /// The default secret key for hashing operations.
     #[serde(default)]
     pub hash_key: Option<String>,
+    /// The default key for encryption operations.
+    ///
+    /// Not serialized, since the secret cannot be serialized.
+    #[serde(default, skip_serializing)]
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+/// An error returned when a PII config is invalid.
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum PiiConfigError {
+    /// A rule encrypts values, but no encryption key is configured.
+    #[fail(
+        display = "rule {} requires an encryption key in vars.encryptionKey",
+        _0
+    )]
+    MissingEncryptionKey(String),
+    /// A rule pseudonymizes values, but no hash key is configured.
+    #[fail(display = "rule {} requires a hash key in vars.hashKey", _0)]
+    MissingHashKey(String),
 }
 
 /// A set of named rule configurations.
@@ -214,6 +318,22 @@ impl PiiConfig {
         serde_json::to_string_pretty(&self)
     }
 
+    /// Checks that all applied rules can be executed.
+    ///
+    /// Rules that encrypt values require a key, either in the rule itself or in
+    /// `vars.encryptionKey`. Since encrypted values are meant to be recovered, falling back to
+    /// another redaction method would be surprising. Instead, such rules are skipped when the
+    /// config is compiled, and this returns the first skipped rule.
+    ///
+    /// Deserialization does not validate the config, so that a single invalid rule does not reject
+    /// the entire project config. Call this where errors can be reported.
+    pub fn validate(&self) -> Result<(), PiiConfigError> {
+        match self.compiled().errors.first() {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+
     /// Get a representation of the `PiiConfig` that is more (CPU-)efficient for processing. Result
     /// is cached in lazycell and directly returned on second call.
     pub fn compiled(&self) -> LazyCellRef<CompiledPiiConfig> {
@@ -0,0 +1,194 @@
+//! Reversible encryption of values.
+//!
+//! Values are encrypted with AES-256-GCM-SIV ([RFC 8452]), an authenticated encryption scheme that
+//! is resistant to nonce reuse. All values are encrypted with the same fixed nonce, which makes
+//! the encryption deterministic: equal values encrypt to the same ciphertext under the same key,
+//! but nothing else is revealed about them. The key ID is authenticated as associated data.
+//!
+//! The result is written as `enc:<key_id>:<ciphertext>` in hexadecimal notation. The key ID allows
+//! to rotate keys while values encrypted with previous keys can still be decrypted.
+//!
+//! [RFC 8452]: https://tools.ietf.org/html/rfc8452
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use aes_gcm_siv::{Aes256GcmSiv, Key, Nonce};
+use failure::Fail;
+
+use crate::pii::EncryptionKey;
+
+/// The prefix of all encrypted values.
+const CIPHERTEXT_PREFIX: &str = "enc:";
+
+/// The fixed nonce used for deterministic encryption.
+const NONCE: [u8; 12] = [0; 12];
+
+/// An error returned when decrypting a value fails.
+#[derive(Debug, Fail, Eq, PartialEq)]
+pub enum DecryptionError {
+    /// The value is not an encrypted value.
+    #[fail(display = "invalid encrypted value")]
+    InvalidFormat,
+    /// None of the given keys matches the key ID of the value.
+    #[fail(display = "unknown encryption key {}", _0)]
+    UnknownKey(String),
+    /// The value was modified or encrypted with a different key.
+    #[fail(display = "failed to authenticate encrypted value")]
+    BadCiphertext,
+}
+
+fn new_cipher(key: &EncryptionKey) -> Aes256GcmSiv {
+    Aes256GcmSiv::new(Key::from_slice(&key.secret))
+}
+
+/// Encrypts a value with the given key.
+pub fn encrypt_value(key: &EncryptionKey, text: &str) -> String {
+    let payload = Payload {
+        msg: text.as_bytes(),
+        aad: key.id.as_bytes(),
+    };
+
+    // Encryption only fails for messages larger than 64 GiB.
+    let ciphertext = new_cipher(key)
+        .encrypt(Nonce::from_slice(&NONCE), payload)
+        .expect("value too large for encryption");
+
+    let mut encrypted = format!("{}{}:", CIPHERTEXT_PREFIX, key.id);
+    for byte in &ciphertext {
+        encrypted.push_str(&format!("{:02X}", byte));
+    }
+
+    encrypted
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decrypts a value encrypted with one of the given keys.
+///
+/// The key is selected by the key ID in the encrypted value.
+pub fn decrypt_value(keys: &[EncryptionKey], value: &str) -> Result<String, DecryptionError> {
+    if !value.starts_with(CIPHERTEXT_PREFIX) {
+        return Err(DecryptionError::InvalidFormat);
+    }
+
+    // The key ID may contain colons, but the hex payload never does.
+    let mut parts = value[CIPHERTEXT_PREFIX.len()..].rsplitn(2, ':');
+    let payload = parts.next().ok_or(DecryptionError::InvalidFormat)?;
+    let key_id = parts.next().ok_or(DecryptionError::InvalidFormat)?;
+
+    let key = keys
+        .iter()
+        .find(|key| key.id == key_id)
+        .ok_or_else(|| DecryptionError::UnknownKey(key_id.to_owned()))?;
+
+    let ciphertext = decode_hex(payload).ok_or(DecryptionError::InvalidFormat)?;
+    let payload = Payload {
+        msg: &ciphertext,
+        aad: key.id.as_bytes(),
+    };
+
+    let plaintext = new_cipher(key)
+        .decrypt(Nonce::from_slice(&NONCE), payload)
+        .map_err(|_| DecryptionError::BadCiphertext)?;
+
+    String::from_utf8(plaintext).map_err(|_| DecryptionError::BadCiphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str, secret: u8) -> EncryptionKey {
+        EncryptionKey {
+            id: id.to_owned(),
+            secret: [secret; 32],
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let key = key("2020-08", 1);
+        let text = "a value longer than a single block of the cipher";
+
+        let encrypted = encrypt_value(&key, text);
+        assert!(encrypted.starts_with("enc:2020-08:"));
+        assert!(!encrypted.contains(text));
+        assert_eq!(encrypted, encrypt_value(&key, text));
+        assert_ne!(encrypted, encrypt_value(&key, "another value"));
+        assert_eq!(decrypt_value(&[key], &encrypted).unwrap(), text);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old_key = key("old", 1);
+        let new_key = key("new", 2);
+        let keys = [old_key.clone(), new_key.clone()];
+
+        let encrypted = encrypt_value(&old_key, "jane.doe@example.com");
+        assert_eq!(
+            decrypt_value(&keys, &encrypted).unwrap(),
+            "jane.doe@example.com"
+        );
+
+        let encrypted = encrypt_value(&new_key, "jane.doe@example.com");
+        assert_eq!(
+            decrypt_value(&keys, &encrypted).unwrap(),
+            "jane.doe@example.com"
+        );
+
+        assert_eq!(
+            decrypt_value(&[old_key], &encrypted),
+            Err(DecryptionError::UnknownKey("new".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_tampered() {
+        let key = key("k1", 1);
+        let encrypted = encrypt_value(&key, "jane.doe@example.com");
+
+        let mut tampered = encrypted.clone();
+        let last = if tampered.pop() == Some('0') {
+            '1'
+        } else {
+            '0'
+        };
+        tampered.push(last);
+        assert_eq!(
+            decrypt_value(&[key.clone()], &tampered),
+            Err(DecryptionError::BadCiphertext)
+        );
+
+        let wrong_key = EncryptionKey {
+            secret: [2; 32],
+            ..key.clone()
+        };
+        assert_eq!(
+            decrypt_value(&[wrong_key], &encrypted),
+            Err(DecryptionError::BadCiphertext)
+        );
+
+        // The key ID is authenticated, so the value cannot be moved to another key ID.
+        let renamed_key = EncryptionKey {
+            id: "k2".to_owned(),
+            ..key.clone()
+        };
+        let moved = encrypted.replacen("enc:k1:", "enc:k2:", 1);
+        assert_eq!(
+            decrypt_value(&[renamed_key], &moved),
+            Err(DecryptionError::BadCiphertext)
+        );
+
+        assert_eq!(
+            decrypt_value(&[key], "jane.doe@example.com"),
+            Err(DecryptionError::InvalidFormat)
+        );
+    }
+}
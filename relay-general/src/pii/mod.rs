@@ -4,9 +4,11 @@ mod builtin;
 mod compiledconfig;
 mod config;
 mod convert;
+mod encryption;
 mod generate_selectors;
 mod legacy;
 mod processor;
+mod pseudonymize;
 mod redactions;
 mod regexes;
 mod utils;
@@ -14,12 +16,14 @@ mod utils;
 pub use self::builtin::BUILTIN_RULES;
 pub use self::compiledconfig::CompiledPiiConfig;
 pub use self::config::{
-    AliasRule, MultipleRule, Pattern, PatternRule, PiiConfig, RedactPairRule, RuleSpec, RuleType,
-    Vars,
+    AliasRule, EncryptionKey, HighEntropyRule, MultipleRule, Pattern, PatternRule, PiiConfig,
+    PiiConfigError, RedactPairRule, RuleSpec, RuleType, Vars,
 };
+pub use self::encryption::{decrypt_value, encrypt_value, DecryptionError};
 pub use self::generate_selectors::selector_suggestions_from_value;
 pub use self::legacy::DataScrubbingConfig;
-pub use self::processor::PiiProcessor;
+pub use self::processor::{PiiMatch, PiiProcessor};
 pub use self::redactions::{
-    HashAlgorithm, HashRedaction, MaskRedaction, Redaction, ReplaceRedaction,
+    EncryptRedaction, HashAlgorithm, HashRedaction, MaskRedaction, PseudonymizeRedaction,
+    Redaction, ReplaceRedaction,
 };
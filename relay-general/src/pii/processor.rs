@@ -6,11 +6,16 @@ use std::mem;
 use hmac::{Hmac, Mac};
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::Serialize;
 use sha1::Sha1;
 use sha2::{Sha256, Sha512};
 
 use crate::pii::compiledconfig::RuleRef;
-use crate::pii::regexes::{get_regex_for_rule_type, PatternType, ReplaceBehavior, ANYTHING_REGEX};
+use crate::pii::encryption::encrypt_value;
+use crate::pii::pseudonymize::pseudonymize_value;
+use crate::pii::regexes::{
+    get_regex_for_rule_type, is_redacted_match, PatternType, ReplaceBehavior, ANYTHING_REGEX,
+};
 use crate::pii::utils::process_pairlist;
 use crate::pii::{CompiledPiiConfig, HashAlgorithm, Redaction, RuleType};
 use crate::processor::{
@@ -24,9 +29,24 @@ lazy_static! {
     Regex::new("\x00").unwrap();
 }
 
+/// A value that a rule would redact, as recorded by a `PiiProcessor` in audit mode.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PiiMatch {
+    /// The path of the matched value in the event.
+    pub path: String,
+    /// The rule that matched, as referenced in the config.
+    pub rule_id: String,
+    /// The byte range of the redaction within the redacted string value.
+    ///
+    /// This is `None` if the rule would remove the entire value.
+    pub range: Option<(usize, usize)>,
+}
+
 /// A processor that performs PII stripping.
 pub struct PiiProcessor<'a> {
     compiled_config: &'a CompiledPiiConfig,
+    audit: Option<Vec<PiiMatch>>,
+    audit_removed_depth: Option<usize>,
 }
 
 impl<'a> PiiProcessor<'a> {
@@ -34,20 +54,72 @@ impl<'a> PiiProcessor<'a> {
     pub fn new(compiled_config: &'a CompiledPiiConfig) -> PiiProcessor<'a> {
         // this constructor needs to be cheap... a new PiiProcessor is created for each event. Move
         // any init logic into CompiledPiiConfig::new.
-        PiiProcessor { compiled_config }
+        PiiProcessor {
+            compiled_config,
+            audit: None,
+            audit_removed_depth: None,
+        }
+    }
+
+    /// Creates a new processor in audit mode.
+    ///
+    /// In audit mode, the processor leaves all values untouched. Instead, it applies the rules to a
+    /// copy of every value and records the resulting redactions, which can be retrieved with
+    /// `into_matches` after processing.
+    pub fn audit(compiled_config: &'a CompiledPiiConfig) -> PiiProcessor<'a> {
+        PiiProcessor {
+            compiled_config,
+            audit: Some(Vec::new()),
+            audit_removed_depth: None,
+        }
+    }
+
+    /// Returns `true` if this processor runs in audit mode.
+    pub fn is_audit(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// Returns all matches recorded in audit mode.
+    ///
+    /// This is empty if the processor does not run in audit mode.
+    pub fn into_matches(self) -> Vec<PiiMatch> {
+        self.audit.unwrap_or_default()
     }
 
     fn apply_all_rules(
-        &self,
+        &mut self,
         meta: &mut Meta,
         state: &ProcessingState<'_>,
-        mut value: Option<&mut String>,
+        value: Option<&mut String>,
     ) -> ProcessingResult {
+        // Values within a container that would be removed are never visited by regular processing.
+        if let Some(depth) = self.audit_removed_depth {
+            if state.depth() > depth {
+                return Ok(());
+            }
+            self.audit_removed_depth = None;
+        }
+
         let pii = state.attrs().pii;
         if pii == Pii::False {
             return Ok(());
         }
 
+        if self.is_audit() {
+            self.audit_rules(state, value.as_deref());
+            return Ok(());
+        }
+
+        self.apply_rules(meta, state, pii, value)
+    }
+
+    fn apply_rules(
+        &self,
+        meta: &mut Meta,
+        state: &ProcessingState<'_>,
+        pii: Pii,
+        mut value: Option<&mut String>,
+    ) -> ProcessingResult {
         for (selector, rules) in self.compiled_config.applications.iter() {
             if pii == Pii::Maybe && !selector.is_specific() {
                 continue;
@@ -63,6 +135,27 @@ impl<'a> PiiProcessor<'a> {
 
         Ok(())
     }
+
+    /// Applies all rules to a copy of the value and records the redactions as matches.
+    fn audit_rules(&mut self, state: &ProcessingState<'_>, value: Option<&String>) {
+        let mut meta = Meta::default();
+        let mut value = value.cloned();
+        let result = self.apply_rules(&mut meta, state, state.attrs().pii, value.as_mut());
+
+        if result.is_err() && value.is_none() {
+            self.audit_removed_depth = Some(state.depth());
+        }
+
+        if let Some(ref mut matches) = self.audit {
+            for remark in meta.iter_remarks() {
+                matches.push(PiiMatch {
+                    path: state.path().to_string(),
+                    rule_id: remark.rule_id().to_owned(),
+                    range: remark.range().cloned(),
+                });
+            }
+        }
+    }
 }
 
 impl<'a> Processor for PiiProcessor<'a> {
@@ -252,7 +345,10 @@ fn apply_regex_to_chunks<'a>(
     }
 
     // Early exit if this regex does not match and return the original chunks.
-    let mut captures_iter = regex.captures_iter(&search_string).peekable();
+    let mut captures_iter = regex
+        .captures_iter(&search_string)
+        .filter(|m| is_redacted_match(&rule.ty, &m[0]))
+        .peekable();
     if captures_iter.peek().is_none() {
         return chunks;
     }
@@ -377,6 +473,34 @@ fn insert_replacement_chunks(rule: &RuleRef, text: &str, output: &mut Vec<Chunk<
                 text: Cow::Owned(replace.text.clone()),
             });
         }
+        Redaction::Encrypt(encrypt) => match encrypt.key {
+            Some(ref key) => output.push(Chunk::Redaction {
+                ty: RemarkType::Encrypted,
+                rule_id: Cow::Owned(rule.origin.to_string()),
+                text: Cow::Owned(encrypt_value(key, text)),
+            }),
+            // Rules without a key are skipped when the config is compiled, see
+            // `PiiConfig::validate`. This only removes the value as a safeguard.
+            None => output.push(Chunk::Redaction {
+                ty: RemarkType::Removed,
+                rule_id: Cow::Owned(rule.origin.to_string()),
+                text: Cow::Borrowed(""),
+            }),
+        },
+        Redaction::Pseudonymize(pseudonymize) => match pseudonymize.key {
+            Some(ref key) => output.push(Chunk::Redaction {
+                ty: RemarkType::Pseudonymized,
+                rule_id: Cow::Owned(rule.origin.to_string()),
+                text: Cow::Owned(pseudonymize_value(text, key)),
+            }),
+            // Rules without a key are skipped when the config is compiled, see
+            // `PiiConfig::validate`. This only removes the value as a safeguard.
+            None => output.push(Chunk::Redaction {
+                ty: RemarkType::Removed,
+                rule_id: Cow::Owned(rule.origin.to_string()),
+                text: Cow::Borrowed(""),
+            }),
+        },
     }
 }
 
@@ -985,3 +1109,336 @@ fn test_ip_address_hashing_does_not_overwrite_id() {
 
     assert_eq!(user.id.value().unwrap().as_str(), "123");
 }
+
+#[test]
+fn test_pseudonymize_user() {
+    fn pseudonymize_user(hash_key: &str) -> User {
+        let config = PiiConfig::from_json(&format!(
+            r##"
+                {{
+                    "vars": {{
+                        "hashKey": "{}"
+                    }},
+                    "applications": {{
+                        "$user.ip_address": ["@ip:pseudonymize"],
+                        "$user.email": ["@email:pseudonymize"]
+                    }}
+                }}
+            "##,
+            hash_key
+        ))
+        .unwrap();
+
+        let mut event = Annotated::new(Event {
+            user: Annotated::new(User {
+                email: Annotated::new("jane.doe@example.com".to_string()),
+                ip_address: Annotated::new(IpAddr("192.168.17.42".to_string())),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let compiled = config.compiled();
+        let mut processor = PiiProcessor::new(&compiled);
+        process_value(&mut event, &mut processor, ProcessingState::root()).unwrap();
+
+        let user = event.value().unwrap().user.value().unwrap();
+        let remark = user.ip_address.meta().iter_remarks().next().unwrap();
+        assert_eq!(remark.ty(), RemarkType::Pseudonymized);
+        assert_eq!(remark.rule_id(), "@ip:pseudonymize");
+
+        user.clone()
+    }
+
+    let user = pseudonymize_user("secret");
+
+    // The IP address remains valid, so it is not moved into the user ID.
+    assert_eq!(user.ip_address.value().unwrap().as_str(), "192.168.17.149");
+    assert!(user.id.value().is_none());
+    assert_eq!(user.email.value().unwrap(), "muid.ahl@example.com");
+
+    // Pseudonyms are stable for the same key and change with the key.
+    assert_eq!(pseudonymize_user("secret"), user);
+    assert_ne!(pseudonymize_user("other").email, user.email);
+}
+
+#[test]
+fn test_pseudonymize_missing_key() {
+    // Pseudonymization requires a key. The config is still accepted, but the rule is skipped.
+    let config = PiiConfig::from_json(
+        r##"
+            {
+                "applications": {
+                    "$user.email": ["@email:pseudonymize"]
+                }
+            }
+        "##,
+    )
+    .unwrap();
+    assert_eq!(
+        config.validate(),
+        Err(crate::pii::PiiConfigError::MissingHashKey(
+            "@email:pseudonymize".to_owned()
+        ))
+    );
+    assert!(config.compiled().applications[0].1.is_empty());
+}
+
+#[test]
+fn test_encrypt_user() {
+    use crate::pii::{decrypt_value, EncryptionKey};
+
+    let config = PiiConfig::from_json(
+        r##"
+            {
+                "rules": {
+                    "encrypt_email": {
+                        "type": "email",
+                        "redaction": {
+                            "method": "encrypt"
+                        }
+                    }
+                },
+                "vars": {
+                    "encryptionKey": {
+                        "id": "k1",
+                        "secret": "YW4gZXhhbXBsZSB2ZXJ5IHZlcnkgc2VjcmV0IGtleS4="
+                    }
+                },
+                "applications": {
+                    "$user.email": ["encrypt_email"]
+                }
+            }
+        "##,
+    )
+    .unwrap();
+
+    let mut event = Annotated::new(Event {
+        user: Annotated::new(User {
+            email: Annotated::new("jane.doe@example.com".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    let compiled = config.compiled();
+    let mut processor = PiiProcessor::new(&compiled);
+    process_value(&mut event, &mut processor, ProcessingState::root()).unwrap();
+
+    let email = &event.value().unwrap().user.value().unwrap().email;
+    let remark = email.meta().iter_remarks().next().unwrap();
+    assert_eq!(remark.ty(), RemarkType::Encrypted);
+    assert_eq!(remark.rule_id(), "encrypt_email");
+
+    let encrypted = email.value().unwrap();
+    assert!(encrypted.starts_with("enc:k1:"));
+
+    let keys = [EncryptionKey {
+        id: "k1".to_string(),
+        secret: *b"an example very very secret key.",
+    }];
+    assert_eq!(
+        decrypt_value(&keys, encrypted).unwrap(),
+        "jane.doe@example.com"
+    );
+}
+
+#[test]
+fn test_encrypt_invalid_config() {
+    // Encryption requires a key. The config is still accepted, but the rule is skipped.
+    let config = PiiConfig::from_json(
+        r##"
+            {
+                "rules": {
+                    "encrypt_email": {
+                        "type": "email",
+                        "redaction": {
+                            "method": "encrypt"
+                        }
+                    }
+                },
+                "applications": {
+                    "$user.email": ["encrypt_email"]
+                }
+            }
+        "##,
+    )
+    .unwrap();
+    assert_eq!(
+        config.validate(),
+        Err(crate::pii::PiiConfigError::MissingEncryptionKey(
+            "encrypt_email".to_owned()
+        ))
+    );
+    assert!(config.compiled().applications[0].1.is_empty());
+
+    // Keys must be 256 bits.
+    let error = PiiConfig::from_json(
+        r##"
+            {
+                "vars": {
+                    "encryptionKey": {
+                        "id": "k1",
+                        "secret": "c2VjcmV0"
+                    }
+                }
+            }
+        "##,
+    )
+    .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("a base64 encoded key of 32 bytes"));
+
+    // Unused encryption rules do not require a key.
+    PiiConfig::from_json(
+        r##"
+            {
+                "rules": {
+                    "encrypt_email": {
+                        "type": "email",
+                        "redaction": {
+                            "method": "encrypt"
+                        }
+                    }
+                }
+            }
+        "##,
+    )
+    .unwrap()
+    .validate()
+    .unwrap();
+}
+
+#[test]
+fn test_encryption_key_not_serialized() {
+    let config = PiiConfig::from_json(
+        r##"
+            {
+                "rules": {
+                    "encrypt_email": {
+                        "type": "email",
+                        "redaction": {
+                            "method": "encrypt",
+                            "key": {
+                                "id": "k2",
+                                "secret": "YW4gZXhhbXBsZSB2ZXJ5IHZlcnkgc2VjcmV0IGtleS4="
+                            }
+                        }
+                    }
+                },
+                "vars": {
+                    "encryptionKey": {
+                        "id": "k1",
+                        "secret": "YW4gZXhhbXBsZSB2ZXJ5IHZlcnkgc2VjcmV0IGtleS4="
+                    }
+                }
+            }
+        "##,
+    )
+    .unwrap();
+
+    let json = config.to_json().unwrap();
+    assert!(!json.contains("secret"), "{}", json);
+
+    // The serialized config can still be read, but lacks the keys.
+    let config = PiiConfig::from_json(&json).unwrap();
+    assert_eq!(config.vars.encryption_key, None);
+}
+
+#[test]
+fn test_audit_mode() {
+    let config = PiiConfig::from_json(
+        r##"
+            {
+                "applications": {
+                    "$string": ["@password", "@userpath"]
+                }
+            }
+        "##,
+    )
+    .unwrap();
+
+    let mut extra = Object::new();
+    extra.insert(
+        "password".to_string(),
+        Annotated::new(ExtraValue(Value::String("hunter2".to_string()))),
+    );
+    extra.insert(
+        "path".to_string(),
+        Annotated::new(ExtraValue(Value::String(
+            "C:\\Users\\jane\\file".to_string(),
+        ))),
+    );
+
+    let mut event = Annotated::new(Event {
+        extra: Annotated::new(extra),
+        ..Default::default()
+    });
+    let original = event.clone();
+
+    let compiled = config.compiled();
+    let mut processor = PiiProcessor::audit(&compiled);
+    process_value(&mut event, &mut processor, ProcessingState::root()).unwrap();
+
+    assert_eq!(event, original);
+    assert_eq!(
+        processor.into_matches(),
+        vec![
+            PiiMatch {
+                path: "extra.password".to_string(),
+                rule_id: "@password".to_string(),
+                range: None,
+            },
+            PiiMatch {
+                path: "extra.path".to_string(),
+                rule_id: "@userpath".to_string(),
+                range: Some((9, 15)),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_audit_mode_removed_container() {
+    let config = PiiConfig::from_json(
+        r##"
+            {
+                "applications": {
+                    "extra": ["@anything:remove"],
+                    "$string": ["@userpath"]
+                }
+            }
+        "##,
+    )
+    .unwrap();
+
+    let mut extra = Object::new();
+    extra.insert(
+        "path".to_string(),
+        Annotated::new(ExtraValue(Value::String(
+            "C:\\Users\\jane\\file".to_string(),
+        ))),
+    );
+
+    let mut event = Annotated::new(Event {
+        extra: Annotated::new(extra),
+        ..Default::default()
+    });
+    let original = event.clone();
+
+    let compiled = config.compiled();
+    let mut processor = PiiProcessor::audit(&compiled);
+    process_value(&mut event, &mut processor, ProcessingState::root()).unwrap();
+
+    // Values within the removed container are not reported.
+    assert_eq!(event, original);
+    assert_eq!(
+        processor.into_matches(),
+        vec![PiiMatch {
+            path: "extra".to_string(),
+            rule_id: "@anything:remove".to_string(),
+            range: None,
+        }]
+    );
+}
@@ -0,0 +1,197 @@
+//! Format-preserving pseudonymization of values.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A deterministic stream of pseudo-random bytes derived from a keyed hash of a value.
+///
+/// Equal values and keys always produce the same stream.
+struct PseudonymStream<'a> {
+    key: &'a str,
+    text: &'a str,
+    counter: u32,
+    block: Vec<u8>,
+    pos: usize,
+}
+
+impl<'a> PseudonymStream<'a> {
+    fn new(key: &'a str, text: &'a str) -> Self {
+        PseudonymStream {
+            key,
+            text,
+            counter: 0,
+            block: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        if self.pos >= self.block.len() {
+            let mut mac = Hmac::<Sha256>::new_varkey(self.key.as_bytes()).unwrap();
+            mac.input(&self.counter.to_be_bytes());
+            mac.input(self.text.as_bytes());
+            self.block = mac.result().code().to_vec();
+            self.counter += 1;
+            self.pos = 0;
+        }
+
+        let byte = self.block[self.pos];
+        self.pos += 1;
+        byte
+    }
+
+    /// Picks a character from the given alphabet.
+    fn next_char(&mut self, alphabet: &[u8]) -> char {
+        alphabet[self.next_byte() as usize % alphabet.len()] as char
+    }
+}
+
+const DIGITS: &[u8] = b"0123456789";
+const NONZERO_DIGITS: &[u8] = b"123456789";
+const LOWERCASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWER_HEX: &[u8] = b"0123456789abcdef";
+const UPPER_HEX: &[u8] = b"0123456789ABCDEF";
+
+/// Replaces a value with a pseudonym of the same shape.
+///
+/// The pseudonym is derived from an HMAC of the value, so that equal values map to the same
+/// pseudonym for the same key:
+///
+///  - IPv4 addresses keep their `/24` prefix, IPv6 addresses keep their `/48` prefix. Since only
+///    the last octet of an IPv4 address is replaced, there are just 256 pseudonyms per prefix. This
+///    is a deliberate k-anonymity tradeoff: different addresses in a subnet may share a pseudonym,
+///    and the original address is only hidden among the 256 addresses of its `/24` network.
+///  - UUIDs remain valid UUIDs in the same notation.
+///  - Email addresses keep their domain.
+///  - All other values keep their length and character classes. Digits remain digits and numbers
+///    do not gain leading zeros.
+pub fn pseudonymize_value(text: &str, key: &str) -> String {
+    let mut stream = PseudonymStream::new(key, text);
+
+    if let Ok(addr) = text.parse::<IpAddr>() {
+        return pseudonymize_ip(addr, &mut stream).to_string();
+    }
+
+    if Uuid::parse_str(text).is_ok() {
+        return pseudonymize_hex(text, &mut stream);
+    }
+
+    if let Some(index) = text.rfind('@') {
+        let (local, domain) = text.split_at(index);
+        if !local.is_empty() && domain.len() > 1 {
+            return pseudonymize_chars(local, &mut stream) + domain;
+        }
+    }
+
+    pseudonymize_chars(text, &mut stream)
+}
+
+fn pseudonymize_ip(addr: IpAddr, stream: &mut PseudonymStream<'_>) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mut octets = addr.octets();
+            octets[3] = stream.next_byte();
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        IpAddr::V6(addr) => {
+            let mut segments = addr.segments();
+            for segment in &mut segments[3..] {
+                *segment = u16::from_be_bytes([stream.next_byte(), stream.next_byte()]);
+            }
+            IpAddr::V6(Ipv6Addr::from(segments))
+        }
+    }
+}
+
+fn pseudonymize_hex(text: &str, stream: &mut PseudonymStream<'_>) -> String {
+    text.chars()
+        .map(|c| match c {
+            '0'..='9' | 'a'..='f' => stream.next_char(LOWER_HEX),
+            'A'..='F' => stream.next_char(UPPER_HEX),
+            _ => c,
+        })
+        .collect()
+}
+
+fn pseudonymize_chars(text: &str, stream: &mut PseudonymStream<'_>) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut prev_digit = false;
+
+    for c in text.chars() {
+        let replacement = match c {
+            '0' if !prev_digit => '0',
+            '0'..='9' if prev_digit => stream.next_char(DIGITS),
+            '0'..='9' => stream.next_char(NONZERO_DIGITS),
+            'a'..='z' => stream.next_char(LOWERCASE),
+            'A'..='Z' => stream.next_char(UPPERCASE),
+            _ => c,
+        };
+
+        prev_digit = c.is_ascii_digit();
+        output.push(replacement);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudonymize_deterministic() {
+        let pseudonym = pseudonymize_value("jane.doe@example.com", "key");
+        assert_eq!(pseudonym, pseudonymize_value("jane.doe@example.com", "key"));
+        assert_ne!(
+            pseudonym,
+            pseudonymize_value("jane.doe@example.com", "other")
+        );
+        assert_ne!(pseudonym, pseudonymize_value("john.doe@example.com", "key"));
+    }
+
+    #[test]
+    fn test_pseudonymize_email() {
+        let pseudonym = pseudonymize_value("jane.doe@example.com", "key");
+        assert!(pseudonym.ends_with("@example.com"));
+        assert_eq!(pseudonym.len(), "jane.doe@example.com".len());
+        assert_eq!(&pseudonym[4..5], ".");
+    }
+
+    #[test]
+    fn test_pseudonymize_ip() {
+        let pseudonym = pseudonymize_value("192.168.17.42", "key");
+        let addr: Ipv4Addr = pseudonym.parse().unwrap();
+        assert_eq!(addr.octets()[..3], [192, 168, 17]);
+
+        let pseudonym = pseudonymize_value("2001:db8:85a3::8a2e:370:7334", "key");
+        let addr: Ipv6Addr = pseudonym.parse().unwrap();
+        assert_eq!(addr.segments()[..3], [0x2001, 0xdb8, 0x85a3]);
+    }
+
+    #[test]
+    fn test_pseudonymize_uuid() {
+        let uuid = "6B6A7A66-0E6E-4E8C-A1B4-4B2F3A0A7C2D";
+        let pseudonym = pseudonymize_value(uuid, "key");
+        assert_ne!(pseudonym, uuid);
+        assert!(Uuid::parse_str(&pseudonym).is_ok());
+        assert!(!pseudonym.chars().any(|c| c.is_ascii_lowercase()));
+
+        let uuid = "6b6a7a660e6e4e8ca1b44b2f3a0a7c2d";
+        let pseudonym = pseudonymize_value(uuid, "key");
+        assert_eq!(pseudonym.len(), 32);
+        assert!(Uuid::parse_str(&pseudonym).is_ok());
+    }
+
+    #[test]
+    fn test_pseudonymize_number() {
+        for number in &["4711", "1234567890", "0815"] {
+            let pseudonym = pseudonymize_value(number, "key");
+            assert_eq!(pseudonym.len(), number.len());
+            assert!(pseudonym.bytes().all(|b| b.is_ascii_digit()));
+            assert_eq!(pseudonym.starts_with('0'), number.starts_with('0'));
+        }
+    }
+}
@@ -1,6 +1,8 @@
 //! Redactions for rules.
 use serde::{Deserialize, Serialize};
 
+use crate::pii::EncryptionKey;
+
 /// Defines the hash algorithm to use for hashing
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
 #[allow(clippy::enum_variant_names)]
@@ -99,6 +101,26 @@ impl Default for HashRedaction {
     }
 }
 
+/// Replaces the value with a pseudonym of the same shape.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PseudonymizeRedaction {
+    /// The secret key (if not to use the default from `vars.hashKey`)
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+/// Replaces the value with a reversible, authenticated encryption of itself.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptRedaction {
+    /// The encryption key (if not to use the default from `vars.encryptionKey`)
+    ///
+    /// Not serialized, since the secret cannot be serialized.
+    #[serde(default, skip_serializing)]
+    pub key: Option<EncryptionKey>,
+}
+
 /// Defines how replacements happen.
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(tag = "method", rename_all = "snake_case")]
@@ -117,6 +139,10 @@ pub enum Redaction {
     Mask(MaskRedaction),
     /// Replaces the value with a hash
     Hash(HashRedaction),
+    /// Replaces the value with a deterministic pseudonym that preserves its format
+    Pseudonymize(PseudonymizeRedaction),
+    /// Replaces the value with its encryption, which holders of the key can decrypt
+    Encrypt(EncryptRedaction),
 }
 
 impl Default for Redaction {
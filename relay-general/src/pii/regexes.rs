@@ -87,12 +87,156 @@ pub fn get_regex_for_rule_type(
         RuleType::UrlAuth => smallvec![(v, &*URL_AUTH_REGEX, ReplaceBehavior::replace_group(1))],
         RuleType::UsSsn => smallvec![(v, &*US_SSN_REGEX, ReplaceBehavior::replace_match())],
         RuleType::Userpath => smallvec![(v, &*PATH_REGEX, ReplaceBehavior::replace_group(1))],
+        RuleType::Iban => smallvec![(v, &*IBAN_REGEX, ReplaceBehavior::replace_match())],
+        RuleType::Phone => smallvec![(v, &*PHONE_REGEX, ReplaceBehavior::replace_match())],
+        RuleType::UkNino => smallvec![(v, &*UK_NINO_REGEX, ReplaceBehavior::replace_match())],
+        RuleType::DeTaxId => smallvec![(v, &*DE_TAX_ID_REGEX, ReplaceBehavior::replace_match())],
+        RuleType::AwsAccessKey => {
+            smallvec![(v, &*AWS_ACCESS_KEY_REGEX, ReplaceBehavior::replace_match())]
+        }
+        RuleType::GithubToken => {
+            smallvec![(v, &*GITHUB_TOKEN_REGEX, ReplaceBehavior::replace_match())]
+        }
+        RuleType::SlackToken => {
+            smallvec![(v, &*SLACK_TOKEN_REGEX, ReplaceBehavior::replace_match())]
+        }
+        RuleType::Jwt => smallvec![(v, &*JWT_REGEX, ReplaceBehavior::replace_match())],
+        RuleType::BearerToken => {
+            smallvec![(v, &*BEARER_TOKEN_REGEX, ReplaceBehavior::replace_group(1))]
+        }
+        RuleType::HighEntropy(_) => {
+            smallvec![(v, &*HIGH_ENTROPY_REGEX, ReplaceBehavior::replace_match())]
+        }
 
         // These ought to have been resolved in CompiledConfig
         RuleType::Alias(_) | RuleType::Multiple(_) => smallvec![],
     }
 }
 
+/// Checks whether a match of the rule's regex should be redacted.
+///
+/// Most rules redact every match. Rules that cannot be expressed as a regular expression alone
+/// use this to reject candidates found by their regex.
+pub fn is_redacted_match(ty: &RuleType, text: &str) -> bool {
+    match ty {
+        RuleType::HighEntropy(ref rule) => {
+            text.bytes().any(|b| b.is_ascii_digit())
+                && text.bytes().any(|b| b.is_ascii_alphabetic())
+                && shannon_entropy(text) >= rule.threshold
+        }
+        RuleType::Iban => is_valid_iban(text),
+        RuleType::Phone => is_valid_phone(text),
+        RuleType::UkNino => is_valid_uk_nino(text),
+        RuleType::DeTaxId => is_valid_de_tax_id(text),
+        _ => true,
+    }
+}
+
+/// Validates the length and mod-97 check digits of an IBAN.
+fn is_valid_iban(text: &str) -> bool {
+    let iban: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !(15..=34).contains(&iban.len()) {
+        return false;
+    }
+
+    // The country code and check digits are moved to the end. Letters count as two digits.
+    let mut remainder = 0;
+    for c in iban[4..].iter().chain(&iban[..4]) {
+        let value = match c.to_digit(36) {
+            Some(value) => value,
+            None => return false,
+        };
+
+        let shift = if value >= 10 { 100 } else { 10 };
+        remainder = (remainder * shift + value) % 97;
+    }
+
+    remainder == 1
+}
+
+/// Validates the number of digits in a phone number.
+///
+/// Numbers in international format have at most 15 digits according to E.164. Numbers in national
+/// format start with a trunk prefix and are required to have at least 9 digits, so that short
+/// numeric identifiers do not qualify.
+fn is_valid_phone(text: &str) -> bool {
+    let digits = text.bytes().filter(u8::is_ascii_digit).count();
+    if text.starts_with('+') {
+        (8..=15).contains(&digits)
+    } else {
+        (9..=12).contains(&digits)
+    }
+}
+
+/// Rejects UK National Insurance numbers with prefixes that are never allocated.
+fn is_valid_uk_nino(text: &str) -> bool {
+    let prefix = text.get(..2).unwrap_or_default();
+    !["BG", "GB", "KN", "NK", "NT", "TN", "ZZ"].contains(&prefix)
+}
+
+/// Validates the digit distribution and ISO 7064 check digit of a German tax ID.
+fn is_valid_de_tax_id(text: &str) -> bool {
+    let digits: Vec<u32> = text.chars().filter_map(|c| c.to_digit(10)).collect();
+    if digits.len() != 11 {
+        return false;
+    }
+
+    // Exactly one digit occurs two or three times in the first ten digits, but never three times
+    // in a row.
+    let mut counts = [0; 10];
+    for &digit in &digits[..10] {
+        counts[digit as usize] += 1;
+    }
+
+    let mut repeated = counts.iter().filter(|&&count| count > 1);
+    match (repeated.next(), repeated.next()) {
+        (Some(2), None) => (),
+        (Some(3), None) => {
+            if digits[..10]
+                .windows(3)
+                .any(|w| w[0] == w[1] && w[1] == w[2])
+            {
+                return false;
+            }
+        }
+        _ => return false,
+    }
+
+    let mut product = 10;
+    for &digit in &digits[..10] {
+        let mut sum = (digit + product) % 10;
+        if sum == 0 {
+            sum = 10;
+        }
+        product = (sum * 2) % 11;
+    }
+
+    let check_digit = match 11 - product {
+        10 => 0,
+        check_digit => check_digit,
+    };
+
+    check_digit == digits[10]
+}
+
+/// Computes the Shannon entropy of a string in bits per character.
+fn shannon_entropy(text: &str) -> f64 {
+    let mut counts = [0usize; 256];
+    for b in text.bytes() {
+        counts[b as usize] += 1;
+    }
+
+    let len = text.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
 #[rustfmt::skip]
 macro_rules! ip {
     (v4s) => { "(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)" };
@@ -231,4 +375,152 @@ lazy_static! {
     static ref PASSWORD_KEY_REGEX: Regex = Regex::new(
         r"(?i)(password|secret|passwd|api_key|apikey|access_token|auth|credentials|mysql_pwd|stripetoken)"
     ).unwrap();
+
+    static ref AWS_ACCESS_KEY_REGEX: Regex = Regex::new(
+        r#"(?x)
+            \b
+                (?:AKIA|ASIA|AGPA|AIDA|AROA|AIPA|ANPA|ANVA)
+                [0-9A-Z]{16}
+            \b
+        "#
+    ).unwrap();
+    static ref GITHUB_TOKEN_REGEX: Regex = Regex::new(
+        r#"(?x)
+            \b(?:
+                gh[pousr]_[a-zA-Z0-9]{36,255}
+                | github_pat_[a-zA-Z0-9_]{22,255}
+            )\b
+        "#
+    ).unwrap();
+    static ref SLACK_TOKEN_REGEX: Regex = Regex::new(
+        r#"(?x)
+            \bxox[abposr]-[a-zA-Z0-9-]{10,}
+        "#
+    ).unwrap();
+    static ref JWT_REGEX: Regex = Regex::new(
+        r#"(?x)
+            \b
+                eyJ[a-zA-Z0-9_-]+     # header, starting with `{"`
+                \.eyJ[a-zA-Z0-9_-]+   # payload, starting with `{"`
+                \.[a-zA-Z0-9_-]*      # signature, empty for unsecured tokens
+        "#
+    ).unwrap();
+    static ref BEARER_TOKEN_REGEX: Regex = Regex::new(
+        r#"(?ix)
+            \bbearer\s+
+            ([a-z0-9._~+/-]{16,}=*)   # short words are prose, not tokens
+        "#
+    ).unwrap();
+    static ref IBAN_REGEX: Regex = Regex::new(
+        r#"(?x)
+            \b
+                [A-Z]{2}[0-9]{2}
+                (?:\ ?[A-Z0-9]{4}){2,7}
+                (?:\ ?[A-Z0-9]{1,4})?
+            \b
+        "#
+    ).unwrap();
+    static ref PHONE_REGEX: Regex = Regex::new(
+        r#"(?x)
+            (?:
+                # international format (E.164), optionally with separators
+                \B\+[1-9][0-9]{0,2}
+                (?:[\ .-]?\([0-9]{1,4}\))?
+                (?:[\ .-]?[0-9]{1,4}){2,5}
+              |
+                # national format with trunk prefix
+                (?:\b0[0-9]{1,4}|\(0[0-9]{1,4}\))
+                (?:[\ /-]?[0-9]{2,4}){1,4}
+            )
+            \b
+        "#
+    ).unwrap();
+    static ref UK_NINO_REGEX: Regex = Regex::new(
+        r#"(?x)
+            \b
+                [A-CEGHJ-PR-TW-Z][A-CEGHJ-NPR-TW-Z]
+                \ ?[0-9]{2}\ ?[0-9]{2}\ ?[0-9]{2}\ ?
+                [A-D]
+            \b
+        "#
+    ).unwrap();
+    static ref DE_TAX_ID_REGEX: Regex = Regex::new(
+        r#"(?x)
+            \b
+                [1-9][0-9]
+                (?:\ ?[0-9]{3}){3}
+            \b
+        "#
+    ).unwrap();
+    // Candidates for the entropy check. Slashes are excluded so that paths do not qualify.
+    static ref HIGH_ENTROPY_REGEX: Regex = Regex::new(
+        r#"[a-zA-Z0-9+_=-]{20,}"#
+    ).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pii::HighEntropyRule;
+
+    #[test]
+    fn test_shannon_entropy() {
+        assert_eq!(shannon_entropy("aaaaaaaa"), 0.0);
+        assert_eq!(shannon_entropy("abababab"), 1.0);
+        assert_eq!(shannon_entropy("abcdefgh"), 3.0);
+    }
+
+    #[test]
+    fn test_iban_checksum() {
+        assert!(is_valid_iban("DE89 3704 0044 0532 0130 00"));
+        assert!(is_valid_iban("GB82WEST12345698765432"));
+        assert!(!is_valid_iban("DE89 3704 0044 0532 0130 01"));
+        assert!(!is_valid_iban("DE89 3704 0044"));
+    }
+
+    #[test]
+    fn test_phone_digits() {
+        assert!(is_valid_phone("+49 30 1234567"));
+        assert!(is_valid_phone("+1 (555) 123-4567"));
+        assert!(is_valid_phone("020 7946 0958"));
+        assert!(!is_valid_phone("01-02"));
+        assert!(!is_valid_phone("+1234567890123456"));
+    }
+
+    #[test]
+    fn test_uk_nino_prefix() {
+        assert!(is_valid_uk_nino("AB 12 34 56 C"));
+        assert!(!is_valid_uk_nino("GB123456A"));
+    }
+
+    #[test]
+    fn test_de_tax_id_checksum() {
+        assert!(is_valid_de_tax_id("86095742719"));
+        assert!(is_valid_de_tax_id("65 929 970 489"));
+        // wrong check digit
+        assert!(!is_valid_de_tax_id("86095742718"));
+        // no repeated digit
+        assert!(!is_valid_de_tax_id("12345678903"));
+    }
+
+    #[test]
+    fn test_high_entropy_match() {
+        let ty = RuleType::HighEntropy(HighEntropyRule::default());
+
+        // random api key
+        assert!(is_redacted_match(&ty, "sk9Fq2LmZx7Rt4VbWn8Ke3Hy"));
+        // long identifier without digits
+        assert!(!is_redacted_match(&ty, "AbstractSingletonProxyFactoryBean"));
+        // sha1 hex digest
+        assert!(!is_redacted_match(
+            &ty,
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        ));
+
+        let ty = RuleType::HighEntropy(HighEntropyRule { threshold: 3.0 });
+        assert!(is_redacted_match(
+            &ty,
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        ));
+    }
 }
@@ -0,0 +1,136 @@
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The maximum length of a monitor slug.
+pub const MAX_MONITOR_SLUG_LENGTH: usize = 50;
+
+/// An error returned when a check-in is invalid.
+#[derive(Debug, Fail)]
+pub enum CheckInError {
+    /// The check-in cannot be parsed.
+    #[fail(display = "invalid check-in json")]
+    InvalidJson(#[cause] serde_json::Error),
+    /// The monitor slug is empty, too long, or contains invalid characters.
+    #[fail(display = "invalid monitor slug")]
+    InvalidSlug,
+    /// The duration is negative or not a number.
+    #[fail(display = "invalid check-in duration")]
+    InvalidDuration,
+}
+
+/// The status of a scheduled job reported in a check-in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckInStatus {
+    /// The job has started and is still running.
+    #[serde(alias = "started")]
+    InProgress,
+    /// The job completed successfully.
+    Ok,
+    /// The job failed.
+    Error,
+}
+
+/// A check-in of a scheduled job, such as a cron job, for monitoring.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CheckIn {
+    /// Unique identifier of this check-in.
+    #[serde(default = "Uuid::new_v4")]
+    pub check_in_id: Uuid,
+    /// The slug of the monitor that this check-in belongs to.
+    pub monitor_slug: String,
+    /// The status of the job.
+    pub status: CheckInStatus,
+    /// The duration of the job in seconds, if it has completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// The environment in which the job runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+}
+
+/// Checks whether the given string is a valid monitor slug.
+///
+/// Slugs consist of lowercase ASCII letters, digits, dashes, and underscores.
+pub fn is_valid_monitor_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= MAX_MONITOR_SLUG_LENGTH
+        && slug
+            .bytes()
+            .all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_'))
+}
+
+impl CheckIn {
+    /// Parses and validates a check-in from JSON.
+    pub fn parse(payload: &[u8]) -> Result<Self, CheckInError> {
+        let check_in: Self = serde_json::from_slice(payload).map_err(CheckInError::InvalidJson)?;
+        check_in.validate()?;
+        Ok(check_in)
+    }
+
+    /// Serializes a check-in back into JSON.
+    pub fn serialize(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(self)
+    }
+
+    /// Checks the monitor slug and duration of the check-in.
+    pub fn validate(&self) -> Result<(), CheckInError> {
+        if !is_valid_monitor_slug(&self.monitor_slug) {
+            return Err(CheckInError::InvalidSlug);
+        }
+
+        match self.duration {
+            Some(duration) if !duration.is_finite() || duration < 0.0 => {
+                Err(CheckInError::InvalidDuration)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_in_roundtrip() {
+        let json = r#"{
+  "check_in_id": "8e9e2f4a-5b6c-4d7e-8f9a-0b1c2d3e4f5a",
+  "monitor_slug": "nightly-backup",
+  "status": "ok",
+  "duration": 12.5,
+  "environment": "production"
+}"#;
+
+        let check_in = CheckIn {
+            check_in_id: "8e9e2f4a-5b6c-4d7e-8f9a-0b1c2d3e4f5a".parse().unwrap(),
+            monitor_slug: "nightly-backup".to_owned(),
+            status: CheckInStatus::Ok,
+            duration: Some(12.5),
+            environment: Some("production".to_owned()),
+        };
+
+        assert_eq_dbg!(check_in, CheckIn::parse(json.as_bytes()).unwrap());
+        assert_eq_str!(json, serde_json::to_string_pretty(&check_in).unwrap());
+    }
+
+    #[test]
+    fn test_check_in_started() {
+        let json = r#"{"monitor_slug":"my_job","status":"started"}"#;
+        let check_in = CheckIn::parse(json.as_bytes()).unwrap();
+        assert_eq!(check_in.status, CheckInStatus::InProgress);
+        assert_eq!(check_in.duration, None);
+    }
+
+    #[test]
+    fn test_check_in_invalid() {
+        let json = r#"{"monitor_slug":"My Job","status":"ok"}"#;
+        let result = CheckIn::parse(json.as_bytes());
+        assert!(matches!(result, Err(CheckInError::InvalidSlug)));
+
+        let json = r#"{"monitor_slug":"job","status":"ok","duration":-1}"#;
+        let result = CheckIn::parse(json.as_bytes());
+        assert!(matches!(result, Err(CheckInError::InvalidDuration)));
+    }
+}
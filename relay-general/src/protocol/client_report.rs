@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use relay_common::DataCategory;
+
+/// Data that was discarded by an SDK before it was sent.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DiscardedEvent {
+    /// The reason why the SDK discarded the data, such as `queue_overflow` or `ratelimit_backoff`.
+    pub reason: String,
+    /// The category of the discarded data.
+    pub category: DataCategory,
+    /// The number of discarded items, or the size in bytes for attachments.
+    pub quantity: u32,
+}
+
+/// A report of data discarded by an SDK since the last report.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClientReport {
+    /// The timestamp of when the report was created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Counters of discarded data per reason and category.
+    #[serde(default)]
+    pub discarded_events: Vec<DiscardedEvent>,
+}
+
+impl ClientReport {
+    /// Parses a client report from JSON.
+    pub fn parse(payload: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(payload)
+    }
+
+    /// Serializes a client report back into JSON.
+    pub fn serialize(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_report_roundtrip() {
+        let json = r#"{
+  "timestamp": "2020-02-07T15:17:00Z",
+  "discarded_events": [
+    {
+      "reason": "queue_overflow",
+      "category": "error",
+      "quantity": 42
+    },
+    {
+      "reason": "ratelimit_backoff",
+      "category": "transaction",
+      "quantity": 7
+    }
+  ]
+}"#;
+
+        let report = ClientReport {
+            timestamp: Some("2020-02-07T15:17:00Z".parse().unwrap()),
+            discarded_events: vec![
+                DiscardedEvent {
+                    reason: "queue_overflow".to_owned(),
+                    category: DataCategory::Error,
+                    quantity: 42,
+                },
+                DiscardedEvent {
+                    reason: "ratelimit_backoff".to_owned(),
+                    category: DataCategory::Transaction,
+                    quantity: 7,
+                },
+            ],
+        };
+
+        assert_eq_dbg!(report, ClientReport::parse(json.as_bytes()).unwrap());
+        assert_eq_str!(json, serde_json::to_string_pretty(&report).unwrap());
+    }
+
+    #[test]
+    fn test_client_report_unknown_category() {
+        let json = r#"{"discarded_events":[{"reason":"x","category":"foo","quantity":1}]}"#;
+        let report = ClientReport::parse(json.as_bytes()).unwrap();
+        assert_eq!(report.timestamp, None);
+        assert_eq!(report.discarded_events[0].category, DataCategory::Unknown);
+    }
+}
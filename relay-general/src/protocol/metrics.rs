@@ -130,6 +130,15 @@ pub struct Metrics {
     /// This metric is measured in Sentry and should be reported in all processing tasks.
     #[metastructure(field = "flag.processing.fatal")]
     pub flag_processing_fatal: Annotated<bool>,
+
+    /// The number of values that the PII audit config of the project would redact.
+    ///
+    /// The audit config is applied in a dry run that leaves the event untouched. This allows to
+    /// review the impact of changes to the PII config before rolling them out.
+    ///
+    /// This metric is measured in Relay during processing.
+    #[metastructure(field = "count.pii_audit.matches")]
+    pub count_pii_audit_matches: Annotated<u64>,
 }
 
 // Do not process Metrics
@@ -1,5 +1,7 @@
 //! Implements the sentry event protocol.
 mod breadcrumb;
+mod checkin;
+mod client_report;
 mod clientsdk;
 mod constants;
 mod contexts;
@@ -10,6 +12,7 @@ mod fingerprint;
 mod logentry;
 mod mechanism;
 mod metrics;
+mod profile;
 mod request;
 #[cfg(feature = "jsonschema")]
 mod schema;
@@ -25,6 +28,10 @@ mod user;
 mod user_report;
 
 pub use self::breadcrumb::Breadcrumb;
+pub use self::checkin::{
+    is_valid_monitor_slug, CheckIn, CheckInError, CheckInStatus, MAX_MONITOR_SLUG_LENGTH,
+};
+pub use self::client_report::{ClientReport, DiscardedEvent};
 pub use self::clientsdk::{ClientSdkInfo, ClientSdkPackage};
 pub use self::constants::{INVALID_ENVIRONMENTS, INVALID_RELEASES, VALID_PLATFORMS};
 pub use self::contexts::{
@@ -44,11 +51,17 @@ pub use self::fingerprint::Fingerprint;
 pub use self::logentry::{LogEntry, Message};
 pub use self::mechanism::{CError, MachException, Mechanism, MechanismMeta, PosixSignal};
 pub use self::metrics::Metrics;
+pub use self::profile::{
+    Profile, ProfileError, ProfileFrame, ProfileSample, ProfileThreadMetadata, SampleProfile,
+};
 pub use self::request::{Cookies, HeaderName, HeaderValue, Headers, Query, Request};
 #[cfg(feature = "jsonschema")]
 pub use self::schema::event_json_schema;
 pub use self::security_report::{Csp, ExpectCt, ExpectStaple, Hpkp, SecurityReportType};
-pub use self::session::{ParseSessionStatusError, SessionAttributes, SessionStatus, SessionUpdate};
+pub use self::session::{
+    ParseSessionStatusError, SessionAggregateItem, SessionAggregates, SessionAttributes,
+    SessionStatus, SessionUpdate,
+};
 pub use self::span::Span;
 pub use self::stacktrace::{Frame, FrameData, FrameVars, RawStacktrace, Stacktrace};
 pub use self::tags::{TagEntry, Tags};
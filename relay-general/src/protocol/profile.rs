@@ -0,0 +1,263 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::pii::{PiiConfig, PiiProcessor};
+use crate::processor::{process_value, FieldAttrs, Pii, ProcessingState, ValueType};
+use crate::types::{Annotated, ProcessingResult};
+
+lazy_static::lazy_static! {
+    /// PII config that scrubs user names from file system paths.
+    static ref USERPATH_PII_CONFIG: PiiConfig =
+        PiiConfig::from_json(r#"{"applications": {"$string": ["@userpath"]}}"#).unwrap();
+
+    /// Field attributes for frame paths, which always qualify for scrubbing.
+    static ref PATH_FIELD_ATTRS: FieldAttrs = FieldAttrs {
+        pii: Pii::True,
+        ..FieldAttrs::default()
+    };
+}
+
+/// Applies the PII processor to an optional path of a frame.
+fn scrub_path(
+    processor: &mut PiiProcessor<'_>,
+    key: &'static str,
+    path: &mut Option<String>,
+) -> ProcessingResult {
+    let mut annotated = Annotated::<String>::from(path.take());
+    let attrs = Some(Cow::Borrowed(&*PATH_FIELD_ATTRS));
+    let state = ProcessingState::root().enter_static(key, attrs, Some(ValueType::String));
+    process_value(&mut annotated, processor, &state)?;
+    *path = annotated.into_value();
+    Ok(())
+}
+
+/// An error returned when a profile is structurally invalid.
+#[derive(Debug, Fail)]
+pub enum ProfileError {
+    /// The profile cannot be parsed.
+    #[fail(display = "invalid profile json")]
+    InvalidJson(#[cause] serde_json::Error),
+    /// The profile does not contain any samples.
+    #[fail(display = "profile has no samples")]
+    NoSamples,
+    /// A sample refers to a stack that does not exist.
+    #[fail(display = "sample refers to unknown stack {}", _0)]
+    InvalidStackId(usize),
+    /// A stack refers to a frame that does not exist.
+    #[fail(display = "stack refers to unknown frame {}", _0)]
+    InvalidFrameId(usize),
+    /// A sample was taken on a thread without metadata.
+    #[fail(display = "missing metadata for thread {}", _0)]
+    MissingThreadMetadata(String),
+}
+
+/// A frame in the stacks of a profile.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileFrame {
+    /// Name of the function being called.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    /// The source file name relative to the project root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// Absolute path to the source file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abs_path: Option<String>,
+    /// Line number within the source file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lineno: Option<u64>,
+    /// Absolute address of the instruction for native frames.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instruction_addr: Option<String>,
+    /// Additional attributes of the frame.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+/// A single sample of a profile.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileSample {
+    /// Index of the sampled stack in `stacks`.
+    pub stack_id: usize,
+    /// Identifier of the thread on which the sample was taken.
+    pub thread_id: String,
+    /// Time since the start of the profile in nanoseconds.
+    pub elapsed_since_start_ns: u64,
+    /// Additional attributes of the sample.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+/// Metadata of a thread on which samples were taken.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileThreadMetadata {
+    /// The name of the thread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The scheduling priority of the thread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>,
+    /// Additional attributes of the thread.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+/// The samples of a profile along with their stacks and frames.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SampleProfile {
+    /// Samples taken during the profile.
+    pub samples: Vec<ProfileSample>,
+    /// Stacks of the samples, each a list of indexes into `frames` starting at the innermost frame.
+    pub stacks: Vec<Vec<usize>>,
+    /// All frames referenced by stacks.
+    pub frames: Vec<ProfileFrame>,
+    /// Metadata of the sampled threads keyed by thread identifier.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub thread_metadata: BTreeMap<String, ProfileThreadMetadata>,
+}
+
+/// A profile recorded by a sampling profiler during a transaction.
+///
+/// The profile is linked to its transaction through the event ID of the envelope.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// The platform of the profiled application.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// The sampled data.
+    pub profile: SampleProfile,
+    /// Additional attributes of the profile, such as device and OS information.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+impl Profile {
+    /// Parses and validates a profile from JSON.
+    pub fn parse(payload: &[u8]) -> Result<Self, ProfileError> {
+        let profile: Self = serde_json::from_slice(payload).map_err(ProfileError::InvalidJson)?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Serializes a profile back into JSON.
+    pub fn serialize(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(self)
+    }
+
+    /// Checks that samples, stacks, frames and thread metadata are consistent.
+    ///
+    /// Thread metadata is optional. If it is present, it must cover all sampled threads.
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        let profile = &self.profile;
+        if profile.samples.is_empty() {
+            return Err(ProfileError::NoSamples);
+        }
+
+        for stack in &profile.stacks {
+            if let Some(&frame_id) = stack.iter().find(|&&id| id >= profile.frames.len()) {
+                return Err(ProfileError::InvalidFrameId(frame_id));
+            }
+        }
+
+        let mut thread_ids = BTreeSet::new();
+        for sample in &profile.samples {
+            if sample.stack_id >= profile.stacks.len() {
+                return Err(ProfileError::InvalidStackId(sample.stack_id));
+            }
+
+            thread_ids.insert(sample.thread_id.as_str());
+        }
+
+        if !profile.thread_metadata.is_empty() {
+            if let Some(thread_id) = thread_ids
+                .into_iter()
+                .find(|id| !profile.thread_metadata.contains_key(*id))
+            {
+                return Err(ProfileError::MissingThreadMetadata(thread_id.to_owned()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes user names from the paths of all frames using the `@userpath` PII rule.
+    pub fn scrub_frame_paths(&mut self) -> ProcessingResult {
+        let compiled = USERPATH_PII_CONFIG.compiled();
+        let mut processor = PiiProcessor::new(&compiled);
+
+        for frame in &mut self.profile.frames {
+            scrub_path(&mut processor, "filename", &mut frame.filename)?;
+            scrub_path(&mut processor, "abs_path", &mut frame.abs_path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile_json(stack_id: usize, frame_id: usize, thread_id: &str) -> String {
+        format!(
+            r#"{{
+                "platform": "cocoa",
+                "profile": {{
+                    "samples": [
+                        {{"stack_id": {}, "thread_id": "{}", "elapsed_since_start_ns": 1000}}
+                    ],
+                    "stacks": [[{}]],
+                    "frames": [{{"function": "main", "abs_path": "/Users/jane/app/main.swift"}}],
+                    "thread_metadata": {{"1": {{"name": "main", "priority": 31}}}}
+                }},
+                "device_model": "iPhone14,2"
+            }}"#,
+            stack_id, thread_id, frame_id
+        )
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = Profile::parse(profile_json(0, 0, "1").as_bytes()).unwrap();
+        assert_eq!(profile.platform.as_deref(), Some("cocoa"));
+        assert_eq!(profile.profile.samples[0].elapsed_since_start_ns, 1000);
+        assert_eq!(profile.profile.thread_metadata["1"].priority, Some(31));
+        assert_eq!(profile.other["device_model"], "iPhone14,2");
+    }
+
+    #[test]
+    fn test_validate_profile() {
+        let result = Profile::parse(profile_json(1, 0, "1").as_bytes());
+        assert!(matches!(result, Err(ProfileError::InvalidStackId(1))));
+
+        let result = Profile::parse(profile_json(0, 2, "1").as_bytes());
+        assert!(matches!(result, Err(ProfileError::InvalidFrameId(2))));
+
+        let result = Profile::parse(profile_json(0, 0, "2").as_bytes());
+        assert!(matches!(
+            result,
+            Err(ProfileError::MissingThreadMetadata(_))
+        ));
+
+        let result = Profile::parse(br#"{"profile":{"samples":[],"stacks":[],"frames":[]}}"#);
+        assert!(matches!(result, Err(ProfileError::NoSamples)));
+    }
+
+    #[test]
+    fn test_scrub_frame_paths() {
+        let mut profile = Profile::parse(profile_json(0, 0, "1").as_bytes()).unwrap();
+        profile.scrub_frame_paths().unwrap();
+
+        let frame = &profile.profile.frames[0];
+        assert_eq!(
+            frame.abs_path.as_deref(),
+            Some("/Users/[user]/app/main.swift")
+        );
+        assert_eq!(frame.filename, None);
+        assert_eq!(frame.function.as_deref(), Some("main"));
+    }
+}
@@ -136,6 +136,74 @@ impl SessionUpdate {
     }
 }
 
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_zero(val: &u32) -> bool {
+    *val == 0
+}
+
+/// The number of sessions that started in the same time window, grouped by their final status.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionAggregateItem {
+    /// The timestamp of when the sessions started.
+    pub started: DateTime<Utc>,
+    /// The distinct identifier.
+    #[serde(rename = "did", default, skip_serializing_if = "Option::is_none")]
+    pub distinct_id: Option<String>,
+    /// The number of sessions that exited without errors.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub exited: u32,
+    /// The number of sessions that exited with errors.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub errored: u32,
+    /// The number of sessions that terminated abnormally.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub abnormal: u32,
+    /// The number of sessions that crashed.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub crashed: u32,
+}
+
+/// Pre-aggregated sessions of a release, sent by SDKs that cannot track individual sessions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionAggregates {
+    /// The aggregated sessions.
+    #[serde(default)]
+    pub aggregates: Vec<SessionAggregateItem>,
+    /// The shared session event attributes.
+    #[serde(rename = "attrs")]
+    pub attributes: SessionAttributes,
+}
+
+impl SessionAggregateItem {
+    /// Returns the number of sessions in this aggregate.
+    pub fn num_sessions(&self) -> u64 {
+        u64::from(self.exited)
+            + u64::from(self.errored)
+            + u64::from(self.abnormal)
+            + u64::from(self.crashed)
+    }
+}
+
+impl SessionAggregates {
+    /// Returns the number of sessions in all aggregates.
+    pub fn num_sessions(&self) -> u64 {
+        self.aggregates
+            .iter()
+            .map(SessionAggregateItem::num_sessions)
+            .sum()
+    }
+
+    /// Parses session aggregates from JSON.
+    pub fn parse(payload: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(payload)
+    }
+
+    /// Serializes session aggregates back into JSON.
+    pub fn serialize(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -246,4 +314,59 @@ mod tests {
         assert_eq_dbg!(update, SessionUpdate::parse(json.as_bytes()).unwrap());
         assert_eq_str!(json, serde_json::to_string_pretty(&update).unwrap());
     }
+
+    #[test]
+    fn test_session_aggregates_roundtrip() {
+        let json = r#"{
+  "aggregates": [
+    {
+      "started": "2020-02-07T14:16:00Z",
+      "exited": 123
+    },
+    {
+      "started": "2020-02-07T14:17:00Z",
+      "did": "foobarbaz",
+      "errored": 2,
+      "crashed": 1
+    }
+  ],
+  "attrs": {
+    "release": "sentry-test@1.0.0",
+    "environment": "production"
+  }
+}"#;
+
+        let aggregates = SessionAggregates {
+            aggregates: vec![
+                SessionAggregateItem {
+                    started: "2020-02-07T14:16:00Z".parse().unwrap(),
+                    distinct_id: None,
+                    exited: 123,
+                    errored: 0,
+                    abnormal: 0,
+                    crashed: 0,
+                },
+                SessionAggregateItem {
+                    started: "2020-02-07T14:17:00Z".parse().unwrap(),
+                    distinct_id: Some("foobarbaz".into()),
+                    exited: 0,
+                    errored: 2,
+                    abnormal: 0,
+                    crashed: 1,
+                },
+            ],
+            attributes: SessionAttributes {
+                release: "sentry-test@1.0.0".to_owned(),
+                environment: Some("production".to_owned()),
+                ip_address: None,
+                user_agent: None,
+            },
+        };
+
+        assert_eq_dbg!(
+            aggregates,
+            SessionAggregates::parse(json.as_bytes()).unwrap()
+        );
+        assert_eq_str!(json, serde_json::to_string_pretty(&aggregates).unwrap());
+    }
 }
@@ -3,7 +3,7 @@ use std::time::Duration;
 use chrono::{DateTime, Duration as SignedDuration, Utc};
 
 use crate::processor::{ProcessValue, ProcessingState, Processor};
-use crate::protocol::{Event, SessionUpdate, Timestamp};
+use crate::protocol::{ClientReport, Event, SessionAggregates, SessionUpdate, Timestamp};
 use crate::types::{Error, ErrorKind, Meta, ProcessingResult};
 
 /// A signed correction that contains the sender's timestamp as well as the drift to the receiver.
@@ -97,6 +97,22 @@ impl ClockDriftProcessor {
             session.started = session.started + correction.drift;
         }
     }
+
+    /// Processes the given client report.
+    pub fn process_client_report(&self, report: &mut ClientReport) {
+        if let (Some(correction), Some(timestamp)) = (self.correction, report.timestamp.as_mut()) {
+            *timestamp = *timestamp + correction.drift;
+        }
+    }
+
+    /// Processes the given session aggregates.
+    pub fn process_session_aggregates(&self, aggregates: &mut SessionAggregates) {
+        if let Some(correction) = self.correction {
+            for aggregate in &mut aggregates.aggregates {
+                aggregate.started = aggregate.started + correction.drift;
+            }
+        }
+    }
 }
 
 impl Processor for ClockDriftProcessor {
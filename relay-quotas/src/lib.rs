@@ -6,9 +6,14 @@
 /// typically happens for disabled keys, projects, or organizations.
 const REJECT_ALL_SECS: u64 = 60;
 
+mod limiter;
+mod memory;
 mod quota;
 mod rate_limit;
+mod tracked;
 
+pub use self::limiter::*;
+pub use self::memory::*;
 pub use self::quota::*;
 pub use self::rate_limit::*;
 
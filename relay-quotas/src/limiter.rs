@@ -0,0 +1,40 @@
+use failure::Fail;
+
+use relay_redis::RedisError;
+
+use crate::quota::{ItemScoping, Quota};
+use crate::rate_limit::RateLimits;
+
+/// An error returned by a `RateLimiter`.
+#[derive(Debug, Fail)]
+pub enum RateLimitingError {
+    /// Failed to communicate with Redis.
+    #[fail(display = "failed to communicate with redis")]
+    Redis(#[cause] RedisError),
+}
+
+/// A service that executes quotas and checks for rate limits.
+///
+/// Rate limiters count consumption of quotas with a `window` in a counter store, such as Redis or
+/// process memory. Implementations must be safe to share between threads, so that a single rate
+/// limiter can be used by all workers processing data.
+///
+/// Quotas with a limit of `0` are always rejected without counting. Quotas that cannot be tracked
+/// due to missing fields are skipped for forward-compatibility.
+pub trait RateLimiter: Send + Sync {
+    /// Checks whether any of the quotas in effect for the given project and project key has been
+    /// exceeded and records consumption of the quota.
+    ///
+    /// By invoking this method, the caller signals that data is being ingested and needs to be
+    /// counted against the quota. This increment happens atomically if none of the quotas have been
+    /// exceeded. Otherwise, a rate limit is returned and data is not counted against the quotas.
+    ///
+    /// If no key is specified, then only organization-wide and project-wide quotas are checked. If
+    /// a key is specified, then key-quotas are also checked.
+    fn is_rate_limited(
+        &self,
+        quotas: &[Quota],
+        item_scoping: ItemScoping<'_>,
+        quantity: usize,
+    ) -> Result<RateLimits, RateLimitingError>;
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use relay_common::UnixTimestamp;
+
+use crate::limiter::{RateLimiter, RateLimitingError};
+use crate::quota::{ItemScoping, Quota};
+use crate::rate_limit::{RateLimit, RateLimits, RetryAfter};
+use crate::tracked::{get_refunded_quota_key, TrackedQuota};
+use crate::REJECT_ALL_SECS;
+
+/// A quota counter for a single time slot.
+#[derive(Debug)]
+struct Counter {
+    value: i64,
+    expiry: UnixTimestamp,
+}
+
+/// Quota counters shared between all clones of a `MemoryRateLimiter`.
+#[derive(Debug, Default)]
+struct Counters {
+    counters: HashMap<String, Counter>,
+    last_eviction: Option<UnixTimestamp>,
+}
+
+impl Counters {
+    /// Returns the current value of the counter, or `0` if the counter does not exist.
+    fn get(&self, key: &str, timestamp: UnixTimestamp) -> i64 {
+        match self.counters.get(key) {
+            Some(counter) if counter.expiry > timestamp => counter.value,
+            _ => 0,
+        }
+    }
+
+    /// Increments the given counter by `quantity` and updates its expiry.
+    fn increment(&mut self, key: String, quantity: i64, expiry: UnixTimestamp) {
+        let counter = self
+            .counters
+            .entry(key)
+            .or_insert(Counter { value: 0, expiry });
+        counter.value += quantity;
+        counter.expiry = expiry;
+    }
+
+    /// Removes all counters of past time slots.
+    ///
+    /// Eviction runs at most once per second, since counters expire with a granularity of seconds.
+    fn evict_expired(&mut self, timestamp: UnixTimestamp) {
+        if self.last_eviction == Some(timestamp) {
+            return;
+        }
+
+        self.counters
+            .retain(|_, counter| counter.expiry > timestamp);
+        self.last_eviction = Some(timestamp);
+    }
+}
+
+/// A service that executes quotas and checks for rate limits in process memory.
+///
+/// This rate limiter has the same semantics as `RedisRateLimiter`: Quotas are counted in fixed time
+/// slots of the quota's `window`, using the same slot calculation and counter keys. Since counters
+/// are kept in memory, quotas are only enforced for the data passing through this instance and
+/// counters are lost on restart.
+///
+/// The rate limiter can be cloned cheaply. All clones share the same counters.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryRateLimiter {
+    counters: Arc<Mutex<Counters>>,
+    max_limit: Option<u64>,
+}
+
+impl MemoryRateLimiter {
+    /// Creates a new `MemoryRateLimiter` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum rate limit in seconds.
+    ///
+    /// By default, this rate limiter will return rate limits based on the quotas' `window` fields.
+    /// If a maximum rate limit is set, this limit is bounded.
+    pub fn max_limit(mut self, max_limit: Option<u64>) -> Self {
+        self.max_limit = max_limit;
+        self
+    }
+
+    /// Refunds the given quantity to all quotas in effect for the given item.
+    ///
+    /// Refunds are tracked in separate counters that are subtracted from consumption in subsequent
+    /// checks of the same time slot.
+    pub fn refund(&self, quotas: &[Quota], item_scoping: ItemScoping<'_>, quantity: usize) {
+        let timestamp = UnixTimestamp::now();
+        let mut counters = self.counters.lock().unwrap();
+
+        for quota in quotas {
+            if !quota.matches(item_scoping) {
+                continue;
+            }
+
+            if let Some(quota) = TrackedQuota::new(quota, item_scoping, timestamp) {
+                let refund_key = get_refunded_quota_key(&quota.key());
+                counters.increment(refund_key, quantity as i64, quota.expiry());
+            }
+        }
+    }
+
+    fn is_rate_limited_at(
+        &self,
+        quotas: &[Quota],
+        item_scoping: ItemScoping<'_>,
+        quantity: usize,
+        timestamp: UnixTimestamp,
+    ) -> RateLimits {
+        let mut tracked_quotas = Vec::new();
+        let mut rate_limits = RateLimits::new();
+
+        for quota in quotas {
+            if !quota.matches(item_scoping) {
+                // Silently skip all quotas that do not apply to this item.
+            } else if quota.limit == Some(0) {
+                // A zero-sized quota is strongest. Do not increment any counters, as one quota has
+                // reached capacity (this is how regular quotas behave as well).
+                let retry_after = self.retry_after(REJECT_ALL_SECS);
+                rate_limits.add(RateLimit::from_quota(quota, &*item_scoping, retry_after));
+            } else if let Some(quota) = TrackedQuota::new(quota, item_scoping, timestamp) {
+                tracked_quotas.push(quota);
+            }
+
+            // Quotas that are neither a static reject-all, nor trackable due to missing fields are
+            // skipped for forward-compatibility.
+        }
+
+        // Either there are no quotas to track, or we already have a rate limit from a zero-sized
+        // quota. In either cases, skip counting and return early.
+        if tracked_quotas.is_empty() || rate_limits.is_limited() {
+            return rate_limits;
+        }
+
+        let quantity = quantity as i64;
+        let mut counters = self.counters.lock().unwrap();
+        counters.evict_expired(timestamp);
+
+        let mut rejected = false;
+        for quota in &tracked_quotas {
+            // A limit of `-1` means that the quota is unlimited. It is still counted.
+            let limit = quota.limit();
+            if limit < 0 {
+                continue;
+            }
+
+            let key = quota.key();
+            let refund_key = get_refunded_quota_key(&key);
+            let consumed = counters.get(&key, timestamp) - counters.get(&refund_key, timestamp);
+
+            if consumed + quantity > limit {
+                let retry_after = self.retry_after((quota.expiry() - timestamp).as_secs());
+                rate_limits.add(RateLimit::from_quota(&*quota, &*item_scoping, retry_after));
+                rejected = true;
+            }
+        }
+
+        if !rejected {
+            for quota in &tracked_quotas {
+                counters.increment(quota.key(), quantity, quota.expiry());
+            }
+        }
+
+        rate_limits
+    }
+
+    /// Creates a rate limit bounded by `max_limit`.
+    fn retry_after(&self, mut seconds: u64) -> RetryAfter {
+        if let Some(max_limit) = self.max_limit {
+            seconds = std::cmp::min(seconds, max_limit);
+        }
+
+        RetryAfter::from_secs(seconds)
+    }
+}
+
+impl RateLimiter for MemoryRateLimiter {
+    fn is_rate_limited(
+        &self,
+        quotas: &[Quota],
+        item_scoping: ItemScoping<'_>,
+        quantity: usize,
+    ) -> Result<RateLimits, RateLimitingError> {
+        let timestamp = UnixTimestamp::now();
+        Ok(self.is_rate_limited_at(quotas, item_scoping, quantity, timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use relay_common::ProjectId;
+
+    use crate::quota::{DataCategories, DataCategory, QuotaScope, ReasonCode, Scoping};
+    use crate::rate_limit::RateLimitScope;
+
+    use super::*;
+
+    fn scoping() -> Scoping {
+        Scoping {
+            organization_id: 42,
+            project_id: ProjectId::new(43),
+            public_key: "a94ae32be2584e0bbd7a4cbb95971fee".to_owned(),
+            key_id: Some(44),
+        }
+    }
+
+    fn quota(limit: Option<u64>, window: Option<u64>) -> Quota {
+        Quota {
+            id: Some("test".to_owned()),
+            categories: DataCategories::new(),
+            scope: QuotaScope::Organization,
+            scope_id: None,
+            limit,
+            window,
+            reason_code: Some(ReasonCode::new("get_lost")),
+        }
+    }
+
+    #[test]
+    fn test_zero_size_quotas() {
+        let quotas = &[
+            Quota {
+                id: None,
+                ..quota(Some(0), None)
+            },
+            Quota {
+                id: Some("42".to_owned()),
+                reason_code: Some(ReasonCode::new("unlimited")),
+                ..quota(None, Some(42))
+            },
+        ];
+
+        let scoping = scoping();
+        let rate_limits: Vec<RateLimit> = MemoryRateLimiter::new()
+            .is_rate_limited(quotas, scoping.item(DataCategory::Error), 1)
+            .unwrap()
+            .into_iter()
+            .collect();
+
+        assert_eq!(
+            rate_limits,
+            vec![RateLimit {
+                categories: DataCategories::new(),
+                scope: RateLimitScope::Organization(42),
+                reason_code: Some(ReasonCode::new("get_lost")),
+                retry_after: rate_limits[0].retry_after,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_simple_quota() {
+        let quotas = &[quota(Some(5), Some(60))];
+        let scoping = scoping();
+        let rate_limiter = MemoryRateLimiter::new();
+
+        for i in 0..10 {
+            let rate_limits: Vec<RateLimit> = rate_limiter
+                .is_rate_limited(quotas, scoping.item(DataCategory::Error), 1)
+                .unwrap()
+                .into_iter()
+                .collect();
+
+            if i >= 5 {
+                assert_eq!(
+                    rate_limits,
+                    vec![RateLimit {
+                        categories: DataCategories::new(),
+                        scope: RateLimitScope::Organization(42),
+                        reason_code: Some(ReasonCode::new("get_lost")),
+                        retry_after: rate_limits[0].retry_after,
+                    }]
+                );
+            } else {
+                assert_eq!(rate_limits, vec![]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_quota_with_quantity() {
+        let quotas = &[quota(Some(500), Some(60))];
+        let scoping = scoping();
+        let rate_limiter = MemoryRateLimiter::new();
+
+        for i in 0..10 {
+            let rate_limits = rate_limiter
+                .is_rate_limited(quotas, scoping.item(DataCategory::Error), 100)
+                .unwrap();
+            assert_eq!(rate_limits.is_limited(), i >= 5);
+        }
+    }
+
+    #[test]
+    fn test_rejected_not_counted() {
+        // The first quota rejects the item. The second quota must not count it, so that items
+        // rejected by a lower quota don't affect unrelated items that share a parent quota.
+        let quotas = &[
+            Quota {
+                id: Some("q1".to_owned()),
+                ..quota(Some(1), Some(60))
+            },
+            Quota {
+                id: Some("q2".to_owned()),
+                ..quota(Some(2), Some(60))
+            },
+        ];
+
+        let scoping = scoping();
+        let rate_limiter = MemoryRateLimiter::new();
+
+        for _ in 0..3 {
+            rate_limiter
+                .is_rate_limited(quotas, scoping.item(DataCategory::Error), 1)
+                .unwrap();
+        }
+
+        let rate_limits = rate_limiter
+            .is_rate_limited(&quotas[1..], scoping.item(DataCategory::Error), 1)
+            .unwrap();
+        assert!(rate_limits.is_ok());
+    }
+
+    #[test]
+    fn test_quota_window_expiry() {
+        let quotas = &[quota(Some(1), Some(60))];
+        let scoping = scoping();
+        let rate_limiter = MemoryRateLimiter::new();
+
+        // The organization id shifts the slots by 42 seconds.
+        let start = UnixTimestamp::from_secs(6042);
+        let rate_limits =
+            rate_limiter.is_rate_limited_at(quotas, scoping.item(DataCategory::Error), 1, start);
+        assert!(rate_limits.is_ok());
+
+        let same_slot = UnixTimestamp::from_secs(6101);
+        let rate_limits = rate_limiter.is_rate_limited_at(
+            quotas,
+            scoping.item(DataCategory::Error),
+            1,
+            same_slot,
+        );
+        assert!(rate_limits.is_limited());
+
+        let next_slot = UnixTimestamp::from_secs(6102);
+        let rate_limits = rate_limiter.is_rate_limited_at(
+            quotas,
+            scoping.item(DataCategory::Error),
+            1,
+            next_slot,
+        );
+        assert!(rate_limits.is_ok());
+    }
+
+    #[test]
+    fn test_refund() {
+        let quotas = &[quota(Some(1), Some(3600))];
+        let scoping = scoping();
+        let rate_limiter = MemoryRateLimiter::new();
+
+        let item_scoping = scoping.item(DataCategory::Error);
+        assert!(rate_limiter
+            .is_rate_limited(quotas, item_scoping, 1)
+            .unwrap()
+            .is_ok());
+        assert!(rate_limiter
+            .is_rate_limited(quotas, item_scoping, 1)
+            .unwrap()
+            .is_limited());
+
+        rate_limiter.refund(quotas, item_scoping, 1);
+        assert!(rate_limiter
+            .is_rate_limited(quotas, item_scoping, 1)
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_max_limit() {
+        let quotas = &[quota(Some(0), None)];
+        let scoping = scoping();
+        let rate_limiter = MemoryRateLimiter::new().max_limit(Some(10));
+
+        let rate_limits = rate_limiter
+            .is_rate_limited(quotas, scoping.item(DataCategory::Error), 1)
+            .unwrap();
+        let rate_limit = rate_limits.longest().unwrap();
+        assert!(rate_limit.retry_after.remaining_seconds() <= 10);
+    }
+}
@@ -103,9 +103,11 @@ impl CategoryUnit {
             DataCategory::Default
             | DataCategory::Error
             | DataCategory::Transaction
-            | DataCategory::Security => Some(Self::Count),
+            | DataCategory::Security
+            | DataCategory::Profile
+            | DataCategory::Monitor => Some(Self::Count),
             DataCategory::Attachment => Some(Self::Bytes),
-            DataCategory::Session => Some(Self::Batched),
+            DataCategory::Session | DataCategory::Metric => Some(Self::Batched),
             DataCategory::Unknown => None,
         }
     }
@@ -1,127 +1,19 @@
-use std::fmt;
 use std::sync::Arc;
 
-use failure::Fail;
-
 use relay_common::UnixTimestamp;
 use relay_redis::{redis::Script, RedisError, RedisPool};
 use sentry::protocol::value;
 
-use crate::quota::{ItemScoping, Quota, QuotaScope};
+use crate::limiter::{RateLimiter, RateLimitingError};
+use crate::quota::{ItemScoping, Quota};
 use crate::rate_limit::{RateLimit, RateLimits, RetryAfter};
+use crate::tracked::{get_refunded_quota_key, TrackedQuota};
 use crate::REJECT_ALL_SECS;
 
-/// The `grace` period allows accomodating for clock drift in TTL
-/// calculation since the clock on the Redis instance used to store quota
-/// metrics may not be in sync with the computer running this code.
-const GRACE: u64 = 60;
-
-/// An error returned by `RedisRateLimiter`.
-#[derive(Debug, Fail)]
-pub enum RateLimitingError {
-    /// Failed to communicate with Redis.
-    #[fail(display = "failed to communicate with redis")]
-    Redis(#[cause] RedisError),
-}
-
 fn load_lua_script() -> Script {
     Script::new(include_str!("is_rate_limited.lua"))
 }
 
-fn get_refunded_quota_key(counter_key: &str) -> String {
-    format!("r:{}", counter_key)
-}
-
-/// A transparent wrapper around an Option that only displays `Some`.
-struct OptionalDisplay<T>(Option<T>);
-
-impl<T> fmt::Display for OptionalDisplay<T>
-where
-    T: fmt::Display,
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.0 {
-            Some(ref value) => write!(f, "{}", value),
-            None => Ok(()),
-        }
-    }
-}
-
-/// Reference to information required for tracking quotas in Redis.
-#[derive(Debug)]
-struct RedisQuota<'a> {
-    /// The original quota.
-    quota: &'a Quota,
-    /// Scopes of the item being tracked.
-    scoping: ItemScoping<'a>,
-    /// The Redis key prefix mapped from the quota id.
-    prefix: &'a str,
-    /// The redis window in seconds mapped from the quota.
-    window: u64,
-    /// The ingestion timestamp determining the rate limiting bucket.
-    timestamp: UnixTimestamp,
-}
-
-impl<'a> RedisQuota<'a> {
-    fn new(quota: &'a Quota, scoping: ItemScoping<'a>, timestamp: UnixTimestamp) -> Option<Self> {
-        // These fields indicate that we *can* track this quota.
-        let prefix = quota.id.as_deref()?;
-        let window = quota.window?;
-
-        Some(Self {
-            quota,
-            scoping,
-            prefix,
-            window,
-            timestamp,
-        })
-    }
-
-    /// Returns the limit value for Redis (`-1` for unlimited, otherwise the limit value).
-    fn limit(&self) -> i64 {
-        self.limit.map(i64::from).unwrap_or(-1)
-    }
-
-    fn shift(&self) -> u64 {
-        self.scoping.organization_id % self.window
-    }
-
-    fn slot(&self) -> u64 {
-        (self.timestamp.as_secs() - self.shift()) / self.window
-    }
-
-    fn expiry(&self) -> UnixTimestamp {
-        let next_slot = self.slot() + 1;
-        let next_start = next_slot * self.window + self.shift();
-        UnixTimestamp::from_secs(next_start + GRACE)
-    }
-
-    fn key(&self) -> String {
-        // The subscope id is only formatted into the key if the quota is not organization-scoped.
-        // The organization id is always included.
-        let subscope = match self.quota.scope {
-            QuotaScope::Organization => None,
-            scope => self.scoping.scope_id(scope),
-        };
-
-        format!(
-            "quota:{id}{{{org}}}{subscope}:{slot}",
-            id = self.prefix,
-            org = self.scoping.organization_id,
-            subscope = OptionalDisplay(subscope),
-            slot = self.slot(),
-        )
-    }
-}
-
-impl std::ops::Deref for RedisQuota<'_> {
-    type Target = Quota;
-
-    fn deref(&self) -> &Self::Target {
-        self.quota
-    }
-}
-
 /// A service that executes quotas and checks for rate limits in a shared cache.
 ///
 /// Quotas handle tracking a project's usage and respond whether or not a project has been
@@ -158,16 +50,18 @@ impl RedisRateLimiter {
         self
     }
 
-    /// Checks whether any of the quotas in effect for the given project and project key has been
-    /// exceeded and records consumption of the quota.
-    ///
-    /// By invoking this method, the caller signals that data is being ingested and needs to be
-    /// counted against the quota. This increment happens atomically if none of the quotas have been
-    /// exceeded. Otherwise, a rate limit is returned and data is not counted against the quotas.
-    ///
-    /// If no key is specified, then only organization-wide and project-wide quotas are checked. If
-    /// a key is specified, then key-quotas are also checked.
-    pub fn is_rate_limited(
+    /// Creates a rate limit bounded by `max_limit`.
+    fn retry_after(&self, mut seconds: u64) -> RetryAfter {
+        if let Some(max_limit) = self.max_limit {
+            seconds = std::cmp::min(seconds, max_limit);
+        }
+
+        RetryAfter::from_secs(seconds)
+    }
+}
+
+impl RateLimiter for RedisRateLimiter {
+    fn is_rate_limited(
         &self,
         quotas: &[Quota],
         item_scoping: ItemScoping<'_>,
@@ -188,7 +82,7 @@ impl RedisRateLimiter {
                 // behave as well).
                 let retry_after = self.retry_after(REJECT_ALL_SECS);
                 rate_limits.add(RateLimit::from_quota(quota, &*item_scoping, retry_after));
-            } else if let Some(quota) = RedisQuota::new(quota, item_scoping, timestamp) {
+            } else if let Some(quota) = TrackedQuota::new(quota, item_scoping, timestamp) {
                 // Remaining quotas are expected to be trackable in Redis.
                 let key = quota.key();
                 let refund_key = get_refunded_quota_key(&key);
@@ -232,15 +126,6 @@ impl RedisRateLimiter {
 
         Ok(rate_limits)
     }
-
-    /// Creates a rate limit bounded by `max_limit`.
-    fn retry_after(&self, mut seconds: u64) -> RetryAfter {
-        if let Some(max_limit) = self.max_limit {
-            seconds = std::cmp::min(seconds, max_limit);
-        }
-
-        RetryAfter::from_secs(seconds)
-    }
 }
 
 #[cfg(test)]
@@ -251,7 +136,7 @@ mod tests {
     use relay_common::ProjectId;
     use relay_redis::redis::Commands;
 
-    use crate::quota::{DataCategories, DataCategory, ReasonCode, Scoping};
+    use crate::quota::{DataCategories, DataCategory, QuotaScope, ReasonCode, Scoping};
     use crate::rate_limit::RateLimitScope;
 
     use super::*;
@@ -481,60 +366,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_get_redis_key_scoped() {
-        let quota = Quota {
-            id: Some("foo".to_owned()),
-            categories: DataCategories::new(),
-            scope: QuotaScope::Project,
-            scope_id: Some("42".to_owned()),
-            window: Some(2),
-            limit: Some(0),
-            reason_code: None,
-        };
-
-        let scoping = ItemScoping {
-            category: DataCategory::Error,
-            scoping: &Scoping {
-                organization_id: 69420,
-                project_id: ProjectId::new(42),
-                public_key: "a94ae32be2584e0bbd7a4cbb95971fee".to_owned(),
-                key_id: Some(4711),
-            },
-        };
-
-        let timestamp = UnixTimestamp::from_secs(123_123_123);
-        let redis_quota = RedisQuota::new(&quota, scoping, timestamp).unwrap();
-        assert_eq!(redis_quota.key(), "quota:foo{69420}42:61561561");
-    }
-
-    #[test]
-    fn test_get_redis_key_unscoped() {
-        let quota = Quota {
-            id: Some("foo".to_owned()),
-            categories: DataCategories::new(),
-            scope: QuotaScope::Organization,
-            scope_id: None,
-            window: Some(10),
-            limit: Some(0),
-            reason_code: None,
-        };
-
-        let scoping = ItemScoping {
-            category: DataCategory::Error,
-            scoping: &Scoping {
-                organization_id: 69420,
-                project_id: ProjectId::new(42),
-                public_key: "a94ae32be2584e0bbd7a4cbb95971fee".to_owned(),
-                key_id: Some(4711),
-            },
-        };
-
-        let timestamp = UnixTimestamp::from_secs(234_531);
-        let redis_quota = RedisQuota::new(&quota, scoping, timestamp).unwrap();
-        assert_eq!(redis_quota.key(), "quota:foo{69420}:23453");
-    }
-
     #[test]
     #[allow(clippy::blacklisted_name, clippy::let_unit_value)]
     fn test_is_rate_limited_script() {
@@ -0,0 +1,177 @@
+use std::fmt;
+
+use relay_common::UnixTimestamp;
+
+use crate::quota::{ItemScoping, Quota, QuotaScope};
+
+/// The `grace` period allows accomodating for clock drift in TTL
+/// calculation since the clock on the Redis instance used to store quota
+/// metrics may not be in sync with the computer running this code.
+const GRACE: u64 = 60;
+
+/// Returns the key of the counter holding refunds for the given counter key.
+pub(crate) fn get_refunded_quota_key(counter_key: &str) -> String {
+    format!("r:{}", counter_key)
+}
+
+/// A transparent wrapper around an Option that only displays `Some`.
+struct OptionalDisplay<T>(Option<T>);
+
+impl<T> fmt::Display for OptionalDisplay<T>
+where
+    T: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(ref value) => write!(f, "{}", value),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reference to information required for tracking quotas in a counter store.
+///
+/// This computes the time slot and counter key of a quota consistently for all rate limiters, so
+/// that counters and refunds are compatible across implementations.
+#[derive(Debug)]
+pub(crate) struct TrackedQuota<'a> {
+    /// The original quota.
+    quota: &'a Quota,
+    /// Scopes of the item being tracked.
+    scoping: ItemScoping<'a>,
+    /// The key prefix mapped from the quota id.
+    prefix: &'a str,
+    /// The window in seconds mapped from the quota.
+    window: u64,
+    /// The ingestion timestamp determining the rate limiting bucket.
+    timestamp: UnixTimestamp,
+}
+
+impl<'a> TrackedQuota<'a> {
+    pub fn new(
+        quota: &'a Quota,
+        scoping: ItemScoping<'a>,
+        timestamp: UnixTimestamp,
+    ) -> Option<Self> {
+        // These fields indicate that we *can* track this quota.
+        let prefix = quota.id.as_deref()?;
+        let window = quota.window?;
+
+        Some(Self {
+            quota,
+            scoping,
+            prefix,
+            window,
+            timestamp,
+        })
+    }
+
+    /// Returns the limit value for counters (`-1` for unlimited, otherwise the limit value).
+    pub fn limit(&self) -> i64 {
+        self.limit.map(i64::from).unwrap_or(-1)
+    }
+
+    fn shift(&self) -> u64 {
+        self.scoping.organization_id % self.window
+    }
+
+    fn slot(&self) -> u64 {
+        (self.timestamp.as_secs() - self.shift()) / self.window
+    }
+
+    /// Returns the time at which the counter of the current slot can be discarded.
+    pub fn expiry(&self) -> UnixTimestamp {
+        let next_slot = self.slot() + 1;
+        let next_start = next_slot * self.window + self.shift();
+        UnixTimestamp::from_secs(next_start + GRACE)
+    }
+
+    /// Returns the key of the counter for the current slot.
+    pub fn key(&self) -> String {
+        // The subscope id is only formatted into the key if the quota is not organization-scoped.
+        // The organization id is always included.
+        let subscope = match self.quota.scope {
+            QuotaScope::Organization => None,
+            scope => self.scoping.scope_id(scope),
+        };
+
+        format!(
+            "quota:{id}{{{org}}}{subscope}:{slot}",
+            id = self.prefix,
+            org = self.scoping.organization_id,
+            subscope = OptionalDisplay(subscope),
+            slot = self.slot(),
+        )
+    }
+}
+
+impl std::ops::Deref for TrackedQuota<'_> {
+    type Target = Quota;
+
+    fn deref(&self) -> &Self::Target {
+        self.quota
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use relay_common::ProjectId;
+
+    use crate::quota::{DataCategories, DataCategory, Scoping};
+
+    use super::*;
+
+    #[test]
+    fn test_get_key_scoped() {
+        let quota = Quota {
+            id: Some("foo".to_owned()),
+            categories: DataCategories::new(),
+            scope: QuotaScope::Project,
+            scope_id: Some("42".to_owned()),
+            window: Some(2),
+            limit: Some(0),
+            reason_code: None,
+        };
+
+        let scoping = ItemScoping {
+            category: DataCategory::Error,
+            scoping: &Scoping {
+                organization_id: 69420,
+                project_id: ProjectId::new(42),
+                public_key: "a94ae32be2584e0bbd7a4cbb95971fee".to_owned(),
+                key_id: Some(4711),
+            },
+        };
+
+        let timestamp = UnixTimestamp::from_secs(123_123_123);
+        let tracked_quota = TrackedQuota::new(&quota, scoping, timestamp).unwrap();
+        assert_eq!(tracked_quota.key(), "quota:foo{69420}42:61561561");
+    }
+
+    #[test]
+    fn test_get_key_unscoped() {
+        let quota = Quota {
+            id: Some("foo".to_owned()),
+            categories: DataCategories::new(),
+            scope: QuotaScope::Organization,
+            scope_id: None,
+            window: Some(10),
+            limit: Some(0),
+            reason_code: None,
+        };
+
+        let scoping = ItemScoping {
+            category: DataCategory::Error,
+            scoping: &Scoping {
+                organization_id: 69420,
+                project_id: ProjectId::new(42),
+                public_key: "a94ae32be2584e0bbd7a4cbb95971fee".to_owned(),
+                key_id: Some(4711),
+            },
+        };
+
+        let timestamp = UnixTimestamp::from_secs(234_531);
+        let tracked_quota = TrackedQuota::new(&quota, scoping, timestamp).unwrap();
+        assert_eq!(tracked_quota.key(), "quota:foo{69420}:23453");
+    }
+}
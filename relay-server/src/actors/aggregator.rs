@@ -0,0 +1,573 @@
+//! This module contains the actor that aggregates custom metrics into buckets.
+//!
+//! Metric values are aggregated per project, metric name, type, tags, and time window. The length
+//! of a time window is configured in `aggregator.bucket_interval`. After a window has ended and an
+//! additional `aggregator.flush_delay` has passed, its buckets are flushed. In processing mode,
+//! buckets are produced to Kafka. Otherwise, they are sent to the upstream in an envelope, where
+//! they are aggregated again.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use futures::future;
+use futures::prelude::*;
+use serde::Serialize;
+
+use relay_common::{metric, LogError, UnixTimestamp};
+use relay_config::Config;
+use relay_quotas::Scoping;
+
+use crate::actors::controller::{Controller, Shutdown};
+use crate::actors::events::create_envelope_request;
+use crate::actors::upstream::UpstreamRelay;
+use crate::envelope::{ContentType, Envelope, Item, ItemType};
+use crate::extractors::RequestMeta;
+use crate::metrics::RelayCounters;
+use crate::utils::{self, Metric, MetricType, MetricValue};
+
+#[cfg(feature = "processing")]
+use crate::actors::store::{StoreForwarder, StoreMetrics};
+
+/// The interval at which the aggregator checks for buckets to flush.
+const FLUSH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The aggregated value of a gauge.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct GaugeValue {
+    /// The last value set on the gauge.
+    pub last: f64,
+    /// The minimum value set on the gauge.
+    pub min: f64,
+    /// The maximum value set on the gauge.
+    pub max: f64,
+    /// The sum of all values set on the gauge.
+    pub sum: f64,
+    /// The number of values set on the gauge.
+    pub count: u64,
+}
+
+/// The aggregated value of a bucket.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum BucketValue {
+    /// The sum of all counter values.
+    Counter(f64),
+    /// All values of a distribution.
+    Distribution(Vec<f64>),
+    /// The unique values of a set.
+    Set(BTreeSet<u32>),
+    /// The aggregated values of a gauge.
+    Gauge(GaugeValue),
+}
+
+impl BucketValue {
+    /// Creates a bucket value from a single metric value.
+    fn new(value: MetricValue) -> Self {
+        match value {
+            MetricValue::Counter(value) => Self::Counter(value),
+            MetricValue::Distribution(value) => Self::Distribution(vec![value]),
+            MetricValue::Set(value) => Self::Set(std::iter::once(value).collect()),
+            MetricValue::Gauge(value) => Self::Gauge(GaugeValue {
+                last: value,
+                min: value,
+                max: value,
+                sum: value,
+                count: 1,
+            }),
+        }
+    }
+
+    /// Adds a metric value to this bucket value.
+    ///
+    /// The value is ignored if its type does not match. Bucket keys contain the type, so this does
+    /// not happen for values of the same bucket.
+    fn insert(&mut self, value: MetricValue) {
+        match (self, value) {
+            (Self::Counter(sum), MetricValue::Counter(value)) => *sum += value,
+            (Self::Distribution(values), MetricValue::Distribution(value)) => values.push(value),
+            (Self::Set(values), MetricValue::Set(value)) => {
+                values.insert(value);
+            }
+            (Self::Gauge(gauge), MetricValue::Gauge(value)) => {
+                gauge.last = value;
+                gauge.min = gauge.min.min(value);
+                gauge.max = gauge.max.max(value);
+                gauge.sum += value;
+                gauge.count += 1;
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns the metric type of this value.
+    pub fn ty(&self) -> MetricType {
+        match self {
+            Self::Counter(_) => MetricType::Counter,
+            Self::Distribution(_) => MetricType::Distribution,
+            Self::Set(_) => MetricType::Set,
+            Self::Gauge(_) => MetricType::Gauge,
+        }
+    }
+}
+
+/// An aggregation of metric values in a time window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bucket {
+    /// The start of the time window as UNIX timestamp in seconds.
+    pub timestamp: u64,
+    /// The name of the metric.
+    pub name: String,
+    /// The aggregated value, which also determines the metric type.
+    pub value: BucketValue,
+    /// Tags of the metric.
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Bucket {
+    /// Appends this bucket to `buffer` in the metric line format.
+    ///
+    /// Gauges can only be represented by their last value in the line format.
+    fn write_line(&self, buffer: &mut String) {
+        let name = &self.name;
+        let ty = self.value.ty();
+        let (tags, timestamp) = (&self.tags, self.timestamp);
+
+        match self.value {
+            BucketValue::Counter(sum) => {
+                utils::write_metric_line(buffer, name, ty, Some(sum), tags, timestamp)
+            }
+            BucketValue::Distribution(ref values) => {
+                utils::write_metric_line(buffer, name, ty, values, tags, timestamp)
+            }
+            BucketValue::Set(ref values) => {
+                utils::write_metric_line(buffer, name, ty, values, tags, timestamp)
+            }
+            BucketValue::Gauge(gauge) => {
+                utils::write_metric_line(buffer, name, ty, Some(gauge.last), tags, timestamp)
+            }
+        }
+    }
+}
+
+/// The key that identifies a bucket.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct BucketKey {
+    project_key: String,
+    timestamp: u64,
+    name: String,
+    ty: MetricType,
+    tags: BTreeMap<String, String>,
+}
+
+impl BucketKey {
+    /// Returns the key used to count the tag combinations of a metric in a time window.
+    fn cardinality_key(&self) -> (String, u64, String) {
+        (self.project_key.clone(), self.timestamp, self.name.clone())
+    }
+}
+
+/// A bucket value along with the time at which it should be flushed.
+#[derive(Debug)]
+struct BucketEntry {
+    value: BucketValue,
+    flush_at: Instant,
+}
+
+/// Information required to flush the buckets of a project.
+#[derive(Clone, Debug)]
+#[cfg_attr(not(feature = "processing"), allow(dead_code))]
+struct ProjectContext {
+    meta: RequestMeta,
+    scoping: Scoping,
+    retention: u16,
+}
+
+/// Buckets of all projects that have not been flushed yet.
+#[derive(Debug)]
+struct Buckets {
+    interval: u64,
+    flush_delay: Duration,
+    max_cardinality: usize,
+    entries: HashMap<BucketKey, BucketEntry>,
+    cardinality: HashMap<(String, u64, String), usize>,
+    project_counts: HashMap<String, usize>,
+}
+
+impl Buckets {
+    fn new(config: &Config) -> Self {
+        Self {
+            interval: config.aggregator_bucket_interval(),
+            flush_delay: config.aggregator_flush_delay(),
+            max_cardinality: config.max_metric_tag_cardinality(),
+            entries: HashMap::new(),
+            cardinality: HashMap::new(),
+            project_counts: HashMap::new(),
+        }
+    }
+
+    /// Computes the time at which the bucket starting at `timestamp` should be flushed.
+    ///
+    /// Buckets are flushed after their time window has ended and the flush delay has passed.
+    /// Timestamps in the future are capped to the current time window, so that buckets are never
+    /// held back longer than one interval plus the flush delay.
+    fn flush_at(&self, timestamp: u64) -> Instant {
+        let now = UnixTimestamp::now().as_secs();
+        let remaining = (timestamp + self.interval)
+            .saturating_sub(now)
+            .min(self.interval);
+        Instant::now() + Duration::from_secs(remaining) + self.flush_delay
+    }
+
+    /// Adds a single metric value to its bucket.
+    fn insert(&mut self, project_key: &str, metric: Metric) {
+        let timestamp = metric.timestamp - metric.timestamp % self.interval;
+
+        let key = BucketKey {
+            project_key: project_key.to_owned(),
+            timestamp,
+            name: metric.name,
+            ty: metric.value.ty(),
+            tags: metric.tags,
+        };
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.value.insert(metric.value);
+            return;
+        }
+
+        let cardinality = self.cardinality.entry(key.cardinality_key()).or_insert(0);
+        if *cardinality >= self.max_cardinality {
+            metric!(
+                counter(RelayCounters::MetricsDropped) += 1,
+                reason = "cardinality"
+            );
+            return;
+        }
+
+        *cardinality += 1;
+        *self
+            .project_counts
+            .entry(key.project_key.clone())
+            .or_insert(0) += 1;
+
+        let entry = BucketEntry {
+            value: BucketValue::new(metric.value),
+            flush_at: self.flush_at(timestamp),
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Removes all buckets that are due, or all buckets if `force` is set, grouped by project.
+    fn take(&mut self, force: bool) -> HashMap<String, Vec<Bucket>> {
+        let now = Instant::now();
+        let mut taken = HashMap::<_, Vec<_>>::new();
+
+        let keys: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| force || entry.flush_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in keys {
+            let entry = match self.entries.remove(&key) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            let cardinality_key = key.cardinality_key();
+            if let Some(cardinality) = self.cardinality.get_mut(&cardinality_key) {
+                *cardinality -= 1;
+                if *cardinality == 0 {
+                    self.cardinality.remove(&cardinality_key);
+                }
+            }
+
+            if let Some(count) = self.project_counts.get_mut(&key.project_key) {
+                *count -= 1;
+                if *count == 0 {
+                    self.project_counts.remove(&key.project_key);
+                }
+            }
+
+            taken.entry(key.project_key).or_default().push(Bucket {
+                timestamp: key.timestamp,
+                name: key.name,
+                value: entry.value,
+                tags: key.tags,
+            });
+        }
+
+        taken
+    }
+
+    /// Returns `true` if there are buckets for the given project.
+    fn contains_project(&self, project_key: &str) -> bool {
+        self.project_counts.contains_key(project_key)
+    }
+}
+
+/// Actor that aggregates custom metrics into buckets and flushes them periodically.
+pub struct Aggregator {
+    upstream: Addr<UpstreamRelay>,
+    buckets: Buckets,
+    projects: HashMap<String, ProjectContext>,
+
+    #[cfg(feature = "processing")]
+    store_forwarder: Option<Addr<StoreForwarder>>,
+}
+
+impl Aggregator {
+    #[cfg(feature = "processing")]
+    pub fn new(
+        config: &Config,
+        upstream: Addr<UpstreamRelay>,
+        store_forwarder: Option<Addr<StoreForwarder>>,
+    ) -> Self {
+        Self {
+            upstream,
+            buckets: Buckets::new(config),
+            projects: HashMap::new(),
+            store_forwarder,
+        }
+    }
+
+    #[cfg(not(feature = "processing"))]
+    pub fn new(config: &Config, upstream: Addr<UpstreamRelay>) -> Self {
+        Self {
+            upstream,
+            buckets: Buckets::new(config),
+            projects: HashMap::new(),
+        }
+    }
+
+    /// Flushes due buckets, or all buckets if `force` is set.
+    ///
+    /// Returns futures that resolve once the buckets of each project have been sent.
+    fn flush(&mut self, force: bool) -> Vec<ResponseFuture<(), ()>> {
+        let flushed = self.buckets.take(force);
+        if flushed.is_empty() {
+            return Vec::new();
+        }
+
+        let mut futures = Vec::with_capacity(flushed.len());
+        for (project_key, buckets) in flushed {
+            let project = match self.projects.get(&project_key) {
+                Some(project) => project.clone(),
+                None => continue,
+            };
+
+            metric!(counter(RelayCounters::MetricBucketsFlushed) += buckets.len() as i64);
+            futures.push(self.send_buckets(project, buckets));
+        }
+
+        let buckets = &self.buckets;
+        self.projects
+            .retain(|project_key, _| buckets.contains_project(project_key));
+
+        futures
+    }
+
+    /// Sends flushed buckets to Kafka in processing mode, or to the upstream otherwise.
+    fn send_buckets(
+        &self,
+        project: ProjectContext,
+        buckets: Vec<Bucket>,
+    ) -> ResponseFuture<(), ()> {
+        #[cfg(feature = "processing")]
+        {
+            if let Some(ref store_forwarder) = self.store_forwarder {
+                let future = store_forwarder
+                    .send(StoreMetrics {
+                        buckets,
+                        scoping: project.scoping,
+                        retention: project.retention,
+                    })
+                    .map_err(|error| log::error!("failed to store metrics: {}", LogError(&error)))
+                    .and_then(|result| {
+                        result.map_err(|error| {
+                            log::error!("failed to store metrics: {}", LogError(&error))
+                        })
+                    });
+
+                return Box::new(future);
+            }
+        }
+
+        let mut payload = String::new();
+        for bucket in &buckets {
+            bucket.write_line(&mut payload);
+        }
+
+        let mut item = Item::new(ItemType::Metrics);
+        item.set_payload(ContentType::Text, payload);
+
+        let mut envelope = Envelope::from_request(None, project.meta);
+        envelope.add_item(item);
+
+        let future = self
+            .upstream
+            .send(create_envelope_request(envelope))
+            .map_err(|error| log::error!("failed to send metrics: {}", LogError(&error)))
+            .and_then(|result| {
+                result.map_err(|error| log::error!("failed to send metrics: {}", LogError(&error)))
+            });
+
+        Box::new(future)
+    }
+}
+
+impl Actor for Aggregator {
+    type Context = Context<Self>;
+
+    fn started(&mut self, context: &mut Self::Context) {
+        Controller::subscribe(context.address());
+        context.run_interval(FLUSH_CHECK_INTERVAL, |slf, ctx| {
+            for future in slf.flush(false) {
+                ctx.spawn(future.into_actor(slf));
+            }
+        });
+        log::info!("metrics aggregator started");
+    }
+
+    fn stopped(&mut self, _context: &mut Self::Context) {
+        log::info!("metrics aggregator stopped");
+    }
+}
+
+/// Adds custom metrics of a project to the aggregator.
+#[derive(Debug)]
+pub struct InsertMetrics {
+    /// Request metadata used to send flushed buckets to the upstream.
+    pub meta: RequestMeta,
+    /// Scoping of the project used to store flushed buckets.
+    pub scoping: Scoping,
+    /// Data retention of the project in days.
+    pub retention: u16,
+    /// The metric values to aggregate.
+    pub metrics: Vec<Metric>,
+}
+
+impl Message for InsertMetrics {
+    type Result = ();
+}
+
+impl Handler<InsertMetrics> for Aggregator {
+    type Result = ();
+
+    fn handle(&mut self, message: InsertMetrics, _context: &mut Self::Context) -> Self::Result {
+        let InsertMetrics {
+            meta,
+            scoping,
+            retention,
+            metrics,
+        } = message;
+
+        let project_key = meta.public_key().to_owned();
+        for metric in metrics {
+            self.buckets.insert(&project_key, metric);
+        }
+
+        let project = ProjectContext {
+            meta,
+            scoping,
+            retention,
+        };
+        self.projects.insert(project_key, project);
+    }
+}
+
+impl Handler<Shutdown> for Aggregator {
+    type Result = ResponseFuture<(), ()>;
+
+    fn handle(&mut self, _message: Shutdown, _context: &mut Self::Context) -> Self::Result {
+        // Flush all buckets, including those of the current time window. The upstream aggregates
+        // them with values sent later.
+        Box::new(future::join_all(self.flush(true)).map(|_| ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_metric(name: &str, value: MetricValue, tag: &str) -> Metric {
+        let mut tags = BTreeMap::new();
+        tags.insert("route".to_owned(), tag.to_owned());
+
+        Metric {
+            name: name.to_owned(),
+            value,
+            timestamp: 1_597_000_005,
+            tags,
+        }
+    }
+
+    #[test]
+    fn test_bucket_value_merge() {
+        let mut counter = BucketValue::new(MetricValue::Counter(1.0));
+        counter.insert(MetricValue::Counter(2.5));
+        assert_eq!(counter, BucketValue::Counter(3.5));
+
+        let mut set = BucketValue::new(MetricValue::Set(1));
+        set.insert(MetricValue::Set(1));
+        set.insert(MetricValue::Set(2));
+        assert_eq!(set, BucketValue::Set(vec![1, 2].into_iter().collect()));
+
+        let mut gauge = BucketValue::new(MetricValue::Gauge(5.0));
+        gauge.insert(MetricValue::Gauge(1.0));
+        gauge.insert(MetricValue::Gauge(3.0));
+        assert_eq!(
+            gauge,
+            BucketValue::Gauge(GaugeValue {
+                last: 3.0,
+                min: 1.0,
+                max: 5.0,
+                sum: 9.0,
+                count: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_bucket_write_line() {
+        let bucket = Bucket {
+            timestamp: 1_597_000_000,
+            name: "response_time".to_owned(),
+            value: BucketValue::Distribution(vec![1.5, 2.0]),
+            tags: BTreeMap::new(),
+        };
+
+        let mut line = String::new();
+        bucket.write_line(&mut line);
+        assert_eq!(line, "response_time:1.5:2|d|T1597000000\n");
+    }
+
+    #[test]
+    fn test_aggregate_and_limit_cardinality() {
+        let mut buckets = Buckets::new(&Config::default());
+        buckets.max_cardinality = 2;
+
+        let key = "e12d836b15bb49d7bbf99e64295d995b";
+        buckets.insert(key, create_metric("hits", MetricValue::Counter(1.0), "a"));
+        buckets.insert(key, create_metric("hits", MetricValue::Counter(1.0), "a"));
+        buckets.insert(key, create_metric("hits", MetricValue::Counter(1.0), "b"));
+        buckets.insert(key, create_metric("hits", MetricValue::Counter(1.0), "c"));
+        assert!(buckets.contains_project(key));
+
+        // Buckets of past time windows are not flushed before the flush delay has passed.
+        assert!(buckets.take(false).is_empty());
+
+        let mut taken = buckets.take(true).remove(key).unwrap();
+        taken.sort_by(|a, b| a.tags.cmp(&b.tags));
+
+        assert_eq!(taken.len(), 2);
+        assert_eq!(taken[0].timestamp, 1_597_000_000);
+        assert_eq!(taken[0].value, BucketValue::Counter(2.0));
+        assert_eq!(taken[1].value, BucketValue::Counter(1.0));
+        assert!(!buckets.contains_project(key));
+        assert!(buckets.cardinality.is_empty());
+        assert!(buckets.project_counts.is_empty());
+    }
+}
@@ -28,6 +28,9 @@ pub use crate::service::ServerError;
 /// optional timeout. They can respond with a future, after which they will be stopped. Once all
 /// registered actors have stopped successfully, the entire system will stop.
 ///
+/// Similarly, actors can register for the [`Reload`] message with [`SubscribeReload`]. It is sent
+/// when the process receives `SIGHUP` and signals that the configuration should be reloaded.
+///
 /// ### Example
 ///
 /// ```ignore
@@ -59,11 +62,15 @@ pub use crate::service::ServerError;
 ///
 /// [`Subscribe`]: struct.Subscribe.html
 /// [`Shutdown`]: struct.Shutdown.html
+/// [`SubscribeReload`]: struct.SubscribeReload.html
+/// [`Reload`]: struct.Reload.html
 pub struct Controller {
     /// Configured timeout for graceful shutdowns.
     timeout: Duration,
     /// Subscribed actors for the shutdown message.
     subscribers: Vec<Recipient<Shutdown>>,
+    /// Subscribed actors for the reload message.
+    reload_subscribers: Vec<Recipient<Reload>>,
 }
 
 impl Controller {
@@ -106,6 +113,22 @@ impl Controller {
         Controller::from_registry().do_send(Subscribe(addr.recipient()))
     }
 
+    /// Subscribes the provided actor to the [`Reload`] signal of the system controller.
+    pub fn subscribe_reload<A>(addr: Addr<A>)
+    where
+        A: Handler<Reload>,
+        A::Context: actix::dev::ToEnvelope<A, Reload>,
+    {
+        Controller::from_registry().do_send(SubscribeReload(addr.recipient()))
+    }
+
+    /// Sends a `Reload` message to all subscribed actors.
+    fn reload(&mut self) {
+        for recipient in &self.reload_subscribers {
+            recipient.do_send(Reload).ok();
+        }
+    }
+
     /// Performs a graceful shutdown with the given timeout.
     ///
     /// This sends a `Shutdown` message to all subscribed actors and waits for them to finish. As
@@ -148,6 +171,7 @@ impl Default for Controller {
         Controller {
             timeout: Duration::from_secs(0),
             subscribers: Vec::new(),
+            reload_subscribers: Vec::new(),
         }
     }
 }
@@ -157,6 +181,7 @@ impl fmt::Debug for Controller {
         f.debug_struct("Controller")
             .field("timeout", &self.timeout)
             .field("subscribers", &self.subscribers.len())
+            .field("reload_subscribers", &self.reload_subscribers.len())
             .finish()
     }
 }
@@ -192,6 +217,10 @@ impl Handler<signal::Signal> for Controller {
                 log::info!("SIGTERM received, stopping in {}s", timeout.as_secs());
                 self.shutdown(context, Some(timeout));
             }
+            signal::SignalType::Hup => {
+                log::info!("SIGHUP received, reloading configuration");
+                self.reload();
+            }
             _ => (),
         }
     }
@@ -263,3 +292,39 @@ pub struct Shutdown {
 impl Message for Shutdown {
     type Result = Result<(), ()>;
 }
+
+/// Subscribtion message for [`Reload`] events.
+///
+/// [`Reload`]: struct.Reload.html
+pub struct SubscribeReload(pub Recipient<Reload>);
+
+impl fmt::Debug for SubscribeReload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SubscribeReload(Reload)")
+    }
+}
+
+impl Message for SubscribeReload {
+    type Result = ();
+}
+
+impl Handler<SubscribeReload> for Controller {
+    type Result = ();
+
+    fn handle(&mut self, message: SubscribeReload, _context: &mut Self::Context) -> Self::Result {
+        self.reload_subscribers.push(message.0)
+    }
+}
+
+/// Reload request message sent by the [`Controller`] to subscribed actors.
+///
+/// This is sent when the process receives `SIGHUP`. Receivers should load the configuration again
+/// and apply it where possible.
+///
+/// [`Controller`]: struct.Controller.html
+#[derive(Debug)]
+pub struct Reload;
+
+impl Message for Reload {
+    type Result = ();
+}
@@ -6,55 +6,74 @@ use std::time::{Duration, Instant};
 
 use actix::prelude::*;
 use chrono::{DateTime, Duration as SignedDuration, Utc};
-use failure::Fail;
+use failure::{Fail, ResultExt};
 use futures::prelude::*;
 use parking_lot::RwLock;
 use serde_json::Value as SerdeValue;
 
-use relay_common::{clone, metric, LogError};
-use relay_config::{Config, RelayMode};
+use relay_common::{clone, metric, LogError, RetryBackoff};
+use relay_config::{Config, RateLimiterKind, RelayMode};
 use relay_general::pii::PiiProcessor;
 use relay_general::processor::{process_value, ProcessingState};
 use relay_general::protocol::{
-    Breadcrumb, Csp, Event, EventId, EventType, ExpectCt, ExpectStaple, Hpkp, LenientString,
-    Metrics, SecurityReportType, SessionUpdate, Timestamp, Values,
+    Breadcrumb, CheckIn, ClientReport, Csp, Event, EventId, EventType, ExpectCt, ExpectStaple,
+    Hpkp, LenientString, Metrics, Profile, SecurityReportType, SessionAggregates, SessionUpdate,
+    Timestamp, Values,
 };
 use relay_general::store::ClockDriftProcessor;
 use relay_general::types::{Annotated, Array, Object, ProcessingAction, Value};
-use relay_quotas::RateLimits;
+use relay_quotas::{DataCategory, MemoryRateLimiter, RateLimiter, RateLimitingError, RateLimits};
 use relay_redis::RedisPool;
 
-use crate::actors::outcome::{DiscardReason, Outcome, OutcomeProducer, TrackOutcome};
+use crate::actors::aggregator::{Aggregator, InsertMetrics};
+use crate::actors::outcome::{
+    ClientDiscardReason, DiscardReason, Outcome, OutcomeProducer, TrackOutcome,
+};
 use crate::actors::project::{
     CheckEnvelope, GetProjectState, Project, ProjectState, UpdateRateLimits,
 };
-use crate::actors::project_cache::ProjectError;
-use crate::actors::upstream::{SendRequest, UpstreamRelay, UpstreamRequestError};
+use crate::actors::project_cache::{GetProject, ProjectCache, ProjectError};
+use crate::actors::reload::UpdateConfig;
+use crate::actors::upstream::{
+    IsAuthenticated, RequestBuilder, SendRequest, UpstreamRelay, UpstreamRequestError,
+};
 use crate::envelope::{self, AttachmentType, ContentType, Envelope, Item, ItemType};
 use crate::metrics::{RelayCounters, RelayHistograms, RelaySets, RelayTimers};
-use crate::service::ServerError;
-use crate::utils::{self, FormDataIter, FutureExt};
+use crate::service::{ServerError, ServerErrorKind};
+use crate::utils::{
+    self, ActorResponse, EnvelopeLimiter, EnvelopeSpool, FormDataIter, FutureExt, ReplaySpool,
+    RuleId, SamplingResult, SpoolEnvelope, SpoolError, SpoolReplay, SpooledEnvelope,
+};
 
 #[cfg(feature = "processing")]
 use {
     crate::actors::store::{StoreEnvelope, StoreError, StoreForwarder},
-    crate::service::ServerErrorKind,
-    crate::utils::EnvelopeLimiter,
     chrono::TimeZone,
-    failure::ResultExt,
     relay_filter::FilterStatKey,
     relay_general::protocol::IpAddr,
     relay_general::store::{GeoIpLookup, StoreConfig, StoreProcessor},
-    relay_quotas::{DataCategory, RateLimitingError, RedisRateLimiter},
+    relay_quotas::RedisRateLimiter,
 };
 
 /// The minimum clock drift for correction to apply.
 const MINIMUM_CLOCK_DRIFT: Duration = Duration::from_secs(55 * 60);
 
+/// The interval in which the spool is checked for envelopes to replay.
+const SPOOL_REPLAY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The maximum number of characters of unknown client discard reasons written to logs.
+const MAX_CLIENT_DISCARD_REASON_LOG_LENGTH: usize = 50;
+
 #[derive(Debug, Fail)]
 pub enum QueueEnvelopeError {
     #[fail(display = "Too many events (event_buffer_size reached)")]
     TooManyEvents,
+
+    #[fail(display = "failed to write envelope to the spool")]
+    SpoolFailed(#[cause] SpoolError),
+
+    #[fail(display = "could not schedule envelope spooling")]
+    ScheduleFailed(#[cause] MailboxError),
 }
 
 #[derive(Debug, Fail)]
@@ -116,7 +135,9 @@ enum ProcessingError {
     #[fail(display = "event rate limited")]
     RateLimited(RateLimits),
 
-    #[cfg(feature = "processing")]
+    #[fail(display = "event dropped by sampling rule {}", _0)]
+    EventSampled(RuleId),
+
     #[fail(display = "failed to apply quotas")]
     QuotasFailed(#[cause] RateLimitingError),
 
@@ -140,6 +161,7 @@ impl ProcessingError {
             Self::RateLimited(ref rate_limits) => rate_limits
                 .longest()
                 .map(|r| Outcome::RateLimited(r.reason_code.clone())),
+            Self::EventSampled(rule_id) => Some(Outcome::FilteredSampling(rule_id)),
 
             // Processing-only outcomes (Sentry-internal Relays)
             #[cfg(feature = "processing")]
@@ -152,16 +174,23 @@ impl ProcessingError {
             | Self::ScheduleFailed(_)
             | Self::ProjectFailed(_)
             | Self::Timeout
+            | Self::QuotasFailed(_)
             | Self::ProcessingFailed(_) => Some(Outcome::Invalid(DiscardReason::Internal)),
             #[cfg(feature = "processing")]
-            Self::StoreFailed(_) | Self::QuotasFailed(_) => {
-                Some(Outcome::Invalid(DiscardReason::Internal))
-            }
+            Self::StoreFailed(_) => Some(Outcome::Invalid(DiscardReason::Internal)),
 
             // If we send to an upstream, we don't emit outcomes.
             Self::SendFailed(_) => None,
         }
     }
+
+    /// Returns `true` if the upstream could not be reached or failed to handle the envelope.
+    fn is_upstream_failure(&self) -> bool {
+        match *self {
+            Self::SendFailed(ref error) => error.is_upstream_failure(),
+            _ => false,
+        }
+    }
 }
 
 type ExtractedEvent = (Annotated<Event>, usize);
@@ -188,12 +217,11 @@ struct ProcessEnvelopeState {
     /// persisted into the Event. All modifications afterwards will have no effect.
     metrics: Metrics,
 
-    /// Rate limits returned in processing mode.
+    /// Rate limits returned by the rate limiter.
     ///
-    /// The rate limiter is invoked in processing mode, after which the resulting limits are stored
-    /// in this field. Note that there can be rate limits even if the envelope still carries items.
-    ///
-    /// These are always empty in non-processing mode, since the rate limiter is not invoked.
+    /// The rate limiter is invoked after the event has been extracted, after which the resulting
+    /// limits are stored in this field. Note that there can be rate limits even if the envelope
+    /// still carries items.
     rate_limits: RateLimits,
 
     /// The state of the project that this envelope belongs to.
@@ -201,6 +229,12 @@ struct ProcessEnvelopeState {
 
     /// UTC date time converted from the `start_time` instant.
     received_at: DateTime<Utc>,
+
+    /// Custom metrics extracted from the envelope, to be sent to the aggregator.
+    insert_metrics: Option<InsertMetrics>,
+
+    /// Outcomes of data discarded by the client, extracted from client reports.
+    client_outcomes: Vec<TrackOutcome>,
 }
 
 impl ProcessEnvelopeState {
@@ -234,13 +268,11 @@ impl ProcessEnvelopeState {
     ///
     /// The data category is computed from the event type. Both `Default` and `Error` events map to
     /// the `Error` data category. If there is no Event, `None` is returned.
-    #[cfg(feature = "processing")]
     fn event_category(&self) -> Option<DataCategory> {
         self.event_type().map(DataCategory::from)
     }
 
     /// Removes the event payload from this processing state.
-    #[cfg(feature = "processing")]
     fn remove_event(&mut self) {
         self.event = Annotated::empty();
     }
@@ -249,8 +281,7 @@ impl ProcessEnvelopeState {
 /// Synchronous service for processing envelopes.
 struct EventProcessor {
     config: Arc<Config>,
-    #[cfg(feature = "processing")]
-    rate_limiter: Option<RedisRateLimiter>,
+    rate_limiter: Option<Arc<dyn RateLimiter>>,
     #[cfg(feature = "processing")]
     geoip_lookup: Option<Arc<GeoIpLookup>>,
 }
@@ -259,7 +290,7 @@ impl EventProcessor {
     #[cfg(feature = "processing")]
     pub fn new(
         config: Arc<Config>,
-        rate_limiter: Option<RedisRateLimiter>,
+        rate_limiter: Option<Arc<dyn RateLimiter>>,
         geoip_lookup: Option<Arc<GeoIpLookup>>,
     ) -> Self {
         Self {
@@ -270,8 +301,65 @@ impl EventProcessor {
     }
 
     #[cfg(not(feature = "processing"))]
-    pub fn new(config: Arc<Config>) -> Self {
-        Self { config }
+    pub fn new(config: Arc<Config>, rate_limiter: Option<Arc<dyn RateLimiter>>) -> Self {
+        Self {
+            config,
+            rate_limiter,
+        }
+    }
+
+    /// Validates pre-aggregated sessions in an item.
+    ///
+    /// Aggregates with timestamps out of range after clock drift correction are removed. Returns
+    /// `false` if the item should be removed from the envelope.
+    fn process_session_aggregates(
+        &self,
+        item: &mut Item,
+        received: DateTime<Utc>,
+        clock_drift_processor: &ClockDriftProcessor,
+    ) -> bool {
+        let mut aggregates = match SessionAggregates::parse(&item.payload()) {
+            Ok(aggregates) => aggregates,
+            Err(error) => {
+                log::debug!("skipping invalid session aggregates: {}", LogError(&error));
+                return false;
+            }
+        };
+
+        let mut changed = false;
+        if clock_drift_processor.is_drifted() {
+            log::trace!("applying clock drift correction to session aggregates");
+            clock_drift_processor.process_session_aggregates(&mut aggregates);
+            changed = true;
+        }
+
+        let max_age = SignedDuration::seconds(self.config.max_session_secs_in_past());
+        let max_future = SignedDuration::seconds(self.config.max_secs_in_future());
+        let count = aggregates.aggregates.len();
+        aggregates.aggregates.retain(|aggregate| {
+            (received - aggregate.started) <= max_age
+                && (aggregate.started - received) <= max_future
+        });
+
+        if aggregates.aggregates.len() != count {
+            log::trace!("skipping session aggregates with out of range timestamps");
+            changed = true;
+        }
+
+        if aggregates.aggregates.is_empty() {
+            return false;
+        }
+
+        if changed {
+            let json_string = match serde_json::to_string(&aggregates) {
+                Ok(json) => json,
+                Err(_) => return false,
+            };
+
+            item.set_payload(ContentType::Json, json_string);
+        }
+
+        true
     }
 
     /// Validates all sessions in the envelope, if any.
@@ -288,6 +376,10 @@ impl EventProcessor {
         let client = envelope.meta().client().map(str::to_owned);
 
         envelope.retain_items(|item| {
+            if item.ty() == ItemType::Sessions {
+                return self.process_session_aggregates(item, received, &clock_drift_processor);
+            }
+
             if item.ty() != ItemType::Session {
                 return true;
             }
@@ -362,6 +454,162 @@ impl EventProcessor {
         Ok(())
     }
 
+    /// Converts client reports in the envelope into outcomes.
+    ///
+    /// Client reports are only converted if this Relay emits outcomes. Otherwise, they are forwarded
+    /// to the upstream. Reports with timestamps out of range after clock drift correction are
+    /// dropped.
+    fn process_client_reports(
+        &self,
+        state: &mut ProcessEnvelopeState,
+    ) -> Result<(), ProcessingError> {
+        if !self.config.emit_outcomes() {
+            return Ok(());
+        }
+
+        let received = state.received_at;
+        let envelope = &mut state.envelope;
+        let clock_drift_processor =
+            ClockDriftProcessor::new(envelope.sent_at(), received).at_least(MINIMUM_CLOCK_DRIFT);
+        let max_age = SignedDuration::seconds(self.config.max_secs_in_past());
+        let max_future = SignedDuration::seconds(self.config.max_secs_in_future());
+
+        let scoping = state.project_state.get_scoping(envelope.meta());
+        let remote_addr = envelope.meta().client_addr();
+
+        while let Some(item) = envelope.take_item_by(|item| item.ty() == ItemType::ClientReport) {
+            let mut report = match ClientReport::parse(&item.payload()) {
+                Ok(report) => report,
+                Err(error) => {
+                    log::debug!("skipping invalid client report: {}", LogError(&error));
+                    continue;
+                }
+            };
+
+            if clock_drift_processor.is_drifted() {
+                log::trace!("applying clock drift correction to client report");
+                clock_drift_processor.process_client_report(&mut report);
+            }
+
+            let timestamp = report.timestamp.unwrap_or(received);
+            if (received - timestamp) > max_age || (timestamp - received) > max_future {
+                log::trace!("skipping client report with out of range timestamp");
+                continue;
+            }
+
+            // Outcomes are timestamped with an `Instant`, so compute it from the age of the report.
+            let age = (Utc::now() - timestamp).to_std().unwrap_or_default();
+            let instant = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+
+            for discarded in report.discarded_events {
+                if discarded.quantity == 0 {
+                    continue;
+                }
+
+                let reason = match ClientDiscardReason::parse(&discarded.reason) {
+                    Some(reason) => reason,
+                    None => {
+                        // The reason is sent by the client, so only log a bounded prefix.
+                        let reason: String = discarded
+                            .reason
+                            .chars()
+                            .take(MAX_CLIENT_DISCARD_REASON_LOG_LENGTH)
+                            .collect();
+                        log::debug!("skipping client discard with unknown reason {:?}", reason);
+                        continue;
+                    }
+                };
+
+                // The quantity cannot be verified and is trusted as reported by the client.
+                state.client_outcomes.push(TrackOutcome {
+                    timestamp: instant,
+                    scoping: scoping.clone(),
+                    outcome: Outcome::ClientDiscard(reason),
+                    event_id: None,
+                    remote_addr,
+                    category: Some(discarded.category),
+                    quantity: Some(discarded.quantity),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates and scrubs all profiles in the envelope, if any.
+    ///
+    /// Profiles are linked to their transaction through the event ID of the envelope. They are
+    /// removed if the envelope has no event ID, if they are structurally invalid, or if scrubbing
+    /// fails. User names are removed from the paths of all frames.
+    fn process_profiles(&self, state: &mut ProcessEnvelopeState) -> Result<(), ProcessingError> {
+        let envelope = &mut state.envelope;
+        let has_event_id = envelope.event_id().is_some();
+
+        envelope.retain_items(|item| {
+            if item.ty() != ItemType::Profile {
+                return true;
+            }
+
+            if !has_event_id {
+                log::debug!("skipping profile without event id");
+                return false;
+            }
+
+            let mut profile = match Profile::parse(&item.payload()) {
+                Ok(profile) => profile,
+                Err(error) => {
+                    log::debug!("skipping invalid profile: {}", LogError(&error));
+                    return false;
+                }
+            };
+
+            if let Err(error) = profile.scrub_frame_paths() {
+                log::debug!("failed to scrub profile: {}", LogError(&error));
+                return false;
+            }
+
+            match profile.serialize() {
+                Ok(json) => {
+                    item.set_payload(ContentType::Json, json);
+                    true
+                }
+                Err(_) => false,
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Validates all monitor check-ins in the envelope, if any.
+    ///
+    /// Check-ins are removed from the envelope if they contain invalid JSON, an invalid monitor
+    /// slug, or an invalid duration.
+    fn process_check_ins(&self, state: &mut ProcessEnvelopeState) -> Result<(), ProcessingError> {
+        state.envelope.retain_items(|item| {
+            if item.ty() != ItemType::CheckIn {
+                return true;
+            }
+
+            let check_in = match CheckIn::parse(&item.payload()) {
+                Ok(check_in) => check_in,
+                Err(error) => {
+                    log::debug!("skipping invalid check-in: {}", LogError(&error));
+                    return false;
+                }
+            };
+
+            match check_in.serialize() {
+                Ok(json) => {
+                    item.set_payload(ContentType::Json, json);
+                    true
+                }
+                Err(_) => false,
+            }
+        });
+
+        Ok(())
+    }
+
     /// Creates and initializes the processing state.
     ///
     /// This applies defaults to the envelope and initializes empty rate limits.
@@ -388,6 +636,8 @@ impl EventProcessor {
             rate_limits: RateLimits::new(),
             project_state,
             received_at: relay_common::instant_to_date_time(start_time),
+            insert_metrics: None,
+            client_outcomes: Vec::new(),
         })
     }
 
@@ -614,6 +864,19 @@ impl EventProcessor {
 
             // session data is never considered as part of deduplication
             ItemType::Session => false,
+            ItemType::Sessions => false,
+
+            // metrics are extracted for aggregation
+            ItemType::Metrics => false,
+
+            // client reports are converted into outcomes
+            ItemType::ClientReport => false,
+
+            // profiles belong to the transaction but are stored separately
+            ItemType::Profile => false,
+
+            // check-ins are independent of events
+            ItemType::CheckIn => false,
         }
     }
 
@@ -909,7 +1172,23 @@ impl EventProcessor {
         })
     }
 
-    #[cfg(feature = "processing")]
+    /// Applies the project's sampling rules to the event.
+    ///
+    /// Since the decision is based on the trace ID, this runs in every Relay. Dropping events early
+    /// reduces traffic to the upstream.
+    fn sample_event(&self, state: &mut ProcessEnvelopeState) -> Result<(), ProcessingError> {
+        let event = match state.event.value() {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+
+        let sampling_config = &state.project_state.config.sampling;
+        match utils::sample_event(sampling_config, event) {
+            SamplingResult::Keep => Ok(()),
+            SamplingResult::Drop(rule_id) => Err(ProcessingError::EventSampled(rule_id)),
+        }
+    }
+
     fn enforce_quotas(&self, state: &mut ProcessEnvelopeState) -> Result<(), ProcessingError> {
         let rate_limiter = match self.rate_limiter.as_ref() {
             Some(rate_limiter) => rate_limiter,
@@ -957,10 +1236,106 @@ impl EventProcessor {
         Ok(())
     }
 
+    /// Extracts custom metrics from the envelope for aggregation.
+    ///
+    /// All metrics items are removed from the envelope. Metrics with names exceeding the maximum
+    /// length or with timestamps too far in the past or future are dropped.
+    fn extract_metrics(&self, state: &mut ProcessEnvelopeState) -> Result<(), ProcessingError> {
+        let timestamp = state.received_at.timestamp() as u64;
+        let max_name_length = self.config.max_metric_name_length();
+        let min_timestamp = timestamp.saturating_sub(self.config.max_metric_secs_in_past());
+        let max_timestamp = timestamp.saturating_add(self.config.max_metric_secs_in_future());
+        let mut metrics = Vec::new();
+
+        let envelope = &mut state.envelope;
+        while let Some(item) = envelope.take_item_by(|item| item.ty() == ItemType::Metrics) {
+            for metric in utils::parse_metrics(&item.payload(), timestamp) {
+                if metric.name.len() > max_name_length {
+                    metric!(
+                        counter(RelayCounters::MetricsDropped) += 1,
+                        reason = "name_length"
+                    );
+                    continue;
+                }
+
+                if metric.timestamp < min_timestamp || metric.timestamp > max_timestamp {
+                    metric!(
+                        counter(RelayCounters::MetricsDropped) += 1,
+                        reason = "timestamp"
+                    );
+                    continue;
+                }
+
+                metrics.push(metric);
+            }
+        }
+
+        if !metrics.is_empty() {
+            let meta = envelope.meta().clone();
+            state.insert_metrics = Some(InsertMetrics {
+                scoping: state.project_state.get_scoping(&meta),
+                retention: envelope.retention(),
+                meta,
+                metrics,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Applies the PII audit config of the project in a dry run.
+    ///
+    /// Matches are counted per builtin rule in metrics. In processing mode, the total number of
+    /// matches is also written into the event's metrics. The event is not modified otherwise.
+    fn audit_event(&self, state: &mut ProcessEnvelopeState) -> Result<(), ProcessingError> {
+        let audit_config = match state.project_state.config.pii_audit_config {
+            Some(ref audit_config) => audit_config,
+            None => return Ok(()),
+        };
+
+        let compiled = audit_config.compiled();
+        let mut processor = PiiProcessor::audit(&compiled);
+        process_value(&mut state.event, &mut processor, ProcessingState::root())
+            .map_err(ProcessingError::ProcessingFailed)?;
+
+        // Custom rule identifiers are chosen by users, so only builtin rules are tagged to bound the
+        // cardinality of the metric.
+        let matches = processor.into_matches();
+        let mut counts = BTreeMap::<&str, i64>::new();
+        for pii_match in &matches {
+            let rule = if pii_match.rule_id.starts_with('@') {
+                pii_match.rule_id.as_str()
+            } else {
+                "custom"
+            };
+            *counts.entry(rule).or_insert(0) += 1;
+        }
+
+        for (rule, count) in counts {
+            metric!(
+                counter(RelayCounters::PiiAuditMatches) += count,
+                rule = rule
+            );
+        }
+
+        if self.config.processing_enabled() && !matches.is_empty() {
+            if let Some(event) = state.event.value_mut() {
+                event
+                    ._metrics
+                    .get_or_insert_with(Default::default)
+                    .count_pii_audit_matches = Annotated::new(matches.len() as u64);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Apply data privacy rules to the event payload.
     ///
     /// This uses both the general `datascrubbing_settings`, as well as the the PII rules.
     fn scrub_event(&self, state: &mut ProcessEnvelopeState) -> Result<(), ProcessingError> {
+        self.audit_event(state)?;
+
         let event = &mut state.event;
         let config = &state.project_state.config;
 
@@ -1013,6 +1388,9 @@ impl EventProcessor {
 
         let mut state = self.prepare_state(message)?;
         self.process_sessions(&mut state)?;
+        self.process_client_reports(&mut state)?;
+        self.process_profiles(&mut state)?;
+        self.process_check_ins(&mut state)?;
 
         if state.creates_event() {
             if_processing!({
@@ -1027,6 +1405,7 @@ impl EventProcessor {
             });
 
             self.finalize_event(&mut state)?;
+            self.sample_event(&mut state)?;
 
             if_processing!({
                 self.store_process_event(&mut state)?;
@@ -1034,9 +1413,8 @@ impl EventProcessor {
             });
         }
 
-        if_processing!({
-            self.enforce_quotas(&mut state)?;
-        });
+        self.enforce_quotas(&mut state)?;
+        self.extract_metrics(&mut state)?;
 
         if state.has_event() {
             self.scrub_event(&mut state)?;
@@ -1061,6 +1439,8 @@ struct ProcessEnvelope {
 struct ProcessEnvelopeResponse {
     envelope: Option<Envelope>,
     rate_limits: RateLimits,
+    insert_metrics: Option<InsertMetrics>,
+    client_outcomes: Vec<TrackOutcome>,
 }
 
 impl From<ProcessEnvelopeState> for ProcessEnvelopeResponse {
@@ -1068,6 +1448,8 @@ impl From<ProcessEnvelopeState> for ProcessEnvelopeResponse {
         Self {
             envelope: Some(state.envelope).filter(|e| !e.is_empty()),
             rate_limits: state.rate_limits,
+            insert_metrics: state.insert_metrics,
+            client_outcomes: state.client_outcomes,
         }
     }
 }
@@ -1087,61 +1469,146 @@ impl Handler<ProcessEnvelope> for EventProcessor {
     }
 }
 
+/// Creates a request to send an envelope to the upstream.
+pub fn create_envelope_request(mut envelope: Envelope) -> SendRequest<impl RequestBuilder> {
+    let project_id = envelope.meta().project_id();
+    SendRequest::post(format!("/api/{}/store/", project_id)).build(move |builder| {
+        // Override the `sent_at` timestamp. Since the event went through basic normalization, all
+        // timestamps have been corrected. We propagate the new `sent_at` to allow the next Relay
+        // to double-check this timestamp and potentially apply correction again. This is done as
+        // close to sending as possible so that we avoid internal delays.
+        envelope.set_sent_at(Utc::now());
+
+        let meta = envelope.meta();
+
+        if let Some(origin) = meta.origin() {
+            builder.header("Origin", origin.to_string());
+        }
+
+        if let Some(user_agent) = meta.user_agent() {
+            builder.header("User-Agent", user_agent);
+        }
+
+        builder
+            .header("X-Sentry-Auth", meta.auth_header())
+            .header("X-Forwarded-For", meta.forwarded_for())
+            .header("Content-Type", envelope::CONTENT_TYPE)
+            .body(envelope.to_vec().map_err(failure::Error::from)?)
+    })
+}
+
 pub type CapturedEvent = Result<Envelope, String>;
 
 pub struct EventManager {
     config: Arc<Config>,
     upstream: Addr<UpstreamRelay>,
     processor: Addr<EventProcessor>,
+    thread_count: usize,
+    rate_limiter: Option<Arc<dyn RateLimiter>>,
+    #[cfg(feature = "processing")]
+    geoip_lookup: Option<Arc<GeoIpLookup>>,
+    project_cache: Addr<ProjectCache>,
+    aggregator: Addr<Aggregator>,
     current_active_events: u32,
     outcome_producer: Addr<OutcomeProducer>,
     captured_events: Arc<RwLock<BTreeMap<EventId, CapturedEvent>>>,
+    spool: Option<Addr<EnvelopeSpool>>,
+    /// The number of envelopes in the spool, including envelopes that are being written.
+    spooled: usize,
+    /// Whether a replay request is pending in the spool.
+    replaying: bool,
+    /// Whether the upstream accepts requests from this Relay.
+    upstream_available: bool,
+    /// Set if sending to the upstream failed. Envelopes are spooled until a probe succeeds.
+    upstream_retry_at: Option<Instant>,
+    upstream_backoff: RetryBackoff,
 
     #[cfg(feature = "processing")]
     store_forwarder: Option<Addr<StoreForwarder>>,
 }
 
+/// Creates the rate limiter for quotas configured in `limits.rate_limiter`.
+///
+/// Returns `None` if counting quotas is disabled.
+fn create_rate_limiter(
+    config: &Config,
+    redis_pool: Option<RedisPool>,
+) -> Result<Option<Arc<dyn RateLimiter>>, ServerError> {
+    let max_limit = config.max_rate_limit();
+
+    #[cfg(feature = "processing")]
+    let redis_limiter = redis_pool.map(|pool| {
+        Arc::new(RedisRateLimiter::new(pool).max_limit(max_limit)) as Arc<dyn RateLimiter>
+    });
+
+    #[cfg(not(feature = "processing"))]
+    let redis_limiter: Option<Arc<dyn RateLimiter>> = {
+        let _ = redis_pool;
+        None
+    };
+
+    let rate_limiter = match (config.rate_limiter(), redis_limiter) {
+        (RateLimiterKind::Disabled, _) => None,
+        (RateLimiterKind::Redis, None) => {
+            log::error!("the redis rate limiter requires processing mode and a redis config");
+            return Err(ServerErrorKind::ConfigError.into());
+        }
+        (RateLimiterKind::Redis, Some(redis_limiter))
+        | (RateLimiterKind::Auto, Some(redis_limiter)) => Some(redis_limiter),
+        (RateLimiterKind::Memory, _) | (RateLimiterKind::Auto, None) => {
+            Some(Arc::new(MemoryRateLimiter::new().max_limit(max_limit)) as Arc<dyn RateLimiter>)
+        }
+    };
+
+    Ok(rate_limiter)
+}
+
+/// Spawns the synchronous event processors in `thread_count` worker threads.
+#[cfg(feature = "processing")]
+fn start_processor(
+    thread_count: usize,
+    config: Arc<Config>,
+    rate_limiter: Option<Arc<dyn RateLimiter>>,
+    geoip_lookup: Option<Arc<GeoIpLookup>>,
+) -> Addr<EventProcessor> {
+    SyncArbiter::start(thread_count, move || {
+        EventProcessor::new(config.clone(), rate_limiter.clone(), geoip_lookup.clone())
+    })
+}
+
+/// Spawns the synchronous event processors in `thread_count` worker threads.
+#[cfg(not(feature = "processing"))]
+fn start_processor(
+    thread_count: usize,
+    config: Arc<Config>,
+    rate_limiter: Option<Arc<dyn RateLimiter>>,
+) -> Addr<EventProcessor> {
+    SyncArbiter::start(thread_count, move || {
+        EventProcessor::new(config.clone(), rate_limiter.clone())
+    })
+}
+
 impl EventManager {
     pub fn create(
         config: Arc<Config>,
         upstream: Addr<UpstreamRelay>,
+        project_cache: Addr<ProjectCache>,
         outcome_producer: Addr<OutcomeProducer>,
         redis_pool: Option<RedisPool>,
     ) -> Result<Self, ServerError> {
         let thread_count = config.cpu_concurrency();
         log::info!("starting {} event processing workers", thread_count);
 
-        #[cfg(not(feature = "processing"))]
-        let _ = redis_pool;
+        let rate_limiter = create_rate_limiter(&config, redis_pool)?;
 
         #[cfg(feature = "processing")]
-        let processor = {
-            let geoip_lookup = match config.geoip_path() {
-                Some(p) => Some(Arc::new(
-                    GeoIpLookup::open(p).context(ServerErrorKind::GeoIpError)?,
-                )),
-                None => None,
-            };
-
-            let rate_limiter = redis_pool
-                .map(|pool| RedisRateLimiter::new(pool).max_limit(config.max_rate_limit()));
-
-            SyncArbiter::start(
-                thread_count,
-                clone!(config, || EventProcessor::new(
-                    config.clone(),
-                    rate_limiter.clone(),
-                    geoip_lookup.clone(),
-                )),
-            )
+        let geoip_lookup = match config.geoip_path() {
+            Some(p) => Some(Arc::new(
+                GeoIpLookup::open(p).context(ServerErrorKind::GeoIpError)?,
+            )),
+            None => None,
         };
 
-        #[cfg(not(feature = "processing"))]
-        let processor = SyncArbiter::start(
-            thread_count,
-            clone!(config, || EventProcessor::new(config.clone())),
-        );
-
         #[cfg(feature = "processing")]
         let store_forwarder = if config.processing_enabled() {
             let actor = StoreForwarder::create(config.clone())?;
@@ -1150,12 +1617,64 @@ impl EventManager {
             None
         };
 
+        #[cfg(feature = "processing")]
+        let aggregator =
+            Aggregator::new(&config, upstream.clone(), store_forwarder.clone()).start();
+
+        #[cfg(not(feature = "processing"))]
+        let aggregator = Aggregator::new(&config, upstream.clone()).start();
+
+        let spool = match config.spool_path() {
+            Some(path) => Some(
+                EnvelopeSpool::open(&path, config.spool_max_disk_size(), config.spool_max_age())
+                    .context(ServerErrorKind::SpoolError)?,
+            ),
+            None => None,
+        };
+
+        let spooled = match spool {
+            Some(ref spool) if !spool.is_empty() => {
+                log::info!("restored {} envelopes from the spool", spool.len());
+                spool.len()
+            }
+            _ => 0,
+        };
+        let spool = spool.map(EnvelopeSpool::start_sync);
+        let upstream_backoff = RetryBackoff::new(config.http_max_retry_interval());
+
+        // Without authentication, there is no way to tell whether the upstream is reachable. In
+        // this case, envelopes are only spooled if the event buffer is full.
+        let upstream_available = config.relay_mode() != RelayMode::Managed;
+
+        #[cfg(feature = "processing")]
+        let processor = start_processor(
+            thread_count,
+            config.clone(),
+            rate_limiter.clone(),
+            geoip_lookup.clone(),
+        );
+
+        #[cfg(not(feature = "processing"))]
+        let processor = start_processor(thread_count, config.clone(), rate_limiter.clone());
+
         Ok(EventManager {
             config,
             upstream,
             processor,
+            thread_count,
+            rate_limiter,
+            #[cfg(feature = "processing")]
+            geoip_lookup,
+            project_cache,
+            aggregator,
             current_active_events: 0,
             captured_events: Arc::default(),
+            spool,
+            spooled,
+            replaying: false,
+            upstream_available,
+            upstream_retry_at: None,
+            upstream_backoff,
 
             #[cfg(feature = "processing")]
             store_forwarder,
@@ -1163,6 +1682,232 @@ impl EventManager {
             outcome_producer,
         })
     }
+
+    /// Returns `true` if the in-memory event buffer cannot take any more envelopes.
+    fn is_buffer_full(&self) -> bool {
+        self.config.event_buffer_size() <= self.current_active_events
+    }
+
+    /// Returns `true` if new envelopes should be written to the spool instead of being queued.
+    ///
+    /// Envelopes are spooled if the upstream is not available or the event buffer is full. As long
+    /// as the spool contains envelopes, new envelopes are appended to it to retain their order.
+    fn should_spool(&self) -> bool {
+        self.spool.is_some()
+            && (self.spooled > 0 || !self.is_upstream_available() || self.is_buffer_full())
+    }
+
+    /// Returns `true` if the upstream is authenticated and the last send did not fail.
+    fn is_upstream_available(&self) -> bool {
+        self.upstream_available && self.upstream_retry_at.is_none()
+    }
+
+    /// Marks the upstream as unavailable after a network error or server error.
+    ///
+    /// Envelopes are spooled until a replayed envelope is sent successfully.
+    fn upstream_failed(&mut self) {
+        if self.upstream_retry_at.is_none() {
+            log::warn!("failed to send envelope to upstream, spooling envelopes");
+            self.upstream_retry_at = Some(Instant::now() + self.upstream_backoff.next_backoff());
+        }
+    }
+
+    /// Marks the upstream as available after an envelope was sent successfully.
+    fn upstream_recovered(&mut self) {
+        if self.upstream_retry_at.take().is_some() {
+            log::info!("upstream recovered, replaying spooled envelopes");
+        }
+        self.upstream_backoff.reset();
+    }
+
+    /// Writes an envelope to the end of the spool.
+    ///
+    /// The returned future resolves once the envelope has been written.
+    fn spool_envelope(
+        &mut self,
+        envelope: Envelope,
+        start_time: Instant,
+    ) -> ResponseActFuture<Self, (), QueueEnvelopeError> {
+        let spool = match self.spool {
+            Some(ref spool) => spool,
+            None => return Box::new(fut::err(QueueEnvelopeError::TooManyEvents)),
+        };
+
+        self.spooled += 1;
+
+        let future = spool
+            .send(SpoolEnvelope {
+                envelope,
+                start_time,
+            })
+            .map_err(QueueEnvelopeError::ScheduleFailed)
+            .and_then(|result| {
+                result.map_err(|error| match error {
+                    SpoolError::Full => QueueEnvelopeError::TooManyEvents,
+                    error => QueueEnvelopeError::SpoolFailed(error),
+                })
+            })
+            .into_actor(self)
+            .map(|_, _, _| metric!(counter(RelayCounters::EnvelopeSpooled) += 1))
+            .map_err(|error, slf, _| {
+                slf.spooled -= 1;
+                error
+            });
+
+        Box::new(future)
+    }
+
+    /// Queues an envelope for asynchronous handling.
+    fn enqueue(
+        &mut self,
+        mut envelope: Envelope,
+        project: Addr<Project>,
+        start_time: Instant,
+        context: &mut Context<Self>,
+    ) {
+        // Split the envelope into event-related items and other items. This allows to fast-track:
+        //  1. Envelopes with only session items. They only require rate limiting.
+        //  2. Event envelope processing can bail out if the event is filtered or rate limited,
+        //     since all items depend on this event.
+        if let Some(event_envelope) = envelope.split_by(Item::requires_event) {
+            self.current_active_events += 1;
+            context.notify(HandleEnvelope {
+                envelope: event_envelope,
+                project: project.clone(),
+                start_time,
+            });
+        }
+
+        self.current_active_events += 1;
+        context.notify(HandleEnvelope {
+            envelope,
+            project,
+            start_time,
+        });
+    }
+
+    /// Updates the availability of the upstream from its authentication state.
+    fn check_upstream(&mut self, context: &mut Context<Self>) {
+        if self.config.relay_mode() != RelayMode::Managed {
+            return;
+        }
+
+        self.upstream
+            .send(IsAuthenticated)
+            .into_actor(self)
+            .map(|is_authenticated, slf, _| {
+                if is_authenticated && !slf.upstream_available {
+                    log::info!("upstream available, replaying spooled envelopes");
+                }
+                slf.upstream_available = is_authenticated;
+            })
+            .drop_err()
+            .spawn(context);
+    }
+
+    /// Drops expired envelopes from the spool and replays as many envelopes as the event buffer
+    /// can take.
+    ///
+    /// After a failed send, a single envelope is replayed as probe once the retry backoff has
+    /// elapsed. If it is sent successfully, the upstream is considered available again.
+    fn replay_spool(&mut self, context: &mut Context<Self>) {
+        self.check_upstream(context);
+
+        let spool = match self.spool {
+            Some(ref spool) if !self.replaying => spool.clone(),
+            _ => return,
+        };
+
+        let capacity = self
+            .config
+            .event_buffer_size()
+            .saturating_sub(self.current_active_events) as usize;
+
+        let max_count = match self.upstream_retry_at {
+            _ if !self.upstream_available => 0,
+            None => capacity,
+            Some(retry_at) if retry_at <= Instant::now() && capacity > 0 && self.spooled > 0 => {
+                let backoff = self.upstream_backoff.next_backoff();
+                self.upstream_retry_at = Some(Instant::now() + backoff);
+                1
+            }
+            Some(_) => 0,
+        };
+
+        self.replaying = true;
+        spool
+            .send(ReplaySpool { max_count })
+            .into_actor(self)
+            .then(|result, slf, ctx| {
+                slf.replaying = false;
+                match result {
+                    Ok(replay) => slf.handle_replay(replay, ctx),
+                    Err(error) => log::error!("failed to replay spool: {}", LogError(&error)),
+                }
+                fut::ok(())
+            })
+            .spawn(context);
+    }
+
+    /// Emits outcomes for expired envelopes and queues replayed envelopes.
+    fn handle_replay(&mut self, replay: SpoolReplay, context: &mut Context<Self>) {
+        self.spooled = self.spooled.saturating_sub(replay.removed);
+
+        for envelope in replay.expired {
+            log::debug!("dropped expired envelope from spool");
+
+            // Do not track outcomes for non-event envelopes (such as individual attachments).
+            if !envelope.items().any(Item::creates_event) {
+                continue;
+            }
+
+            self.outcome_producer.do_send(TrackOutcome {
+                timestamp: Instant::now(),
+                scoping: envelope.meta().get_partial_scoping(),
+                outcome: Outcome::Invalid(DiscardReason::SpoolExpired),
+                event_id: envelope.event_id(),
+                remote_addr: envelope.meta().client_addr(),
+                category: None,
+                quantity: None,
+            });
+        }
+
+        metric!(histogram(RelayHistograms::SpoolSize) = replay.size as u64);
+
+        for spooled in replay.replayed {
+            self.replay_envelope(spooled, context);
+        }
+    }
+
+    /// Resolves the project of a spooled envelope and queues it for handling.
+    fn replay_envelope(&mut self, spooled: SpooledEnvelope, context: &mut Context<Self>) {
+        let SpooledEnvelope {
+            envelope,
+            start_time,
+        } = spooled;
+
+        // Reserve a slot in the event buffer while the project is being resolved, so that the next
+        // replay does not exceed the buffer size.
+        self.current_active_events += 1;
+
+        let project_id = envelope.meta().project_id();
+        self.project_cache
+            .send(GetProject { id: project_id })
+            .into_actor(self)
+            .then(move |result, slf, ctx| {
+                slf.current_active_events -= 1;
+
+                match result {
+                    Ok(project) => slf.enqueue(envelope, project, start_time, ctx),
+                    Err(error) => {
+                        log::error!("failed to replay spooled envelope: {}", LogError(&error))
+                    }
+                }
+
+                fut::ok(())
+            })
+            .spawn(context);
+    }
 }
 
 impl Actor for EventManager {
@@ -1173,6 +1918,12 @@ impl Actor for EventManager {
         // should ensure that we're not dropping events unintentionally after we've accepted them.
         let mailbox_size = self.config.event_buffer_size() as usize;
         context.set_mailbox_capacity(mailbox_size);
+
+        if self.spool.is_some() {
+            self.check_upstream(context);
+            context.run_interval(SPOOL_REPLAY_INTERVAL, |slf, ctx| slf.replay_spool(ctx));
+        }
+
         log::info!("event manager started");
     }
 
@@ -1192,9 +1943,9 @@ impl Message for QueueEnvelope {
 }
 
 impl Handler<QueueEnvelope> for EventManager {
-    type Result = Result<Option<EventId>, QueueEnvelopeError>;
+    type Result = ActorResponse<Self, Option<EventId>, QueueEnvelopeError>;
 
-    fn handle(&mut self, mut message: QueueEnvelope, context: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, message: QueueEnvelope, context: &mut Self::Context) -> Self::Result {
         metric!(
             histogram(RelayHistograms::EnvelopeQueueSize) = u64::from(self.current_active_events)
         );
@@ -1207,38 +1958,33 @@ impl Handler<QueueEnvelope> for EventManager {
             }
         );
 
-        if self.config.event_buffer_size() <= self.current_active_events {
-            return Err(QueueEnvelopeError::TooManyEvents);
-        }
-
         let event_id = message.envelope.event_id();
 
-        // Split the envelope into event-related items and other items. This allows to fast-track:
-        //  1. Envelopes with only session items. They only require rate limiting.
-        //  2. Event envelope processing can bail out if the event is filtered or rate limited,
-        //     since all items depend on this event.
-        if let Some(event_envelope) = message.envelope.split_by(Item::requires_event) {
-            self.current_active_events += 1;
-            context.notify(HandleEnvelope {
-                envelope: event_envelope,
-                project: message.project.clone(),
-                start_time: message.start_time,
-            });
+        if self.should_spool() {
+            log::trace!("spooling event");
+            let future = self
+                .spool_envelope(message.envelope, message.start_time)
+                .map(move |_, _, _| event_id);
+            return ActorResponse::future(future);
         }
 
-        self.current_active_events += 1;
-        context.notify(HandleEnvelope {
-            envelope: message.envelope,
-            project: message.project,
-            start_time: message.start_time,
-        });
+        if self.is_buffer_full() {
+            return ActorResponse::reply(Err(QueueEnvelopeError::TooManyEvents));
+        }
+
+        self.enqueue(
+            message.envelope,
+            message.project,
+            message.start_time,
+            context,
+        );
 
         // Actual event handling is performed asynchronously in a separate future. The lifetime of
         // that future will be tied to the EventManager's context. This allows to keep the Project
         // actor alive even if it is cleaned up in the ProjectManager.
 
         log::trace!("queued event");
-        Ok(event_id)
+        ActorResponse::ok(event_id)
     }
 }
 
@@ -1274,9 +2020,12 @@ impl Handler<HandleEnvelope> for EventManager {
 
         let upstream = self.upstream.clone();
         let processor = self.processor.clone();
+        let aggregator = self.aggregator.clone();
         let outcome_producer = self.outcome_producer.clone();
+        let client_outcome_producer = self.outcome_producer.clone();
         let captured_events = self.captured_events.clone();
         let capture = self.config.relay_mode() == RelayMode::Capture;
+        let spool_enabled = self.spool.is_some();
 
         #[cfg(feature = "processing")]
         let store_forwarder = self.store_forwarder.clone();
@@ -1298,6 +2047,10 @@ impl Handler<HandleEnvelope> for EventManager {
 
         let scoping = Rc::new(RefCell::new(envelope.meta().get_partial_scoping()));
 
+        // With a spool, a copy of the envelope is retained while it is sent to the upstream. If the
+        // upstream cannot be reached, the envelope is spooled instead of being dropped.
+        let unsent = Rc::new(RefCell::new(None));
+
         metric!(set(RelaySets::UniqueProjects) = project_id.value() as i64);
 
         let future = project
@@ -1320,6 +2073,15 @@ impl Handler<HandleEnvelope> for EventManager {
                     .and_then(|result| result.map_err(ProcessingError::ProjectFailed))
                     .map(|state| (envelope, state))
             }))
+            .and_then(|(envelope, project_state)| {
+                // Sample transactions based on the trace context in the envelope headers before
+                // spending time on parsing them in the processor.
+                let sampling_config = &project_state.config.sampling;
+                match utils::sample_envelope(sampling_config, &envelope) {
+                    SamplingResult::Keep => Ok((envelope, project_state)),
+                    SamplingResult::Drop(rule_id) => Err(ProcessingError::EventSampled(rule_id)),
+                }
+            })
             .and_then(move |(envelope, project_state)| {
                 processor
                     .send(ProcessEnvelope {
@@ -1339,12 +2101,31 @@ impl Handler<HandleEnvelope> for EventManager {
                     project.do_send(UpdateRateLimits(rate_limits.clone()));
                 }
 
+                // Custom metrics and client reports are removed from the envelope and handled
+                // separately. If the envelope contained nothing else, there is nothing left to send.
+                let has_extracted =
+                    processed.insert_metrics.is_some() || !processed.client_outcomes.is_empty();
+
+                if let Some(insert_metrics) = processed.insert_metrics {
+                    aggregator.do_send(insert_metrics);
+                }
+
+                for outcome in processed.client_outcomes {
+                    client_outcome_producer.do_send(outcome);
+                }
+
                 match processed.envelope {
-                    Some(envelope) => Ok(envelope),
+                    Some(envelope) => Ok(Some(envelope)),
+                    None if has_extracted => Ok(None),
                     None => Err(ProcessingError::RateLimited(rate_limits)),
                 }
             }))
-            .and_then(clone!(captured_events, scoping, |mut envelope| {
+            .and_then(clone!(captured_events, scoping, unsent, |envelope| {
+                let envelope = match envelope {
+                    Some(envelope) => envelope,
+                    None => return Box::new(Ok(()).into_future()) as ResponseFuture<_, _>,
+                };
+
                 #[cfg(feature = "processing")]
                 {
                     if let Some(store_forwarder) = store_forwarder {
@@ -1379,32 +2160,11 @@ impl Handler<HandleEnvelope> for EventManager {
                 }
 
                 log::trace!("sending event to sentry endpoint");
-                let request = SendRequest::post(format!("/api/{}/store/", project_id)).build(
-                    move |builder| {
-                        // Override the `sent_at` timestamp. Since the event went through basic
-                        // normalization, all timestamps have been corrected. We propagate the new
-                        // `sent_at` to allow the next Relay to double-check this timestamp and
-                        // potentially apply correction again. This is done as close to sending as
-                        // possible so that we avoid internal delays.
-                        envelope.set_sent_at(Utc::now());
-
-                        let meta = envelope.meta();
-
-                        if let Some(origin) = meta.origin() {
-                            builder.header("Origin", origin.to_string());
-                        }
-
-                        if let Some(user_agent) = meta.user_agent() {
-                            builder.header("User-Agent", user_agent);
-                        }
-
-                        builder
-                            .header("X-Sentry-Auth", meta.auth_header())
-                            .header("X-Forwarded-For", meta.forwarded_for())
-                            .header("Content-Type", envelope::CONTENT_TYPE)
-                            .body(envelope.to_vec().map_err(failure::Error::from)?)
-                    },
-                );
+                if spool_enabled {
+                    unsent.replace(Some(envelope.clone()));
+                }
+
+                let request = create_envelope_request(envelope);
 
                 let future = upstream
                     .send(request)
@@ -1424,8 +2184,27 @@ impl Handler<HandleEnvelope> for EventManager {
             }))
             .into_actor(self)
             .timeout(self.config.event_buffer_expiry(), ProcessingError::Timeout)
-            .map(|_, _, _| metric!(counter(RelayCounters::EnvelopeAccepted) += 1))
-            .map_err(move |error, slf, _| {
+            .map(clone!(unsent, |_, slf, _| {
+                if unsent.borrow_mut().take().is_some() {
+                    slf.upstream_recovered();
+                }
+                metric!(counter(RelayCounters::EnvelopeAccepted) += 1)
+            }))
+            .map_err(move |error, slf, ctx| {
+                if error.is_upstream_failure() {
+                    slf.upstream_failed();
+
+                    if let Some(envelope) = unsent.borrow_mut().take() {
+                        log::debug!("spooling envelope after failed send: {}", LogError(&error));
+                        slf.spool_envelope(envelope, start_time)
+                            .map_err(|error, _, _| {
+                                log::error!("failed to spool envelope: {}", LogError(&error))
+                            })
+                            .spawn(ctx);
+                        return;
+                    }
+                }
+
                 metric!(counter(RelayCounters::EnvelopeRejected) += 1);
 
                 // if we are in capture mode, we stash away the event instead of
@@ -1466,6 +2245,8 @@ impl Handler<HandleEnvelope> for EventManager {
                         outcome,
                         event_id,
                         remote_addr,
+                        category: None,
+                        quantity: None,
                     })
                 }
             })
@@ -1480,6 +2261,33 @@ impl Handler<HandleEnvelope> for EventManager {
     }
 }
 
+impl Handler<UpdateConfig> for EventManager {
+    type Result = ();
+
+    fn handle(&mut self, message: UpdateConfig, context: &mut Self::Context) -> Self::Result {
+        let UpdateConfig(config) = message;
+        context.set_mailbox_capacity(config.event_buffer_size() as usize);
+
+        // Start new processors with the updated config. The rate limiter, GeoIP database, and the
+        // number of worker threads cannot change at runtime and are retained. Processors of the
+        // old arbiter finish their queued envelopes and stop once their address is dropped.
+        #[cfg(feature = "processing")]
+        let processor = start_processor(
+            self.thread_count,
+            config.clone(),
+            self.rate_limiter.clone(),
+            self.geoip_lookup.clone(),
+        );
+
+        #[cfg(not(feature = "processing"))]
+        let processor =
+            start_processor(self.thread_count, config.clone(), self.rate_limiter.clone());
+
+        self.processor = processor;
+        self.config = config;
+    }
+}
+
 pub struct GetCapturedEvent {
     pub event_id: EventId,
 }
@@ -1502,6 +2310,12 @@ mod tests {
 
     use chrono::{DateTime, TimeZone, Utc};
 
+    use relay_quotas::{
+        DataCategories, ItemScoping, Quota, QuotaScope, RateLimit, ReasonCode, RetryAfter,
+    };
+
+    use crate::extractors::RequestMeta;
+
     fn create_breadcrumbs_item(breadcrumbs: &[(Option<DateTime<Utc>>, &str)]) -> Item {
         let mut data = Vec::new();
 
@@ -1643,4 +2457,99 @@ mod tests {
         // regression test to ensure we don't fail parsing an empty file
         result.expect("event_from_attachments");
     }
+
+    /// A rate limiter that rejects all items of the given data category.
+    struct RejectCategory(DataCategory);
+
+    impl RateLimiter for RejectCategory {
+        fn is_rate_limited(
+            &self,
+            quotas: &[Quota],
+            item_scoping: ItemScoping<'_>,
+            _quantity: usize,
+        ) -> Result<RateLimits, RateLimitingError> {
+            let mut rate_limits = RateLimits::new();
+
+            if item_scoping.category == self.0 {
+                for quota in quotas {
+                    let retry_after = RetryAfter::from_secs(60);
+                    rate_limits.add(RateLimit::from_quota(quota, &*item_scoping, retry_after));
+                }
+            }
+
+            Ok(rate_limits)
+        }
+    }
+
+    fn create_processor(rate_limiter: Arc<dyn RateLimiter>) -> EventProcessor {
+        let config = Arc::new(Config::default());
+
+        #[cfg(feature = "processing")]
+        {
+            EventProcessor::new(config, Some(rate_limiter), None)
+        }
+
+        #[cfg(not(feature = "processing"))]
+        {
+            EventProcessor::new(config, Some(rate_limiter))
+        }
+    }
+
+    fn create_quota_message() -> ProcessEnvelope {
+        let dsn = "https://e12d836b15bb49d7bbf99e64295d995b:@sentry.io/42"
+            .parse()
+            .unwrap();
+
+        let mut envelope = Envelope::from_request(Some(EventId::new()), RequestMeta::new(dsn));
+        let mut item = Item::new(ItemType::Event);
+        item.set_payload(ContentType::Json, r#"{"message":"hello"}"#);
+        envelope.add_item(item);
+
+        let mut project_state = ProjectState::allowed();
+        project_state.config.quotas = vec![Quota {
+            id: Some("test".to_owned()),
+            categories: DataCategories::new(),
+            scope: QuotaScope::Project,
+            scope_id: None,
+            limit: Some(1),
+            window: Some(3600),
+            reason_code: Some(ReasonCode::new("get_lost")),
+        }];
+
+        ProcessEnvelope {
+            envelope,
+            project_state: Arc::new(project_state),
+            start_time: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_enforce_quotas_rejects_event() {
+        let processor = create_processor(Arc::new(RejectCategory(DataCategory::Error)));
+        let response = processor.process(create_quota_message()).unwrap();
+
+        assert!(response.envelope.is_none());
+        assert!(response.rate_limits.is_limited());
+    }
+
+    #[test]
+    fn test_enforce_quotas_other_category() {
+        let processor = create_processor(Arc::new(RejectCategory(DataCategory::Transaction)));
+        let response = processor.process(create_quota_message()).unwrap();
+
+        assert!(response.envelope.is_some());
+        assert!(response.rate_limits.is_ok());
+    }
+
+    #[test]
+    fn test_enforce_quotas_memory() {
+        let processor = create_processor(Arc::new(MemoryRateLimiter::new()));
+
+        let response = processor.process(create_quota_message()).unwrap();
+        assert!(response.envelope.is_some());
+
+        let response = processor.process(create_quota_message()).unwrap();
+        assert!(response.envelope.is_none());
+        assert!(response.rate_limits.is_limited());
+    }
 }
@@ -8,6 +8,7 @@ use futures::prelude::*;
 use relay_config::{Config, RelayMode};
 
 use crate::actors::controller::{Controller, Shutdown};
+use crate::actors::reload::UpdateConfig;
 use crate::actors::upstream::{IsAuthenticated, UpstreamRelay};
 
 pub struct Healthcheck {
@@ -43,6 +44,14 @@ impl Handler<Shutdown> for Healthcheck {
     }
 }
 
+impl Handler<UpdateConfig> for Healthcheck {
+    type Result = ();
+
+    fn handle(&mut self, message: UpdateConfig, _context: &mut Self::Context) -> Self::Result {
+        self.config = message.0;
+    }
+}
+
 pub enum IsHealthy {
     /// Check if the Relay is alive at all.
     Liveness,
@@ -19,6 +19,8 @@
 //!  - [`UpstreamRelay`]: Abstraction for communication with the upstream (either another Relay or
 //!    Sentry). It manages an internal client connector to throttle requests and ensures this relay
 //!    is authenticated before sending queries (e.g. project config or public keys).
+//!  - [`ConfigReloader`]: Reloads the configuration on `SIGHUP` and sends it to all actors that
+//!    can apply changes at runtime.
 //!
 //! ### Example
 //!
@@ -38,7 +40,9 @@
 //! [`EventManager`]: controller/struct.EventManager.html
 //! [`EventProcessor`]: controller/struct.EventProcessor.html
 //! [`UpstreamRelay`]: controller/struct.UpstreamRelay.html
+//! [`ConfigReloader`]: reload/struct.ConfigReloader.html
 
+pub mod aggregator;
 pub mod connector;
 pub mod controller;
 pub mod events;
@@ -50,6 +54,7 @@ pub mod project_keys;
 pub mod project_local;
 pub mod project_upstream;
 pub mod relays;
+pub mod reload;
 pub mod server;
 pub mod upstream;
 
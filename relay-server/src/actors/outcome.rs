@@ -20,10 +20,12 @@ use relay_common::{LogError, ProjectId};
 use relay_config::Config;
 use relay_filter::FilterStatKey;
 use relay_general::protocol::EventId;
-use relay_quotas::{ReasonCode, Scoping};
+use relay_quotas::{DataCategory, ReasonCode, Scoping};
 
+use crate::actors::reload::UpdateConfig;
 use crate::actors::upstream::SendQuery;
 use crate::actors::upstream::{UpstreamQuery, UpstreamRelay};
+use crate::utils::RuleId;
 use crate::ServerError;
 
 // Choose the outcome module implementation (either processing or non-processing).
@@ -43,6 +45,9 @@ pub struct SendOutcomes {
     pub outcomes: Vec<TrackRawOutcome>,
 }
 
+/// The outcome source of data that was discarded by SDKs and reported in client reports.
+const CLIENT_OUTCOME_SOURCE: &str = "client";
+
 impl UpstreamQuery for SendOutcomes {
     type Response = SendOutcomesResponse;
 
@@ -76,6 +81,10 @@ pub struct TrackOutcome {
     pub event_id: Option<EventId>,
     /// The client ip address.
     pub remote_addr: Option<IpAddr>,
+    /// The category of the affected data, if known.
+    pub category: Option<DataCategory>,
+    /// The number of affected items or bytes, if the outcome applies to more than one event.
+    pub quantity: Option<u32>,
 }
 
 impl Message for TrackOutcome {
@@ -96,6 +105,9 @@ pub enum Outcome {
     #[cfg_attr(not(feature = "processing"), allow(dead_code))]
     Filtered(FilterStatKey),
 
+    /// The event has been dropped by a sampling rule.
+    FilteredSampling(RuleId),
+
     /// The event has been rate limited.
     RateLimited(Option<ReasonCode>),
 
@@ -105,26 +117,47 @@ pub enum Outcome {
     /// Reserved but unused in Sentry.
     #[allow(dead_code)]
     Abuse,
+
+    /// The data has been discarded by the SDK before it was sent, as reported in a client report.
+    ClientDiscard(ClientDiscardReason),
 }
 
 impl Outcome {
     fn to_outcome_id(&self) -> u8 {
         match self {
             Outcome::Accepted => 0,
-            Outcome::Filtered(_) => 1,
+            Outcome::Filtered(_) | Outcome::FilteredSampling(_) => 1,
             Outcome::RateLimited(_) => 2,
             Outcome::Invalid(_) => 3,
             Outcome::Abuse => 4,
+            Outcome::ClientDiscard(_) => 5,
+        }
+    }
+
+    /// Returns the name of this outcome, as used in logs and metric tags.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Accepted => "accepted",
+            Outcome::Filtered(_) | Outcome::FilteredSampling(_) => "filtered",
+            Outcome::RateLimited(_) => "rate_limited",
+            Outcome::Invalid(_) => "invalid",
+            Outcome::Abuse => "abuse",
+            Outcome::ClientDiscard(_) => "client_discard",
         }
     }
 
-    fn to_reason(&self) -> Option<&str> {
+    /// Returns the reason code of this outcome, if any.
+    pub fn to_reason(&self) -> Option<Cow<'_, str>> {
         match self {
             Outcome::Accepted => None,
-            Outcome::Invalid(discard_reason) => Some(discard_reason.name()),
-            Outcome::Filtered(filter_key) => Some(filter_key.name()),
-            Outcome::RateLimited(code_opt) => code_opt.as_ref().map(|code| code.as_str()),
+            Outcome::Invalid(discard_reason) => Some(Cow::Borrowed(discard_reason.name())),
+            Outcome::Filtered(filter_key) => Some(Cow::Borrowed(filter_key.name())),
+            Outcome::FilteredSampling(rule_id) => Some(Cow::Owned(format!("Sampled:{}", rule_id))),
+            Outcome::RateLimited(code_opt) => {
+                code_opt.as_ref().map(|code| Cow::Borrowed(code.as_str()))
+            }
             Outcome::Abuse => None,
+            Outcome::ClientDiscard(reason) => Some(Cow::Borrowed(reason.name())),
         }
     }
 }
@@ -199,6 +232,9 @@ pub enum DiscardReason {
     /// [Relay] Parsing the event msgpack payload failed due to a syntax error.
     InvalidMsgpack,
 
+    /// [Relay] Decoding a protobuf payload, such as OpenTelemetry traces, failed.
+    InvalidProtobuf,
+
     /// [Relay] Parsing a multipart form-data request failed.
     InvalidMultipart,
 
@@ -218,6 +254,9 @@ pub enum DiscardReason {
     /// [Relay] An event envelope was submitted but no payload could be extracted.
     NoEventPayload,
 
+    /// [Relay] The envelope was buffered in the spool for longer than `spool.max_age`.
+    SpoolExpired,
+
     /// [All] An error in Relay caused event ingestion to fail. This is the catch-all and usually
     /// indicates bugs in Relay, rather than an expected failure.
     Internal,
@@ -251,16 +290,64 @@ impl DiscardReason {
             DiscardReason::InvalidJson => "invalid_json",
             DiscardReason::InvalidMultipart => "invalid_multipart",
             DiscardReason::InvalidMsgpack => "invalid_msgpack",
+            DiscardReason::InvalidProtobuf => "invalid_protobuf",
             DiscardReason::InvalidTransaction => "invalid_transaction",
             DiscardReason::InvalidEnvelope => "invalid_envelope",
             DiscardReason::ProjectState => "project_state",
             DiscardReason::DuplicateItem => "duplicate_item",
             DiscardReason::NoEventPayload => "no_event_payload",
+            DiscardReason::SpoolExpired => "spool_expired",
             DiscardReason::Internal => "internal",
         }
     }
 }
 
+/// Reason for data discarded by an SDK, as reported in client reports.
+///
+/// Used in `Outcome::ClientDiscard`. Client reports with other reasons are not converted into
+/// outcomes, since their reasons are arbitrary strings sent by clients.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ClientDiscardReason {
+    /// The SDK dropped the data because its transport queue was full.
+    QueueOverflow,
+
+    /// The SDK dropped the data because it was still backing off from a rate limit.
+    RatelimitBackoff,
+
+    /// The SDK failed to send the data due to a network error.
+    NetworkError,
+
+    /// The SDK dropped the data due to its configured sample rate.
+    SampleRate,
+
+    /// The data was dropped in the `before_send` callback of the SDK.
+    BeforeSend,
+}
+
+impl ClientDiscardReason {
+    /// Parses a reason from a client report, returning `None` for unknown reasons.
+    pub fn parse(reason: &str) -> Option<Self> {
+        Some(match reason {
+            "queue_overflow" => ClientDiscardReason::QueueOverflow,
+            "ratelimit_backoff" => ClientDiscardReason::RatelimitBackoff,
+            "network_error" => ClientDiscardReason::NetworkError,
+            "sample_rate" => ClientDiscardReason::SampleRate,
+            "before_send" => ClientDiscardReason::BeforeSend,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClientDiscardReason::QueueOverflow => "queue_overflow",
+            ClientDiscardReason::RatelimitBackoff => "ratelimit_backoff",
+            ClientDiscardReason::NetworkError => "network_error",
+            ClientDiscardReason::SampleRate => "sample_rate",
+            ClientDiscardReason::BeforeSend => "before_send",
+        }
+    }
+}
+
 /// The outcome message is serialized as json and placed on the Kafka topic or in
 /// the http using TrackRawOutcome
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -289,14 +376,17 @@ pub struct TrackRawOutcome {
     /// The source of the outcome (which Relay sent it)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     source: Option<String>,
+    /// The category of the affected data.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<DataCategory>,
+    /// The number of affected items or bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quantity: Option<u32>,
 }
 
 impl TrackRawOutcome {
     fn from_outcome(msg: TrackOutcome, config: &Config) -> Self {
-        let reason = match msg.outcome.to_reason() {
-            None => None,
-            Some(reason) => Some(reason.to_string()),
-        };
+        let reason = msg.outcome.to_reason().map(Cow::into_owned);
 
         let date_time = relay_common::instant_to_date_time(msg.timestamp);
 
@@ -310,8 +400,12 @@ impl TrackRawOutcome {
         };
 
         // since TrackOutcome objects come only from this Relay (and not any downstream
-        // Relays), set the source to whatever our current outcome source is.
-        let source = config.outcome_source().map(str::to_owned);
+        // Relays), set the source to whatever our current outcome source is. Discards reported by
+        // clients get their own source, so they can be told apart from Relay's own outcomes.
+        let source = match msg.outcome {
+            Outcome::ClientDiscard(_) => Some(CLIENT_OUTCOME_SOURCE.to_owned()),
+            _ => config.outcome_source().map(str::to_owned),
+        };
 
         TrackRawOutcome {
             timestamp,
@@ -323,6 +417,8 @@ impl TrackRawOutcome {
             event_id: msg.event_id,
             remote_addr: msg.remote_addr.map(|addr| addr.to_string()),
             source,
+            category: msg.category,
+            quantity: msg.quantity,
         }
     }
 }
@@ -365,6 +461,7 @@ mod processing {
                 2 => "rate_limited",
                 3 => "invalid",
                 4 => "abuse",
+                5 => "client_discard",
                 _ => "<unknown>",
             }
         }
@@ -475,6 +572,21 @@ mod processing {
         }
     }
 
+    impl Handler<UpdateConfig> for ProcessingOutcomeProducer {
+        type Result = ();
+
+        fn handle(&mut self, message: UpdateConfig, context: &mut Self::Context) -> Self::Result {
+            let UpdateConfig(config) = message;
+            context.set_mailbox_capacity(config.event_buffer_size() as usize);
+
+            if let Some(ref http_producer) = self.http_producer {
+                http_producer.do_send(UpdateConfig(config.clone()));
+            }
+
+            self.config = config;
+        }
+    }
+
     impl Handler<TrackOutcome> for ProcessingOutcomeProducer {
         type Result = Result<(), OutcomeError>;
 
@@ -593,3 +705,63 @@ impl Handler<TrackOutcome> for HttpOutcomeProducer {
         self.handle(TrackRawOutcome::from_outcome(message, &self.config), _ctx)
     }
 }
+
+impl Handler<UpdateConfig> for HttpOutcomeProducer {
+    type Result = ();
+
+    fn handle(&mut self, message: UpdateConfig, _context: &mut Self::Context) -> Self::Result {
+        // The new batch interval applies to the next batch, a pending flush remains scheduled.
+        self.config = message.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_discard_reason() {
+        for name in &[
+            "queue_overflow",
+            "ratelimit_backoff",
+            "network_error",
+            "sample_rate",
+            "before_send",
+        ] {
+            let reason = ClientDiscardReason::parse(name).unwrap();
+            assert_eq!(reason.name(), *name);
+        }
+
+        assert_eq!(ClientDiscardReason::parse(""), None);
+        assert_eq!(ClientDiscardReason::parse("Queue_Overflow"), None);
+        assert_eq!(ClientDiscardReason::parse(&"x".repeat(1000)), None);
+    }
+
+    #[test]
+    fn test_client_discard_outcome() {
+        let outcome = TrackOutcome {
+            timestamp: Instant::now(),
+            scoping: Scoping {
+                organization_id: 0,
+                project_id: ProjectId::new(42),
+                public_key: "e12d836b15bb49d7bbf99e64295d995b".to_owned(),
+                key_id: Some(17),
+            },
+            outcome: Outcome::ClientDiscard(ClientDiscardReason::QueueOverflow),
+            event_id: None,
+            remote_addr: None,
+            category: Some(DataCategory::Transaction),
+            quantity: Some(3),
+        };
+
+        let raw = TrackRawOutcome::from_outcome(outcome, &Config::default());
+        assert_eq!(raw.outcome, 5);
+        assert_eq!(raw.reason.as_deref(), Some("queue_overflow"));
+        assert_eq!(raw.source.as_deref(), Some("client"));
+        assert_eq!(raw.org_id, None);
+        assert_eq!(raw.project_id, ProjectId::new(42));
+        assert_eq!(raw.key_id, Some(17));
+        assert_eq!(raw.category, Some(DataCategory::Transaction));
+        assert_eq!(raw.quantity, Some(3));
+    }
+}
@@ -9,7 +9,7 @@ use serde_json::Value;
 use url::Url;
 
 use relay_auth::PublicKey;
-use relay_common::{metric, ProjectId};
+use relay_common::{metric, LogError, ProjectId};
 use relay_config::{Config, RelayMode};
 use relay_filter::{matches_any_origin, FiltersConfig};
 use relay_general::pii::{DataScrubbingConfig, PiiConfig};
@@ -20,10 +20,11 @@ use crate::actors::project_cache::{FetchProjectState, ProjectCache, ProjectError
 use crate::envelope::Envelope;
 use crate::extractors::RequestMeta;
 use crate::metrics::RelayCounters;
-use crate::utils::{ActorResponse, EnvelopeLimiter, Response};
+use crate::utils::{ActorResponse, EnvelopeLimiter, Response, SamplingConfig};
 
 /// The current status of a project state. Return value of `ProjectState::outdated`.
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Outdated {
     /// The project state is perfectly up to date.
     Updated,
@@ -63,6 +64,11 @@ pub struct ProjectConfig {
     pub trusted_relays: Vec<PublicKey>,
     /// Configuration for PII stripping.
     pub pii_config: Option<PiiConfig>,
+    /// Configuration for PII stripping that is applied in audit mode.
+    ///
+    /// Matches of this config are reported in metrics, but the event is not modified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pii_audit_config: Option<PiiConfig>,
     /// The grouping configuration.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub grouping_config: Option<Value>,
@@ -77,6 +83,9 @@ pub struct ProjectConfig {
     pub event_retention: Option<u16>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub quotas: Vec<Quota>,
+    /// Configuration for sampling transactions.
+    #[serde(skip_serializing_if = "SamplingConfig::is_empty")]
+    pub sampling: SamplingConfig,
 }
 
 impl Default for ProjectConfig {
@@ -85,11 +94,28 @@ impl Default for ProjectConfig {
             allowed_domains: vec!["*".to_string()],
             trusted_relays: vec![],
             pii_config: None,
+            pii_audit_config: None,
             grouping_config: None,
             filter_settings: FiltersConfig::default(),
             datascrubbing_settings: DataScrubbingConfig::default(),
             event_retention: None,
             quotas: Vec::new(),
+            sampling: SamplingConfig::default(),
+        }
+    }
+}
+
+impl ProjectConfig {
+    /// Logs errors of PII rules that cannot be executed. These rules are skipped in processing.
+    fn log_pii_config_errors(&self, project_id: ProjectId) {
+        for config in self.pii_config.iter().chain(&self.pii_audit_config) {
+            if let Err(error) = config.validate() {
+                log::error!(
+                    "invalid PII config in project {}: {}",
+                    project_id,
+                    LogError(&error)
+                );
+            }
         }
     }
 }
@@ -102,6 +128,8 @@ pub struct LimitedProjectConfig {
     pub trusted_relays: Vec<PublicKey>,
     pub pii_config: Option<PiiConfig>,
     pub datascrubbing_settings: DataScrubbingConfig,
+    #[serde(skip_serializing_if = "SamplingConfig::is_empty")]
+    pub sampling: SamplingConfig,
 }
 
 /// The project state is a cached server state of a project.
@@ -503,6 +531,7 @@ impl Project {
 
                 if let Some(ref state) = slf.state {
                     log::debug!("project {} state updated", id);
+                    state.config.log_pii_config_errors(id);
                     sender.send(state.clone()).ok();
                 }
             })
@@ -584,6 +613,27 @@ impl Handler<GetProjectState> for Project {
     }
 }
 
+/// Returns the cached project state without fetching it.
+///
+/// Returns `None` if the state has not been fetched yet.
+pub struct GetCachedProjectState;
+
+impl Message for GetCachedProjectState {
+    type Result = Option<Arc<ProjectState>>;
+}
+
+impl Handler<GetCachedProjectState> for Project {
+    type Result = Option<Arc<ProjectState>>;
+
+    fn handle(
+        &mut self,
+        _message: GetCachedProjectState,
+        _context: &mut Context<Self>,
+    ) -> Self::Result {
+        self.state.clone()
+    }
+}
+
 /// Checks the envelope against project configuration and rate limits.
 ///
 /// When `fetched`, then the project state is ensured to be up to date. When `cached`, an outdated
@@ -673,3 +723,25 @@ impl Handler<UpdateRateLimits> for Project {
         self.rate_limits.merge(rate_limits);
     }
 }
+
+/// Removes all cached rate limits of the project.
+///
+/// Responds with the number of active rate limits that were removed.
+pub struct ClearRateLimits;
+
+impl Message for ClearRateLimits {
+    type Result = usize;
+}
+
+impl Handler<ClearRateLimits> for Project {
+    type Result = usize;
+
+    fn handle(&mut self, _message: ClearRateLimits, _context: &mut Self::Context) -> Self::Result {
+        self.rate_limits.clean_expired();
+        let cleared = self.rate_limits.iter().count();
+
+        log::debug!("project {} rate limits cleared", self.id);
+        self.rate_limits = RateLimits::new();
+        cleared
+    }
+}
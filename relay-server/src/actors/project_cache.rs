@@ -15,6 +15,7 @@ use relay_redis::RedisPool;
 use crate::actors::project::{Project, ProjectState};
 use crate::actors::project_local::LocalProjectSource;
 use crate::actors::project_upstream::UpstreamProjectSource;
+use crate::actors::reload::UpdateConfig;
 use crate::actors::upstream::UpstreamRelay;
 use crate::metrics::{RelayCounters, RelayHistograms, RelayTimers};
 use crate::utils::Response;
@@ -113,6 +114,82 @@ impl Actor for ProjectCache {
     }
 }
 
+impl Handler<UpdateConfig> for ProjectCache {
+    type Result = ();
+
+    fn handle(&mut self, message: UpdateConfig, context: &mut Self::Context) -> Self::Result {
+        let UpdateConfig(config) = message;
+        context.set_mailbox_capacity(config.event_buffer_size() as usize);
+
+        // Existing projects retain the previous config until they are evicted.
+        self.local_source.do_send(UpdateConfig(config.clone()));
+        self.upstream_source.do_send(UpdateConfig(config.clone()));
+        self.config = config;
+    }
+}
+
+/// Returns the addresses of all projects in the cache.
+pub struct ListProjects;
+
+impl Message for ListProjects {
+    type Result = Vec<(ProjectId, Addr<Project>)>;
+}
+
+impl Handler<ListProjects> for ProjectCache {
+    type Result = Vec<(ProjectId, Addr<Project>)>;
+
+    fn handle(&mut self, _message: ListProjects, _context: &mut Self::Context) -> Self::Result {
+        self.projects
+            .iter()
+            .map(|(id, entry)| (*id, entry.project.clone()))
+            .collect()
+    }
+}
+
+/// Returns a project from the cache without creating it.
+pub struct GetCachedProject {
+    pub id: ProjectId,
+}
+
+impl Message for GetCachedProject {
+    type Result = Option<Addr<Project>>;
+}
+
+impl Handler<GetCachedProject> for ProjectCache {
+    type Result = Option<Addr<Project>>;
+
+    fn handle(&mut self, message: GetCachedProject, _context: &mut Self::Context) -> Self::Result {
+        self.projects
+            .get(&message.id)
+            .map(|entry| entry.project.clone())
+    }
+}
+
+/// Removes projects from the cache, so that their states are fetched again on next access.
+///
+/// If `id` is `None`, all projects are evicted. Returns the number of evicted projects.
+pub struct EvictProjects {
+    pub id: Option<ProjectId>,
+}
+
+impl Message for EvictProjects {
+    type Result = usize;
+}
+
+impl Handler<EvictProjects> for ProjectCache {
+    type Result = usize;
+
+    fn handle(&mut self, message: EvictProjects, _context: &mut Self::Context) -> Self::Result {
+        let evicted = match message.id {
+            Some(id) => self.projects.remove(&id).map_or(0, |_| 1),
+            None => self.projects.drain().count(),
+        };
+
+        log::debug!("evicted {} projects from cache", evicted);
+        evicted
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetProject {
     pub id: ProjectId,
@@ -2,22 +2,59 @@ use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
 use std::io;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::{Duration, SystemTime};
 
 use actix::prelude::*;
+use failure::Fail;
 use futures::{sync::oneshot, Future};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use serde::de::DeserializeOwned;
 
 use relay_common::{LogError, ProjectId};
 use relay_config::Config;
 
 use crate::actors::project::ProjectState;
 use crate::actors::project_cache::FetchOptionalProjectState;
+use crate::actors::reload::UpdateConfig;
+
+/// Delay for collecting file system events before project configs are reloaded.
+const WATCH_DEBOUNCE_DELAY: Duration = Duration::from_millis(500);
+
+/// Interval at which the watcher thread checks whether it has been stopped.
+const WATCH_STOP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// File in a project directory that contains the project state.
+const PROJECT_CONFIG_FILE: &str = "config.yml";
+
+/// File in a project directory that contains the public keys of the project.
+const PROJECT_KEYS_FILE: &str = "keys.yml";
+
+/// File in a project directory that contains the PII config of the project.
+const PROJECT_PII_FILE: &str = "pii.yml";
+
+#[derive(Debug, Fail)]
+enum LoadStateError {
+    #[fail(display = "could not read {}", _0)]
+    Io(String, #[cause] io::Error),
+
+    #[fail(display = "could not parse {}", _0)]
+    Json(String, #[cause] serde_json::Error),
+
+    #[fail(display = "could not parse {}", _0)]
+    Yaml(String, #[cause] serde_yaml::Error),
+}
 
 pub struct LocalProjectSource {
     config: Arc<Config>,
     local_states: HashMap<ProjectId, Arc<ProjectState>>,
+    /// Incremented whenever a new watcher is started to discard updates of previous watchers.
+    generation: usize,
+    /// Signals the current watcher thread to stop.
+    watch_stop: Arc<AtomicBool>,
 }
 
 impl LocalProjectSource {
@@ -25,8 +62,29 @@ impl LocalProjectSource {
         LocalProjectSource {
             config,
             local_states: HashMap::new(),
+            generation: 0,
+            watch_stop: Arc::new(AtomicBool::new(false)),
         }
     }
+
+    /// Stops the current watcher and starts watching the project configs directory again.
+    fn start_watching(&mut self, context: &mut Context<Self>) {
+        self.watch_stop.store(true, Ordering::Relaxed);
+        self.watch_stop = Arc::new(AtomicBool::new(false));
+        self.generation += 1;
+
+        // `watch_local_states` returns a future that resolves as soon as the first read is done.
+        watch_local_states(
+            context.address(),
+            self.config.clone(),
+            self.generation,
+            self.watch_stop.clone(),
+        )
+        .into_actor(self)
+        // Block entire actor on first local state read, such that we don't e.g. drop events on
+        // startup
+        .wait(context);
+    }
 }
 
 impl Actor for LocalProjectSource {
@@ -36,19 +94,34 @@ impl Actor for LocalProjectSource {
         log::info!("project local cache started");
 
         // Start the background thread that reads the local states from disk.
-        // `poll_local_states` returns a future that resolves as soon as the first read is done.
-        poll_local_states(context.address(), self.config.clone())
-            .into_actor(self)
-            // Block entire actor on first local state read, such that we don't e.g. drop events on
-            // startup
-            .wait(context);
+        self.start_watching(context);
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.watch_stop.store(true, Ordering::Relaxed);
         log::info!("project local cache stopped");
     }
 }
 
+impl Handler<UpdateConfig> for LocalProjectSource {
+    type Result = ();
+
+    fn handle(&mut self, message: UpdateConfig, context: &mut Self::Context) -> Self::Result {
+        let UpdateConfig(config) = message;
+        let changed = config.project_configs_path() != self.config.project_configs_path()
+            || config.local_cache_interval() != self.config.local_cache_interval();
+        self.config = config;
+
+        if changed {
+            log::info!(
+                "reloading static project configs from {:?}",
+                self.config.project_configs_path()
+            );
+            self.start_watching(context);
+        }
+    }
+}
+
 impl Handler<FetchOptionalProjectState> for LocalProjectSource {
     type Result = Option<Arc<ProjectState>>;
 
@@ -61,8 +134,14 @@ impl Handler<FetchOptionalProjectState> for LocalProjectSource {
     }
 }
 
+/// Updates of local project states.
+///
+/// Each entry replaces the state of a single project. `None` removes the project state, for
+/// instance when its file has been deleted. The `initial` update of a watcher replaces all states.
 struct UpdateLocalStates {
-    states: HashMap<ProjectId, Arc<ProjectState>>,
+    generation: usize,
+    initial: bool,
+    states: HashMap<ProjectId, Option<Arc<ProjectState>>>,
 }
 
 impl Message for UpdateLocalStates {
@@ -73,84 +152,653 @@ impl Handler<UpdateLocalStates> for LocalProjectSource {
     type Result = ();
 
     fn handle(&mut self, message: UpdateLocalStates, _context: &mut Context<Self>) -> Self::Result {
-        self.local_states = message.states;
-    }
-}
+        // Updates from watchers of a previous config are outdated.
+        if message.generation != self.generation {
+            return;
+        }
 
-fn load_local_states(projects_path: &Path) -> io::Result<HashMap<ProjectId, Arc<ProjectState>>> {
-    let mut states = HashMap::new();
+        if message.initial {
+            self.local_states.clear();
+        }
 
-    let directory = match fs::read_dir(projects_path) {
-        Ok(directory) => directory,
-        Err(error) => {
-            return match error.kind() {
-                io::ErrorKind::NotFound => Ok(states),
-                _ => Err(error),
+        for (id, state) in message.states {
+            match state {
+                Some(state) => self.local_states.insert(id, state),
+                None => self.local_states.remove(&id),
             };
         }
+    }
+}
+
+/// Returns the project id for a project config file or directory.
+///
+/// Project configs are either stored in a single file `<project_id>.json` or `<project_id>.yml`,
+/// or in a directory `<project_id>/`. Returns `None` if the path is not named like a project
+/// config.
+fn project_id_for_path(path: &Path) -> Option<ProjectId> {
+    let name = match path.extension().and_then(OsStr::to_str) {
+        Some("json") | Some("yml") | Some("yaml") => path.file_stem()?,
+        Some(_) => return None,
+        None => path.file_name()?,
+    };
+
+    name.to_str()?.parse().ok()
+}
+
+/// Returns the precedence of a project config file or directory, lower values take precedence.
+///
+/// If there are multiple configs for the same project, only the one with the highest precedence
+/// is used: a directory, then a `.json` file, then a `.yml` file, and finally a `.yaml` file.
+/// Configs of the same kind, such as `42.json` and `042.json`, are ordered by their path.
+fn source_precedence(path: &Path) -> (u8, &Path) {
+    let rank = match path.extension().and_then(OsStr::to_str) {
+        None => 0,
+        Some("json") => 1,
+        Some("yml") => 2,
+        Some(_) => 3,
+    };
+
+    (rank, path)
+}
+
+/// Parses a JSON or YAML file, depending on its extension.
+fn load_file<T>(path: &Path) -> Result<T, LoadStateError>
+where
+    T: DeserializeOwned,
+{
+    let name = path.display().to_string();
+    let file = fs::File::open(path).map_err(|e| LoadStateError::Io(name.clone(), e))?;
+    let reader = io::BufReader::new(file);
+
+    if path.extension().map_or(false, |ext| ext == "json") {
+        serde_json::from_reader(reader).map_err(|e| LoadStateError::Json(name, e))
+    } else {
+        serde_yaml::from_reader(reader).map_err(|e| LoadStateError::Yaml(name, e))
+    }
+}
+
+/// Parses an optional file in a project directory.
+fn load_optional_file<T>(path: &Path) -> Result<Option<T>, LoadStateError>
+where
+    T: DeserializeOwned,
+{
+    if path.exists() {
+        load_file(path).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Loads a project directory and merges its files into a single project state.
+///
+/// `config.yml` is required and has the same structure as a project config file. The optional
+/// `keys.yml` and `pii.yml` replace the public keys and PII config of the project, respectively.
+fn load_project_dir(path: &Path) -> Result<ProjectState, LoadStateError> {
+    let mut state: ProjectState = load_file(&path.join(PROJECT_CONFIG_FILE))?;
+
+    if let Some(public_keys) = load_optional_file(&path.join(PROJECT_KEYS_FILE))? {
+        state.public_keys = public_keys;
+    }
+
+    if let Some(pii_config) = load_optional_file(&path.join(PROJECT_PII_FILE))? {
+        state.config.pii_config = Some(pii_config);
+    }
+
+    Ok(state)
+}
+
+fn load_local_state(path: &Path) -> Result<ProjectState, LoadStateError> {
+    let state = if path.is_dir() {
+        load_project_dir(path)?
+    } else {
+        load_file(path)?
     };
 
-    // only printed when directory even exists.
-    log::debug!("Loading local states from directory {:?}", projects_path);
+    Ok(ProjectState::sanitize(state))
+}
+
+/// Returns the last modification time of a project config file or directory.
+///
+/// For directories, this is the latest modification of the directory itself and all project files
+/// within it.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    if !path.is_dir() {
+        return modified;
+    }
+
+    [PROJECT_CONFIG_FILE, PROJECT_KEYS_FILE, PROJECT_PII_FILE]
+        .iter()
+        .filter_map(|name| {
+            fs::metadata(path.join(name))
+                .and_then(|m| m.modified())
+                .ok()
+        })
+        .chain(modified)
+        .max()
+}
 
-    for entry in directory {
-        let entry = entry?;
-        let path = entry.path();
+/// Tracks project configs in a directory and loads them incrementally.
+///
+/// Only project configs that have been modified since they were last loaded are read. If a config
+/// cannot be loaded, the error is logged and the previous state of the project remains in place.
+/// If a project has multiple configs, only the one with the highest precedence is used and an
+/// error is logged, see [`source_precedence`].
+///
+/// [`source_precedence`]: fn.source_precedence.html
+struct LocalStates {
+    path: PathBuf,
+    /// The canonical form of `path`, which is used in file system notifications.
+    canonical_path: PathBuf,
+    sources: HashMap<PathBuf, Option<SystemTime>>,
+}
 
-        if !entry.metadata()?.is_file() {
-            log::warn!("skipping {:?}, not a file", path);
-            continue;
+impl LocalStates {
+    fn new(path: PathBuf) -> Self {
+        LocalStates {
+            canonical_path: fs::canonicalize(&path).unwrap_or_else(|_| path.clone()),
+            path,
+            sources: HashMap::new(),
         }
+    }
 
-        if path.extension().map(|x| x != "json").unwrap_or(true) {
-            log::warn!("skipping {:?}, file extension must be .json", path);
-            continue;
+    /// Returns the project config file or directory that contains the given path.
+    ///
+    /// The path may be relative to the canonical form of the directory, in which case the returned
+    /// path is still relative to the configured directory.
+    fn source_for_path(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path
+            .strip_prefix(&self.canonical_path)
+            .or_else(|_| path.strip_prefix(&self.path))
+            .ok()?;
+
+        let mut components = relative.components();
+        let name = components.next()?;
+        if components.count() > 1 {
+            return None;
         }
 
-        let id = match path
-            .file_stem()
-            .and_then(OsStr::to_str)
-            .and_then(|stem| stem.parse().ok())
-        {
-            Some(id) => id,
-            None => {
-                log::warn!("skipping {:?}, filename is not a valid project id", path);
+        Some(self.path.join(name))
+    }
+
+    /// Returns the config with the highest precedence for the given project, other than `path`.
+    fn other_source(&self, id: ProjectId, path: &Path) -> Option<PathBuf> {
+        self.sources
+            .keys()
+            .filter(|source| *source != path && project_id_for_path(source) == Some(id))
+            .min_by(|a, b| source_precedence(a).cmp(&source_precedence(b)))
+            .cloned()
+    }
+
+    /// Scans the entire directory and returns updates for all changed and removed projects.
+    fn scan(&mut self) -> HashMap<ProjectId, Option<Arc<ProjectState>>> {
+        let mut updates = HashMap::new();
+
+        let directory = match fs::read_dir(&self.path) {
+            Ok(directory) => Some(directory),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => {
+                log::error!(
+                    "failed to read static project configs: {}",
+                    LogError(&error)
+                );
+                return updates;
+            }
+        };
+
+        let mut seen = Vec::new();
+        for entry in directory.into_iter().flatten() {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(error) => {
+                    log::error!("failed to read project config: {}", LogError(&error));
+                    continue;
+                }
+            };
+
+            if project_id_for_path(&path).is_none() {
                 continue;
             }
+
+            if self.sources.get(&path) != Some(&modified_time(&path)) {
+                updates.extend(self.update_source(&path));
+            }
+
+            seen.push(path);
+        }
+
+        let removed: Vec<_> = self
+            .sources
+            .keys()
+            .filter(|path| !seen.contains(path))
+            .cloned()
+            .collect();
+
+        for path in removed {
+            updates.extend(self.remove_source(&path));
+        }
+
+        updates
+    }
+
+    /// Loads a single project config file or directory.
+    ///
+    /// Returns `None` if the path is not a project config or if it cannot be loaded.
+    fn update_source(&mut self, path: &Path) -> Option<(ProjectId, Option<Arc<ProjectState>>)> {
+        let id = project_id_for_path(path)?;
+
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return self.remove_source(path)
+            }
+            Err(error) => {
+                log::error!("failed to read {:?}: {}", path, LogError(&error));
+                return None;
+            }
         };
 
-        let state = serde_json::from_reader(io::BufReader::new(fs::File::open(path)?))?;
-        states.insert(id, Arc::new(ProjectState::sanitize(state)));
+        // Files must have an extension, directories must not.
+        if metadata.is_dir() == path.extension().is_some() {
+            log::warn!("skipping {:?}, not a project config", path);
+            return None;
+        }
+
+        // Remember the modification time even if loading fails, so that a broken config is only
+        // read again after it has been changed.
+        self.sources.insert(path.to_owned(), modified_time(path));
+
+        if let Some(other) = self.other_source(id, path) {
+            if source_precedence(&other) < source_precedence(path) {
+                log::error!(
+                    "ignoring duplicate project config {:?}, using {:?}",
+                    path,
+                    other
+                );
+                return None;
+            }
+
+            log::error!(
+                "ignoring duplicate project config {:?}, using {:?}",
+                other,
+                path
+            );
+        }
+
+        log::debug!("loading project config {:?}", path);
+        match load_local_state(path) {
+            Ok(state) => Some((id, Some(Arc::new(state)))),
+            Err(error) => {
+                log::error!(
+                    "failed to load project config, keeping previous state: {}",
+                    LogError(&error)
+                );
+                None
+            }
+        }
+    }
+
+    /// Removes the state of a deleted project config file or directory.
+    ///
+    /// If there is another config for the same project, the one with the highest precedence is
+    /// loaded instead.
+    fn remove_source(&mut self, path: &Path) -> Option<(ProjectId, Option<Arc<ProjectState>>)> {
+        self.sources.remove(path)?;
+        let id = project_id_for_path(path)?;
+        log::debug!("removing project config {:?}", path);
+
+        match self.other_source(id, path) {
+            Some(other) => self.update_source(&other),
+            None => Some((id, None)),
+        }
+    }
+
+    /// Reloads the project config that contains the given path.
+    fn refresh(&mut self, path: &Path) -> Option<(ProjectId, Option<Arc<ProjectState>>)> {
+        let source = self.source_for_path(path)?;
+        self.update_source(&source)
     }
 
-    Ok(states)
+    /// Returns updates for a file system event, or `None` if the event can be ignored.
+    fn handle_event(
+        &mut self,
+        event: DebouncedEvent,
+    ) -> Option<HashMap<ProjectId, Option<Arc<ProjectState>>>> {
+        let mut updates = HashMap::new();
+
+        match event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Remove(path) => {
+                updates.extend(self.refresh(&path));
+            }
+            DebouncedEvent::Rename(from, to) => {
+                updates.extend(self.refresh(&from));
+                updates.extend(self.refresh(&to));
+            }
+            DebouncedEvent::Rescan => {
+                updates.extend(self.scan());
+            }
+            DebouncedEvent::Error(error, path) => {
+                log::error!(
+                    "failed to watch project configs {:?}: {}",
+                    path,
+                    LogError(&error)
+                );
+            }
+            DebouncedEvent::NoticeWrite(_)
+            | DebouncedEvent::NoticeRemove(_)
+            | DebouncedEvent::Chmod(_) => (),
+        }
+
+        if updates.is_empty() {
+            None
+        } else {
+            Some(updates)
+        }
+    }
+}
+
+/// Watches the directory with file system notifications and sends updates to the manager.
+///
+/// Returns an error if the watcher cannot be created. Otherwise, this blocks the current thread
+/// until `stop` is set.
+fn run_watcher(
+    states: &mut LocalStates,
+    manager: &Addr<LocalProjectSource>,
+    generation: usize,
+    stop: &AtomicBool,
+) -> Result<(), notify::Error> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::watcher(sender, WATCH_DEBOUNCE_DELAY)?;
+    watcher.watch(&states.path, RecursiveMode::Recursive)?;
+
+    log::debug!("watching static project configs in {:?}", states.path);
+
+    // Files may have changed between the initial read and the start of the watcher.
+    let mut pending = Some(states.scan());
+
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        if let Some(updates) = pending.filter(|updates| !updates.is_empty()) {
+            manager.do_send(UpdateLocalStates {
+                generation,
+                initial: false,
+                states: updates,
+            });
+        }
+
+        pending = match receiver.recv_timeout(WATCH_STOP_INTERVAL) {
+            Ok(event) => states.handle_event(event),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            // The watcher has been dropped.
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+    }
 }
 
-fn poll_local_states(
+fn watch_local_states(
     manager: Addr<LocalProjectSource>,
     config: Arc<Config>,
+    generation: usize,
+    stop: Arc<AtomicBool>,
 ) -> impl Future<Item = (), Error = ()> {
     let (sender, receiver) = oneshot::channel();
 
     let _ = thread::spawn(move || {
-        let path = config.project_configs_path();
-        let mut sender = Some(sender);
-
-        loop {
-            match load_local_states(&path) {
-                Ok(states) => {
-                    manager.do_send(UpdateLocalStates { states });
-                    sender.take().map(|sender| sender.send(()).ok());
-                }
-                Err(error) => log::error!(
-                    "failed to load static project configs: {}",
-                    LogError(&error)
-                ),
-            }
+        let mut states = LocalStates::new(config.project_configs_path());
+
+        manager.do_send(UpdateLocalStates {
+            generation,
+            initial: true,
+            states: states.scan(),
+        });
+        sender.send(()).ok();
+
+        if let Err(error) = run_watcher(&mut states, &manager, generation, &stop) {
+            log::warn!(
+                "cannot watch static project configs, polling instead: {}",
+                LogError(&error)
+            );
+        }
 
+        // Fall back to polling if file system notifications are not available, for instance if
+        // the directory does not exist.
+        while !stop.load(Ordering::Relaxed) {
             thread::sleep(config.local_cache_interval());
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let updates = states.scan();
+            if !updates.is_empty() {
+                manager.do_send(UpdateLocalStates {
+                    generation,
+                    initial: false,
+                    states: updates,
+                });
+            }
         }
     });
 
     receiver.map_err(|_| ())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use relay_general::protocol::EventId;
+
+    fn project_dir() -> PathBuf {
+        let path = std::env::temp_dir().join(format!("relay-projects-{}", EventId::new()));
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_scan_incremental() {
+        let dir = project_dir();
+        let mut states = LocalStates::new(dir.clone());
+
+        fs::write(dir.join("42.json"), r#"{"slug": "a"}"#).unwrap();
+        fs::write(dir.join("43.json"), r#"{"slug": "b"}"#).unwrap();
+        fs::write(dir.join("README"), "not a project").unwrap();
+
+        let updates = states.scan();
+        assert_eq!(updates.len(), 2);
+        let state = updates[&ProjectId::new(42)].as_ref().unwrap();
+        assert_eq!(state.slug.as_deref(), Some("a"));
+
+        // Unchanged files are not loaded again.
+        assert!(states.scan().is_empty());
+
+        fs::remove_file(dir.join("43.json")).unwrap();
+        let updates = states.scan();
+        assert_eq!(updates.len(), 1);
+        assert!(updates[&ProjectId::new(43)].is_none());
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_parse_error_keeps_state() {
+        let dir = project_dir();
+        let mut states = LocalStates::new(dir.clone());
+        let path = dir.join("42.json");
+
+        fs::write(&path, r#"{"slug": "a"}"#).unwrap();
+        assert_eq!(states.scan().len(), 1);
+
+        // A broken file does not produce an update, which retains the previous state.
+        fs::write(&path, r#"{"slug": "#).unwrap();
+        assert!(states.update_source(&path).is_none());
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_load_yaml() {
+        let dir = project_dir();
+        let mut states = LocalStates::new(dir.clone());
+
+        fs::write(dir.join("42.yml"), "slug: a\ndisabled: true\n").unwrap();
+
+        let updates = states.scan();
+        let state = updates[&ProjectId::new(42)].as_ref().unwrap();
+        assert_eq!(state.slug.as_deref(), Some("a"));
+        assert!(state.disabled);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_load_project_dir() {
+        let dir = project_dir();
+        let mut states = LocalStates::new(dir.clone());
+        let project_dir = dir.join("42");
+        fs::create_dir(&project_dir).unwrap();
+
+        fs::write(project_dir.join("config.yml"), "slug: a\n").unwrap();
+        fs::write(
+            project_dir.join("keys.yml"),
+            "- publicKey: e12d836b15bb49d7bbf99e64295d995b\n  isEnabled: true\n",
+        )
+        .unwrap();
+        fs::write(
+            project_dir.join("pii.yml"),
+            "applications:\n  $string: [\"@ip\"]\n",
+        )
+        .unwrap();
+
+        let updates = states.scan();
+        let state = updates[&ProjectId::new(42)].as_ref().unwrap();
+        assert_eq!(state.slug.as_deref(), Some("a"));
+        assert_eq!(state.public_keys.len(), 1);
+        assert!(state.config.pii_config.is_some());
+
+        // Changes to files within the directory are attributed to the project.
+        let keys_path = project_dir.join("keys.yml");
+        fs::remove_file(&keys_path).unwrap();
+        let (id, state) = states.refresh(&keys_path).unwrap();
+        assert_eq!(id, ProjectId::new(42));
+        assert!(state.unwrap().public_keys.is_empty());
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let dir = project_dir();
+        let path = dir.join("42.yml");
+        fs::write(&path, "slug: a\ndisabled: 42\n").unwrap();
+
+        let error = load_local_state(&path).unwrap_err();
+        let message = LogError(&error).to_string();
+        assert!(message.contains("42.yml"));
+        assert!(message.contains("line 2"));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_duplicate_configs() {
+        let dir = project_dir();
+        let mut states = LocalStates::new(dir.clone());
+        let project_dir = dir.join("42");
+        fs::create_dir(&project_dir).unwrap();
+
+        fs::write(dir.join("42.yml"), "slug: yml\n").unwrap();
+        fs::write(dir.join("42.json"), r#"{"slug": "json"}"#).unwrap();
+        fs::write(project_dir.join("config.yml"), "slug: dir\n").unwrap();
+
+        // The directory takes precedence regardless of the order in which configs are read.
+        let updates = states.scan();
+        let state = updates[&ProjectId::new(42)].as_ref().unwrap();
+        assert_eq!(state.slug.as_deref(), Some("dir"));
+
+        // Changes to configs with lower precedence are ignored.
+        fs::write(dir.join("42.json"), r#"{"slug": "changed"}"#).unwrap();
+        assert!(states.update_source(&dir.join("42.json")).is_none());
+
+        // Removing the directory falls back to the JSON file, then to the YAML file.
+        fs::remove_dir_all(&project_dir).unwrap();
+        let (_, state) = states.remove_source(&project_dir).unwrap();
+        assert_eq!(state.unwrap().slug.as_deref(), Some("changed"));
+
+        fs::remove_file(dir.join("42.json")).unwrap();
+        let updates = states.scan();
+        let state = updates[&ProjectId::new(42)].as_ref().unwrap();
+        assert_eq!(state.slug.as_deref(), Some("yml"));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_source_precedence() {
+        let mut paths = vec![
+            Path::new("42.yaml"),
+            Path::new("42.yml"),
+            Path::new("42.json"),
+            Path::new("042.json"),
+            Path::new("42"),
+        ];
+
+        paths.sort_by(|a, b| source_precedence(a).cmp(&source_precedence(b)));
+        let names: Vec<_> = paths.iter().map(|path| path.to_str().unwrap()).collect();
+        assert_eq!(names, ["42", "042.json", "42.json", "42.yml", "42.yaml"]);
+    }
+
+    #[test]
+    fn test_source_for_path() {
+        let dir = project_dir();
+        let states = LocalStates::new(dir.clone());
+
+        let source = states.source_for_path(&dir.join("42.json"));
+        assert_eq!(source, Some(dir.join("42.json")));
+        let source = states.source_for_path(&dir.join("42").join("keys.yml"));
+        assert_eq!(source, Some(dir.join("42")));
+
+        assert_eq!(states.source_for_path(&dir), None);
+        assert_eq!(states.source_for_path(&dir.join("42/a/b.yml")), None);
+        assert_eq!(states.source_for_path(Path::new("/42.json")), None);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_source_for_canonical_path() {
+        let dir = project_dir();
+        let link = std::env::temp_dir().join(format!("relay-projects-{}", EventId::new()));
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+
+        // Notifications report canonical paths, which are mapped back to the configured path.
+        let mut states = LocalStates::new(link.clone());
+        fs::write(dir.join("42.json"), r#"{"slug": "a"}"#).unwrap();
+        assert_eq!(states.scan().len(), 1);
+
+        let canonical = fs::canonicalize(&dir).unwrap().join("42.json");
+        assert_eq!(
+            states.source_for_path(&canonical),
+            Some(link.join("42.json"))
+        );
+
+        fs::write(dir.join("42.json"), r#"{"slug": "b"}"#).unwrap();
+        let (_, state) = states.refresh(&canonical).unwrap();
+        assert_eq!(state.unwrap().slug.as_deref(), Some("b"));
+        assert_eq!(states.sources.len(), 1);
+
+        fs::remove_file(link).ok();
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_missing_directory() {
+        let mut states = LocalStates::new(std::env::temp_dir().join("relay-projects-missing"));
+        assert!(states.scan().is_empty());
+    }
+}
@@ -15,6 +15,7 @@ use relay_config::Config;
 
 use crate::actors::project::ProjectState;
 use crate::actors::project_cache::{FetchProjectState, ProjectError, ProjectStateResponse};
+use crate::actors::reload::UpdateConfig;
 use crate::actors::upstream::{SendQuery, UpstreamQuery, UpstreamRelay};
 use crate::metrics::{RelayCounters, RelayHistograms, RelayTimers};
 use crate::utils::{self, ErrorBoundary};
@@ -279,6 +280,16 @@ impl Actor for UpstreamProjectSource {
     }
 }
 
+impl Handler<UpdateConfig> for UpstreamProjectSource {
+    type Result = ();
+
+    fn handle(&mut self, message: UpdateConfig, context: &mut Self::Context) -> Self::Result {
+        let UpdateConfig(config) = message;
+        context.set_mailbox_capacity(config.event_buffer_size() as usize);
+        self.config = config;
+    }
+}
+
 impl Handler<FetchProjectState> for UpstreamProjectSource {
     type Result = ResponseFuture<ProjectStateResponse, ()>;
 
@@ -8,14 +8,19 @@ use std::time::{Duration, Instant};
 use ::actix::fut;
 use ::actix::prelude::*;
 use actix_web::{http::Method, HttpResponse, ResponseError};
+use bytes::Bytes;
 use failure::Fail;
 use futures::{future, future::Shared, sync::oneshot, Future};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use relay_auth::{PublicKey, RelayId};
+use relay_auth::{
+    PublicKey, RegisterChallenge, RegisterRequest, RegisterResponse, Registration, RelayId,
+    UnpackError,
+};
 use relay_common::{LogError, RetryBackoff};
 use relay_config::Config;
 
+use crate::actors::reload::UpdateConfig;
 use crate::actors::upstream::{SendQuery, UpstreamQuery, UpstreamRelay};
 use crate::utils::{self, ApiErrorResponse, Response};
 
@@ -35,6 +40,204 @@ impl ResponseError for KeyError {
     }
 }
 
+/// Maximum age of signed registration requests and pending challenges.
+const REGISTRATION_MAX_AGE: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Fail, Debug)]
+pub enum RegistrationError {
+    #[fail(display = "invalid relay signature")]
+    BadSignature(#[cause] UnpackError),
+
+    #[fail(display = "relay is not allowed to register")]
+    NotAllowed,
+
+    #[fail(display = "no pending challenge for relay")]
+    NoChallenge,
+
+    #[fail(display = "invalid challenge token")]
+    InvalidToken,
+
+    #[fail(display = "relay is registered with a different public key")]
+    KeyConflict,
+}
+
+impl ResponseError for RegistrationError {
+    fn error_response(&self) -> HttpResponse {
+        let response = ApiErrorResponse::from_fail(self);
+        match self {
+            RegistrationError::NotAllowed | RegistrationError::KeyConflict => {
+                HttpResponse::Forbidden().json(&response)
+            }
+            _ => HttpResponse::BadRequest().json(&response),
+        }
+    }
+}
+
+/// A challenge issued to a downstream Relay that has not completed registration.
+#[derive(Debug)]
+struct PendingChallenge {
+    public_key: PublicKey,
+    token: String,
+    created_at: Instant,
+}
+
+impl PendingChallenge {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.created_at) >= REGISTRATION_MAX_AGE
+    }
+}
+
+/// Downstream Relays registered with this Relay as registration authority.
+///
+/// Registrations are kept in memory only. After a restart, downstream Relays receive `401
+/// Unauthorized` for their requests and register again.
+#[derive(Debug, Default)]
+struct Registrations {
+    relays: HashMap<RelayId, RelayInfo>,
+    challenges: HashMap<RelayId, PendingChallenge>,
+}
+
+impl Registrations {
+    /// Returns a registered downstream Relay, if it is still allowed.
+    ///
+    /// Keys that are no longer allowed are omitted. If only the next key of a rotating Relay is
+    /// still allowed, it is returned as the Relay's public key.
+    fn get(&self, relay_id: RelayId, config: &Config) -> Option<RelayInfo> {
+        let relay = self.relays.get(&relay_id)?;
+
+        let mut keys = std::iter::once(&relay.public_key)
+            .chain(relay.next_public_key.as_ref())
+            .filter(|key| config.is_allowed_relay(key))
+            .cloned();
+
+        let mut info = RelayInfo::new(keys.next()?);
+        info.next_public_key = keys.next();
+        Some(info)
+    }
+
+    /// Issues a challenge for a verified register request.
+    fn create_challenge(
+        &mut self,
+        request: &RegisterRequest,
+        config: &Config,
+        now: Instant,
+    ) -> Result<RegisterChallenge, RegistrationError> {
+        if !config.is_allowed_relay(request.public_key()) {
+            log::warn!(
+                "relay {} with unknown public key {} attempted to register",
+                request.relay_id(),
+                request.public_key()
+            );
+            return Err(RegistrationError::NotAllowed);
+        }
+
+        self.challenges
+            .retain(|_, challenge| !challenge.is_expired(now));
+
+        let challenge = request.create_challenge();
+        log::debug!("issuing register challenge to relay {}", request.relay_id());
+
+        self.challenges.insert(
+            *request.relay_id(),
+            PendingChallenge {
+                public_key: request.public_key().clone(),
+                token: challenge.token().to_owned(),
+                created_at: now,
+            },
+        );
+
+        Ok(challenge)
+    }
+
+    /// Completes registration with a signed response to a pending challenge.
+    ///
+    /// If the Relay is already registered with another key, the response must also be signed with
+    /// that key in `current_signature` to register the new key as next key of a rotation.
+    fn register(
+        &mut self,
+        data: &[u8],
+        signature: &str,
+        current_signature: Option<&str>,
+        config: &Config,
+        now: Instant,
+    ) -> Result<Registration, RegistrationError> {
+        let relay_id = *RegisterResponse::unpack_unsafe(data)
+            .map_err(RegistrationError::BadSignature)?
+            .relay_id();
+
+        let challenge = self
+            .challenges
+            .remove(&relay_id)
+            .filter(|challenge| !challenge.is_expired(now))
+            .ok_or(RegistrationError::NoChallenge)?;
+
+        let max_age = chrono::Duration::from_std(REGISTRATION_MAX_AGE).ok();
+        let response: RegisterResponse = challenge
+            .public_key
+            .unpack(data, signature, max_age)
+            .map_err(RegistrationError::BadSignature)?;
+
+        if response.token() != challenge.token {
+            return Err(RegistrationError::InvalidToken);
+        }
+
+        if !config.is_allowed_relay(&challenge.public_key) {
+            return Err(RegistrationError::NotAllowed);
+        }
+
+        // A Relay that rotates its credentials registers its next key while still signing
+        // requests with the current key. Keep accepting the current key as long as it is allowed.
+        // The current key must co-sign the registration, since otherwise any allowed key could
+        // replace the keys of another Relay.
+        let registered = self.get(relay_id, config);
+        let current_key = registered
+            .as_ref()
+            .map(|relay| &relay.public_key)
+            .filter(|key| **key != challenge.public_key);
+
+        let signed_by_current = match (current_key, current_signature) {
+            (Some(key), Some(current_signature)) => {
+                key.verify_timestamp(data, current_signature, max_age)
+            }
+            _ => false,
+        };
+
+        let is_next_key = registered
+            .as_ref()
+            .and_then(|relay| relay.next_public_key.as_ref())
+            == Some(&challenge.public_key);
+
+        let info = match current_key {
+            Some(public_key) if signed_by_current => {
+                log::info!("relay {} registered its next public key", relay_id);
+                let mut info = RelayInfo::new(public_key.clone());
+                info.next_public_key = Some(challenge.public_key);
+                info
+            }
+            Some(_) if is_next_key => {
+                log::info!("relay {} completed its key rotation", relay_id);
+                RelayInfo::new(challenge.public_key)
+            }
+            Some(_) => {
+                log::warn!(
+                    "relay {} attempted to register public key {} without its current key",
+                    relay_id,
+                    challenge.public_key
+                );
+                return Err(RegistrationError::KeyConflict);
+            }
+            None => {
+                log::info!("relay {} registered", relay_id);
+                RelayInfo::new(challenge.public_key)
+            }
+        };
+
+        self.relays.insert(relay_id, info);
+
+        Ok(response.create_registration())
+    }
+}
+
 #[derive(Debug)]
 enum RelayState {
     Exists {
@@ -108,6 +311,7 @@ pub struct RelayCache {
     upstream: Addr<UpstreamRelay>,
     relays: HashMap<RelayId, RelayState>,
     relay_channels: HashMap<RelayId, RelayInfoChannel>,
+    registrations: Registrations,
 }
 
 impl RelayCache {
@@ -118,6 +322,7 @@ impl RelayCache {
             upstream,
             relays: HashMap::new(),
             relay_channels: HashMap::new(),
+            registrations: Registrations::default(),
         }
     }
 
@@ -191,6 +396,10 @@ impl RelayCache {
         relay_id: RelayId,
         context: &mut Context<Self>,
     ) -> Response<(RelayId, Option<RelayInfo>), KeyError> {
+        if let Some(relay) = self.registrations.get(relay_id, &self.config) {
+            return Response::ok((relay_id, Some(relay)));
+        }
+
         if let Some(key) = self.relays.get(&relay_id) {
             if key.is_valid_cache(&self.config) {
                 return Response::ok((relay_id, key.as_option().cloned()));
@@ -258,6 +467,71 @@ impl Handler<GetRelay> for RelayCache {
     }
 }
 
+impl Handler<UpdateConfig> for RelayCache {
+    type Result = ();
+
+    fn handle(&mut self, message: UpdateConfig, _context: &mut Self::Context) -> Self::Result {
+        let UpdateConfig(config) = message;
+        self.config = config;
+    }
+}
+
+/// Issues a registration challenge to a downstream Relay.
+///
+/// The request must have been verified with [`RegisterRequest::bootstrap_unpack`]. Only Relays
+/// with public keys in `auth.allowed_relays` may register.
+///
+/// [`RegisterRequest::bootstrap_unpack`]: ../../../relay_auth/struct.RegisterRequest.html
+#[derive(Debug)]
+pub struct CreateChallenge(pub RegisterRequest);
+
+impl Message for CreateChallenge {
+    type Result = Result<RegisterChallenge, RegistrationError>;
+}
+
+impl Handler<CreateChallenge> for RelayCache {
+    type Result = Result<RegisterChallenge, RegistrationError>;
+
+    fn handle(&mut self, message: CreateChallenge, _context: &mut Self::Context) -> Self::Result {
+        let CreateChallenge(request) = message;
+        self.registrations
+            .create_challenge(&request, &self.config, Instant::now())
+    }
+}
+
+/// Completes registration of a downstream Relay with a signed challenge response.
+#[derive(Debug)]
+pub struct RegisterRelay {
+    pub data: Bytes,
+    pub signature: String,
+    /// Signature of the current key of a Relay that registers its next key.
+    pub current_signature: Option<String>,
+}
+
+impl Message for RegisterRelay {
+    type Result = Result<Registration, RegistrationError>;
+}
+
+impl Handler<RegisterRelay> for RelayCache {
+    type Result = Result<Registration, RegistrationError>;
+
+    fn handle(&mut self, message: RegisterRelay, _context: &mut Self::Context) -> Self::Result {
+        let RegisterRelay {
+            data,
+            signature,
+            current_signature,
+        } = message;
+
+        self.registrations.register(
+            &data,
+            &signature,
+            current_signature.as_deref(),
+            &self.config,
+            Instant::now(),
+        )
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetRelays {
     pub relay_ids: Vec<RelayId>,
@@ -304,6 +578,12 @@ pub struct RelayInfo {
     /// The public key that this Relay uses to authenticate and sign requests.
     pub public_key: PublicKey,
 
+    /// The public key that this Relay rotates to, if it is rotating its credentials.
+    ///
+    /// Requests signed with either key are accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_public_key: Option<PublicKey>,
+
     /// Marks an internal relay that has privileged access to more project configuration.
     #[serde(default)]
     pub internal: bool,
@@ -313,9 +593,24 @@ impl RelayInfo {
     pub fn new(public_key: PublicKey) -> Self {
         Self {
             public_key,
+            next_public_key: None,
             internal: false,
         }
     }
+
+    /// Verifies and unpacks signed data with the public key or the next public key of this Relay.
+    pub fn unpack<D>(&self, data: &[u8], signature: &str) -> Result<D, UnpackError>
+    where
+        D: DeserializeOwned,
+    {
+        let result = self.public_key.unpack(data, signature, None);
+        match self.next_public_key {
+            Some(ref next_public_key) if result.is_err() => {
+                next_public_key.unpack(data, signature, None)
+            }
+            _ => result,
+        }
+    }
 }
 
 impl Message for GetRelays {
@@ -367,3 +662,335 @@ impl Handler<GetRelays> for RelayCache {
         Response::future(future)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use relay_auth::{generate_key_pair, generate_relay_id, SecretKey};
+
+    fn authority_config(allowed_relays: &[&PublicKey]) -> Config {
+        Config::from_json_value(serde_json::json!({
+            "auth": {
+                "authority": true,
+                "allowed_relays": allowed_relays,
+            }
+        }))
+        .unwrap()
+    }
+
+    fn create_challenge(
+        registrations: &mut Registrations,
+        relay_id: &RelayId,
+        public_key: &PublicKey,
+        config: &Config,
+        now: Instant,
+    ) -> Result<RegisterChallenge, RegistrationError> {
+        let request = RegisterRequest::new(relay_id, public_key);
+        registrations.create_challenge(&request, config, now)
+    }
+
+    fn respond<S: Serialize>(
+        registrations: &mut Registrations,
+        response: S,
+        secret_key: &SecretKey,
+        config: &Config,
+        now: Instant,
+    ) -> Result<Registration, RegistrationError> {
+        let (data, signature) = secret_key.pack(response);
+        registrations.register(&data, &signature, None, config, now)
+    }
+
+    fn register(
+        registrations: &mut Registrations,
+        relay_id: &RelayId,
+        secret_key: &SecretKey,
+        public_key: &PublicKey,
+        current_key: Option<&SecretKey>,
+        config: &Config,
+    ) -> Result<Registration, RegistrationError> {
+        let now = Instant::now();
+        let challenge = create_challenge(registrations, relay_id, public_key, config, now)?;
+        let (data, signature) = secret_key.pack(challenge.create_response());
+        let current_signature = current_key.map(|key| key.sign(&data));
+        registrations.register(&data, &signature, current_signature.as_deref(), config, now)
+    }
+
+    #[test]
+    fn test_register() {
+        let relay_id = generate_relay_id();
+        let (secret_key, public_key) = generate_key_pair();
+        let config = authority_config(&[&public_key]);
+        let mut registrations = Registrations::default();
+        let now = Instant::now();
+
+        let challenge =
+            create_challenge(&mut registrations, &relay_id, &public_key, &config, now).unwrap();
+        let response = challenge.create_response();
+        let registration =
+            respond(&mut registrations, response, &secret_key, &config, now).unwrap();
+        assert_eq!(*registration.relay_id(), relay_id);
+
+        let relay = registrations.get(relay_id, &config).unwrap();
+        assert_eq!(relay.public_key, public_key);
+        assert_eq!(relay.next_public_key, None);
+    }
+
+    #[test]
+    fn test_register_not_allowed() {
+        let relay_id = generate_relay_id();
+        let (_, public_key) = generate_key_pair();
+        let (_, other_key) = generate_key_pair();
+        let mut registrations = Registrations::default();
+        let now = Instant::now();
+
+        let config = authority_config(&[&other_key]);
+        let result = create_challenge(&mut registrations, &relay_id, &public_key, &config, now);
+        assert!(matches!(result, Err(RegistrationError::NotAllowed)));
+
+        // Allowed keys are only accepted if this Relay is a registration authority.
+        let config = Config::from_json_value(serde_json::json!({
+            "auth": {"allowed_relays": [&public_key]}
+        }))
+        .unwrap();
+        let result = create_challenge(&mut registrations, &relay_id, &public_key, &config, now);
+        assert!(matches!(result, Err(RegistrationError::NotAllowed)));
+
+        assert!(registrations.challenges.is_empty());
+        assert!(registrations.get(relay_id, &config).is_none());
+    }
+
+    #[test]
+    fn test_register_removed_from_allow_list() {
+        let relay_id = generate_relay_id();
+        let (secret_key, public_key) = generate_key_pair();
+        let config = authority_config(&[&public_key]);
+        let mut registrations = Registrations::default();
+        let now = Instant::now();
+
+        let challenge =
+            create_challenge(&mut registrations, &relay_id, &public_key, &config, now).unwrap();
+
+        // The key is removed from the allow list before the challenge is answered.
+        let config = authority_config(&[]);
+        let response = challenge.create_response();
+        let result = respond(&mut registrations, response, &secret_key, &config, now);
+        assert!(matches!(result, Err(RegistrationError::NotAllowed)));
+        assert!(registrations.get(relay_id, &config).is_none());
+    }
+
+    #[test]
+    fn test_register_invalid_token() {
+        let relay_id = generate_relay_id();
+        let (secret_key, public_key) = generate_key_pair();
+        let config = authority_config(&[&public_key]);
+        let mut registrations = Registrations::default();
+        let now = Instant::now();
+
+        let challenge =
+            create_challenge(&mut registrations, &relay_id, &public_key, &config, now).unwrap();
+
+        let forged: RegisterResponse = serde_json::from_value(serde_json::json!({
+            "relay_id": relay_id,
+            "token": "invalid",
+        }))
+        .unwrap();
+        let result = respond(&mut registrations, forged, &secret_key, &config, now);
+        assert!(matches!(result, Err(RegistrationError::InvalidToken)));
+
+        // A challenge can only be answered once.
+        let response = challenge.create_response();
+        let result = respond(&mut registrations, response, &secret_key, &config, now);
+        assert!(matches!(result, Err(RegistrationError::NoChallenge)));
+        assert!(registrations.get(relay_id, &config).is_none());
+    }
+
+    #[test]
+    fn test_register_bad_signature() {
+        let relay_id = generate_relay_id();
+        let (_, public_key) = generate_key_pair();
+        let (other_secret_key, _) = generate_key_pair();
+        let config = authority_config(&[&public_key]);
+        let mut registrations = Registrations::default();
+        let now = Instant::now();
+
+        let challenge =
+            create_challenge(&mut registrations, &relay_id, &public_key, &config, now).unwrap();
+        let response = challenge.create_response();
+        let result = respond(
+            &mut registrations,
+            response,
+            &other_secret_key,
+            &config,
+            now,
+        );
+        assert!(matches!(result, Err(RegistrationError::BadSignature(_))));
+        assert!(registrations.get(relay_id, &config).is_none());
+    }
+
+    #[test]
+    fn test_register_expired_challenge() {
+        let relay_id = generate_relay_id();
+        let (secret_key, public_key) = generate_key_pair();
+        let config = authority_config(&[&public_key]);
+        let mut registrations = Registrations::default();
+        let now = Instant::now();
+
+        let challenge =
+            create_challenge(&mut registrations, &relay_id, &public_key, &config, now).unwrap();
+
+        let later = now + REGISTRATION_MAX_AGE;
+        let response = challenge.create_response();
+        let result = respond(&mut registrations, response, &secret_key, &config, later);
+        assert!(matches!(result, Err(RegistrationError::NoChallenge)));
+        assert!(registrations.get(relay_id, &config).is_none());
+    }
+
+    #[test]
+    fn test_register_without_challenge() {
+        let relay_id = generate_relay_id();
+        let (secret_key, public_key) = generate_key_pair();
+        let config = authority_config(&[&public_key]);
+        let mut registrations = Registrations::default();
+
+        let challenge = RegisterRequest::new(&relay_id, &public_key).create_challenge();
+        let response = challenge.create_response();
+        let result = respond(
+            &mut registrations,
+            response,
+            &secret_key,
+            &config,
+            Instant::now(),
+        );
+        assert!(matches!(result, Err(RegistrationError::NoChallenge)));
+    }
+
+    #[test]
+    fn test_register_rotation() {
+        let relay_id = generate_relay_id();
+        let (secret_key, public_key) = generate_key_pair();
+        let (next_secret_key, next_public_key) = generate_key_pair();
+        let config = authority_config(&[&public_key, &next_public_key]);
+        let mut registrations = Registrations::default();
+
+        register(
+            &mut registrations,
+            &relay_id,
+            &secret_key,
+            &public_key,
+            None,
+            &config,
+        )
+        .unwrap();
+
+        register(
+            &mut registrations,
+            &relay_id,
+            &next_secret_key,
+            &next_public_key,
+            Some(&secret_key),
+            &config,
+        )
+        .unwrap();
+
+        let relay = registrations.get(relay_id, &config).unwrap();
+        assert_eq!(relay.public_key, public_key);
+        assert_eq!(relay.next_public_key, Some(next_public_key.clone()));
+
+        // Once the rotation is complete, the Relay registers with only the next key.
+        register(
+            &mut registrations,
+            &relay_id,
+            &next_secret_key,
+            &next_public_key,
+            None,
+            &config,
+        )
+        .unwrap();
+
+        let relay = registrations.get(relay_id, &config).unwrap();
+        assert_eq!(relay.public_key, next_public_key);
+        assert_eq!(relay.next_public_key, None);
+    }
+
+    #[test]
+    fn test_register_key_conflict() {
+        let relay_id = generate_relay_id();
+        let (secret_key, public_key) = generate_key_pair();
+        let (other_secret_key, other_public_key) = generate_key_pair();
+        let config = authority_config(&[&public_key, &other_public_key]);
+        let mut registrations = Registrations::default();
+
+        register(
+            &mut registrations,
+            &relay_id,
+            &secret_key,
+            &public_key,
+            None,
+            &config,
+        )
+        .unwrap();
+
+        // Another allowed key cannot register for this relay without the current key.
+        let result = register(
+            &mut registrations,
+            &relay_id,
+            &other_secret_key,
+            &other_public_key,
+            None,
+            &config,
+        );
+        assert!(matches!(result, Err(RegistrationError::KeyConflict)));
+
+        let result = register(
+            &mut registrations,
+            &relay_id,
+            &other_secret_key,
+            &other_public_key,
+            Some(&other_secret_key),
+            &config,
+        );
+        assert!(matches!(result, Err(RegistrationError::KeyConflict)));
+
+        let relay = registrations.get(relay_id, &config).unwrap();
+        assert_eq!(relay.public_key, public_key);
+        assert_eq!(relay.next_public_key, None);
+
+        // If the current key is no longer allowed, the relay can register with a new key.
+        let config = authority_config(&[&other_public_key]);
+        register(
+            &mut registrations,
+            &relay_id,
+            &other_secret_key,
+            &other_public_key,
+            None,
+            &config,
+        )
+        .unwrap();
+
+        let relay = registrations.get(relay_id, &config).unwrap();
+        assert_eq!(relay.public_key, other_public_key);
+    }
+
+    #[test]
+    fn test_relay_info_unpack() {
+        let (secret_key, public_key) = generate_key_pair();
+        let (next_secret_key, next_public_key) = generate_key_pair();
+        let (other_secret_key, _) = generate_key_pair();
+
+        let mut info = RelayInfo::new(public_key);
+        let (data, signature) = next_secret_key.pack("rotating");
+        assert!(info.unpack::<String>(&data, &signature).is_err());
+
+        info.next_public_key = Some(next_public_key);
+        let unpacked: String = info.unpack(&data, &signature).unwrap();
+        assert_eq!(unpacked, "rotating");
+
+        let (data, signature) = secret_key.pack("current");
+        let unpacked: String = info.unpack(&data, &signature).unwrap();
+        assert_eq!(unpacked, "current");
+
+        let (data, signature) = other_secret_key.pack("other");
+        assert!(info.unpack::<String>(&data, &signature).is_err());
+    }
+}
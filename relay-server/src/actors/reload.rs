@@ -0,0 +1,127 @@
+//! Defines an actor to reload the configuration at runtime.
+//!
+//! See the [`ConfigReloader`] struct for more information.
+//!
+//! [`ConfigReloader`]: struct.ConfigReloader.html
+
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+use actix::prelude::*;
+use parking_lot::RwLock;
+
+use relay_common::LogError;
+use relay_config::Config;
+
+use crate::actors::controller::{Controller, Reload};
+use crate::metrics;
+
+/// Actor that reloads the configuration when the process receives `SIGHUP`.
+///
+/// The configuration is loaded again from the same config folder and the overrides from the
+/// command line and environment are applied again. If the new configuration is invalid, an error
+/// is logged and the current configuration remains in place.
+///
+/// Otherwise, the new configuration is stored in the shared config handle of the service state and
+/// sent to all registered actors with an [`UpdateConfig`] message. The log level, the metric prefix,
+/// and the default metric tags are applied directly. Some options, such as the listen address,
+/// cannot be changed at runtime. Changes to these options are logged and only take effect after a
+/// restart.
+///
+/// [`UpdateConfig`]: struct.UpdateConfig.html
+pub struct ConfigReloader {
+    config: Arc<RwLock<Arc<Config>>>,
+    recipients: Vec<Recipient<UpdateConfig>>,
+}
+
+impl ConfigReloader {
+    /// Creates a new reloader for the given shared config handle.
+    pub fn new(config: Arc<RwLock<Arc<Config>>>) -> Self {
+        ConfigReloader {
+            config,
+            recipients: Vec::new(),
+        }
+    }
+
+    /// Registers an actor to receive configuration updates.
+    pub fn register<A>(mut self, addr: &Addr<A>) -> Self
+    where
+        A: Handler<UpdateConfig>,
+        A::Context: actix::dev::ToEnvelope<A, UpdateConfig>,
+    {
+        self.recipients.push(addr.clone().recipient());
+        self
+    }
+}
+
+impl fmt::Debug for ConfigReloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigReloader")
+            .field("config", &self.config)
+            .field("recipients", &self.recipients.len())
+            .finish()
+    }
+}
+
+impl Actor for ConfigReloader {
+    type Context = Context<Self>;
+
+    fn started(&mut self, context: &mut Self::Context) {
+        Controller::subscribe_reload(context.address());
+    }
+}
+
+impl Handler<Reload> for ConfigReloader {
+    type Result = ();
+
+    fn handle(&mut self, _message: Reload, _context: &mut Self::Context) -> Self::Result {
+        let current = self.config.read().clone();
+
+        let config = match current.reload() {
+            Ok(config) => Arc::new(config),
+            Err(error) => {
+                log::error!(
+                    "failed to reload config, keeping current config: {}",
+                    LogError(&error)
+                );
+                return;
+            }
+        };
+
+        for option in current.restart_required(&config) {
+            log::warn!(
+                "config option {} changed, restart required to apply",
+                option
+            );
+        }
+
+        *self.config.write() = config.clone();
+
+        // An explicit `RUST_LOG` takes precedence over the configured log level.
+        if env::var_os("RUST_LOG").is_none() {
+            log::set_max_level(config.log_level_filter());
+        }
+
+        metrics::reload_metrics(&config);
+
+        for recipient in &self.recipients {
+            recipient.do_send(UpdateConfig(config.clone())).ok();
+        }
+
+        log::info!("config reloaded");
+    }
+}
+
+/// Replaces the configuration of an actor.
+///
+/// Actors should apply the new configuration where possible. Options that require a restart are
+/// logged by the [`ConfigReloader`] and can be ignored.
+///
+/// [`ConfigReloader`]: struct.ConfigReloader.html
+#[derive(Debug)]
+pub struct UpdateConfig(pub Arc<Config>);
+
+impl Message for UpdateConfig {
+    type Result = ();
+}
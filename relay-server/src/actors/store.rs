@@ -1,6 +1,7 @@
 //! This module contains the actor that forwards events and attachments to the Sentry store.
 //! The actor uses kafka topics to forward data to Sentry
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -13,15 +14,21 @@ use rdkafka::ClientConfig;
 use rmp_serde::encode::Error as RmpError;
 use serde::{ser::Error, Serialize};
 
-use relay_common::{metric, ProjectId, UnixTimestamp, Uuid};
+use relay_common::{metric, LogError, ProjectId, UnixTimestamp, Uuid};
 use relay_config::{Config, KafkaTopic};
-use relay_general::protocol::{self, EventId, SessionStatus, SessionUpdate};
+use relay_general::protocol::{
+    self, CheckIn, EventId, SessionAggregates, SessionStatus, SessionUpdate,
+};
 use relay_quotas::Scoping;
 
+use crate::actors::aggregator::{Bucket, BucketValue};
+use crate::actors::controller::{Controller, Shutdown};
 use crate::envelope::{AttachmentType, Envelope, Item, ItemType};
 use crate::metrics::RelayCounters;
 use crate::service::{ServerError, ServerErrorKind};
-use crate::utils::{CaptureErrorContext, ThreadedProducer};
+use crate::utils::{
+    CaptureErrorContext, MetricType, SessionAggregate, SessionAggregator, ThreadedProducer,
+};
 
 lazy_static::lazy_static! {
     static ref NAMESPACE_DID: Uuid =
@@ -44,6 +51,7 @@ pub enum StoreError {
 pub struct StoreForwarder {
     config: Arc<Config>,
     producer: Arc<ThreadedProducer>,
+    session_aggregator: Option<SessionAggregator>,
 }
 
 fn make_distinct_id(s: &str) -> Uuid {
@@ -62,9 +70,16 @@ impl StoreForwarder {
             .create_with_context(CaptureErrorContext)
             .context(ServerErrorKind::KafkaError)?;
 
+        let session_aggregator = if config.aggregate_sessions() {
+            Some(SessionAggregator::new(config.session_max_buckets()))
+        } else {
+            None
+        };
+
         Ok(Self {
             config,
             producer: Arc::new(producer),
+            session_aggregator,
         })
     }
 
@@ -153,10 +168,36 @@ impl StoreForwarder {
         self.produce(KafkaTopic::Attachments, message)
     }
 
-    fn produce_session(
+    fn produce_profile(
         &self,
-        org_id: u64,
-        project_id: ProjectId,
+        scoping: &Scoping,
+        event_id: EventId,
+        start_time: Instant,
+        retention_days: u16,
+        item: &Item,
+    ) -> Result<(), StoreError> {
+        let message = KafkaMessage::Profile(ProfileKafkaMessage {
+            org_id: scoping.organization_id,
+            project_id: scoping.project_id,
+            key_id: scoping.key_id,
+            event_id,
+            received: UnixTimestamp::from_instant(start_time).as_secs(),
+            payload: item.payload(),
+            retention_days,
+        });
+
+        self.produce(KafkaTopic::Profiles, message)?;
+        metric!(
+            counter(RelayCounters::ProcessingMessageProduced) += 1,
+            event_type = "profile"
+        );
+
+        Ok(())
+    }
+
+    fn produce_session(
+        &mut self,
+        scoping: &Scoping,
         event_retention: u16,
         item: &Item,
     ) -> Result<(), StoreError> {
@@ -165,9 +206,17 @@ impl StoreForwarder {
             Err(_) => return Ok(()),
         };
 
+        if let Some(ref mut aggregator) = self.session_aggregator {
+            aggregator.add_update(scoping, event_retention, &session);
+            if aggregator.is_full() {
+                self.flush_sessions();
+            }
+            return Ok(());
+        }
+
         let message = KafkaMessage::Session(SessionKafkaMessage {
-            org_id,
-            project_id,
+            org_id: scoping.organization_id,
+            project_id: scoping.project_id,
             session_id: session.session_id,
             distinct_id: session
                 .distinct_id
@@ -191,6 +240,104 @@ impl StoreForwarder {
         log::trace!("Sending session item to kafka");
         self.produce(KafkaTopic::Sessions, message)
     }
+
+    fn produce_check_in(
+        &self,
+        scoping: &Scoping,
+        start_time: Instant,
+        retention_days: u16,
+        item: &Item,
+    ) -> Result<(), StoreError> {
+        let check_in = match CheckIn::parse(&item.payload()) {
+            Ok(check_in) => check_in,
+            Err(_) => return Ok(()),
+        };
+
+        let message = KafkaMessage::CheckIn(CheckInKafkaMessage {
+            org_id: scoping.organization_id,
+            project_id: scoping.project_id,
+            received: UnixTimestamp::from_instant(start_time).as_secs(),
+            check_in,
+            retention_days,
+        });
+
+        log::trace!("Sending check-in item to kafka");
+        self.produce(KafkaTopic::Monitors, message)?;
+        metric!(
+            counter(RelayCounters::ProcessingMessageProduced) += 1,
+            event_type = "check_in"
+        );
+
+        Ok(())
+    }
+
+    fn produce_session_aggregates(
+        &mut self,
+        scoping: &Scoping,
+        event_retention: u16,
+        item: &Item,
+    ) -> Result<(), StoreError> {
+        let aggregates = match SessionAggregates::parse(&item.payload()) {
+            Ok(aggregates) => aggregates,
+            Err(_) => return Ok(()),
+        };
+
+        if let Some(ref mut aggregator) = self.session_aggregator {
+            aggregator.add_aggregates(scoping, event_retention, &aggregates);
+            if aggregator.is_full() {
+                self.flush_sessions();
+            }
+            return Ok(());
+        }
+
+        // Without aggregation, pre-aggregated sessions are still rolled up into the same buckets
+        // and written immediately.
+        let mut aggregator = SessionAggregator::new(usize::max_value());
+        aggregator.add_aggregates(scoping, event_retention, &aggregates);
+        self.produce_aggregated_sessions(aggregator.take())
+    }
+
+    fn produce_aggregated_sessions(
+        &self,
+        aggregates: Vec<SessionAggregate>,
+    ) -> Result<(), StoreError> {
+        log::trace!("Sending {} session aggregates to kafka", aggregates.len());
+        for aggregate in aggregates {
+            let message = KafkaMessage::SessionAggregate(SessionAggregateKafkaMessage {
+                org_id: aggregate.org_id,
+                project_id: aggregate.project_id,
+                release: aggregate.release,
+                environment: aggregate.environment,
+                status: aggregate.status,
+                started: aggregate.started,
+                quantity: aggregate.quantity,
+                started_count: aggregate.started_count,
+                errored: aggregate.errored,
+                duration_quantiles: aggregate.duration_quantiles,
+                retention_days: aggregate.retention_days,
+            });
+
+            self.produce(KafkaTopic::SessionAggregates, message)?;
+            metric!(
+                counter(RelayCounters::ProcessingMessageProduced) += 1,
+                event_type = "session_aggregate"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Writes all aggregated sessions to Kafka.
+    fn flush_sessions(&mut self) {
+        let aggregates = match self.session_aggregator {
+            Some(ref mut aggregator) if !aggregator.is_empty() => aggregator.take(),
+            _ => return,
+        };
+
+        if let Err(error) = self.produce_aggregated_sessions(aggregates) {
+            log::error!("failed to flush session aggregates: {}", LogError(&error));
+        }
+    }
 }
 
 /// StoreMessageForwarder is an async actor since the only thing it does is put the messages
@@ -204,14 +351,33 @@ impl Actor for StoreForwarder {
         let mailbox_size = self.config.event_buffer_size() as usize;
         context.set_mailbox_capacity(mailbox_size);
 
+        if self.session_aggregator.is_some() {
+            Controller::subscribe(context.address());
+            context.run_interval(self.config.session_flush_interval(), |slf, _| {
+                slf.flush_sessions()
+            });
+        }
+
         log::info!("store forwarder started");
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.flush_sessions();
         log::info!("store forwarder stopped");
     }
 }
 
+impl Handler<Shutdown> for StoreForwarder {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, _message: Shutdown, _context: &mut Self::Context) -> Self::Result {
+        // Sessions stored after this point are flushed on the next interval or when the actor
+        // stops, whichever happens first.
+        self.flush_sessions();
+        Ok(())
+    }
+}
+
 /// Common attributes for both standalone attachments and processing-relevant attachments.
 #[derive(Debug, Serialize)]
 struct ChunkedAttachment {
@@ -325,6 +491,20 @@ struct UserReportKafkaMessage {
     event_id: EventId,
 }
 
+/// An aggregated bucket of custom metrics.
+#[derive(Debug, Serialize)]
+struct MetricKafkaMessage {
+    org_id: u64,
+    project_id: ProjectId,
+    name: String,
+    #[serde(rename = "type")]
+    ty: MetricType,
+    value: BucketValue,
+    timestamp: u64,
+    tags: BTreeMap<String, String>,
+    retention_days: u16,
+}
+
 #[derive(Debug, Serialize)]
 struct SessionKafkaMessage {
     org_id: u64,
@@ -342,6 +522,46 @@ struct SessionKafkaMessage {
     retention_days: u16,
 }
 
+/// Sessions of a release and status that started in the same minute.
+#[derive(Debug, Serialize)]
+struct SessionAggregateKafkaMessage {
+    org_id: u64,
+    project_id: ProjectId,
+    release: String,
+    environment: Option<String>,
+    status: SessionStatus,
+    started: i64,
+    quantity: u32,
+    started_count: u32,
+    errored: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    duration_quantiles: Vec<(f64, f64)>,
+    retention_days: u16,
+}
+
+/// A profile of a transaction, linked to the transaction by its event ID.
+#[derive(Debug, Serialize)]
+struct ProfileKafkaMessage {
+    org_id: u64,
+    project_id: ProjectId,
+    key_id: Option<u64>,
+    event_id: EventId,
+    received: u64,
+    payload: Bytes,
+    retention_days: u16,
+}
+
+/// A check-in of a scheduled job.
+#[derive(Debug, Serialize)]
+struct CheckInKafkaMessage {
+    org_id: u64,
+    project_id: ProjectId,
+    received: u64,
+    #[serde(flatten)]
+    check_in: CheckIn,
+    retention_days: u16,
+}
+
 /// An enum over all possible ingest messages.
 #[derive(Debug, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -352,6 +572,10 @@ enum KafkaMessage {
     AttachmentChunk(AttachmentChunkKafkaMessage),
     UserReport(UserReportKafkaMessage),
     Session(SessionKafkaMessage),
+    SessionAggregate(SessionAggregateKafkaMessage),
+    Metric(MetricKafkaMessage),
+    Profile(ProfileKafkaMessage),
+    CheckIn(CheckInKafkaMessage),
 }
 
 impl KafkaMessage {
@@ -363,6 +587,11 @@ impl KafkaMessage {
             Self::AttachmentChunk(message) => &message.event_id.0,
             Self::UserReport(message) => &message.event_id.0,
             Self::Session(message) => &message.session_id,
+            Self::Profile(message) => &message.event_id.0,
+            // Check-ins of the same monitor are kept in order.
+            Self::CheckIn(message) => return message.check_in.monitor_slug.as_bytes(),
+            // Aggregates do not need to be partitioned by key.
+            Self::SessionAggregate(_) | Self::Metric(_) => return &[],
         };
 
         event_id.as_bytes()
@@ -370,8 +599,20 @@ impl KafkaMessage {
 
     /// Serializes the message into its binary format.
     fn serialize(&self) -> Result<Vec<u8>, StoreError> {
-        if let KafkaMessage::Session(ref message) = *self {
-            return serde_json::to_vec(&message).map_err(StoreError::InvalidJson);
+        match *self {
+            KafkaMessage::Session(ref message) => {
+                return serde_json::to_vec(&message).map_err(StoreError::InvalidJson);
+            }
+            KafkaMessage::SessionAggregate(ref message) => {
+                return serde_json::to_vec(&message).map_err(StoreError::InvalidJson);
+            }
+            KafkaMessage::Metric(ref message) => {
+                return serde_json::to_vec(&message).map_err(StoreError::InvalidJson);
+            }
+            KafkaMessage::CheckIn(ref message) => {
+                return serde_json::to_vec(&message).map_err(StoreError::InvalidJson);
+            }
+            _ => (),
         }
 
         rmp_serde::to_vec_named(&self).map_err(StoreError::InvalidMsgPack)
@@ -447,9 +688,19 @@ impl Handler<StoreEnvelope> for StoreForwarder {
                     )?;
                 }
                 ItemType::Session => {
-                    self.produce_session(
-                        scoping.organization_id,
-                        scoping.project_id,
+                    self.produce_session(&scoping, retention, item)?;
+                }
+                ItemType::Sessions => {
+                    self.produce_session_aggregates(&scoping, retention, item)?;
+                }
+                ItemType::CheckIn => {
+                    self.produce_check_in(&scoping, start_time, retention, item)?;
+                }
+                ItemType::Profile => {
+                    self.produce_profile(
+                        &scoping,
+                        event_id.ok_or(StoreError::NoEventId)?,
+                        start_time,
                         retention,
                         item,
                     )?;
@@ -494,3 +745,48 @@ impl Handler<StoreEnvelope> for StoreForwarder {
         Ok(())
     }
 }
+
+/// Message sent to the StoreForwarder containing aggregated custom metrics of a project.
+#[derive(Clone, Debug)]
+pub struct StoreMetrics {
+    pub buckets: Vec<Bucket>,
+    pub scoping: Scoping,
+    pub retention: u16,
+}
+
+impl Message for StoreMetrics {
+    type Result = Result<(), StoreError>;
+}
+
+impl Handler<StoreMetrics> for StoreForwarder {
+    type Result = Result<(), StoreError>;
+
+    fn handle(&mut self, message: StoreMetrics, _ctx: &mut Self::Context) -> Self::Result {
+        let StoreMetrics {
+            buckets,
+            scoping,
+            retention,
+        } = message;
+
+        for bucket in buckets {
+            let message = KafkaMessage::Metric(MetricKafkaMessage {
+                org_id: scoping.organization_id,
+                project_id: scoping.project_id,
+                name: bucket.name,
+                ty: bucket.value.ty(),
+                value: bucket.value,
+                timestamp: bucket.timestamp,
+                tags: bucket.tags,
+                retention_days: retention,
+            });
+
+            self.produce(KafkaTopic::Metrics, message)?;
+            metric!(
+                counter(RelayCounters::ProcessingMessageProduced) += 1,
+                event_type = "metric"
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 //! This actor can be used for sending signed requests to the upstream relay.
 use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::str;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use ::actix::fut;
 use ::actix::prelude::*;
@@ -15,13 +17,16 @@ use itertools::Itertools;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 
-use relay_auth::{RegisterChallenge, RegisterRequest, RegisterResponse, Registration};
+use relay_auth::{
+    PublicKey, RegisterChallenge, RegisterRequest, RegisterResponse, Registration, SecretKey,
+};
 use relay_common::{tryf, LogError, RetryBackoff};
-use relay_config::{Config, RelayMode};
+use relay_config::{Config, RelayMode, UpstreamDescriptor, UpstreamStrategy};
 use relay_quotas::{
     DataCategories, QuotaScope, RateLimit, RateLimitScope, RateLimits, RetryAfter, Scoping,
 };
 
+use crate::actors::reload::UpdateConfig;
 use crate::utils::{self, ApiErrorResponse};
 
 #[derive(Fail, Debug)]
@@ -181,31 +186,230 @@ fn handle_response(
     Box::new(future)
 }
 
+/// Health and registration state of a single upstream.
+struct UpstreamState {
+    descriptor: UpstreamDescriptor<'static>,
+    auth_state: AuthState,
+    auth_backoff: RetryBackoff,
+    health_backoff: RetryBackoff,
+    unhealthy_until: Option<Instant>,
+    /// Whether the upstream has acknowledged the next key pair of a key rotation.
+    rotated: bool,
+}
+
+impl UpstreamState {
+    fn new(descriptor: UpstreamDescriptor<'static>, config: &Config) -> Self {
+        UpstreamState {
+            descriptor,
+            auth_state: AuthState::Unknown,
+            auth_backoff: RetryBackoff::new(config.http_max_retry_interval()),
+            health_backoff: RetryBackoff::new(config.http_max_retry_interval()),
+            unhealthy_until: None,
+            rotated: false,
+        }
+    }
+
+    /// Returns `true` if requests to this upstream have not failed recently.
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.unhealthy_until.map_or(true, |until| now >= until)
+    }
+
+    /// Excludes this upstream from requests for the next backoff interval.
+    ///
+    /// The backoff starts with a zero interval, so a single failure is tolerated.
+    fn mark_failed(&mut self) {
+        let interval = self.health_backoff.next_backoff();
+        if interval > Duration::new(0, 0) {
+            log::warn!(
+                "upstream {} failed, excluding it for {} seconds",
+                self.descriptor,
+                interval.as_secs()
+            );
+        }
+
+        self.unhealthy_until = Some(Instant::now() + interval);
+    }
+
+    /// Resets the health of this upstream after a successful request.
+    fn mark_healthy(&mut self) {
+        if self.health_backoff.started() {
+            log::info!("upstream {} recovered", self.descriptor);
+        }
+
+        self.health_backoff.reset();
+        self.unhealthy_until = None;
+    }
+}
+
+/// Signs a query with the given secret key.
+fn pack_query<Q: UpstreamQuery>(query: &Q, secret_key: &SecretKey) -> impl RequestBuilder {
+    let (json, signature) = secret_key.pack(query);
+
+    move |builder: &mut ClientRequestBuilder| {
+        builder
+            .header("X-Sentry-Relay-Signature", signature.as_str())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(json.clone())
+    }
+}
+
+/// Signs a query with the next secret key of a key rotation and co-signs it with the current key.
+///
+/// The current signature proves to a registration authority that the next key belongs to the
+/// same Relay.
+fn pack_rotation_query<Q: UpstreamQuery>(
+    query: &Q,
+    next_key: &SecretKey,
+    current_key: &SecretKey,
+) -> impl RequestBuilder {
+    let (json, signature) = next_key.pack(query);
+    let current_signature = current_key.sign(&json);
+
+    move |builder: &mut ClientRequestBuilder| {
+        builder
+            .header("X-Sentry-Relay-Signature", signature.as_str())
+            .header(
+                "X-Sentry-Relay-Current-Signature",
+                current_signature.as_str(),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(json.clone())
+    }
+}
+
+fn create_upstream_states(config: &Config) -> Vec<UpstreamState> {
+    config
+        .upstream_descriptors()
+        .iter()
+        .map(|descriptor| UpstreamState::new(descriptor.clone().into_owned(), config))
+        .collect()
+}
+
+impl UpstreamRequestError {
+    /// Returns `true` if the upstream could not be reached or failed to handle the request.
+    ///
+    /// Requests failing with such errors are retried with the next upstream.
+    pub fn is_upstream_failure(&self) -> bool {
+        match self {
+            UpstreamRequestError::SendFailed(_) => true,
+            UpstreamRequestError::ResponseError(code, _) => code.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the connection to the upstream could not be established.
+    ///
+    /// In this case, the upstream has not received the request, so it is safe to send it again.
+    fn is_connect_error(&self) -> bool {
+        match self {
+            UpstreamRequestError::SendFailed(SendRequestError::Connector(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the upstream rejected the signature of this Relay.
+    ///
+    /// This happens if the upstream does not know this Relay, for instance, because it lost its
+    /// registrations in a restart.
+    fn is_unauthorized(&self) -> bool {
+        match self {
+            UpstreamRequestError::ResponseError(code, _) => *code == StatusCode::UNAUTHORIZED,
+            _ => false,
+        }
+    }
+}
+
+/// Sends requests to one or more upstreams.
+///
+/// If multiple upstreams are configured, requests are sent to healthy upstreams according to the
+/// configured [`UpstreamStrategy`]. Upstreams are considered unhealthy for a backoff interval after
+/// connection errors or `5XX` responses, in which case the request is retried with the next
+/// upstream. In managed mode, this Relay registers with every upstream separately. If an upstream
+/// rejects a signed query with `401 Unauthorized`, this Relay registers with it again.
+///
+/// [`UpstreamStrategy`]: ../../../relay_config/enum.UpstreamStrategy.html
 pub struct UpstreamRelay {
-    backoff: RetryBackoff,
     config: Arc<Config>,
-    auth_state: AuthState,
+    upstreams: Vec<UpstreamState>,
+    /// Incremented whenever the list of upstreams changes to discard outdated responses.
+    generation: usize,
+    /// The upstream to start with for the next request in round-robin mode.
+    next_upstream: usize,
 }
 
 impl UpstreamRelay {
     pub fn new(config: Arc<Config>) -> Self {
         UpstreamRelay {
-            backoff: RetryBackoff::new(config.http_max_retry_interval()),
+            upstreams: create_upstream_states(&config),
             config,
-            auth_state: AuthState::Unknown,
+            generation: 0,
+            next_upstream: 0,
         }
     }
 
-    fn assert_authenticated(&self) -> Result<(), UpstreamRequestError> {
-        if !self.auth_state.is_authenticated() {
-            Err(UpstreamRequestError::NotAuthenticated)
+    /// Returns the state of the upstream at `index` if it is still current.
+    fn upstream_mut(&mut self, index: usize, generation: usize) -> Option<&mut UpstreamState> {
+        if generation == self.generation {
+            self.upstreams.get_mut(index)
         } else {
-            Ok(())
+            None
+        }
+    }
+
+    fn is_authenticated(&self) -> bool {
+        self.upstreams
+            .iter()
+            .any(|upstream| upstream.auth_state.is_authenticated())
+    }
+
+    fn authenticate_all(&self, context: &mut Context<Self>) {
+        if self.config.relay_mode() != RelayMode::Managed {
+            return;
+        }
+
+        for index in 0..self.upstreams.len() {
+            context.notify(Authenticate {
+                index,
+                generation: self.generation,
+            });
         }
     }
 
+    /// Returns the indexes of upstreams in the order in which they should be tried.
+    ///
+    /// Healthy upstreams come first in the order of the configured strategy, followed by unhealthy
+    /// upstreams as a last resort. If `authenticated` is set, only upstreams that this Relay is
+    /// registered with are returned.
+    fn select_upstreams(&mut self, authenticated: bool) -> VecDeque<usize> {
+        let count = self.upstreams.len();
+        let start = match self.config.upstream_strategy() {
+            UpstreamStrategy::Failover => 0,
+            UpstreamStrategy::RoundRobin => {
+                let start = self.next_upstream % count;
+                self.next_upstream = start + 1;
+                start
+            }
+        };
+
+        self.order_upstreams(start, authenticated)
+    }
+
+    /// Returns the indexes of upstreams starting at `start`, with healthy upstreams first.
+    fn order_upstreams(&self, start: usize, authenticated: bool) -> VecDeque<usize> {
+        let count = self.upstreams.len();
+        let now = Instant::now();
+        let upstreams = &self.upstreams;
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) = (0..count)
+            .map(|offset| (start + offset) % count)
+            .filter(|&index| !authenticated || upstreams[index].auth_state.is_authenticated())
+            .partition(|&index| upstreams[index].is_healthy(now));
+
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
     fn send_request<P, F>(
         &self,
+        index: usize,
         method: Method,
         path: P,
         build: F,
@@ -214,15 +418,16 @@ impl UpstreamRelay {
         F: FnOnce(&mut ClientRequestBuilder) -> Result<ClientRequest, ActixError>,
         P: AsRef<str>,
     {
+        let descriptor = &self.upstreams[index].descriptor;
         let host_header = self
             .config
             .http_host_header()
-            .unwrap_or_else(|| self.config.upstream_descriptor().host());
+            .unwrap_or_else(|| descriptor.host());
 
         let mut builder = ClientRequest::build();
         builder
             .method(method)
-            .uri(self.config.upstream_descriptor().get_url(path.as_ref()))
+            .uri(descriptor.get_url(path.as_ref()))
             .set_header("Host", host_header);
 
         if let Some(ref credentials) = self.config.credentials() {
@@ -255,29 +460,142 @@ impl UpstreamRelay {
         Box::new(future)
     }
 
+    /// Registers with the upstream at `index` again after it rejected a signed query.
+    fn reauthenticate(&mut self, index: usize, generation: usize, context: &mut Context<Self>) {
+        // Skip if a registration is already in progress.
+        let upstream = match self.upstream_mut(index, generation) {
+            Some(upstream) if upstream.auth_state.is_authenticated() => upstream,
+            _ => return,
+        };
+
+        log::warn!(
+            "upstream ({}) rejected the relay, registering again",
+            upstream.descriptor
+        );
+
+        upstream.auth_state = AuthState::Unknown;
+        let interval = upstream.auth_backoff.next_backoff();
+        context.notify_later(Authenticate { index, generation }, interval);
+    }
+
+    /// Sends a request to the first upstream in `upstreams` and fails over to the next upstreams
+    /// on connection errors and server errors.
+    ///
+    /// Requests with non-idempotent methods, such as `POST`, may have been handled by an upstream
+    /// even if it times out or responds with a server error. To avoid duplicate delivery, they only
+    /// fail over if the connection could not be established.
+    ///
+    /// If `signed` is set, the request is a signed query and a `401 Unauthorized` response
+    /// triggers a new registration with the upstream.
+    fn send_request_with_failover<B>(
+        &mut self,
+        mut upstreams: VecDeque<usize>,
+        method: Method,
+        path: String,
+        signed: bool,
+        mut builder: B,
+    ) -> ResponseActFuture<Self, ClientResponse, UpstreamRequestError>
+    where
+        B: RequestBuilder,
+    {
+        let index = match upstreams.pop_front() {
+            Some(index) => index,
+            None => return Box::new(fut::err(UpstreamRequestError::NotAuthenticated)),
+        };
+
+        let generation = self.generation;
+        let future = self
+            .send_request(index, method.clone(), &path, |b| builder.build_request(b))
+            .into_actor(self)
+            .then(move |result, slf, ctx| {
+                let failed = match result {
+                    Ok(_) => false,
+                    Err(ref error) => error.is_upstream_failure(),
+                };
+
+                if let Err(ref error) = result {
+                    if signed && error.is_unauthorized() {
+                        slf.reauthenticate(index, generation, ctx);
+                    }
+                }
+
+                let upstream = match slf.upstream_mut(index, generation) {
+                    Some(upstream) => upstream,
+                    None => return Box::new(fut::result(result)) as ResponseActFuture<_, _, _>,
+                };
+
+                if !failed {
+                    upstream.mark_healthy();
+                    return Box::new(fut::result(result));
+                }
+
+                upstream.mark_failed();
+
+                if let Err(ref error) = result {
+                    let can_retry = method.is_idempotent() || error.is_connect_error();
+                    if can_retry && !upstreams.is_empty() {
+                        log::warn!("failing over to next upstream: {}", LogError(error));
+                        return slf
+                            .send_request_with_failover(upstreams, method, path, signed, builder);
+                    }
+                }
+
+                Box::new(fut::result(result))
+            });
+
+        Box::new(future)
+    }
+
+    /// Returns `true` if all upstreams have acknowledged the next key pair of a key rotation.
+    fn is_rotated(&self) -> bool {
+        self.upstreams.iter().all(|upstream| upstream.rotated)
+    }
+
+    /// Returns the key pair for signing queries.
+    ///
+    /// During a key rotation, queries are signed with the current key pair until all upstreams
+    /// have acknowledged the next key pair.
+    fn signing_keys(&self) -> Result<(SecretKey, PublicKey), UpstreamRequestError> {
+        let credentials = self
+            .config
+            .credentials()
+            .ok_or(UpstreamRequestError::NoCredentials)?;
+
+        Ok(match credentials.next {
+            Some(ref next) if self.is_rotated() => {
+                (next.secret_key.clone(), next.public_key.clone())
+            }
+            _ => (
+                credentials.secret_key.clone(),
+                credentials.public_key.clone(),
+            ),
+        })
+    }
+
+    /// Sends a query to the upstream at `index` without failing over.
     fn send_query<Q: UpstreamQuery>(
         &self,
+        index: usize,
         query: Q,
+        secret_key: &SecretKey,
+    ) -> ResponseFuture<Q::Response, UpstreamRequestError> {
+        let builder = pack_query(&query, secret_key);
+        self.send_packed_query(index, &query, builder)
+    }
+
+    /// Sends a query that has been signed into `builder` to the upstream at the given index.
+    fn send_packed_query<Q: UpstreamQuery>(
+        &self,
+        index: usize,
+        query: &Q,
+        mut builder: impl RequestBuilder,
     ) -> ResponseFuture<Q::Response, UpstreamRequestError> {
         let method = query.method();
         let path = query.path();
-
-        let credentials = tryf!(self
-            .config
-            .credentials()
-            .ok_or(UpstreamRequestError::NoCredentials));
-
-        let (json, signature) = credentials.secret_key.pack(query);
-
         let max_response_size = self.config.max_api_payload_size();
 
         let future = self
-            .send_request(method, path, |builder| {
-                builder
-                    .header("X-Sentry-Relay-Signature", signature)
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(json)
-            })
+            .send_request(index, method, path, |b| builder.build_request(b))
             .and_then(move |r| {
                 r.json()
                     .limit(max_response_size)
@@ -294,11 +612,11 @@ impl Actor for UpstreamRelay {
     fn started(&mut self, context: &mut Self::Context) {
         log::info!("upstream relay started");
 
-        self.backoff.reset();
-
-        if self.config.relay_mode() == RelayMode::Managed {
-            context.notify(Authenticate);
+        for upstream in &mut self.upstreams {
+            upstream.auth_backoff.reset();
         }
+
+        self.authenticate_all(context);
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
@@ -306,7 +624,11 @@ impl Actor for UpstreamRelay {
     }
 }
 
-struct Authenticate;
+/// Registers this Relay with the upstream at the given index.
+struct Authenticate {
+    index: usize,
+    generation: usize,
+}
 
 impl Message for Authenticate {
     type Result = Result<(), ()>;
@@ -315,38 +637,68 @@ impl Message for Authenticate {
 impl Handler<Authenticate> for UpstreamRelay {
     type Result = ResponseActFuture<Self, (), ()>;
 
-    fn handle(&mut self, _msg: Authenticate, _ctx: &mut Self::Context) -> Self::Result {
-        let credentials = match self.config.credentials() {
-            Some(x) => x,
+    fn handle(&mut self, message: Authenticate, _ctx: &mut Self::Context) -> Self::Result {
+        let Authenticate { index, generation } = message;
+
+        let relay_id = match self.config.relay_id() {
+            Some(relay_id) => *relay_id,
             None => return Box::new(fut::err(())),
         };
 
-        log::info!(
-            "registering with upstream ({})",
-            self.config.upstream_descriptor()
-        );
+        let (secret_key, public_key) = match self.signing_keys() {
+            Ok(keys) => keys,
+            Err(_) => return Box::new(fut::err(())),
+        };
+
+        let request = RegisterRequest::new(&relay_id, &public_key);
+
+        let upstream = match self.upstream_mut(index, generation) {
+            Some(upstream) => upstream,
+            None => return Box::new(fut::err(())),
+        };
 
-        self.auth_state = AuthState::RegisterRequestChallenge;
-        let request = RegisterRequest::new(&credentials.id, &credentials.public_key);
+        log::info!("registering with upstream ({})", upstream.descriptor);
+        upstream.auth_state = AuthState::RegisterRequestChallenge;
 
         let future = self
-            .send_query(request)
+            .send_query(index, request, &secret_key)
             .into_actor(self)
-            .and_then(|challenge, slf, _ctx| {
+            .and_then(move |challenge, slf, _ctx| {
                 log::debug!("got register challenge (token = {})", challenge.token());
-                slf.auth_state = AuthState::RegisterChallengeResponse;
+                if let Some(upstream) = slf.upstream_mut(index, generation) {
+                    upstream.auth_state = AuthState::RegisterChallengeResponse;
+                }
+
                 let challenge_response = challenge.create_response();
 
                 log::debug!("sending register challenge response");
-                slf.send_query(challenge_response).into_actor(slf)
+                slf.send_query(index, challenge_response, &secret_key)
+                    .into_actor(slf)
             })
-            .map(|_, slf, _ctx| {
-                log::debug!("relay successfully registered with upstream");
-                slf.auth_state = AuthState::Registered;
+            .map(move |_, slf, ctx| {
+                let rotate = slf.config.next_public_key().is_some();
+                if let Some(upstream) = slf.upstream_mut(index, generation) {
+                    log::debug!(
+                        "relay successfully registered with upstream ({})",
+                        upstream.descriptor
+                    );
+                    upstream.auth_state = AuthState::Registered;
+                    upstream.auth_backoff.reset();
+
+                    if rotate && !upstream.rotated {
+                        ctx.notify(RotateCredentials { index, generation });
+                    }
+                }
             })
-            .map_err(|err, slf, ctx| {
+            .map_err(move |err, slf, ctx| {
                 log::error!("authentication encountered error: {}", LogError(&err));
-                slf.auth_state = AuthState::Error;
+
+                let upstream = match slf.upstream_mut(index, generation) {
+                    Some(upstream) => upstream,
+                    None => return,
+                };
+
+                upstream.auth_state = AuthState::Error;
 
                 // Do not retry client errors including authentication failures since client errors
                 // are usually permanent. This allows the upstream to reject unsupported Relays
@@ -357,13 +709,13 @@ impl Handler<Authenticate> for UpstreamRelay {
                 };
 
                 if should_retry {
-                    let interval = slf.backoff.next_backoff();
+                    let interval = upstream.auth_backoff.next_backoff();
                     log::debug!(
                         "scheduling authentication retry in {} seconds",
                         interval.as_secs()
                     );
 
-                    ctx.notify_later(Authenticate, interval);
+                    ctx.notify_later(Authenticate { index, generation }, interval);
                 }
             });
 
@@ -371,6 +723,241 @@ impl Handler<Authenticate> for UpstreamRelay {
     }
 }
 
+/// Registers the next key pair of a key rotation with the upstream at the given index.
+///
+/// The registration is signed with the next key pair and co-signed with the current key pair. Once
+/// all upstreams have acknowledged the next key pair, queries are signed with it. Until then, the
+/// current key pair remains in use.
+struct RotateCredentials {
+    index: usize,
+    generation: usize,
+}
+
+impl Message for RotateCredentials {
+    type Result = Result<(), ()>;
+}
+
+impl Handler<RotateCredentials> for UpstreamRelay {
+    type Result = ResponseActFuture<Self, (), ()>;
+
+    fn handle(&mut self, message: RotateCredentials, _ctx: &mut Self::Context) -> Self::Result {
+        let RotateCredentials { index, generation } = message;
+
+        let (relay_id, current_key, next) = match self.config.credentials() {
+            Some(credentials) => match credentials.next {
+                Some(ref next) => (credentials.id, credentials.secret_key.clone(), next.clone()),
+                None => return Box::new(fut::ok(())),
+            },
+            None => return Box::new(fut::err(())),
+        };
+
+        let upstream = match self.upstream_mut(index, generation) {
+            Some(upstream) if !upstream.rotated => upstream,
+            _ => return Box::new(fut::ok(())),
+        };
+
+        log::info!(
+            "registering next public key {} with upstream ({})",
+            next.public_key,
+            upstream.descriptor
+        );
+
+        let request = RegisterRequest::new(&relay_id, &next.public_key);
+        let secret_key = next.secret_key;
+
+        let future = self
+            .send_query(index, request, &secret_key)
+            .into_actor(self)
+            .and_then(move |challenge, slf, _ctx| {
+                let challenge_response = challenge.create_response();
+                let builder = pack_rotation_query(&challenge_response, &secret_key, &current_key);
+                slf.send_packed_query(index, &challenge_response, builder)
+                    .into_actor(slf)
+            })
+            .map(move |_, slf, _ctx| {
+                if let Some(upstream) = slf.upstream_mut(index, generation) {
+                    log::info!(
+                        "upstream ({}) acknowledged the next public key",
+                        upstream.descriptor
+                    );
+                    upstream.rotated = true;
+                    upstream.auth_backoff.reset();
+                }
+
+                if slf.is_rotated() {
+                    log::info!("all upstreams acknowledged the next public key, signing with it");
+                }
+            })
+            .map_err(move |err, slf, ctx| {
+                log::error!("key rotation encountered error: {}", LogError(&err));
+
+                let upstream = match slf.upstream_mut(index, generation) {
+                    Some(upstream) => upstream,
+                    None => return,
+                };
+
+                // Client errors indicate that the upstream does not accept the next key. Retrying
+                // does not help until the key is trusted upstream and the config is reloaded.
+                let should_retry = match err {
+                    UpstreamRequestError::ResponseError(code, _) => !code.is_client_error(),
+                    _ => true,
+                };
+
+                if should_retry {
+                    let interval = upstream.auth_backoff.next_backoff();
+                    ctx.notify_later(RotateCredentials { index, generation }, interval);
+                }
+            });
+
+        Box::new(future)
+    }
+}
+
+impl Handler<UpdateConfig> for UpstreamRelay {
+    type Result = ();
+
+    fn handle(&mut self, message: UpdateConfig, context: &mut Self::Context) -> Self::Result {
+        let UpdateConfig(config) = message;
+        if self.update_config(config) {
+            self.authenticate_all(context);
+        }
+    }
+}
+
+impl UpstreamRelay {
+    /// Replaces the config and resets the upstream states if upstreams or credentials changed.
+    ///
+    /// Returns `true` if the upstream states were reset and this Relay needs to register again.
+    fn update_config(&mut self, config: Arc<Config>) -> bool {
+        let upstreams_changed = config.upstream_descriptors() != self.config.upstream_descriptors();
+        let credentials_changed = config.credentials() != self.config.credentials();
+        self.config = config;
+
+        if upstreams_changed {
+            log::info!(
+                "upstream changed to {}",
+                self.config.upstream_descriptors().iter().format(", ")
+            );
+            self.next_upstream = 0;
+        } else if credentials_changed {
+            log::info!("credentials changed, registering again");
+        } else {
+            return false;
+        }
+
+        self.upstreams = create_upstream_states(&self.config);
+        self.generation += 1;
+        true
+    }
+
+    /// Selects the upstream for a request that is not sent through this actor.
+    ///
+    /// If `pinned` is set, the first healthy upstream is selected regardless of the strategy, so
+    /// that consecutive requests reach the same upstream.
+    fn select_upstream(&mut self, pinned: bool) -> SelectedUpstream {
+        // There is always at least one upstream.
+        let index = if pinned {
+            self.order_upstreams(0, false)[0]
+        } else {
+            self.select_upstreams(false)[0]
+        };
+        SelectedUpstream {
+            descriptor: self.upstreams[index].descriptor.clone(),
+            index,
+            generation: self.generation,
+        }
+    }
+
+    /// Updates the health of an upstream returned by `select_upstream`.
+    fn report_upstream_health(&mut self, index: usize, generation: usize, failed: bool) {
+        if let Some(upstream) = self.upstream_mut(index, generation) {
+            if failed {
+                upstream.mark_failed();
+            } else {
+                upstream.mark_healthy();
+            }
+        }
+    }
+}
+
+/// Selects the upstream for a request that is sent directly instead of through this actor.
+///
+/// This is used by the forward endpoint, which streams responses back to the client. The upstream
+/// is selected in the same way as for other requests, but there is no failover. The outcome of the
+/// request must be reported with [`ReportUpstreamHealth`].
+///
+/// [`ReportUpstreamHealth`]: struct.ReportUpstreamHealth.html
+pub struct SelectUpstream {
+    /// Selects the first healthy upstream instead of following the configured strategy.
+    ///
+    /// This is required for requests that depend on state kept by the upstream between requests,
+    /// such as the challenge and response of a Relay registration.
+    pub pinned: bool,
+}
+
+/// The upstream returned by [`SelectUpstream`].
+///
+/// [`SelectUpstream`]: struct.SelectUpstream.html
+#[derive(Debug)]
+pub struct SelectedUpstream {
+    pub descriptor: UpstreamDescriptor<'static>,
+    index: usize,
+    generation: usize,
+}
+
+impl SelectedUpstream {
+    /// Creates a message that reports the outcome of a request to this upstream.
+    ///
+    /// A request `failed` if the upstream could not be reached or responded with a server error.
+    pub fn report_health(&self, failed: bool) -> ReportUpstreamHealth {
+        ReportUpstreamHealth {
+            index: self.index,
+            generation: self.generation,
+            failed,
+        }
+    }
+}
+
+impl Message for SelectUpstream {
+    type Result = SelectedUpstream;
+}
+
+impl Handler<SelectUpstream> for UpstreamRelay {
+    type Result = MessageResult<SelectUpstream>;
+
+    fn handle(&mut self, message: SelectUpstream, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.select_upstream(message.pinned))
+    }
+}
+
+/// Reports the outcome of a request to an upstream returned by [`SelectUpstream`].
+///
+/// [`SelectUpstream`]: struct.SelectUpstream.html
+#[derive(Debug)]
+pub struct ReportUpstreamHealth {
+    index: usize,
+    generation: usize,
+    failed: bool,
+}
+
+impl Message for ReportUpstreamHealth {
+    type Result = ();
+}
+
+impl Handler<ReportUpstreamHealth> for UpstreamRelay {
+    type Result = ();
+
+    fn handle(&mut self, message: ReportUpstreamHealth, _ctx: &mut Self::Context) -> Self::Result {
+        let ReportUpstreamHealth {
+            index,
+            generation,
+            failed,
+        } = message;
+
+        self.report_upstream_health(index, generation, failed);
+    }
+}
+
 pub struct IsAuthenticated;
 
 impl Message for IsAuthenticated {
@@ -381,12 +968,15 @@ impl Handler<IsAuthenticated> for UpstreamRelay {
     type Result = bool;
 
     fn handle(&mut self, _msg: IsAuthenticated, _ctx: &mut Self::Context) -> Self::Result {
-        self.auth_state.is_authenticated()
+        self.is_authenticated()
     }
 }
 
+/// Builds requests to the upstream.
+///
+/// Builders may be invoked multiple times if a request fails over to another upstream.
 pub trait RequestBuilder: 'static {
-    fn build_request(self, _: &mut ClientRequestBuilder) -> Result<ClientRequest, ActixError>;
+    fn build_request(&mut self, _: &mut ClientRequestBuilder) -> Result<ClientRequest, ActixError>;
 }
 
 pub trait ResponseTransformer: 'static {
@@ -397,7 +987,7 @@ pub trait ResponseTransformer: 'static {
 
 impl RequestBuilder for () {
     fn build_request(
-        self,
+        &mut self,
         builder: &mut ClientRequestBuilder,
     ) -> Result<ClientRequest, ActixError> {
         builder.finish()
@@ -406,10 +996,10 @@ impl RequestBuilder for () {
 
 impl<F> RequestBuilder for F
 where
-    F: FnOnce(&mut ClientRequestBuilder) -> Result<ClientRequest, ActixError> + 'static,
+    F: FnMut(&mut ClientRequestBuilder) -> Result<ClientRequest, ActixError> + 'static,
 {
     fn build_request(
-        self,
+        &mut self,
         builder: &mut ClientRequestBuilder,
     ) -> Result<ClientRequest, ActixError> {
         self(builder)
@@ -465,7 +1055,7 @@ impl SendRequest {
 impl<B, T> SendRequest<B, T> {
     pub fn build<F>(self, callback: F) -> SendRequest<F, T>
     where
-        F: FnOnce(&mut ClientRequestBuilder) -> Result<ClientRequest, ActixError> + 'static,
+        F: FnMut(&mut ClientRequestBuilder) -> Result<ClientRequest, ActixError> + 'static,
     {
         SendRequest {
             method: self.method,
@@ -492,7 +1082,7 @@ where
     T: Send,
     E: From<UpstreamRequestError> + Send,
 {
-    type Result = ResponseFuture<T, E>;
+    type Result = ResponseActFuture<Self, T, E>;
 
     fn handle(&mut self, message: SendRequest<B, R>, _ctx: &mut Self::Context) -> Self::Result {
         let SendRequest {
@@ -502,11 +1092,18 @@ where
             transformer,
         } = message;
 
-        Box::new(
-            self.send_request(method, path, |b| builder.build_request(b))
-                .from_err()
-                .and_then(|r| transformer.transform_response(r)),
-        )
+        let upstreams = self.select_upstreams(false);
+        let future = self
+            .send_request_with_failover(upstreams, method, path, false, builder)
+            .map_err(|error, _, _| E::from(error))
+            .and_then(move |response, slf, _| {
+                transformer
+                    .transform_response(response)
+                    .into_future()
+                    .into_actor(slf)
+            });
+
+        Box::new(future)
     }
 }
 
@@ -525,11 +1122,34 @@ impl<T: UpstreamQuery> Message for SendQuery<T> {
 }
 
 impl<T: UpstreamQuery> Handler<SendQuery<T>> for UpstreamRelay {
-    type Result = ResponseFuture<T::Response, UpstreamRequestError>;
+    type Result = ResponseActFuture<Self, T::Response, UpstreamRequestError>;
 
     fn handle(&mut self, message: SendQuery<T>, _ctx: &mut Self::Context) -> Self::Result {
-        tryf!(self.assert_authenticated());
-        self.send_query(message.0)
+        let upstreams = self.select_upstreams(true);
+        if upstreams.is_empty() {
+            return Box::new(fut::err(UpstreamRequestError::NotAuthenticated));
+        }
+
+        let query = message.0;
+        let method = query.method();
+        let path = query.path().into_owned();
+        let builder = match self.signing_keys() {
+            Ok((secret_key, _)) => pack_query(&query, &secret_key),
+            Err(error) => return Box::new(fut::err(error)),
+        };
+
+        let max_response_size = self.config.max_api_payload_size();
+        let future = self
+            .send_request_with_failover(upstreams, method, path, true, builder)
+            .and_then(move |response, slf, _| {
+                response
+                    .json()
+                    .limit(max_response_size)
+                    .map_err(UpstreamRequestError::InvalidJson)
+                    .into_actor(slf)
+            });
+
+        Box::new(future)
     }
 }
 
@@ -554,3 +1174,205 @@ impl UpstreamQuery for RegisterResponse {
         Cow::Borrowed("/api/0/relays/register/response/")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use actix_web::client::ClientConnectorError;
+    use relay_config::Credentials;
+    use relay_general::protocol::EventId;
+    use serde_json::json;
+
+    fn create_relay(strategy: &str) -> UpstreamRelay {
+        let config = Config::from_json_value(json!({
+            "relay": {
+                "upstreams": ["http://a/", "http://b/", "http://c/"],
+                "upstream_strategy": strategy,
+            }
+        }))
+        .unwrap();
+
+        UpstreamRelay::new(Arc::new(config))
+    }
+
+    fn mark_unhealthy(relay: &mut UpstreamRelay, index: usize) {
+        relay.upstreams[index].unhealthy_until = Some(Instant::now() + Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_select_failover() {
+        let mut relay = create_relay("failover");
+        assert_eq!(relay.select_upstreams(false), vec![0, 1, 2]);
+        assert_eq!(relay.select_upstreams(false), vec![0, 1, 2]);
+
+        mark_unhealthy(&mut relay, 0);
+        assert_eq!(relay.select_upstreams(false), vec![1, 2, 0]);
+
+        mark_unhealthy(&mut relay, 1);
+        assert_eq!(relay.select_upstreams(false), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn test_select_round_robin() {
+        let mut relay = create_relay("round_robin");
+        assert_eq!(relay.select_upstreams(false), vec![0, 1, 2]);
+        assert_eq!(relay.select_upstreams(false), vec![1, 2, 0]);
+        assert_eq!(relay.select_upstreams(false), vec![2, 0, 1]);
+        assert_eq!(relay.select_upstreams(false), vec![0, 1, 2]);
+
+        mark_unhealthy(&mut relay, 1);
+        assert_eq!(relay.select_upstreams(false), vec![2, 0, 1]);
+        assert_eq!(relay.select_upstreams(false), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn test_select_authenticated() {
+        let mut relay = create_relay("failover");
+        assert!(relay.select_upstreams(true).is_empty());
+
+        relay.upstreams[1].auth_state = AuthState::Registered;
+        relay.upstreams[2].auth_state = AuthState::Registered;
+        mark_unhealthy(&mut relay, 1);
+        assert_eq!(relay.select_upstreams(true), vec![2, 1]);
+    }
+
+    #[test]
+    fn test_report_upstream_health() {
+        let mut relay = create_relay("failover");
+
+        // A single failure is tolerated.
+        let selected = relay.select_upstream(false);
+        assert_eq!(selected.index, 0);
+        relay.report_upstream_health(selected.index, selected.generation, true);
+        assert_eq!(relay.select_upstream(false).index, 0);
+
+        relay.report_upstream_health(selected.index, selected.generation, true);
+        let selected = relay.select_upstream(false);
+        assert_eq!(selected.index, 1);
+        assert_eq!(selected.descriptor, "http://b/".parse().unwrap());
+
+        relay.report_upstream_health(0, selected.generation, false);
+        assert_eq!(relay.select_upstream(false).index, 0);
+    }
+
+    #[test]
+    fn test_select_pinned() {
+        let mut relay = create_relay("round_robin");
+        assert_eq!(relay.select_upstream(true).index, 0);
+        assert_eq!(relay.select_upstream(true).index, 0);
+        assert_eq!(relay.select_upstream(false).index, 0);
+        assert_eq!(relay.select_upstream(false).index, 1);
+        assert_eq!(relay.select_upstream(true).index, 0);
+
+        mark_unhealthy(&mut relay, 0);
+        assert_eq!(relay.select_upstream(true).index, 1);
+    }
+
+    #[test]
+    fn test_generation() {
+        let mut relay = create_relay("failover");
+        let generation = relay.generation;
+
+        // Reloading the same config keeps the upstream states.
+        let config = relay.config.clone();
+        assert!(!relay.update_config(config));
+        assert!(relay.upstream_mut(0, generation).is_some());
+
+        let config = Config::from_json_value(json!({
+            "relay": {"upstreams": ["http://b/", "http://c/"]}
+        }))
+        .unwrap();
+
+        assert!(relay.update_config(Arc::new(config)));
+        assert!(relay.upstream_mut(0, generation).is_none());
+        assert!(relay.upstream_mut(0, relay.generation).is_some());
+
+        // Reports for upstreams of the previous config are discarded.
+        relay.report_upstream_health(0, generation, true);
+        relay.report_upstream_health(0, generation, true);
+        assert_eq!(relay.select_upstream(false).index, 0);
+    }
+
+    #[test]
+    fn test_signing_keys() {
+        let dir = std::env::temp_dir().join(format!("relay-config-{}", EventId::new()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("config.yml"),
+            "relay:\n  upstreams: [\"http://a/\", \"http://b/\"]\n",
+        )
+        .unwrap();
+
+        let mut credentials = Credentials::generate();
+        let next_public_key = credentials.start_rotation().public_key.clone();
+
+        let mut config = Config::from_path(&dir).unwrap();
+        config
+            .replace_credentials(Some(credentials.clone()))
+            .unwrap();
+        fs::remove_dir_all(dir).ok();
+
+        let mut relay = UpstreamRelay::new(Arc::new(config));
+        let (_, public_key) = relay.signing_keys().unwrap();
+        assert_eq!(public_key, credentials.public_key);
+
+        // Queries are signed with the current key until all upstreams acknowledged the next key.
+        relay.upstreams[0].rotated = true;
+        assert!(!relay.is_rotated());
+        let (_, public_key) = relay.signing_keys().unwrap();
+        assert_eq!(public_key, credentials.public_key);
+
+        relay.upstreams[1].rotated = true;
+        assert!(relay.is_rotated());
+        let (_, public_key) = relay.signing_keys().unwrap();
+        assert_eq!(public_key, next_public_key);
+    }
+
+    #[test]
+    fn test_is_upstream_failure() {
+        let error = UpstreamRequestError::SendFailed(SendRequestError::Timeout);
+        assert!(error.is_upstream_failure());
+
+        let error =
+            UpstreamRequestError::ResponseError(StatusCode::BAD_GATEWAY, Default::default());
+        assert!(error.is_upstream_failure());
+
+        let error =
+            UpstreamRequestError::ResponseError(StatusCode::BAD_REQUEST, Default::default());
+        assert!(!error.is_upstream_failure());
+
+        let error = UpstreamRequestError::RateLimited(UpstreamRateLimits::new());
+        assert!(!error.is_upstream_failure());
+        assert!(!UpstreamRequestError::NotAuthenticated.is_upstream_failure());
+    }
+
+    #[test]
+    fn test_is_connect_error() {
+        let error = UpstreamRequestError::SendFailed(SendRequestError::Connector(
+            ClientConnectorError::Timeout,
+        ));
+        assert!(error.is_connect_error());
+
+        // The request may have been received before the timeout.
+        let error = UpstreamRequestError::SendFailed(SendRequestError::Timeout);
+        assert!(!error.is_connect_error());
+
+        let error =
+            UpstreamRequestError::ResponseError(StatusCode::BAD_GATEWAY, Default::default());
+        assert!(!error.is_connect_error());
+    }
+
+    #[test]
+    fn test_is_unauthorized() {
+        let error =
+            UpstreamRequestError::ResponseError(StatusCode::UNAUTHORIZED, Default::default());
+        assert!(error.is_unauthorized());
+
+        let error = UpstreamRequestError::ResponseError(StatusCode::FORBIDDEN, Default::default());
+        assert!(!error.is_unauthorized());
+        assert!(!UpstreamRequestError::NotAuthenticated.is_unauthorized());
+    }
+}
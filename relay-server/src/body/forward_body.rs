@@ -1,5 +1,7 @@
 use actix::ResponseFuture;
-use actix_web::{error::PayloadError, http::StatusCode, HttpMessage, HttpResponse, ResponseError};
+use actix_web::error::PayloadError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, ResponseError};
 use bytes::{Bytes, BytesMut};
 use failure::Fail;
 use futures::prelude::*;
@@ -42,16 +44,17 @@ impl From<PayloadError> for ForwardPayloadError {
 }
 
 /// Future that resolves to a complete store endpoint body.
-pub struct ForwardBody<T: HttpMessage> {
+pub struct ForwardBody {
     limit: usize,
-    stream: Option<T::Stream>,
+    size: utils::RequestBodySize,
+    stream: Option<<HttpRequest as HttpMessage>::Stream>,
     err: Option<ForwardPayloadError>,
     fut: Option<ResponseFuture<Bytes, ForwardPayloadError>>,
 }
 
-impl<T: HttpMessage> ForwardBody<T> {
+impl ForwardBody {
     /// Create `ForwardBody` for request.
-    pub fn new(req: &T, limit: usize) -> ForwardBody<T> {
+    pub fn new<S>(req: &HttpRequest<S>, limit: usize) -> Self {
         // Check the content length first. If we detect an overflow from the content length header,
         // keep the payload in the request to drain it correctly in the `ReadRequestMiddleware`.
         if let Some(length) = utils::get_content_length(req) {
@@ -62,6 +65,7 @@ impl<T: HttpMessage> ForwardBody<T> {
 
         ForwardBody {
             limit,
+            size: utils::RequestBodySize::of(req),
             stream: Some(req.payload()),
             err: None,
             fut: None,
@@ -71,6 +75,7 @@ impl<T: HttpMessage> ForwardBody<T> {
     fn err(e: ForwardPayloadError) -> Self {
         ForwardBody {
             limit: 0,
+            size: utils::RequestBodySize::default(),
             stream: None,
             fut: None,
             err: Some(e),
@@ -78,10 +83,7 @@ impl<T: HttpMessage> ForwardBody<T> {
     }
 }
 
-impl<T> Future for ForwardBody<T>
-where
-    T: HttpMessage + 'static,
-{
+impl Future for ForwardBody {
     type Item = Bytes;
     type Error = ForwardPayloadError;
 
@@ -95,6 +97,7 @@ where
         }
 
         let limit = self.limit;
+        let size = self.size.clone();
         let body = Some(BytesMut::with_capacity(8192));
 
         let future = self
@@ -103,6 +106,7 @@ where
             .expect("Can not be used second time")
             .map_err(ForwardPayloadError::from)
             .fold(body, move |body_opt, chunk| {
+                size.add(chunk.len());
                 Ok::<_, ForwardPayloadError>(body_opt.and_then(|mut body| {
                     if (body.len() + chunk.len()) > limit {
                         None
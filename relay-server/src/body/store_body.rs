@@ -76,6 +76,7 @@ impl From<PayloadError> for StorePayloadError {
 /// Future that resolves to a complete store endpoint body.
 pub struct StoreBody {
     limit: usize,
+    size: utils::RequestBodySize,
 
     // These states are mutually exclusive:
     result: Option<Result<Bytes, StorePayloadError>>,
@@ -89,6 +90,7 @@ impl StoreBody {
         if let Some(body) = data_from_querystring(req) {
             return StoreBody {
                 limit,
+                size: utils::RequestBodySize::default(),
                 stream: None,
                 result: Some(decode_bytes(body.as_bytes())),
                 fut: None,
@@ -105,6 +107,7 @@ impl StoreBody {
 
         StoreBody {
             limit,
+            size: utils::RequestBodySize::of(req),
             result: None,
             fut: None,
             stream: Some(req.payload()),
@@ -114,6 +117,7 @@ impl StoreBody {
     fn err(e: StorePayloadError) -> Self {
         StoreBody {
             limit: 0,
+            size: utils::RequestBodySize::default(),
             result: Some(Err(e)),
             fut: None,
             stream: None,
@@ -135,6 +139,7 @@ impl Future for StoreBody {
         }
 
         let limit = self.limit;
+        let size = self.size.clone();
         let body = Some(BytesMut::with_capacity(8192));
 
         let future = self
@@ -143,6 +148,8 @@ impl Future for StoreBody {
             .expect("Can not be used second time")
             .map_err(StorePayloadError::from)
             .fold(body, move |body_opt, chunk| {
+                size.add(chunk.len());
+
                 // Ensure that the stream is always fully consumed. Erroring here would leave a
                 // broken TCP stream that cannot be used with keep-alive connections.
                 Ok::<_, StorePayloadError>(body_opt.and_then(|mut body| {
@@ -0,0 +1,287 @@
+//! Admin API to inspect and invalidate the project cache.
+//!
+//! All endpoints require the bearer token configured in `admin.token`.
+
+use actix::prelude::*;
+use actix_web::{Error, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use failure::Fail;
+use futures::{future, Future};
+use serde::Serialize;
+
+use relay_common::{instant_to_date_time, ParseProjectIdError, ProjectId};
+
+use crate::actors::project::{
+    ClearRateLimits, GetCachedProjectState, LimitedProjectState, Outdated, Project, ProjectState,
+};
+use crate::actors::project_cache::{EvictProjects, GetCachedProject, ListProjects};
+use crate::extractors::{AdminAuth, CurrentServiceState};
+use crate::service::ServiceApp;
+use crate::utils::ApiErrorResponse;
+
+#[derive(Debug, Fail)]
+#[fail(display = "invalid project id")]
+struct BadProjectId(#[cause] ParseProjectIdError);
+
+impl ResponseError for BadProjectId {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest().json(&ApiErrorResponse::from_fail(self))
+    }
+}
+
+/// Extracts the required `project_id` path parameter.
+///
+/// Unlike `Path<ProjectId>`, which responds with `404 Not Found`, this rejects invalid project IDs
+/// with `400 Bad Request`.
+#[derive(Debug)]
+struct ProjectIdParam(ProjectId);
+
+impl<S> FromRequest<S> for ProjectIdParam {
+    type Config = ();
+    type Result = Result<Self, Error>;
+
+    fn from_request(request: &HttpRequest<S>, _cfg: &Self::Config) -> Self::Result {
+        let project_id = request
+            .match_info()
+            .get("project_id")
+            .unwrap_or_default()
+            .parse()
+            .map_err(BadProjectId)?;
+
+        Ok(ProjectIdParam(project_id))
+    }
+}
+
+/// Summary of a project in the project cache.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedProject {
+    project_id: ProjectId,
+    /// The time at which the state was last fetched. `None` if it has not been fetched yet.
+    last_fetch: Option<DateTime<Utc>>,
+    /// The expiry status of the state. `None` if it has not been fetched yet.
+    outdated: Option<Outdated>,
+    /// Whether the state is disabled or invalid.
+    disabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ListProjectsResponse {
+    projects: Vec<CachedProject>,
+}
+
+/// Serializes a project state in the format exposed to external Relays.
+#[derive(Debug, Serialize)]
+struct ProjectStateResponse(#[serde(with = "LimitedProjectState")] ProjectState);
+
+#[derive(Debug, Serialize)]
+struct EvictProjectsResponse {
+    evicted: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ClearRateLimitsResponse {
+    /// The number of active rate limits removed across all projects.
+    cleared: usize,
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn list_projects(
+    state: CurrentServiceState,
+    _auth: AdminAuth,
+) -> ResponseFuture<HttpResponse, Error> {
+    let config = state.config();
+
+    let future = state
+        .project_cache()
+        .send(ListProjects)
+        .map_err(Error::from)
+        .and_then(move |projects| {
+            let futures = projects.into_iter().map(move |(project_id, project)| {
+                let config = config.clone();
+                project
+                    .send(GetCachedProjectState)
+                    .map(move |project_state| CachedProject {
+                        project_id,
+                        last_fetch: project_state
+                            .as_ref()
+                            .map(|s| instant_to_date_time(s.last_fetch)),
+                        outdated: project_state.as_ref().map(|s| s.outdated(&config)),
+                        disabled: project_state.map_or(false, |s| s.invalid() || s.disabled()),
+                    })
+            });
+
+            future::join_all(futures).map_err(Error::from)
+        })
+        .map(|mut projects| {
+            projects.sort_by_key(|project| project.project_id);
+            HttpResponse::Ok().json(ListProjectsResponse { projects })
+        });
+
+    Box::new(future)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_project(
+    state: CurrentServiceState,
+    _auth: AdminAuth,
+    project_id: ProjectIdParam,
+) -> ResponseFuture<HttpResponse, Error> {
+    let future = state
+        .project_cache()
+        .send(GetCachedProject { id: project_id.0 })
+        .and_then(|project| match project {
+            Some(project) => Box::new(project.send(GetCachedProjectState)) as ResponseFuture<_, _>,
+            None => Box::new(future::ok(None)),
+        })
+        .map_err(Error::from)
+        .map(|project_state| match project_state {
+            Some(project_state) => {
+                HttpResponse::Ok().json(ProjectStateResponse((*project_state).clone()))
+            }
+            None => HttpResponse::NotFound().finish(),
+        });
+
+    Box::new(future)
+}
+
+fn evict_projects(
+    state: &CurrentServiceState,
+    id: Option<ProjectId>,
+) -> ResponseFuture<HttpResponse, Error> {
+    let future = state
+        .project_cache()
+        .send(EvictProjects { id })
+        .map_err(Error::from)
+        .map(|evicted| HttpResponse::Ok().json(EvictProjectsResponse { evicted }));
+
+    Box::new(future)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn evict_all_projects(
+    state: CurrentServiceState,
+    _auth: AdminAuth,
+) -> ResponseFuture<HttpResponse, Error> {
+    evict_projects(&state, None)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn evict_project(
+    state: CurrentServiceState,
+    _auth: AdminAuth,
+    project_id: ProjectIdParam,
+) -> ResponseFuture<HttpResponse, Error> {
+    evict_projects(&state, Some(project_id.0))
+}
+
+fn clear_rate_limits<F>(projects: F) -> ResponseFuture<HttpResponse, Error>
+where
+    F: Future<Item = Vec<Addr<Project>>, Error = MailboxError> + 'static,
+{
+    let future = projects
+        .and_then(|projects| {
+            let futures = projects
+                .into_iter()
+                .map(|project| project.send(ClearRateLimits));
+            future::join_all(futures)
+        })
+        .map_err(Error::from)
+        .map(|cleared| {
+            HttpResponse::Ok().json(ClearRateLimitsResponse {
+                cleared: cleared.into_iter().sum(),
+            })
+        });
+
+    Box::new(future)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn clear_all_rate_limits(
+    state: CurrentServiceState,
+    _auth: AdminAuth,
+) -> ResponseFuture<HttpResponse, Error> {
+    let projects = state.project_cache().send(ListProjects).map(|projects| {
+        projects
+            .into_iter()
+            .map(|(_, project)| project)
+            .collect::<Vec<_>>()
+    });
+
+    clear_rate_limits(projects)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn clear_project_rate_limits(
+    state: CurrentServiceState,
+    _auth: AdminAuth,
+    project_id: ProjectIdParam,
+) -> ResponseFuture<HttpResponse, Error> {
+    let projects = state
+        .project_cache()
+        .send(GetCachedProject { id: project_id.0 })
+        .map(|project| project.into_iter().collect::<Vec<_>>());
+
+    clear_rate_limits(projects)
+}
+
+pub fn configure_app(app: ServiceApp) -> ServiceApp {
+    app.resource("/api/relay/projects/", |r| {
+        r.name("internal-admin-projects");
+        r.get().with(list_projects);
+        r.delete().with(evict_all_projects);
+    })
+    .resource("/api/relay/projects/{project_id}/", |r| {
+        r.name("internal-admin-project");
+        r.get().with(get_project);
+        r.delete().with(evict_project);
+    })
+    .resource("/api/relay/ratelimits/", |r| {
+        r.name("internal-admin-ratelimits");
+        r.delete().with(clear_all_rate_limits);
+    })
+    .resource("/api/relay/ratelimits/{project_id}/", |r| {
+        r.name("internal-admin-project-ratelimits");
+        r.delete().with(clear_project_rate_limits);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    fn extract_project_id(project_id: &'static str) -> Result<ProjectId, StatusCode> {
+        let request = TestRequest::default()
+            .param("project_id", project_id)
+            .finish();
+
+        ProjectIdParam::from_request(&request, &())
+            .map(|param| param.0)
+            .map_err(|error| error.as_response_error().error_response().status())
+    }
+
+    #[test]
+    fn test_project_id_param() {
+        assert_eq!(extract_project_id("42"), Ok(ProjectId::new(42)));
+    }
+
+    #[test]
+    fn test_project_id_param_invalid() {
+        assert_eq!(extract_project_id("abc"), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(extract_project_id("-1"), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(extract_project_id(""), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_project_id_param_missing() {
+        let request = TestRequest::default().finish();
+        let error = ProjectIdParam::from_request(&request, &()).unwrap_err();
+        assert_eq!(
+            error.as_response_error().error_response().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -2,29 +2,32 @@
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
 
 use actix::prelude::*;
 use actix_web::http::{header, StatusCode};
 use actix_web::middleware::cors::{Cors, CorsBuilder};
 use actix_web::{HttpRequest, HttpResponse, ResponseError};
 use failure::Fail;
-use futures::prelude::*;
+use futures::{future, prelude::*};
 use sentry::Hub;
 use sentry_actix::ActixWebHubExt;
 use serde::Deserialize;
 
-use relay_common::{clone, metric, tryf, LogError};
-use relay_general::protocol::{EventId, EventType};
-use relay_quotas::RateLimits;
+use relay_common::{clone, metric, LogError};
+use relay_general::protocol::{EventId, EventType, SessionAggregates};
+use relay_quotas::{RateLimits, Scoping};
 
-use crate::actors::events::{QueueEnvelope, QueueEnvelopeError};
+use crate::actors::events::{EventManager, QueueEnvelope, QueueEnvelopeError};
 use crate::actors::outcome::{DiscardReason, Outcome, TrackOutcome};
-use crate::actors::project::CheckEnvelope;
+use crate::actors::project::{CheckEnvelope, Project};
 use crate::actors::project_cache::{GetProject, ProjectError};
 use crate::body::StorePayloadError;
-use crate::envelope::{AttachmentType, Envelope, EnvelopeError, ItemType, Items};
+use crate::envelope::{AttachmentType, Envelope, EnvelopeError, Item, ItemType, Items};
 use crate::extractors::RequestMeta;
 use crate::metrics::RelayCounters;
+use crate::middlewares::AccessLogInfo;
 use crate::service::{ServiceApp, ServiceState};
 use crate::utils::{self, ApiErrorResponse, FormDataIter, MultipartError};
 use relay_config::Config;
@@ -49,6 +52,15 @@ pub enum BadStoreRequest {
     #[fail(display = "invalid messagepack data")]
     InvalidMsgpack(#[cause] rmp_serde::decode::Error),
 
+    #[fail(display = "invalid protobuf data")]
+    InvalidProtobuf(#[cause] prost::DecodeError),
+
+    #[fail(display = "too many spans in request")]
+    TooManySpans,
+
+    #[fail(display = "unsupported content type")]
+    UnsupportedContentType,
+
     #[fail(display = "invalid event envelope")]
     InvalidEnvelope(#[cause] EnvelopeError),
 
@@ -67,6 +79,9 @@ pub enum BadStoreRequest {
     #[fail(display = "invalid event id")]
     InvalidEventId,
 
+    #[fail(display = "invalid check-in")]
+    InvalidCheckIn,
+
     #[fail(display = "failed to queue envelope")]
     QueueFailed(#[cause] QueueEnvelopeError),
 
@@ -94,6 +109,9 @@ impl BadStoreRequest {
             BadStoreRequest::EmptyBody => Outcome::Invalid(DiscardReason::NoData),
             BadStoreRequest::InvalidJson(_) => Outcome::Invalid(DiscardReason::InvalidJson),
             BadStoreRequest::InvalidMsgpack(_) => Outcome::Invalid(DiscardReason::InvalidMsgpack),
+            BadStoreRequest::InvalidProtobuf(_) => Outcome::Invalid(DiscardReason::InvalidProtobuf),
+            BadStoreRequest::TooManySpans => Outcome::Invalid(DiscardReason::TooLarge),
+            BadStoreRequest::UnsupportedContentType => Outcome::Invalid(DiscardReason::ContentType),
             BadStoreRequest::InvalidMultipart(_) => {
                 Outcome::Invalid(DiscardReason::InvalidMultipart)
             }
@@ -102,9 +120,12 @@ impl BadStoreRequest {
                 Outcome::Invalid(DiscardReason::MissingMinidumpUpload)
             }
             BadStoreRequest::InvalidEnvelope(_) => Outcome::Invalid(DiscardReason::InvalidEnvelope),
+            BadStoreRequest::InvalidCheckIn => Outcome::Invalid(DiscardReason::InvalidJson),
 
             BadStoreRequest::QueueFailed(event_error) => match event_error {
                 QueueEnvelopeError::TooManyEvents => Outcome::Invalid(DiscardReason::Internal),
+                QueueEnvelopeError::SpoolFailed(_) => Outcome::Invalid(DiscardReason::Internal),
+                QueueEnvelopeError::ScheduleFailed(_) => Outcome::Invalid(DiscardReason::Internal),
             },
 
             BadStoreRequest::ProjectFailed(project_error) => match project_error {
@@ -176,8 +197,10 @@ impl ResponseError for BadStoreRequest {
                 // now executed asynchronously in `EventProcessor`.
                 HttpResponse::Forbidden().json(&body)
             }
-            BadStoreRequest::PayloadError(StorePayloadError::Overflow) => {
-                HttpResponse::PayloadTooLarge().json(&body)
+            BadStoreRequest::PayloadError(StorePayloadError::Overflow)
+            | BadStoreRequest::TooManySpans => HttpResponse::PayloadTooLarge().json(&body),
+            BadStoreRequest::UnsupportedContentType => {
+                HttpResponse::UnsupportedMediaType().json(&body)
             }
             _ => {
                 // In all other cases, we indicate a generic bad request to the client and render
@@ -313,6 +336,20 @@ pub fn cors(app: ServiceApp) -> CorsBuilder<ServiceState> {
     builder
 }
 
+/// Returns the number of session updates in a session item for `max_session_count`.
+///
+/// Session aggregates count each aggregate bucket, since every bucket is processed like a session
+/// update. The number of sessions in a bucket is charged against quotas instead, see
+/// `EnvelopeSummary`.
+fn count_session_updates(item: &Item) -> usize {
+    match item.ty() {
+        ItemType::Sessions => SessionAggregates::parse(&item.payload())
+            .map(|aggregates| aggregates.aggregates.len())
+            .unwrap_or(1),
+        _ => 1,
+    }
+}
+
 /// Checks for size limits of items in this envelope.
 ///
 /// Returns `true`, if the envelope adheres to the configured size limits. Otherwise, returns
@@ -325,6 +362,7 @@ pub fn cors(app: ServiceApp) -> CorsBuilder<ServiceState> {
 ///  - `max_attachment_size`
 ///  - `max_attachments_size`
 ///  - `max_session_count`
+///  - `max_profile_size`
 fn check_envelope_size_limits(config: &Config, envelope: &Envelope) -> bool {
     let mut event_size = 0;
     let mut attachments_size = 0;
@@ -344,8 +382,16 @@ fn check_envelope_size_limits(config: &Config, envelope: &Envelope) -> bool {
 
                 attachments_size += item.len()
             }
-            ItemType::Session => session_count += 1,
-            ItemType::UserReport => (),
+            ItemType::Session | ItemType::Sessions => session_count += count_session_updates(item),
+            ItemType::Profile => {
+                if item.len() > config.max_profile_size() {
+                    return false;
+                }
+            }
+            ItemType::UserReport
+            | ItemType::Metrics
+            | ItemType::ClientReport
+            | ItemType::CheckIn => (),
         }
     }
 
@@ -354,6 +400,65 @@ fn check_envelope_size_limits(config: &Config, envelope: &Envelope) -> bool {
         && session_count <= config.max_session_count()
 }
 
+/// Validates a store-like request before its payload is read.
+///
+/// This records the project in the access log, so that rejected requests can be filtered, checks
+/// the protocol version, and reports the version in metrics.
+fn prepare_store_like_request(
+    request: &HttpRequest<ServiceState>,
+    meta: &RequestMeta,
+) -> Result<(), BadStoreRequest> {
+    let project_id = meta.project_id();
+    request.extensions_mut().insert(AccessLogInfo {
+        project_id: Some(project_id),
+        public_key: Some(meta.public_key().to_owned()),
+        outcome: None,
+    });
+
+    // For now, we only handle <= v8 and drop everything else
+    let version = meta.version();
+    if version > relay_common::PROTOCOL_VERSION {
+        // TODO: Delegate to forward_upstream here
+        return Err(BadStoreRequest::UnsupportedProtocolVersion(version));
+    }
+
+    let hub = Hub::from_request(request);
+    hub.configure_scope(|scope| {
+        scope.set_user(Some(sentry::User {
+            id: Some(project_id.to_string()),
+            ..Default::default()
+        }));
+    });
+
+    metric!(
+        counter(RelayCounters::EventProtocol) += 1,
+        version = &format!("{}", version)
+    );
+
+    Ok(())
+}
+
+/// Records a rejected store-like request in metrics and the access log.
+///
+/// Returns the error response for the request. Server errors are logged.
+fn reject_store_like_request(
+    request: &HttpRequest<ServiceState>,
+    error: &BadStoreRequest,
+) -> HttpResponse {
+    metric!(counter(RelayCounters::EnvelopeRejected) += 1);
+
+    if let Some(info) = request.extensions_mut().get_mut::<AccessLogInfo>() {
+        info.outcome = Some(error.to_outcome());
+    }
+
+    let response = error.error_response();
+    if response.status().is_server_error() {
+        log::error!("error handling request: {}", LogError(error));
+    }
+
+    response
+}
+
 /// Handles Sentry events.
 ///
 /// Sentry events may come either directly from a http request ( the store endpoint calls this
@@ -376,32 +481,16 @@ where
     R: FnOnce(Option<EventId>) -> HttpResponse + Copy + 'static,
 {
     let start_time = meta.start_time();
-
-    // For now, we only handle <= v8 and drop everything else
-    let version = meta.version();
-    if version > relay_common::PROTOCOL_VERSION {
-        // TODO: Delegate to forward_upstream here
-        tryf!(Err(BadStoreRequest::UnsupportedProtocolVersion(version)));
-    }
-
     let project_id = meta.project_id();
-    let hub = Hub::from_request(&request);
-    hub.configure_scope(|scope| {
-        scope.set_user(Some(sentry::User {
-            id: Some(project_id.to_string()),
-            ..Default::default()
-        }));
-    });
-
-    metric!(
-        counter(RelayCounters::EventProtocol) += 1,
-        version = &format!("{}", version)
-    );
+    if let Err(error) = prepare_store_like_request(&request, &meta) {
+        return Box::new(future::ok(reject_store_like_request(&request, &error)));
+    }
 
     let event_manager = request.state().event_manager();
     let project_manager = request.state().project_cache();
     let outcome_producer = request.state().outcome_producer();
     let remote_addr = meta.client_addr();
+    let access_log_request = request.clone();
 
     let scoping = Rc::new(RefCell::new(meta.get_partial_scoping()));
     let event_id = Rc::new(RefCell::new(None));
@@ -413,42 +502,17 @@ where
         .and_then(clone!(event_id, scoping, |project| {
             extract_envelope(&request, meta)
                 .into_future()
-                .and_then(clone!(project, |envelope| {
+                .and_then(clone!(scoping, |envelope| {
                     event_id.replace(envelope.event_id());
-
-                    project
-                        .send(CheckEnvelope::cached(envelope))
-                        .map_err(BadStoreRequest::ScheduleFailed)
-                        .and_then(|result| result.map_err(BadStoreRequest::ProjectFailed))
-                }))
-                .and_then(clone!(scoping, |response| {
-                    scoping.replace(response.scoping);
-
-                    let checked = response.result.map_err(BadStoreRequest::EventRejected)?;
-
-                    // Skip over queuing and issue a rate limit right away
-                    let envelope = match checked.envelope {
-                        Some(envelope) => envelope,
-                        None => return Err(BadStoreRequest::RateLimited(checked.rate_limits)),
-                    };
-
-                    if check_envelope_size_limits(&config, &envelope) {
-                        Ok((envelope, checked.rate_limits))
-                    } else {
-                        Err(BadStoreRequest::PayloadError(StorePayloadError::Overflow))
-                    }
+                    check_and_queue_envelope(
+                        envelope,
+                        project,
+                        scoping,
+                        config,
+                        event_manager,
+                        start_time,
+                    )
                 }))
-                .and_then(move |(envelope, rate_limits)| {
-                    event_manager
-                        .send(QueueEnvelope {
-                            envelope,
-                            project,
-                            start_time,
-                        })
-                        .map_err(BadStoreRequest::ScheduleFailed)
-                        .and_then(|result| result.map_err(BadStoreRequest::QueueFailed))
-                        .map(move |event_id| (event_id, rate_limits))
-                })
                 .and_then(move |(event_id, rate_limits)| {
                     if rate_limits.is_limited() {
                         Err(BadStoreRequest::RateLimited(rate_limits))
@@ -458,7 +522,7 @@ where
                 })
         }))
         .or_else(move |error: BadStoreRequest| {
-            metric!(counter(RelayCounters::EnvelopeRejected) += 1);
+            let response = reject_store_like_request(&access_log_request, &error);
 
             if is_event {
                 outcome_producer.do_send(TrackOutcome {
@@ -467,6 +531,8 @@ where
                     outcome: error.to_outcome(),
                     event_id: *event_id.borrow(),
                     remote_addr,
+                    category: None,
+                    quantity: None,
                 });
             }
 
@@ -474,12 +540,150 @@ where
                 return Ok(create_response(*event_id.borrow()));
             }
 
-            let response = error.error_response();
-            if response.status().is_server_error() {
-                log::error!("error handling request: {}", LogError(&error));
+            Ok(response)
+        });
+
+    Box::new(future)
+}
+
+/// Checks an envelope against the project state and rate limits and queues it for processing.
+///
+/// Returns the event id and the rate limits that apply to the envelope. Rate limited items have
+/// been removed from the queued envelope. The scoping is updated as soon as it is known, so that
+/// outcomes for rejected envelopes can be attributed to the right organization and key.
+fn check_and_queue_envelope(
+    envelope: Envelope,
+    project: Addr<Project>,
+    scoping: Rc<RefCell<Scoping>>,
+    config: Arc<Config>,
+    event_manager: Addr<EventManager>,
+    start_time: Instant,
+) -> impl Future<Item = (Option<EventId>, RateLimits), Error = BadStoreRequest> {
+    project
+        .send(CheckEnvelope::cached(envelope))
+        .map_err(BadStoreRequest::ScheduleFailed)
+        .and_then(|result| result.map_err(BadStoreRequest::ProjectFailed))
+        .and_then(move |response| {
+            scoping.replace(response.scoping);
+
+            let checked = response.result.map_err(BadStoreRequest::EventRejected)?;
+
+            // Skip over queuing and issue a rate limit right away
+            let envelope = match checked.envelope {
+                Some(envelope) => envelope,
+                None => return Err(BadStoreRequest::RateLimited(checked.rate_limits)),
+            };
+
+            if check_envelope_size_limits(&config, &envelope) {
+                Ok((envelope, checked.rate_limits))
+            } else {
+                Err(BadStoreRequest::PayloadError(StorePayloadError::Overflow))
             }
+        })
+        .and_then(move |(envelope, rate_limits)| {
+            event_manager
+                .send(QueueEnvelope {
+                    envelope,
+                    project,
+                    start_time,
+                })
+                .map_err(BadStoreRequest::ScheduleFailed)
+                .and_then(|result| result.map_err(BadStoreRequest::QueueFailed))
+                .map(move |event_id| (event_id, rate_limits))
+        })
+}
 
-            Ok(response)
+/// Handles requests that produce multiple envelopes, such as batches of traces.
+///
+/// This works like `handle_store_like_request`, except that every envelope is checked and queued
+/// individually, and outcomes are emitted for every rejected envelope. If any envelope is rejected,
+/// the response reflects the first rejection. Otherwise, the response is created with
+/// `create_response`.
+pub fn handle_store_like_batch<F, R, I>(
+    meta: RequestMeta,
+    request: HttpRequest<ServiceState>,
+    extract_envelopes: F,
+    create_response: R,
+) -> ResponseFuture<HttpResponse, BadStoreRequest>
+where
+    F: FnOnce(&HttpRequest<ServiceState>, RequestMeta) -> I + 'static,
+    I: IntoFuture<Item = Vec<Envelope>, Error = BadStoreRequest> + 'static,
+    R: FnOnce() -> HttpResponse + 'static,
+{
+    let start_time = meta.start_time();
+    let project_id = meta.project_id();
+    if let Err(error) = prepare_store_like_request(&request, &meta) {
+        return Box::new(future::ok(reject_store_like_request(&request, &error)));
+    }
+
+    let event_manager = request.state().event_manager();
+    let project_manager = request.state().project_cache();
+    let outcome_producer = request.state().outcome_producer();
+    let remote_addr = meta.client_addr();
+    let access_log_request = request.clone();
+    let partial_scoping = meta.get_partial_scoping();
+    let config = request.state().config();
+
+    let track_outcome = move |error: &BadStoreRequest, scoping: Scoping, event_id| {
+        outcome_producer.do_send(TrackOutcome {
+            timestamp: start_time,
+            scoping,
+            outcome: error.to_outcome(),
+            event_id,
+            remote_addr,
+            category: None,
+            quantity: None,
+        });
+    };
+
+    let future = project_manager
+        .send(GetProject { id: project_id })
+        .map_err(BadStoreRequest::ScheduleFailed)
+        .and_then(move |project| {
+            extract_envelopes(&request, meta)
+                .into_future()
+                .map(move |envelopes| (project, envelopes))
+        })
+        .map_err(clone!(track_outcome, partial_scoping, |error| {
+            track_outcome(&error, partial_scoping, None);
+            error
+        }))
+        .and_then(move |(project, envelopes)| {
+            let futures = envelopes.into_iter().map(move |envelope| {
+                let event_id = envelope.event_id();
+                let scoping = Rc::new(RefCell::new(partial_scoping.clone()));
+                let track_outcome = track_outcome.clone();
+
+                check_and_queue_envelope(
+                    envelope,
+                    project.clone(),
+                    scoping.clone(),
+                    config.clone(),
+                    event_manager.clone(),
+                    start_time,
+                )
+                .then(move |result| -> Result<_, BadStoreRequest> {
+                    Ok(match result {
+                        Ok((_, rate_limits)) if rate_limits.is_limited() => {
+                            Some(BadStoreRequest::RateLimited(rate_limits))
+                        }
+                        Ok(_) => None,
+                        Err(error) => {
+                            track_outcome(&error, scoping.borrow().clone(), event_id);
+                            Some(error)
+                        }
+                    })
+                })
+            });
+
+            future::join_all(futures)
+        })
+        .and_then(|rejections| match rejections.into_iter().flatten().next() {
+            Some(error) => Err(error),
+            None => Ok(create_response()),
+        })
+        .or_else(move |error: BadStoreRequest| {
+            Ok(reject_store_like_request(&access_log_request, &error))
         });
 
     Box::new(future)
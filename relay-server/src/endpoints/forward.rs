@@ -2,17 +2,22 @@
 //!
 //! This endpoint will issue a client request to the upstream and append relay's own headers
 //! (`X-Forwarded-For` and `Sentry-Relay-Id`). The response is then streamed back to the origin.
+//!
+//! If multiple upstreams are configured, the request is sent to a healthy upstream selected by
+//! the `UpstreamRelay` actor. Forwarded requests are not retried with other upstreams, but their
+//! outcome is reported back to update the health of the selected upstream.
 
 use ::actix::prelude::*;
 use actix_web::client::ClientRequest;
 use actix_web::http::{header, header::HeaderName, uri::PathAndQuery, ContentEncoding};
 use actix_web::{AsyncResponder, Error, HttpMessage, HttpRequest, HttpResponse};
-use futures::prelude::*;
+use futures::{future, prelude::*};
 use lazy_static::lazy_static;
 
-use relay_common::GlobMatcher;
+use relay_common::{clone, GlobMatcher};
 use relay_config::Config;
 
+use crate::actors::upstream::SelectUpstream;
 use crate::body::ForwardBody;
 use crate::endpoints::statics;
 use crate::extractors::ForwardedFor;
@@ -32,6 +37,9 @@ static HOP_BY_HOP_HEADERS: &[HeaderName] = &[
 /// Headers ignored in addition to the headers defined in `HOP_BY_HOP_HEADERS`.
 static IGNORED_REQUEST_HEADERS: &[HeaderName] = &[header::CONTENT_ENCODING, header::CONTENT_LENGTH];
 
+/// Path prefix of the endpoints for Relay registration.
+const REGISTER_PATH: &str = "/api/0/relays/register/";
+
 /// Route classes with request body limit overrides.
 #[derive(Clone, Copy, Debug)]
 enum SpecialRoute {
@@ -66,16 +74,22 @@ fn get_limit_for_path(path: &str, config: &Config) -> usize {
 /// This endpoint will create a proxy request to the upstream for every incoming request and stream
 /// the request body back to the origin. Regardless of the incoming connection, the connection to
 /// the upstream uses its own HTTP version and transfer encoding.
-fn forward_upstream(request: &HttpRequest<ServiceState>) -> ResponseFuture<HttpResponse, Error> {
+pub fn forward_upstream(
+    request: &HttpRequest<ServiceState>,
+) -> ResponseFuture<HttpResponse, Error> {
     let config = request.state().config();
-    let upstream = config.upstream_descriptor();
+    let upstream_relay = request.state().upstream_relay();
     let limit = get_limit_for_path(request.path(), &config);
 
+    // The challenge and response of a registration must reach the same upstream.
+    let pinned = request.path().starts_with(REGISTER_PATH);
+
     let path_and_query = request
         .uri()
         .path_and_query()
         .map(PathAndQuery::as_str)
-        .unwrap_or("");
+        .unwrap_or("")
+        .to_owned();
 
     let mut forwarded_request_builder = ClientRequest::build();
     for (key, value) in request.headers() {
@@ -88,24 +102,45 @@ fn forward_upstream(request: &HttpRequest<ServiceState>) -> ResponseFuture<HttpR
         forwarded_request_builder.header(key.clone(), value.clone());
     }
 
-    let host_header = config.http_host_header().unwrap_or_else(|| upstream.host());
     forwarded_request_builder
         .no_default_headers()
         .disable_decompress()
         .method(request.method().clone())
-        .uri(upstream.get_url(path_and_query))
-        .set_header("Host", host_header)
         .set_header("X-Forwarded-For", ForwardedFor::from(request));
 
     ForwardBody::new(request, limit)
         .map_err(Error::from)
-        .and_then(move |data| forwarded_request_builder.body(data).map_err(Error::from))
-        .and_then(move |request| {
-            request
-                .send()
-                .conn_timeout(config.http_connection_timeout())
-                .timeout(config.http_timeout())
+        .and_then(clone!(upstream_relay, |data| {
+            upstream_relay
+                .send(SelectUpstream { pinned })
                 .map_err(Error::from)
+                .map(|selected| (data, selected))
+        }))
+        .and_then(move |(data, selected)| {
+            let upstream = &selected.descriptor;
+            let host_header = config.http_host_header().unwrap_or_else(|| upstream.host());
+
+            let request = forwarded_request_builder
+                .uri(upstream.get_url(&path_and_query))
+                .set_header("Host", host_header)
+                .body(data)
+                .map_err(Error::from);
+
+            future::result(request).and_then(move |request| {
+                request
+                    .send()
+                    .conn_timeout(config.http_connection_timeout())
+                    .timeout(config.http_timeout())
+                    .then(move |result| {
+                        let failed = match result {
+                            Ok(ref response) => response.status().is_server_error(),
+                            Err(_) => true,
+                        };
+
+                        upstream_relay.do_send(selected.report_health(failed));
+                        result.map_err(Error::from)
+                    })
+            })
         })
         .and_then(move |response| {
             let mut forwarded_response = HttpResponse::build(response.status());
@@ -5,6 +5,7 @@
 
 use crate::service::ServiceApp;
 
+mod admin;
 mod attachments;
 mod common;
 mod envelope;
@@ -12,9 +13,13 @@ mod events;
 mod forward;
 mod healthcheck;
 mod minidump;
+mod monitor;
+mod otlp;
 mod outcomes;
 mod project_configs;
+mod prometheus;
 mod public_keys;
+mod register;
 mod security_report;
 mod statics;
 mod store;
@@ -25,10 +30,13 @@ pub fn configure_app(app: ServiceApp) -> ServiceApp {
         // Internal routes pointing to /api/relay
         .configure(healthcheck::configure_app)
         .configure(events::configure_app)
+        .configure(admin::configure_app)
         .handler("/api/relay", statics::not_found)
+        .configure(prometheus::configure_app)
         // Web API routes pointing to /api/0
         .configure(project_configs::configure_app)
         .configure(public_keys::configure_app)
+        .configure(register::configure_app)
         .configure(outcomes::configure_app)
         // Ingestion routes pointing to /api/<project_id>/
         .configure(store::configure_app)
@@ -37,6 +45,8 @@ pub fn configure_app(app: ServiceApp) -> ServiceApp {
         .configure(minidump::configure_app)
         .configure(attachments::configure_app)
         .configure(unreal::configure_app)
+        .configure(otlp::configure_app)
+        .configure(monitor::configure_app)
         // `forward` must be last as it creates a wildcard proxy
         .configure(forward::configure_app)
 }
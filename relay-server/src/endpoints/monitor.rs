@@ -0,0 +1,187 @@
+//! Endpoint for check-ins of scheduled jobs.
+//!
+//! Jobs report their status to the monitor identified by the slug in the URL. Parameters are read
+//! from a JSON request body or, if the body is empty, from the query string. This allows to send
+//! check-ins with a plain `GET` request, for instance with `curl` at the end of a cron job.
+//!
+//! The response contains the ID of the check-in. Clients can pass their own `check_in_id`, for
+//! instance to report the completion of a job under the ID of its `in_progress` check-in.
+
+use actix_web::actix::ResponseFuture;
+use actix_web::{HttpRequest, HttpResponse};
+use futures::Future;
+use serde::{Deserialize, Serialize};
+
+use relay_common::Uuid;
+use relay_general::protocol::{CheckIn, CheckInStatus, EventId};
+
+use crate::body::ForwardBody;
+use crate::endpoints::common::{self, BadStoreRequest};
+use crate::envelope::{ContentType, Envelope, Item, ItemType};
+use crate::extractors::RequestMeta;
+use crate::service::{ServiceApp, ServiceState};
+
+/// Parameters of a check-in submitted to the endpoint.
+#[derive(Debug, Deserialize)]
+struct CheckInParams {
+    /// The ID of the check-in. A new ID is generated if the client does not provide one.
+    #[serde(default)]
+    check_in_id: Option<Uuid>,
+    /// The status of the job. Defaults to `ok` for heartbeats without status.
+    #[serde(default)]
+    status: Option<CheckInStatus>,
+    /// The duration of the job in seconds.
+    #[serde(default)]
+    duration: Option<f64>,
+    /// The environment in which the job runs.
+    #[serde(default)]
+    environment: Option<String>,
+}
+
+impl CheckInParams {
+    /// Reads check-in parameters from the query string of the request.
+    fn from_query(request: &HttpRequest<ServiceState>) -> Result<Self, BadStoreRequest> {
+        let query = request.query();
+
+        let check_in_id = match query.get("check_in_id") {
+            Some(check_in_id) => Some(
+                check_in_id
+                    .parse()
+                    .map_err(|_| BadStoreRequest::InvalidCheckIn)?,
+            ),
+            None => None,
+        };
+
+        let status = match query.get("status") {
+            Some(status) => serde_json::from_value(status.as_str().into())
+                .map(Some)
+                .map_err(|_| BadStoreRequest::InvalidCheckIn)?,
+            None => None,
+        };
+
+        let duration = match query.get("duration") {
+            Some(duration) => Some(
+                duration
+                    .parse()
+                    .map_err(|_| BadStoreRequest::InvalidCheckIn)?,
+            ),
+            None => None,
+        };
+
+        Ok(CheckInParams {
+            check_in_id,
+            status,
+            duration,
+            environment: query.get("environment").cloned(),
+        })
+    }
+
+    /// Creates a validated check-in for the given monitor.
+    fn into_check_in(self, monitor_slug: String) -> Result<CheckIn, BadStoreRequest> {
+        let check_in = CheckIn {
+            check_in_id: self.check_in_id.unwrap_or_else(Uuid::new_v4),
+            monitor_slug,
+            status: self.status.unwrap_or(CheckInStatus::Ok),
+            duration: self.duration,
+            environment: self.environment,
+        };
+
+        check_in
+            .validate()
+            .map_err(|_| BadStoreRequest::InvalidCheckIn)?;
+
+        Ok(check_in)
+    }
+}
+
+fn extract_envelope(
+    request: &HttpRequest<ServiceState>,
+    meta: RequestMeta,
+) -> ResponseFuture<Envelope, BadStoreRequest> {
+    // Slugs are case-insensitive in URLs but stored in lowercase.
+    let monitor_slug = request
+        .match_info()
+        .get("monitor_slug")
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    let query_params = CheckInParams::from_query(request);
+    let max_payload_size = request.state().config().max_event_size();
+
+    let future = ForwardBody::new(request, max_payload_size)
+        .map_err(|_| BadStoreRequest::InvalidCheckIn)
+        .and_then(move |data| {
+            let params = if data.is_empty() {
+                query_params?
+            } else {
+                serde_json::from_slice(&data).map_err(BadStoreRequest::InvalidJson)?
+            };
+
+            let check_in = params.into_check_in(monitor_slug)?;
+            let json = check_in.serialize().map_err(BadStoreRequest::InvalidJson)?;
+
+            let mut item = Item::new(ItemType::CheckIn);
+            item.set_payload(ContentType::Json, json);
+
+            // The check-in ID doubles as envelope ID, so that it can be returned to the client.
+            let event_id = EventId(check_in.check_in_id);
+            let mut envelope = Envelope::from_request(Some(event_id), meta);
+            envelope.add_item(item);
+
+            Ok(envelope)
+        });
+
+    Box::new(future)
+}
+
+#[derive(Serialize)]
+struct CheckInResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<Uuid>,
+}
+
+fn create_response(id: Option<EventId>) -> HttpResponse {
+    HttpResponse::Accepted().json(CheckInResponse {
+        id: id.map(|event_id| event_id.0),
+    })
+}
+
+/// Handler for check-ins of scheduled jobs.
+fn store_check_in(
+    meta: RequestMeta,
+    request: HttpRequest<ServiceState>,
+) -> ResponseFuture<HttpResponse, BadStoreRequest> {
+    common::handle_store_like_request(
+        meta,
+        false,
+        request,
+        extract_envelope,
+        create_response,
+        true,
+    )
+}
+
+pub fn configure_app(app: ServiceApp) -> ServiceApp {
+    common::cors(app)
+        .resource(
+            &common::normpath(r"/api/{project:\d+}/cron/{monitor_slug:[a-zA-Z0-9_-]+}/"),
+            |r| {
+                r.name("store-check-in");
+                r.post().with(store_check_in);
+                r.get().with(store_check_in);
+            },
+        )
+        // Check-in endpoint with the public key in the path, for clients that cannot set headers
+        // or query parameters for authentication.
+        .resource(
+            &common::normpath(
+                r"/api/{project:\d+}/cron/{monitor_slug:[a-zA-Z0-9_-]+}/{sentry_key:\w+}/",
+            ),
+            |r| {
+                r.name("store-check-in-with-key");
+                r.post().with(store_check_in);
+                r.get().with(store_check_in);
+            },
+        )
+        .register()
+}
@@ -0,0 +1,144 @@
+//! Endpoint for OpenTelemetry traces.
+//!
+//! Traces are accepted in the OTLP/HTTP protocol and converted into transaction events, which are
+//! then processed like regular transactions. See `utils::otlp` for the conversion.
+
+use actix::prelude::*;
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use futures::{future, Future};
+
+use relay_general::protocol::{Event, EventId};
+use relay_general::types::Annotated;
+
+use crate::body::StoreBody;
+use crate::endpoints::common::{self, BadStoreRequest};
+use crate::envelope::{ContentType, Envelope, Item, ItemType};
+use crate::extractors::RequestMeta;
+use crate::service::{ServiceApp, ServiceState};
+use crate::utils;
+
+/// The encoding of an OTLP request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OtlpEncoding {
+    Protobuf,
+    Json,
+}
+
+impl OtlpEncoding {
+    /// Determines the encoding from the request's content type.
+    fn from_request(request: &HttpRequest<ServiceState>) -> Option<Self> {
+        let content_type = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        match mime {
+            "application/x-protobuf" | "application/protobuf" => Some(OtlpEncoding::Protobuf),
+            "application/json" => Some(OtlpEncoding::Json),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            OtlpEncoding::Protobuf => "application/x-protobuf",
+            OtlpEncoding::Json => "application/json",
+        }
+    }
+}
+
+/// Creates an envelope with a single transaction item from a converted event.
+fn create_envelope(event: Event, meta: &RequestMeta) -> Result<Envelope, BadStoreRequest> {
+    let event_id = event.id.value().cloned().unwrap_or_else(EventId::new);
+    let json = Annotated::new(event)
+        .to_json()
+        .map_err(BadStoreRequest::InvalidJson)?;
+
+    let mut item = Item::new(ItemType::Transaction);
+    item.set_payload(ContentType::Json, json);
+
+    let mut envelope = Envelope::from_request(Some(event_id), meta.clone());
+    envelope.add_item(item);
+    Ok(envelope)
+}
+
+fn extract_envelopes(
+    request: &HttpRequest<ServiceState>,
+    meta: RequestMeta,
+    encoding: Option<OtlpEncoding>,
+) -> ResponseFuture<Vec<Envelope>, BadStoreRequest> {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => return Box::new(future::err(BadStoreRequest::UnsupportedContentType)),
+    };
+
+    let max_payload_size = request.state().config().max_envelope_size();
+    let future = StoreBody::new(&request, max_payload_size)
+        .map_err(BadStoreRequest::PayloadError)
+        .and_then(move |data| {
+            if data.is_empty() {
+                return Err(BadStoreRequest::EmptyBody);
+            }
+
+            let traces = match encoding {
+                OtlpEncoding::Protobuf => {
+                    utils::decode_protobuf(&data).map_err(BadStoreRequest::InvalidProtobuf)?
+                }
+                OtlpEncoding::Json => {
+                    utils::decode_json(&data).map_err(BadStoreRequest::InvalidJson)?
+                }
+            };
+
+            if utils::count_spans(&traces) > utils::MAX_SPANS_PER_REQUEST {
+                return Err(BadStoreRequest::TooManySpans);
+            }
+
+            utils::convert_traces(traces)
+                .into_iter()
+                .map(|event| create_envelope(event, &meta))
+                .collect()
+        });
+
+    Box::new(future)
+}
+
+/// Creates an empty `ExportTraceServiceResponse`, which indicates full success.
+fn create_response(encoding: Option<OtlpEncoding>) -> HttpResponse {
+    match encoding.unwrap_or(OtlpEncoding::Json) {
+        OtlpEncoding::Protobuf => HttpResponse::Ok()
+            .content_type(OtlpEncoding::Protobuf.content_type())
+            .finish(),
+        OtlpEncoding::Json => HttpResponse::Ok()
+            .content_type(OtlpEncoding::Json.content_type())
+            .body("{}"),
+    }
+}
+
+/// Handler for the OTLP/HTTP traces endpoint.
+fn store_otlp_traces(
+    meta: RequestMeta,
+    request: HttpRequest<ServiceState>,
+) -> ResponseFuture<HttpResponse, BadStoreRequest> {
+    let encoding = OtlpEncoding::from_request(&request);
+    common::handle_store_like_batch(
+        meta,
+        request,
+        move |request, meta| extract_envelopes(request, meta, encoding),
+        move || create_response(encoding),
+    )
+}
+
+pub fn configure_app(app: ServiceApp) -> ServiceApp {
+    common::cors(app)
+        .resource(
+            &common::normpath(r"/api/{project:\d+}/otlp/v1/traces"),
+            |r| {
+                r.name("store-otlp-traces");
+                r.post().with(store_otlp_traces);
+            },
+        )
+        .register()
+}
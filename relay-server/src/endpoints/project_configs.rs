@@ -49,6 +49,9 @@ fn get_project_configs(
     let relay = body.relay;
     let full = relay.internal && body.inner.full_config;
 
+    // Downstream Relays registered with this Relay have access to all projects.
+    let allowed = state.config().is_allowed_relay(&relay.public_key);
+
     let futures = body.inner.projects.into_iter().map(move |project_id| {
         let relay = relay.clone();
         state
@@ -61,6 +64,7 @@ fn get_project_configs(
                 // If public key is known (even if rate-limited, which is Some(false)), it has
                 // access to the project config
                 if relay.internal
+                    || allowed
                     || project_state
                         .config
                         .trusted_relays
@@ -0,0 +1,31 @@
+//! Exposes internal metrics in the Prometheus text format.
+use actix_web::{http::header, HttpResponse};
+
+use relay_common::metrics;
+
+use crate::extractors::CurrentServiceState;
+use crate::service::ServiceApp;
+
+/// Content type of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[allow(clippy::needless_pass_by_value)]
+fn prometheus_metrics(state: CurrentServiceState) -> HttpResponse {
+    if !state.config().metrics_prometheus() {
+        return HttpResponse::NotFound().finish();
+    }
+
+    match metrics::prometheus_aggregator() {
+        Some(aggregator) => HttpResponse::Ok()
+            .header(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)
+            .body(aggregator.render()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+pub fn configure_app(app: ServiceApp) -> ServiceApp {
+    app.resource("/metrics", |r| {
+        r.name("internal-prometheus-metrics");
+        r.get().with(prometheus_metrics);
+    })
+}
@@ -0,0 +1,115 @@
+//! Endpoints for registering downstream Relays.
+//!
+//! If this Relay is configured as registration authority, downstream Relays register with it
+//! directly. Otherwise, registration requests are forwarded to the upstream.
+
+use ::actix::prelude::*;
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse};
+use futures::{future, prelude::*};
+
+use relay_auth::RegisterRequest;
+
+use crate::actors::relays::{CreateChallenge, RegisterRelay, RegistrationError};
+use crate::endpoints::forward;
+use crate::service::{ServiceApp, ServiceState};
+use crate::utils::ApiErrorResponse;
+
+/// Maximum age of signed register requests in minutes.
+const REGISTER_REQUEST_MAX_AGE: i64 = 15;
+
+/// Extracts the relay signature header from a register request.
+fn get_signature(request: &HttpRequest<ServiceState>) -> Result<String, HttpResponse> {
+    request
+        .headers()
+        .get("X-Sentry-Relay-Signature")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .ok_or_else(|| {
+            HttpResponse::Unauthorized().json(ApiErrorResponse::with_detail(
+                "missing header: X-Sentry-Relay-Signature",
+            ))
+        })
+}
+
+fn register_challenge(request: &HttpRequest<ServiceState>) -> ResponseFuture<HttpResponse, Error> {
+    let config = request.state().config();
+    if !config.registration_authority() {
+        return forward::forward_upstream(request);
+    }
+
+    let signature = match get_signature(request) {
+        Ok(signature) => signature,
+        Err(response) => return Box::new(future::ok(response)),
+    };
+
+    let relay_cache = request.state().relay_cache();
+    let max_age = chrono::Duration::minutes(REGISTER_REQUEST_MAX_AGE);
+
+    let future = request
+        .body()
+        .limit(config.max_api_payload_size())
+        .map_err(Error::from)
+        .and_then(move |body| {
+            RegisterRequest::bootstrap_unpack(&body, &signature, Some(max_age))
+                .map_err(|error| Error::from(RegistrationError::BadSignature(error)))
+        })
+        .and_then(move |register_request| {
+            relay_cache
+                .send(CreateChallenge(register_request))
+                .map_err(Error::from)
+        })
+        .and_then(|result| result.map_err(Error::from))
+        .map(|challenge| HttpResponse::Ok().json(challenge));
+
+    Box::new(future)
+}
+
+fn register_response(request: &HttpRequest<ServiceState>) -> ResponseFuture<HttpResponse, Error> {
+    let config = request.state().config();
+    if !config.registration_authority() {
+        return forward::forward_upstream(request);
+    }
+
+    let signature = match get_signature(request) {
+        Ok(signature) => signature,
+        Err(response) => return Box::new(future::ok(response)),
+    };
+
+    // Relays rotating their credentials also sign the response with their current key.
+    let current_signature = request
+        .headers()
+        .get("X-Sentry-Relay-Current-Signature")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    let relay_cache = request.state().relay_cache();
+
+    let future = request
+        .body()
+        .limit(config.max_api_payload_size())
+        .map_err(Error::from)
+        .and_then(move |data| {
+            relay_cache
+                .send(RegisterRelay {
+                    data,
+                    signature,
+                    current_signature,
+                })
+                .map_err(Error::from)
+        })
+        .and_then(|result| result.map_err(Error::from))
+        .map(|registration| HttpResponse::Ok().json(registration));
+
+    Box::new(future)
+}
+
+pub fn configure_app(app: ServiceApp) -> ServiceApp {
+    app.resource("/api/0/relays/register/challenge/", |r| {
+        r.name("relay-register-challenge");
+        r.post().f(register_challenge);
+    })
+    .resource("/api/0/relays/register/response/", |r| {
+        r.name("relay-register-response");
+        r.post().f(register_response);
+    })
+}
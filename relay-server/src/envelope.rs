@@ -91,6 +91,16 @@ pub enum ItemType {
     UserReport,
     /// Session update data.
     Session,
+    /// Aggregated session data.
+    Sessions,
+    /// Custom metrics in a statsd-like line format.
+    Metrics,
+    /// Counters of data discarded by the client, encoded as JSON.
+    ClientReport,
+    /// Sampled profile of a transaction, encoded as JSON.
+    Profile,
+    /// Check-in of a scheduled job for monitoring, encoded as JSON.
+    CheckIn,
 }
 
 impl ItemType {
@@ -118,6 +128,11 @@ impl fmt::Display for ItemType {
             Self::UnrealReport => write!(f, "unreal report"),
             Self::UserReport => write!(f, "user feedback"),
             Self::Session => write!(f, "session"),
+            Self::Sessions => write!(f, "aggregated sessions"),
+            Self::Metrics => write!(f, "metrics"),
+            Self::ClientReport => write!(f, "client report"),
+            Self::Profile => write!(f, "profile"),
+            Self::CheckIn => write!(f, "check-in"),
         }
     }
 }
@@ -500,13 +515,20 @@ impl Item {
             ItemType::FormData => false,
 
             // The remaining item types cannot carry event payloads.
-            ItemType::UserReport | ItemType::Session => false,
+            ItemType::UserReport
+            | ItemType::Session
+            | ItemType::Sessions
+            | ItemType::Metrics
+            | ItemType::ClientReport
+            | ItemType::Profile
+            | ItemType::CheckIn => false,
         }
     }
 
     /// Determines whether the given item requires an event with identifier.
     ///
-    /// This is true for all items except session health events.
+    /// This is true for all items except session health events, custom metrics, client reports, and
+    /// check-ins.
     pub fn requires_event(&self) -> bool {
         match self.ty() {
             ItemType::Event => true,
@@ -518,6 +540,11 @@ impl Item {
             ItemType::UnrealReport => true,
             ItemType::UserReport => true,
             ItemType::Session => false,
+            ItemType::Sessions => false,
+            ItemType::Metrics => false,
+            ItemType::ClientReport => false,
+            ItemType::Profile => true,
+            ItemType::CheckIn => false,
         }
     }
 }
@@ -525,6 +552,48 @@ impl Item {
 pub type Items = SmallVec<[Item; 3]>;
 pub type ItemIter<'a> = std::slice::Iter<'a, Item>;
 
+/// User information in the trace context of an envelope.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct TraceUserContext {
+    /// The segment of the user, such as a subscription plan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment: Option<String>,
+
+    /// Other attributes for forward compatibility.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+/// The trace context of the event in an envelope.
+///
+/// The trace context is set by the SDK that starts a trace and is sent along with all events of
+/// that trace. It contains the attributes needed to make a sampling decision, so that events can be
+/// sampled before their payload is parsed.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TraceContext {
+    /// The trace ID, a random 128-bit hex string.
+    pub trace_id: String,
+
+    /// The public key of the project that started the trace.
+    pub public_key: String,
+
+    /// The release of the application that started the trace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+
+    /// The environment of the application that started the trace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+
+    /// The user that started the trace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<TraceUserContext>,
+
+    /// Other attributes for forward compatibility.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct EnvelopeHeaders<M = RequestMeta> {
     /// Unique identifier of the event associated to this envelope.
@@ -551,6 +620,19 @@ pub struct EnvelopeHeaders<M = RequestMeta> {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     sent_at: Option<DateTime<Utc>>,
 
+    /// Raw trace context header, set by the SDK.
+    ///
+    /// This is serialized verbatim so that downstream Relays propagate the header unchanged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trace: Option<serde_json::Value>,
+
+    /// Trace context of the event in this envelope, parsed from the `trace` header.
+    ///
+    /// This allows to sample transactions without parsing the event payload. An invalid trace
+    /// context is ignored.
+    #[serde(skip)]
+    trace_context: Option<TraceContext>,
+
     /// Other attributes for forward compatibility.
     #[serde(flatten)]
     other: BTreeMap<String, Value>,
@@ -590,6 +672,8 @@ impl EnvelopeHeaders<PartialMeta> {
             meta: meta.copy_to(request_meta),
             retention: self.retention,
             sent_at: self.sent_at,
+            trace: self.trace,
+            trace_context: self.trace_context,
             other: self.other,
         })
     }
@@ -610,6 +694,8 @@ impl Envelope {
                 meta,
                 retention: None,
                 sent_at: None,
+                trace: None,
+                trace_context: None,
                 other: BTreeMap::new(),
             },
             items: Items::new(),
@@ -617,7 +703,6 @@ impl Envelope {
     }
 
     /// Parses an envelope from bytes.
-    #[allow(dead_code)]
     pub fn parse_bytes(bytes: Bytes) -> Result<Self, EnvelopeError> {
         let (headers, offset) = Self::parse_headers(&bytes)?;
         let items = Self::parse_items(&bytes, offset)?;
@@ -689,6 +774,11 @@ impl Envelope {
         self.headers.sent_at = Some(sent_at);
     }
 
+    /// Returns the trace context of the event in this envelope, if set by the SDK.
+    pub fn trace_context(&self) -> Option<&TraceContext> {
+        self.headers.trace_context.as_ref()
+    }
+
     /// Sets the data retention in days for items in this envelope.
     pub fn set_retention(&mut self, retention: u16) {
         self.headers.retention = Some(retention);
@@ -813,12 +903,19 @@ impl Envelope {
     {
         let mut stream = serde_json::Deserializer::from_slice(slice).into_iter();
 
-        let headers = match stream.next() {
+        let mut headers: EnvelopeHeaders<M> = match stream.next() {
             None => return Err(EnvelopeError::MissingHeader),
             Some(Err(error)) => return Err(EnvelopeError::InvalidHeader(error)),
             Some(Ok(headers)) => headers,
         };
 
+        // The trace context is optional for processing, so a malformed header is treated as if it
+        // was missing rather than rejecting the entire envelope.
+        headers.trace_context = headers
+            .trace
+            .as_ref()
+            .and_then(|value| TraceContext::deserialize(value).ok());
+
         // Each header is terminated by a UNIX newline.
         Self::require_termination(slice, stream.byte_offset())?;
 
@@ -1188,6 +1285,52 @@ mod tests {
         Envelope::parse_request(bytes, request_meta()).unwrap();
     }
 
+    #[test]
+    fn test_parse_request_trace_context() {
+        let bytes = Bytes::from("{\"event_id\":\"9ec79c33ec9942ab8353589fcb2e04dc\",\"trace\":{\"trace_id\":\"4c79f60c11214eb38604f4ae0781bfb2\",\"public_key\":\"e12d836b15bb49d7bbf99e64295d995b\",\"release\":\"1.0.0\",\"user\":{\"segment\":\"vip\"},\"foo\":\"bar\"}}");
+        let envelope = Envelope::parse_request(bytes, request_meta()).unwrap();
+
+        let trace = envelope.trace_context().unwrap();
+        assert_eq!(trace.trace_id, "4c79f60c11214eb38604f4ae0781bfb2");
+        assert_eq!(trace.public_key, "e12d836b15bb49d7bbf99e64295d995b");
+        assert_eq!(trace.release.as_deref(), Some("1.0.0"));
+        assert_eq!(trace.environment, None);
+
+        let segment = trace.user.as_ref().and_then(|user| user.segment.as_deref());
+        assert_eq!(segment, Some("vip"));
+
+        assert_eq!(trace.other["foo"], Value::String("bar".to_owned()));
+
+        // The header is propagated unchanged, including unknown attributes.
+        let mut buffer = Vec::new();
+        envelope.serialize(&mut buffer).unwrap();
+        let parsed = Envelope::parse_bytes(buffer.into()).unwrap();
+        assert_eq!(parsed.trace_context(), Some(trace));
+        assert_eq!(parsed.headers.trace, envelope.headers.trace);
+    }
+
+    #[test]
+    fn test_parse_request_invalid_trace_context() {
+        // The trace context lacks the public key and has an invalid release.
+        let bytes = Bytes::from("{\"event_id\":\"9ec79c33ec9942ab8353589fcb2e04dc\",\"trace\":{\"trace_id\":\"4c79f60c11214eb38604f4ae0781bfb2\",\"release\":42}}\n{\"type\":\"transaction\",\"length\":2}\n{}");
+        let envelope = Envelope::parse_request(bytes, request_meta()).unwrap();
+        assert_eq!(envelope.trace_context(), None);
+        assert_eq!(envelope.len(), 1);
+
+        // Invalid headers are still propagated verbatim.
+        let mut buffer = Vec::new();
+        envelope.serialize(&mut buffer).unwrap();
+        let parsed = Envelope::parse_bytes(buffer.into()).unwrap();
+        assert_eq!(parsed.headers.trace, envelope.headers.trace);
+        assert!(parsed.headers.trace.is_some());
+
+        let bytes = Bytes::from(
+            "{\"event_id\":\"9ec79c33ec9942ab8353589fcb2e04dc\",\"trace\":\"invalid\"}",
+        );
+        let envelope = Envelope::parse_request(bytes, request_meta()).unwrap();
+        assert_eq!(envelope.trace_context(), None);
+    }
+
     #[test]
     fn test_serialize_envelope_empty() {
         let event_id = EventId("9ec79c33ec9942ab8353589fcb2e04dc".parse().unwrap());
@@ -0,0 +1,80 @@
+use actix_web::http::header;
+use actix_web::{Error, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use failure::Fail;
+
+use crate::service::ServiceState;
+use crate::utils::ApiErrorResponse;
+
+const BEARER_PREFIX: &str = "Bearer ";
+
+#[derive(Fail, Debug)]
+enum AdminAuthError {
+    #[fail(display = "admin API is not enabled")]
+    Disabled,
+    #[fail(display = "missing or invalid admin token")]
+    InvalidToken,
+}
+
+impl ResponseError for AdminAuthError {
+    fn error_response(&self) -> HttpResponse {
+        let response = ApiErrorResponse::from_fail(self);
+        match self {
+            AdminAuthError::Disabled => HttpResponse::NotFound().json(&response),
+            AdminAuthError::InvalidToken => HttpResponse::Unauthorized().json(&response),
+        }
+    }
+}
+
+/// Compares two strings in constant time with respect to their contents.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+/// Guards endpoints of the admin API.
+///
+/// Requests must carry the token configured in `admin.token` in an `Authorization: Bearer` header.
+/// If no token is configured, the admin API is disabled and all requests are rejected.
+#[derive(Debug)]
+pub struct AdminAuth;
+
+impl FromRequest<ServiceState> for AdminAuth {
+    type Config = ();
+    type Result = Result<Self, Error>;
+
+    fn from_request(request: &HttpRequest<ServiceState>, _cfg: &Self::Config) -> Self::Result {
+        let config = request.state().config();
+        let expected = config.admin_token().ok_or(AdminAuthError::Disabled)?;
+
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with(BEARER_PREFIX))
+            .map(|value| &value[BEARER_PREFIX.len()..]);
+
+        match token {
+            Some(token) if constant_time_eq(token, expected) => Ok(AdminAuth),
+            _ => Err(AdminAuthError::InvalidToken.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secret", "secreT"));
+        assert!(!constant_time_eq("secret", "secret2"));
+        assert!(constant_time_eq("", ""));
+    }
+}
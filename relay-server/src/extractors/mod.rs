@@ -2,11 +2,13 @@ use actix_web::State;
 
 use crate::service::ServiceState;
 
+mod admin_auth;
 mod forwarded_for;
 mod request_meta;
 mod signed_json;
 mod start_time;
 
+pub use self::admin_auth::*;
 pub use self::forwarded_for::*;
 pub use self::request_meta::*;
 pub use self::signed_json::*;
@@ -78,8 +78,7 @@ impl<T: DeserializeOwned + 'static> FromRequest<ServiceState> for SignedJson<T>
             .join(req.body().map_err(Error::from))
             .and_then(move |(relay, body)| {
                 relay
-                    .public_key
-                    .unpack(&body, &relay_sig, None)
+                    .unpack(&body, &relay_sig)
                     .map(|inner| SignedJson { inner, relay })
                     .map_err(|_| Error::from(SignatureError::BadSignature))
             });
@@ -26,6 +26,8 @@ use crate::actors::controller::Controller;
 use crate::actors::server::Server;
 
 pub use crate::actors::controller::ServerError;
+pub use crate::metrics::init_metrics;
+pub use crate::middlewares::ACCESS_LOG_TARGET;
 
 /// Runs a relay web server and spawns all internal worker threads.
 ///
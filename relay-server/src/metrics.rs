@@ -1,4 +1,50 @@
-use relay_common::metrics::{CounterMetric, HistogramMetric, SetMetric, TimerMetric};
+use std::collections::BTreeMap;
+
+use relay_common::metrics::{self, CounterMetric, HistogramMetric, SetMetric, TimerMetric};
+use relay_config::{Config, ConfigError};
+
+/// Returns the configured default tags, including the hostname tag if enabled.
+fn default_tags(config: &Config) -> BTreeMap<String, String> {
+    let mut default_tags = config.metrics_default_tags().clone();
+    if let Some(hostname_tag) = config.metrics_hostname_tag() {
+        if let Some(hostname) = hostname::get().ok().and_then(|s| s.into_string().ok()) {
+            default_tags.insert(hostname_tag.to_owned(), hostname);
+        }
+    }
+    default_tags
+}
+
+/// Initializes the metrics system.
+///
+/// Metrics are reported to statsd and aggregated for Prometheus as configured. If neither is
+/// configured, metrics are disabled.
+pub fn init_metrics(config: &Config) -> Result<(), ConfigError> {
+    let addrs = config.statsd_addrs()?;
+    if addrs.is_empty() && !config.metrics_prometheus() {
+        return Ok(());
+    }
+
+    let default_tags = default_tags(config);
+    if config.metrics_prometheus() {
+        let statsd_host = if addrs.is_empty() {
+            None
+        } else {
+            Some(&addrs[..])
+        };
+        metrics::configure_prometheus(config.metrics_prefix(), statsd_host, default_tags);
+    } else {
+        metrics::configure_statsd(config.metrics_prefix(), &addrs[..], default_tags);
+    }
+
+    Ok(())
+}
+
+/// Applies the metric prefix and default tags of a reloaded config.
+///
+/// Changes to the statsd address or the Prometheus endpoint require a restart.
+pub fn reload_metrics(config: &Config) {
+    metrics::reconfigure(config.metrics_prefix(), default_tags(config));
+}
 
 /// Set metrics used by Relay
 pub enum RelaySets {
@@ -33,6 +79,11 @@ pub enum RelayHistograms {
     /// Once the envelope finishes processing and is sent downstream, the envelope is considered
     /// handled and it leaves the queue.
     EnvelopeQueueSize,
+    /// The combined size of all envelopes in the on-disk spool in bytes.
+    ///
+    /// This metric is only reported if `spool.path` is configured. Envelopes are written to the
+    /// spool if the event queue is full or the upstream is not available.
+    SpoolSize,
     /// The size of the request body as seen by Relay after it is extracted from a request.
     ///
     /// For envelope requests, this is the full size of the envelope. For JSON store requests, this
@@ -63,6 +114,7 @@ impl HistogramMetric for RelayHistograms {
         match self {
             RelayHistograms::EnvelopeQueueSizePct => "event.queue_size.pct",
             RelayHistograms::EnvelopeQueueSize => "event.queue_size",
+            RelayHistograms::SpoolSize => "spool.size",
             RelayHistograms::RequestSizeBytesRaw => "event.size_bytes.raw",
             RelayHistograms::RequestSizeBytesUncompressed => "event.size_bytes.uncompressed",
             RelayHistograms::ProjectStatePending => "project_state.pending",
@@ -174,6 +226,8 @@ pub enum RelayCounters {
     /// rejected because they are malformed or any other errors during processing (including
     /// filtered events, invalid payloads and rate limits).
     EnvelopeRejected,
+    /// Number of envelopes written to the on-disk spool instead of being queued for processing.
+    EnvelopeSpooled,
     /// Represents a group of counters incremented for every outcome emitted by Relay, implemented
     /// with tags. The following tags are present for each event outcome:
     ///
@@ -254,6 +308,22 @@ pub enum RelayCounters {
     ConnectorErrors,
     /// The number of upstream connections that experienced a timeout.
     ConnectorTimeouts,
+    /// The number of custom metric buckets flushed from the aggregator.
+    MetricBucketsFlushed,
+    /// The number of custom metric values dropped before aggregation.
+    ///
+    /// This metric is tagged with:
+    ///  - `reason`: Either `name_length` for metric names exceeding the maximum length,
+    ///    `timestamp` for metrics with timestamps too far in the past or future, or `cardinality`
+    ///    for values exceeding the maximum number of tag combinations.
+    MetricsDropped,
+    /// The number of values that the PII audit config of a project would redact.
+    ///
+    /// The audit config is applied in a dry run before regular PII scrubbing and does not modify
+    /// events. This metric is tagged with:
+    ///  - `rule`: The identifier of the matching builtin rule, such as `@email`, or `custom` for
+    ///    rules defined in the config.
+    PiiAuditMatches,
 }
 
 impl CounterMetric for RelayCounters {
@@ -261,6 +331,7 @@ impl CounterMetric for RelayCounters {
         match self {
             RelayCounters::EnvelopeAccepted => "event.accepted",
             RelayCounters::EnvelopeRejected => "event.rejected",
+            RelayCounters::EnvelopeSpooled => "event.spooled",
             #[cfg(feature = "processing")]
             RelayCounters::Outcomes => "events.outcomes",
             RelayCounters::ProjectStateGet => "project_state.get",
@@ -282,6 +353,9 @@ impl CounterMetric for RelayCounters {
             RelayCounters::ConnectorClosed => "connector.closed",
             RelayCounters::ConnectorErrors => "connector.errors",
             RelayCounters::ConnectorTimeouts => "connector.timeouts",
+            RelayCounters::MetricBucketsFlushed => "metrics.buckets.flushed",
+            RelayCounters::MetricsDropped => "metrics.dropped",
+            RelayCounters::PiiAuditMatches => "event.pii_audit.matches",
         }
     }
 }
@@ -4,12 +4,19 @@ use actix_web::error::Error;
 use actix_web::middleware::{Finished, Middleware, Response, Started};
 use actix_web::{http::header, Body, HttpMessage, HttpRequest, HttpResponse};
 use futures::prelude::*;
+use serde::Serialize;
 
-use relay_common::metric;
+use relay_common::{metric, ProjectId};
+use relay_config::Config;
 
+use crate::actors::outcome::Outcome;
 use crate::constants::SERVER;
 use crate::metrics::{RelayCounters, RelayTimers};
-use crate::utils::ApiErrorResponse;
+use crate::service::ServiceState;
+use crate::utils::{ApiErrorResponse, RequestBodySize};
+
+/// The log target of access log records.
+pub const ACCESS_LOG_TARGET: &str = "relay_server::access_log";
 
 /// Basic metrics
 pub struct Metrics;
@@ -101,12 +108,249 @@ pub struct ReadRequestMiddleware;
 
 impl<S> Middleware<S> for ReadRequestMiddleware {
     fn response(&self, req: &HttpRequest<S>, resp: HttpResponse) -> Result<Response, Error> {
+        let size = RequestBodySize::of(req);
         let future = req
             .payload()
-            .for_each(|_| Ok(()))
+            .for_each(move |chunk| {
+                size.add(chunk.len());
+                Ok(())
+            })
             .map(|_| resp)
             .map_err(Error::from);
 
         Ok(Response::Future(Box::new(future)))
     }
 }
+
+/// Request details recorded by endpoints for the access log.
+///
+/// Endpoints insert this into the request extensions once they know the project and the outcome
+/// of a request. All fields are optional, since not all endpoints handle project requests.
+///
+/// Store endpoints insert this in `handle_store_like_request`. Requests rejected before, for
+/// instance due to missing or invalid authentication, never carry this information. They are
+/// skipped if the access log is filtered by project IDs or public keys.
+#[derive(Clone, Debug, Default)]
+pub struct AccessLogInfo {
+    /// The project the request was sent to.
+    pub project_id: Option<ProjectId>,
+    /// The public key used to authenticate the request.
+    pub public_key: Option<String>,
+    /// The outcome, if the request was rejected.
+    pub outcome: Option<Outcome>,
+}
+
+/// A structured access log record.
+#[derive(Debug, Serialize)]
+struct AccessLogEntry<'a> {
+    route: &'a str,
+    method: &'a str,
+    path: &'a str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project_id: Option<ProjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    public_key: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_size: Option<usize>,
+    duration_ms: f64,
+}
+
+/// Returns `true` if a request passes the filters and sampling of the access log.
+fn is_access_logged(config: &Config, status: u16, info: Option<&AccessLogInfo>) -> bool {
+    if !config.access_log_enabled() {
+        return false;
+    }
+
+    let status_codes = config.access_log_status_codes();
+    if !status_codes.is_empty() && !status_codes.iter().any(|f| f.matches(status)) {
+        return false;
+    }
+
+    let project_ids = config.access_log_project_ids();
+    let project_id = info.and_then(|info| info.project_id);
+    if !project_ids.is_empty() && !project_id.map_or(false, |id| project_ids.contains(&id.value()))
+    {
+        return false;
+    }
+
+    let public_keys = config.access_log_public_keys();
+    let public_key = info.and_then(|info| info.public_key.as_deref());
+    if !public_keys.is_empty()
+        && !public_key.map_or(false, |key| public_keys.iter().any(|k| k == key))
+    {
+        return false;
+    }
+
+    let sample_rate = config.access_log_sample_rate();
+    sample_rate >= 1.0 || rand::random::<f64>() < sample_rate
+}
+
+/// Writes sampled requests to the access log.
+///
+/// Records are logged with the target [`ACCESS_LOG_TARGET`] and contain a JSON object with details
+/// on the request. Which requests are logged is controlled by the `logging.access_log` section of
+/// the config. Endpoints can add details using [`AccessLogInfo`].
+///
+/// [`ACCESS_LOG_TARGET`]: constant.ACCESS_LOG_TARGET.html
+/// [`AccessLogInfo`]: struct.AccessLogInfo.html
+pub struct AccessLog;
+
+impl Middleware<ServiceState> for AccessLog {
+    fn finish(&self, req: &HttpRequest<ServiceState>, resp: &HttpResponse) -> Finished {
+        let config = req.state().config();
+        let status = resp.status().as_u16();
+        let extensions = req.extensions();
+        let info = extensions.get::<AccessLogInfo>();
+
+        if !is_access_logged(&config, status, info) {
+            return Finished::Done;
+        }
+
+        let project_id = info.and_then(|info| info.project_id);
+        let public_key = info.and_then(|info| info.public_key.as_deref());
+        let outcome = info.and_then(|info| info.outcome.as_ref());
+        let duration = extensions
+            .get::<StartTime>()
+            .map(|start_time| start_time.0.elapsed())
+            .unwrap_or_default();
+
+        // Prefer the number of bytes actually read, since chunked requests have no content length.
+        let request_size = extensions
+            .get::<RequestBodySize>()
+            .map(RequestBodySize::get)
+            .or_else(|| {
+                req.headers()
+                    .get(header::CONTENT_LENGTH)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+            });
+
+        let response_size = match resp.body() {
+            Body::Binary(binary) => Some(binary.len()),
+            _ => None,
+        };
+
+        let entry = AccessLogEntry {
+            route: req.resource().name(),
+            method: req.method().as_str(),
+            path: req.path(),
+            status,
+            project_id,
+            public_key,
+            outcome: outcome.map(Outcome::name),
+            reason: outcome.and_then(Outcome::to_reason),
+            request_size,
+            response_size,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+        };
+
+        if let Ok(json) = serde_json::to_string(&entry) {
+            // The access log is enabled independently of `logging.level`. Bypass the global maximum
+            // level, which `log::info!` would check first.
+            log::logger().log(
+                &log::Record::builder()
+                    .args(format_args!("{}", json))
+                    .level(log::Level::Info)
+                    .target(ACCESS_LOG_TARGET)
+                    .module_path(Some(module_path!()))
+                    .file(Some(file!()))
+                    .line(Some(line!()))
+                    .build(),
+            );
+        }
+
+        Finished::Done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn access_log_config(access_log: serde_json::Value) -> Config {
+        Config::from_json_value(json!({ "logging": { "access_log": access_log } })).unwrap()
+    }
+
+    fn access_log_info(project_id: u64, public_key: &str) -> AccessLogInfo {
+        AccessLogInfo {
+            project_id: Some(ProjectId::new(project_id)),
+            public_key: Some(public_key.to_owned()),
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn test_access_log_disabled() {
+        let config = access_log_config(json!({}));
+        assert!(!is_access_logged(&config, 200, None));
+    }
+
+    #[test]
+    fn test_access_log_status_codes() {
+        let config = access_log_config(json!({
+            "enabled": true,
+            "status_codes": ["4xx", 503],
+        }));
+
+        assert!(is_access_logged(&config, 400, None));
+        assert!(is_access_logged(&config, 429, None));
+        assert!(is_access_logged(&config, 503, None));
+        assert!(!is_access_logged(&config, 200, None));
+        assert!(!is_access_logged(&config, 500, None));
+    }
+
+    #[test]
+    fn test_access_log_project_ids() {
+        let config = access_log_config(json!({
+            "enabled": true,
+            "project_ids": [42],
+        }));
+
+        let info = access_log_info(42, "a94ae32be2584e0bbd7a4cbb95971fee");
+        assert!(is_access_logged(&config, 200, Some(&info)));
+
+        let info = access_log_info(43, "a94ae32be2584e0bbd7a4cbb95971fee");
+        assert!(!is_access_logged(&config, 200, Some(&info)));
+
+        // Requests rejected before the project is known are skipped.
+        assert!(!is_access_logged(&config, 401, None));
+        assert!(!is_access_logged(
+            &config,
+            401,
+            Some(&AccessLogInfo::default())
+        ));
+    }
+
+    #[test]
+    fn test_access_log_public_keys() {
+        let config = access_log_config(json!({
+            "enabled": true,
+            "public_keys": ["a94ae32be2584e0bbd7a4cbb95971fee"],
+        }));
+
+        let info = access_log_info(42, "a94ae32be2584e0bbd7a4cbb95971fee");
+        assert!(is_access_logged(&config, 200, Some(&info)));
+
+        let info = access_log_info(42, "e12d836b15bb49d7bbf99e64295d995b");
+        assert!(!is_access_logged(&config, 200, Some(&info)));
+        assert!(!is_access_logged(&config, 200, None));
+    }
+
+    #[test]
+    fn test_access_log_sample_rate() {
+        let config = access_log_config(json!({"enabled": true, "sample_rate": 0.0}));
+        assert!((0..100).all(|_| !is_access_logged(&config, 200, None)));
+
+        let config = access_log_config(json!({"enabled": true, "sample_rate": 1.0}));
+        assert!((0..100).all(|_| is_access_logged(&config, 200, None)));
+    }
+}
@@ -6,6 +6,7 @@ use actix_web::{server, App};
 use failure::ResultExt;
 use failure::{Backtrace, Context, Fail};
 use listenfd::ListenFd;
+use parking_lot::RwLock;
 use sentry_actix::SentryMiddleware;
 
 use relay_common::clone;
@@ -20,9 +21,12 @@ use crate::actors::outcome::OutcomeProducer;
 use crate::actors::project_cache::ProjectCache;
 use crate::actors::project_keys::ProjectKeyLookup;
 use crate::actors::relays::RelayCache;
+use crate::actors::reload::ConfigReloader;
 use crate::actors::upstream::UpstreamRelay;
 use crate::endpoints;
-use crate::middlewares::{AddCommonHeaders, ErrorHandlers, Metrics, ReadRequestMiddleware};
+use crate::middlewares::{
+    AccessLog, AddCommonHeaders, ErrorHandlers, Metrics, ReadRequestMiddleware,
+};
 
 /// Common error type for the relay server.
 #[derive(Debug)]
@@ -64,6 +68,10 @@ pub enum ServerErrorKind {
     /// Initializing the Redis cluster client failed.
     #[fail(display = "could not initialize redis cluster client")]
     RedisError,
+
+    /// Opening the envelope spool failed.
+    #[fail(display = "could not open the envelope spool")]
+    SpoolError,
 }
 
 impl Fail for ServerError {
@@ -106,7 +114,7 @@ impl From<Context<ServerErrorKind>> for ServerError {
 /// Server state.
 #[derive(Clone)]
 pub struct ServiceState {
-    config: Arc<Config>,
+    config: Arc<RwLock<Arc<Config>>>,
     relay_cache: Addr<RelayCache>,
     project_cache: Addr<ProjectCache>,
     upstream_relay: Addr<UpstreamRelay>,
@@ -131,33 +139,49 @@ impl ServiceState {
             _ => None,
         };
 
+        let project_cache =
+            ProjectCache::new(config.clone(), upstream_relay.clone(), redis_pool.clone()).start();
+
         let event_manager = EventManager::create(
             config.clone(),
             upstream_relay.clone(),
+            project_cache.clone(),
             outcome_producer.clone(),
-            redis_pool.clone(),
+            redis_pool,
         )
         .context(ServerErrorKind::ConfigError)?
         .start();
 
-        let project_cache =
-            ProjectCache::new(config.clone(), upstream_relay.clone(), redis_pool).start();
+        let healthcheck = Healthcheck::new(config.clone(), upstream_relay.clone()).start();
+        let relay_cache = RelayCache::new(config.clone(), upstream_relay.clone()).start();
+
+        let shared_config = Arc::new(RwLock::new(config.clone()));
+        ConfigReloader::new(shared_config.clone())
+            .register(&event_manager)
+            .register(&project_cache)
+            .register(&upstream_relay)
+            .register(&outcome_producer)
+            .register(&healthcheck)
+            .register(&relay_cache)
+            .start();
 
         Ok(ServiceState {
-            config: config.clone(),
-            key_lookup: ProjectKeyLookup::new(config.clone(), upstream_relay.clone()).start(),
-            upstream_relay: upstream_relay.clone(),
-            relay_cache: RelayCache::new(config.clone(), upstream_relay.clone()).start(),
+            config: shared_config,
+            key_lookup: ProjectKeyLookup::new(config, upstream_relay.clone()).start(),
+            upstream_relay,
+            relay_cache,
             project_cache,
-            healthcheck: Healthcheck::new(config, upstream_relay).start(),
+            healthcheck,
             event_manager,
             outcome_producer,
         })
     }
 
-    /// Returns an atomically counted reference to the config.
+    /// Returns an atomically counted reference to the current config.
+    ///
+    /// The config can be replaced at runtime when the process receives `SIGHUP`.
     pub fn config(&self) -> Arc<Config> {
-        self.config.clone()
+        self.config.read().clone()
     }
 
     /// Returns the current relay public key cache.
@@ -165,6 +189,11 @@ impl ServiceState {
         self.relay_cache.clone()
     }
 
+    /// Returns the actor for requests to the upstream.
+    pub fn upstream_relay(&self) -> Addr<UpstreamRelay> {
+        self.upstream_relay.clone()
+    }
+
     /// Returns the current project cache.
     pub fn project_cache(&self) -> Addr<ProjectCache> {
         self.project_cache.clone()
@@ -197,6 +226,7 @@ fn make_app(state: ServiceState) -> ServiceApp {
     App::with_state(state)
         .middleware(SentryMiddleware::new())
         .middleware(Metrics)
+        .middleware(AccessLog)
         .middleware(AddCommonHeaders)
         .middleware(ErrorHandlers)
         .middleware(ReadRequestMiddleware)
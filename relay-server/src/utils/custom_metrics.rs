@@ -0,0 +1,340 @@
+//! Protocol for custom metrics sent in envelopes.
+//!
+//! Metrics are sent in `metrics` items in a statsd-like line format. Each line contains one or more
+//! values of a single metric:
+//!
+//! ```plain
+//! <name>:<value>[:<value>...]|<type>[|#<key>:<value>,...][|T<timestamp>]
+//! ```
+//!
+//! The type is one of `c` (counter), `d` (distribution), `s` (set), or `g` (gauge). Set values
+//! that are not unsigned 32-bit integers are hashed. If the timestamp is omitted, the time at which
+//! Relay received the item is used. Example:
+//!
+//! ```plain
+//! endpoint.response_time:57:68|d|#route:user_index,env:prod|T1597130000
+//! endpoint.hits:1|c|#route:user_index
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+
+use relay_common::LogError;
+
+/// An error returned when parsing a metric line fails.
+#[derive(Clone, Copy, Debug, Eq, Fail, PartialEq)]
+pub enum ParseMetricError {
+    /// The line is not valid UTF-8.
+    #[fail(display = "metric is not valid utf-8")]
+    InvalidUtf8,
+    /// The name or type are missing or invalid.
+    #[fail(display = "invalid metric name or type")]
+    InvalidFormat,
+    /// One of the values cannot be parsed for the metric type.
+    #[fail(display = "invalid metric value")]
+    InvalidValue,
+    /// The tags or timestamp are invalid.
+    #[fail(display = "invalid metric tags or timestamp")]
+    InvalidSuffix,
+}
+
+/// The type of a metric, which determines how its values are aggregated.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricType {
+    /// Values are summed up.
+    Counter,
+    /// All values are retained.
+    Distribution,
+    /// Unique values are counted.
+    Set,
+    /// The last value is retained, along with the minimum, maximum, sum, and count.
+    Gauge,
+}
+
+impl MetricType {
+    /// Returns the metric type for the type code in the line format.
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "c" => Some(Self::Counter),
+            "d" => Some(Self::Distribution),
+            "s" => Some(Self::Set),
+            "g" => Some(Self::Gauge),
+            _ => None,
+        }
+    }
+
+    /// Returns the type code of this metric type in the line format.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Counter => "c",
+            Self::Distribution => "d",
+            Self::Set => "s",
+            Self::Gauge => "g",
+        }
+    }
+}
+
+impl fmt::Display for MetricType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Counter => write!(f, "counter"),
+            Self::Distribution => write!(f, "distribution"),
+            Self::Set => write!(f, "set"),
+            Self::Gauge => write!(f, "gauge"),
+        }
+    }
+}
+
+/// A single value of a metric.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetricValue {
+    /// A value added to a counter.
+    Counter(f64),
+    /// A value added to a distribution.
+    Distribution(f64),
+    /// A value added to a set.
+    Set(u32),
+    /// A value set on a gauge.
+    Gauge(f64),
+}
+
+impl MetricValue {
+    /// Parses a value of the given metric type.
+    fn parse(ty: MetricType, value: &str) -> Result<Self, ParseMetricError> {
+        let float = || match value.parse::<f64>() {
+            Ok(float) if float.is_finite() => Ok(float),
+            _ => Err(ParseMetricError::InvalidValue),
+        };
+
+        Ok(match ty {
+            MetricType::Counter => Self::Counter(float()?),
+            MetricType::Distribution => Self::Distribution(float()?),
+            MetricType::Set => Self::Set(hash_set_value(value)),
+            MetricType::Gauge => Self::Gauge(float()?),
+        })
+    }
+
+    /// Returns the type of this value.
+    pub fn ty(self) -> MetricType {
+        match self {
+            Self::Counter(_) => MetricType::Counter,
+            Self::Distribution(_) => MetricType::Distribution,
+            Self::Set(_) => MetricType::Set,
+            Self::Gauge(_) => MetricType::Gauge,
+        }
+    }
+}
+
+/// Converts a set value into a 32-bit integer.
+///
+/// Integer values are used verbatim, so that hashed values remain stable when they are forwarded.
+/// All other values are hashed.
+fn hash_set_value(value: &str) -> u32 {
+    value
+        .parse()
+        .unwrap_or_else(|_| crc32fast::hash(value.as_bytes()))
+}
+
+/// A single metric value with its name, tags and timestamp.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    /// The name of the metric.
+    pub name: String,
+    /// The value of the metric, which also determines its type.
+    pub value: MetricValue,
+    /// The UNIX timestamp in seconds at which the value was recorded.
+    pub timestamp: u64,
+    /// Tags of the metric.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Parses the tags of a metric line, such as `key:value,key2:value2`.
+fn parse_tags(string: &str) -> Result<BTreeMap<String, String>, ParseMetricError> {
+    let mut tags = BTreeMap::new();
+
+    for pair in string.split(',').filter(|pair| !pair.is_empty()) {
+        let mut parts = pair.splitn(2, ':');
+        let key = parts.next().unwrap_or_default();
+        if key.is_empty() {
+            return Err(ParseMetricError::InvalidSuffix);
+        }
+
+        let value = parts.next().unwrap_or_default();
+        tags.insert(key.to_owned(), value.to_owned());
+    }
+
+    Ok(tags)
+}
+
+/// Parses a single metric line and appends all its values to `metrics`.
+fn parse_line(
+    line: &str,
+    timestamp: u64,
+    metrics: &mut Vec<Metric>,
+) -> Result<(), ParseMetricError> {
+    let mut components = line.split('|');
+
+    let mut values = components.next().unwrap_or_default().split(':');
+    let name = values.next().unwrap_or_default();
+    if name.is_empty() {
+        return Err(ParseMetricError::InvalidFormat);
+    }
+
+    let ty = components
+        .next()
+        .and_then(MetricType::from_code)
+        .ok_or(ParseMetricError::InvalidFormat)?;
+
+    let values = values
+        .map(|value| MetricValue::parse(ty, value))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if values.is_empty() {
+        return Err(ParseMetricError::InvalidValue);
+    }
+
+    let mut tags = BTreeMap::new();
+    let mut timestamp = timestamp;
+
+    for component in components {
+        if component.starts_with('#') {
+            tags = parse_tags(&component[1..])?;
+        } else if component.starts_with('T') {
+            timestamp = component[1..]
+                .parse()
+                .map_err(|_| ParseMetricError::InvalidSuffix)?;
+        }
+    }
+
+    metrics.extend(values.into_iter().map(|value| Metric {
+        name: name.to_owned(),
+        value,
+        timestamp,
+        tags: tags.clone(),
+    }));
+
+    Ok(())
+}
+
+/// Parses all metrics from the payload of a `metrics` item.
+///
+/// Metrics without an explicit timestamp are assigned the given `timestamp`. Invalid lines are
+/// skipped, so that valid metrics in the same item can still be ingested.
+pub fn parse_metrics(payload: &[u8], timestamp: u64) -> Vec<Metric> {
+    let mut metrics = Vec::new();
+
+    for line in payload.split(|&byte| byte == b'\n') {
+        let result = std::str::from_utf8(line)
+            .map_err(|_| ParseMetricError::InvalidUtf8)
+            .map(str::trim)
+            .and_then(|line| match line {
+                "" => Ok(()),
+                line => parse_line(line, timestamp, &mut metrics),
+            });
+
+        if let Err(error) = result {
+            log::debug!("skipping invalid metric: {}", LogError(&error));
+        }
+    }
+
+    metrics
+}
+
+/// Writes a metric line with the given values to `buffer`.
+///
+/// This is the inverse of parsing. It is used to forward aggregated metrics to the upstream.
+pub fn write_metric_line<I, V>(
+    buffer: &mut String,
+    name: &str,
+    ty: MetricType,
+    values: I,
+    tags: &BTreeMap<String, String>,
+    timestamp: u64,
+) where
+    I: IntoIterator<Item = V>,
+    V: fmt::Display,
+{
+    use std::fmt::Write;
+
+    buffer.push_str(name);
+    for value in values {
+        write!(buffer, ":{}", value).ok();
+    }
+
+    write!(buffer, "|{}", ty.code()).ok();
+
+    for (index, (key, value)) in tags.iter().enumerate() {
+        buffer.push_str(if index == 0 { "|#" } else { "," });
+        write!(buffer, "{}:{}", key, value).ok();
+    }
+
+    writeln!(buffer, "|T{}", timestamp).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_counter() {
+        let metrics = parse_metrics(b"foo:42|c", 1_597_000_000);
+        assert_eq!(
+            metrics,
+            vec![Metric {
+                name: "foo".to_owned(),
+                value: MetricValue::Counter(42.0),
+                timestamp: 1_597_000_000,
+                tags: BTreeMap::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_tags_and_timestamp() {
+        let metrics = parse_metrics(b"foo:17.5:21|d|#route:index,env:prod|T1597130000", 0);
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].value, MetricValue::Distribution(17.5));
+        assert_eq!(metrics[1].value, MetricValue::Distribution(21.0));
+        assert_eq!(metrics[1].timestamp, 1_597_130_000);
+        assert_eq!(metrics[1].tags["route"], "index");
+        assert_eq!(metrics[1].tags["env"], "prod");
+    }
+
+    #[test]
+    fn test_parse_set() {
+        let metrics = parse_metrics(b"users:42:alice|s", 0);
+        assert_eq!(metrics[0].value, MetricValue::Set(42));
+        assert_eq!(
+            metrics[1].value,
+            MetricValue::Set(crc32fast::hash(b"alice"))
+        );
+    }
+
+    #[test]
+    fn test_parse_skips_invalid_lines() {
+        let payload = b"foo:1|c\nbar|c\nbaz:x|g\n:1|c\nqux:1|x\n\nquux:2|g\n";
+        let metrics = parse_metrics(payload, 0);
+        let names: Vec<_> = metrics.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["foo", "quux"]);
+    }
+
+    #[test]
+    fn test_write_roundtrip() {
+        let mut tags = BTreeMap::new();
+        tags.insert("env".to_owned(), "prod".to_owned());
+
+        let mut line = String::new();
+        write_metric_line(&mut line, "foo", MetricType::Set, &[1, 2], &tags, 42);
+        assert_eq!(line, "foo:1:2|s|#env:prod|T42\n");
+
+        let metrics = parse_metrics(line.as_bytes(), 0);
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[1].value, MetricValue::Set(2));
+        assert_eq!(metrics[1].tags, tags);
+        assert_eq!(metrics[1].timestamp, 42);
+    }
+}
@@ -1,31 +1,45 @@
 mod actix;
 mod api;
+mod custom_metrics;
 mod error_boundary;
 mod multipart;
+mod otlp;
 mod param_parser;
 mod rate_limits;
 mod request;
+mod sampling;
 mod shutdown;
+mod spool;
 mod timer;
 
 #[cfg(feature = "processing")]
 mod kafka;
 
+#[cfg(feature = "processing")]
+mod session_aggregator;
+
 #[cfg(feature = "processing")]
 mod unreal;
 
 pub use self::actix::*;
 pub use self::api::*;
+pub use self::custom_metrics::*;
 pub use self::error_boundary::*;
 pub use self::multipart::*;
+pub use self::otlp::*;
 pub use self::param_parser::*;
 pub use self::rate_limits::*;
 pub use self::request::*;
+pub use self::sampling::*;
 pub use self::shutdown::*;
+pub use self::spool::*;
 pub use self::timer::*;
 
 #[cfg(feature = "processing")]
 pub use self::kafka::*;
 
+#[cfg(feature = "processing")]
+pub use self::session_aggregator::*;
+
 #[cfg(feature = "processing")]
 pub use self::unreal::*;
@@ -10,6 +10,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::envelope::{AttachmentType, ContentType, Item, ItemType, Items};
 use crate::service::ServiceState;
+use crate::utils::RequestBodySize;
 
 #[derive(Debug, Fail)]
 pub enum MultipartError {
@@ -25,13 +26,15 @@ pub enum MultipartError {
 struct TerminatedPayload {
     inner: Option<Payload>,
     end: Option<Bytes>,
+    size: RequestBodySize,
 }
 
 impl TerminatedPayload {
-    pub fn new(payload: Payload) -> Self {
+    pub fn new(payload: Payload, size: RequestBodySize) -> Self {
         Self {
             inner: Some(payload),
             end: Some(Bytes::from_static(b"\r\n")),
+            size,
         }
     }
 }
@@ -48,6 +51,10 @@ impl Stream for TerminatedPayload {
                     // Remove the stream to fuse, then fall through.
                     self.inner = None;
                 }
+                Ok(Async::Ready(Some(chunk))) => {
+                    self.size.add(chunk.len());
+                    return Ok(Async::Ready(Some(chunk)));
+                }
                 poll => return poll,
             }
         }
@@ -320,7 +327,7 @@ impl MultipartItems {
         };
 
         // The payload is internally clonable which allows to consume it at the end of this future.
-        let payload = TerminatedPayload::new(request.payload());
+        let payload = TerminatedPayload::new(request.payload(), RequestBodySize::of(request));
         let multipart = multipart::Multipart::new(Ok(boundary), payload.clone());
 
         let future = consume_stream(self, multipart)
@@ -0,0 +1,1053 @@
+//! Conversion of OpenTelemetry traces into transaction events.
+//!
+//! Relay accepts traces in the OpenTelemetry Protocol (OTLP) in both the binary protobuf and the
+//! JSON encoding. Spans are grouped by their local root, which is the first span of a trace within
+//! a service. Each local root becomes a transaction event and all of its descendants become spans
+//! of that transaction.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{TimeZone, Utc};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+use relay_general::protocol::{
+    ClientSdkInfo, Context, Contexts, Event, EventId, EventType, LenientString, PairList, Request,
+    Span, SpanId, SpanStatus, TagEntry, Tags, Timestamp, TraceContext, TraceId,
+};
+use relay_general::types::{Annotated, Object, Value};
+
+/// Maximum depth of nested attribute values that are converted.
+const MAX_VALUE_DEPTH: usize = 8;
+
+/// Maximum number of spans accepted in a single trace export request.
+pub const MAX_SPANS_PER_REQUEST: usize = 10_000;
+
+/// Data model of the OTLP trace export request.
+///
+/// This is a subset of the `opentelemetry.proto.collector.trace.v1` package. Fields that Relay
+/// does not use are skipped during decoding. All messages can also be deserialized from the OTLP
+/// JSON encoding, where trace and span identifiers are hex-encoded.
+pub mod proto {
+    use prost::{Enumeration, Message};
+    use serde::Deserialize;
+
+    /// Request body of the OTLP trace export.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct ExportTraceServiceRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub resource_spans: Vec<ResourceSpans>,
+    }
+
+    /// Spans emitted by a single resource, such as a service instance.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct ResourceSpans {
+        #[prost(message, optional, tag = "1")]
+        pub resource: Option<Resource>,
+        #[prost(message, repeated, tag = "2")]
+        pub scope_spans: Vec<ScopeSpans>,
+    }
+
+    /// Attributes describing the entity that produced telemetry.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct Resource {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    /// Spans produced by a single instrumentation scope.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct ScopeSpans {
+        #[prost(message, repeated, tag = "2")]
+        pub spans: Vec<Span>,
+    }
+
+    /// A single operation within a trace.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct Span {
+        #[prost(bytes, tag = "1")]
+        #[serde(deserialize_with = "super::deserialize_hex")]
+        pub trace_id: Vec<u8>,
+        #[prost(bytes, tag = "2")]
+        #[serde(deserialize_with = "super::deserialize_hex")]
+        pub span_id: Vec<u8>,
+        #[prost(bytes, tag = "4")]
+        #[serde(deserialize_with = "super::deserialize_hex")]
+        pub parent_span_id: Vec<u8>,
+        #[prost(string, tag = "5")]
+        pub name: String,
+        #[prost(enumeration = "SpanKind", tag = "6")]
+        pub kind: i32,
+        #[prost(fixed64, tag = "7")]
+        #[serde(deserialize_with = "super::deserialize_number")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "8")]
+        #[serde(deserialize_with = "super::deserialize_number")]
+        pub end_time_unix_nano: u64,
+        #[prost(message, repeated, tag = "9")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(message, optional, tag = "15")]
+        pub status: Option<Status>,
+    }
+
+    /// The relationship of a span to its parent and children.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Enumeration)]
+    #[repr(i32)]
+    pub enum SpanKind {
+        Unspecified = 0,
+        Internal = 1,
+        Server = 2,
+        Client = 3,
+        Producer = 4,
+        Consumer = 5,
+    }
+
+    /// The status of a finished span.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct Status {
+        #[prost(string, tag = "2")]
+        pub message: String,
+        #[prost(enumeration = "StatusCode", tag = "3")]
+        pub code: i32,
+    }
+
+    /// The status code of a span.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Enumeration)]
+    #[repr(i32)]
+    pub enum StatusCode {
+        Unset = 0,
+        Ok = 1,
+        Error = 2,
+    }
+
+    /// A named attribute value.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct KeyValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<AnyValue>,
+    }
+
+    /// An attribute value of any supported type.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(from = "super::JsonAnyValue")]
+    pub struct AnyValue {
+        #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4, 5, 6, 7")]
+        pub value: Option<any_value::Value>,
+    }
+
+    pub mod any_value {
+        use prost::Oneof;
+
+        /// The typed content of an `AnyValue`.
+        #[derive(Clone, PartialEq, Oneof)]
+        pub enum Value {
+            #[prost(string, tag = "1")]
+            StringValue(String),
+            #[prost(bool, tag = "2")]
+            BoolValue(bool),
+            #[prost(int64, tag = "3")]
+            IntValue(i64),
+            #[prost(double, tag = "4")]
+            DoubleValue(f64),
+            #[prost(message, tag = "5")]
+            ArrayValue(super::ArrayValue),
+            #[prost(message, tag = "6")]
+            KvlistValue(super::KeyValueList),
+            #[prost(bytes, tag = "7")]
+            BytesValue(Vec<u8>),
+        }
+    }
+
+    /// A list of attribute values.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct ArrayValue {
+        #[prost(message, repeated, tag = "1")]
+        pub values: Vec<AnyValue>,
+    }
+
+    /// A list of named attribute values.
+    #[derive(Clone, PartialEq, Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct KeyValueList {
+        #[prost(message, repeated, tag = "1")]
+        pub values: Vec<KeyValue>,
+    }
+}
+
+use self::proto::any_value::Value as ProtoValue;
+use self::proto::{AnyValue, ExportTraceServiceRequest, KeyValue, SpanKind, StatusCode};
+
+/// Decodes an OTLP trace export request from its binary protobuf encoding.
+pub fn decode_protobuf(data: &[u8]) -> Result<ExportTraceServiceRequest, prost::DecodeError> {
+    prost::Message::decode(data)
+}
+
+/// Decodes an OTLP trace export request from its JSON encoding.
+pub fn decode_json(data: &[u8]) -> Result<ExportTraceServiceRequest, serde_json::Error> {
+    serde_json::from_slice(data)
+}
+
+/// Returns the total number of spans in an OTLP trace export request.
+pub fn count_spans(request: &ExportTraceServiceRequest) -> usize {
+    request
+        .resource_spans
+        .iter()
+        .flat_map(|resource_spans| &resource_spans.scope_spans)
+        .map(|scope_spans| scope_spans.spans.len())
+        .sum()
+}
+
+/// JSON representation of `AnyValue`, where the oneof is encoded as one of several keys.
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct JsonAnyValue {
+    string_value: Option<String>,
+    bool_value: Option<bool>,
+    #[serde(deserialize_with = "deserialize_optional_number")]
+    int_value: Option<i64>,
+    double_value: Option<f64>,
+    array_value: Option<proto::ArrayValue>,
+    kvlist_value: Option<proto::KeyValueList>,
+    bytes_value: Option<String>,
+}
+
+impl From<JsonAnyValue> for AnyValue {
+    fn from(json: JsonAnyValue) -> Self {
+        let value = if let Some(value) = json.string_value {
+            Some(ProtoValue::StringValue(value))
+        } else if let Some(value) = json.bool_value {
+            Some(ProtoValue::BoolValue(value))
+        } else if let Some(value) = json.int_value {
+            Some(ProtoValue::IntValue(value))
+        } else if let Some(value) = json.double_value {
+            Some(ProtoValue::DoubleValue(value))
+        } else if let Some(value) = json.array_value {
+            Some(ProtoValue::ArrayValue(value))
+        } else if let Some(value) = json.kvlist_value {
+            Some(ProtoValue::KvlistValue(value))
+        } else {
+            json.bytes_value
+                .and_then(|value| base64::decode(&value).ok())
+                .map(ProtoValue::BytesValue)
+        };
+
+        AnyValue { value }
+    }
+}
+
+/// Either a number or a string containing a number.
+///
+/// The OTLP JSON encoding represents 64-bit integers as strings, but some exporters emit numbers.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber<T> {
+    Number(T),
+    String(String),
+}
+
+impl<T> StringOrNumber<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    fn into_number<E: de::Error>(self) -> Result<T, E> {
+        match self {
+            StringOrNumber::Number(number) => Ok(number),
+            StringOrNumber::String(string) => string.parse().map_err(E::custom),
+        }
+    }
+}
+
+fn deserialize_number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
+{
+    StringOrNumber::deserialize(deserializer)?.into_number()
+}
+
+fn deserialize_optional_number<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
+{
+    match Option::<StringOrNumber<T>>::deserialize(deserializer)? {
+        Some(value) => value.into_number().map(Some),
+        None => Ok(None),
+    }
+}
+
+fn deserialize_hex<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let string = String::deserialize(deserializer)?;
+    if string.len() % 2 != 0 {
+        return Err(de::Error::custom("odd number of hex digits"));
+    }
+
+    (0..string.len())
+        .step_by(2)
+        .map(|index| {
+            string
+                .get(index..index + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| de::Error::custom("invalid hex digit"))
+        })
+        .collect()
+}
+
+/// Formats an identifier as lowercase hex string, or returns `None` if the identifier is empty.
+fn hex_id(bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() {
+        return None;
+    }
+
+    Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Converts nanoseconds since the UNIX epoch into a timestamp.
+fn timestamp(nanos: u64) -> Annotated<Timestamp> {
+    if nanos == 0 {
+        return Annotated::empty();
+    }
+
+    let secs = (nanos / 1_000_000_000) as i64;
+    let subsec_nanos = (nanos % 1_000_000_000) as u32;
+    Annotated::from(
+        Utc.timestamp_opt(secs, subsec_nanos)
+            .single()
+            .map(Timestamp),
+    )
+}
+
+/// Converts an attribute value into a protocol value.
+fn convert_value(value: AnyValue, depth: usize) -> Option<Value> {
+    if depth > MAX_VALUE_DEPTH {
+        return None;
+    }
+
+    Some(match value.value? {
+        ProtoValue::StringValue(value) => Value::String(value),
+        ProtoValue::BoolValue(value) => Value::Bool(value),
+        ProtoValue::IntValue(value) => Value::I64(value),
+        ProtoValue::DoubleValue(value) => Value::F64(value),
+        ProtoValue::ArrayValue(array) => Value::Array(
+            array
+                .values
+                .into_iter()
+                .map(|value| Annotated::from(convert_value(value, depth + 1)))
+                .collect(),
+        ),
+        ProtoValue::KvlistValue(list) => Value::Object(convert_attributes(list.values, depth + 1)),
+        ProtoValue::BytesValue(bytes) => Value::String(base64::encode(&bytes)),
+    })
+}
+
+/// Converts a list of attributes into an object.
+fn convert_attributes(attributes: Vec<KeyValue>, depth: usize) -> Object<Value> {
+    attributes
+        .into_iter()
+        .filter_map(|kv| Some((kv.key, convert_value(kv.value?, depth)?)))
+        .map(|(key, value)| (key, Annotated::new(value)))
+        .collect()
+}
+
+/// Returns an attribute value as string, if it is a string.
+fn get_str<'a>(attributes: &'a Object<Value>, key: &str) -> Option<&'a str> {
+    match attributes.get(key)?.value()? {
+        Value::String(string) => Some(string.as_str()),
+        _ => None,
+    }
+}
+
+/// Returns an HTTP status code from the attributes.
+fn get_http_status_code(attributes: &Object<Value>) -> Option<i64> {
+    match attributes.get("http.status_code")?.value()? {
+        Value::I64(code) => Some(*code),
+        Value::U64(code) => Some(*code as i64),
+        Value::String(code) => code.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Derives the span operation from well-known attributes and the span kind.
+fn span_op(kind: SpanKind, attributes: &Object<Value>) -> String {
+    if attributes.contains_key("http.method") {
+        return match kind {
+            SpanKind::Server => "http.server",
+            SpanKind::Client => "http.client",
+            _ => "http",
+        }
+        .to_owned();
+    }
+
+    if let Some(system) = get_str(attributes, "db.system") {
+        return format!("db.{}", system);
+    }
+
+    if attributes.contains_key("db.statement") {
+        return "db".to_owned();
+    }
+
+    if attributes.contains_key("messaging.system") {
+        return match kind {
+            SpanKind::Producer => "queue.publish",
+            SpanKind::Consumer => "queue.process",
+            _ => "queue",
+        }
+        .to_owned();
+    }
+
+    if attributes.contains_key("rpc.system") {
+        return match kind {
+            SpanKind::Server => "rpc.server",
+            SpanKind::Client => "rpc.client",
+            _ => "rpc",
+        }
+        .to_owned();
+    }
+
+    match kind {
+        SpanKind::Server => "server",
+        SpanKind::Client => "client",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+        SpanKind::Internal | SpanKind::Unspecified => "default",
+    }
+    .to_owned()
+}
+
+/// Derives a human readable span description from well-known attributes.
+fn span_description(name: &str, attributes: &Object<Value>) -> String {
+    if let Some(statement) = get_str(attributes, "db.statement") {
+        return statement.to_owned();
+    }
+
+    if let Some(method) = get_str(attributes, "http.method") {
+        let target = get_str(attributes, "http.url")
+            .or_else(|| get_str(attributes, "http.target"))
+            .or_else(|| get_str(attributes, "http.route"));
+
+        if let Some(target) = target {
+            return format!("{} {}", method, target);
+        }
+    }
+
+    name.to_owned()
+}
+
+/// Maps an HTTP status code to a span status.
+fn status_from_http(code: i64) -> Option<SpanStatus> {
+    Some(match code {
+        401 => SpanStatus::Unauthenticated,
+        403 => SpanStatus::PermissionDenied,
+        404 => SpanStatus::NotFound,
+        409 => SpanStatus::AlreadyExists,
+        429 => SpanStatus::ResourceExhausted,
+        499 => SpanStatus::Cancelled,
+        501 => SpanStatus::Unimplemented,
+        503 => SpanStatus::Unavailable,
+        504 => SpanStatus::DeadlineExceeded,
+        _ if (100..400).contains(&code) => SpanStatus::Ok,
+        _ if (400..500).contains(&code) => SpanStatus::InvalidArgument,
+        _ if (500..600).contains(&code) => SpanStatus::InternalError,
+        _ => return None,
+    })
+}
+
+/// Derives the span status from the OTLP status and the HTTP status code.
+fn span_status(status: Option<&proto::Status>, attributes: &Object<Value>) -> SpanStatus {
+    let code = status
+        .and_then(|status| StatusCode::from_i32(status.code))
+        .unwrap_or(StatusCode::Unset);
+
+    let http_status = get_http_status_code(attributes).and_then(status_from_http);
+
+    match (code, http_status) {
+        (StatusCode::Ok, _) => SpanStatus::Ok,
+        (StatusCode::Error, Some(SpanStatus::Ok)) | (StatusCode::Error, None) => {
+            SpanStatus::Unknown
+        }
+        (_, Some(status)) => status,
+        (StatusCode::Unset, None) => SpanStatus::Ok,
+    }
+}
+
+/// Common fields of a converted span.
+struct ConvertedSpan {
+    trace_id: Annotated<TraceId>,
+    span_id: Annotated<SpanId>,
+    parent_span_id: Annotated<SpanId>,
+    op: String,
+    description: String,
+    status: SpanStatus,
+    start_timestamp: Annotated<Timestamp>,
+    timestamp: Annotated<Timestamp>,
+    attributes: Object<Value>,
+}
+
+impl ConvertedSpan {
+    fn new(span: proto::Span) -> Self {
+        let attributes = convert_attributes(span.attributes, 0);
+        let kind = SpanKind::from_i32(span.kind).unwrap_or(SpanKind::Unspecified);
+
+        ConvertedSpan {
+            trace_id: Annotated::from(hex_id(&span.trace_id).map(TraceId)),
+            span_id: Annotated::from(hex_id(&span.span_id).map(SpanId)),
+            parent_span_id: Annotated::from(hex_id(&span.parent_span_id).map(SpanId)),
+            op: span_op(kind, &attributes),
+            description: span_description(&span.name, &attributes),
+            status: span_status(span.status.as_ref(), &attributes),
+            start_timestamp: timestamp(span.start_time_unix_nano),
+            timestamp: timestamp(span.end_time_unix_nano),
+            attributes,
+        }
+    }
+
+    /// Returns the attributes as additional `data` field, if there are any.
+    fn data(attributes: Object<Value>) -> Object<Value> {
+        let mut other = Object::new();
+        if !attributes.is_empty() {
+            other.insert("data".to_owned(), Annotated::new(Value::Object(attributes)));
+        }
+        other
+    }
+
+    fn into_span(self) -> Span {
+        Span {
+            timestamp: self.timestamp,
+            start_timestamp: self.start_timestamp,
+            description: Annotated::new(self.description),
+            op: Annotated::new(self.op),
+            span_id: self.span_id,
+            parent_span_id: self.parent_span_id,
+            trace_id: self.trace_id,
+            status: Annotated::new(self.status),
+            other: Self::data(self.attributes),
+        }
+    }
+}
+
+/// Attributes of the resource that apply to all transactions.
+struct ResourceInfo {
+    service_name: Option<String>,
+    release: Option<String>,
+    environment: Option<String>,
+    server_name: Option<String>,
+    sdk: Option<(String, Option<String>)>,
+}
+
+impl ResourceInfo {
+    fn new(resource: Option<proto::Resource>) -> Self {
+        let attributes = resource
+            .map(|resource| convert_attributes(resource.attributes, 0))
+            .unwrap_or_default();
+
+        let get = |key: &str| get_str(&attributes, key).map(str::to_owned);
+
+        ResourceInfo {
+            service_name: get("service.name"),
+            release: get("service.version"),
+            environment: get("deployment.environment"),
+            server_name: get("host.name"),
+            sdk: get("telemetry.sdk.name").map(|name| (name, get("telemetry.sdk.version"))),
+        }
+    }
+}
+
+/// Creates a transaction event from a local root span and its descendants.
+fn create_transaction(
+    resource: &ResourceInfo,
+    root: proto::Span,
+    spans: Vec<proto::Span>,
+) -> Event {
+    let kind = SpanKind::from_i32(root.kind).unwrap_or(SpanKind::Unspecified);
+    let name = root.name.clone();
+    let root = ConvertedSpan::new(root);
+
+    let request = match kind {
+        SpanKind::Server => get_str(&root.attributes, "http.method").map(|method| Request {
+            method: Annotated::new(method.to_owned()),
+            url: Annotated::from(get_str(&root.attributes, "http.url").map(str::to_owned)),
+            ..Default::default()
+        }),
+        _ => None,
+    };
+
+    let mut contexts = Contexts::new();
+    contexts.add(Context::Trace(Box::new(TraceContext {
+        trace_id: root.trace_id,
+        span_id: root.span_id,
+        parent_span_id: root.parent_span_id,
+        op: Annotated::new(root.op),
+        status: Annotated::new(root.status),
+        other: ConvertedSpan::data(root.attributes),
+    })));
+
+    let tags = resource.service_name.as_ref().map(|service_name| {
+        Tags(PairList(vec![Annotated::new(TagEntry(
+            Annotated::new("service.name".to_owned()),
+            Annotated::new(service_name.clone()),
+        ))]))
+    });
+
+    let client_sdk = resource.sdk.as_ref().map(|(name, version)| ClientSdkInfo {
+        name: Annotated::new(name.clone()),
+        version: Annotated::from(version.clone()),
+        ..Default::default()
+    });
+
+    Event {
+        id: Annotated::new(EventId::new()),
+        ty: Annotated::new(EventType::Transaction),
+        transaction: Annotated::new(name),
+        platform: Annotated::new("other".to_owned()),
+        start_timestamp: root.start_timestamp,
+        timestamp: root.timestamp,
+        release: Annotated::from(resource.release.clone().map(LenientString)),
+        environment: Annotated::from(resource.environment.clone()),
+        server_name: Annotated::from(resource.server_name.clone()),
+        request: Annotated::from(request),
+        contexts: Annotated::new(contexts),
+        tags: Annotated::from(tags),
+        client_sdk: Annotated::from(client_sdk),
+        spans: Annotated::new(
+            spans
+                .into_iter()
+                .map(|span| Annotated::new(ConvertedSpan::new(span).into_span()))
+                .collect(),
+        ),
+        ..Default::default()
+    }
+}
+
+/// Resolves the index of the local root for every span.
+///
+/// Roots are resolved by following parents within the given spans. Every span is visited once,
+/// since all spans on a walked path are assigned the root at the end of the path. Spans that form
+/// a cycle in malformed input become their own roots, so that no span is dropped.
+fn resolve_roots(spans: &[proto::Span]) -> Vec<usize> {
+    let index: HashMap<(&[u8], &[u8]), usize> = spans
+        .iter()
+        .enumerate()
+        .map(|(i, span)| ((span.trace_id.as_slice(), span.span_id.as_slice()), i))
+        .collect();
+
+    let parent_of = |i: usize| {
+        let span = &spans[i];
+        let key = (span.trace_id.as_slice(), span.parent_span_id.as_slice());
+        index.get(&key).copied().filter(|&parent| parent != i)
+    };
+
+    let mut roots: Vec<Option<usize>> = vec![None; spans.len()];
+    let mut on_path = vec![false; spans.len()];
+    let mut path = Vec::new();
+
+    for start in 0..spans.len() {
+        let mut current = start;
+
+        let root = loop {
+            if let Some(root) = roots[current] {
+                break root;
+            }
+
+            match parent_of(current) {
+                None => {
+                    roots[current] = Some(current);
+                    break current;
+                }
+                Some(parent) if on_path[parent] => {
+                    // The parent is already on the path, so all spans from the parent to the
+                    // current span form a cycle. Each of them becomes its own root, and the spans
+                    // below the cycle belong to the span through which the cycle was entered.
+                    let position = path.iter().position(|&i| i == parent).unwrap_or(0);
+                    for member in path.drain(position..).chain(Some(current)) {
+                        on_path[member] = false;
+                        roots[member] = Some(member);
+                    }
+                    break parent;
+                }
+                Some(parent) => {
+                    path.push(current);
+                    on_path[current] = true;
+                    current = parent;
+                }
+            }
+        };
+
+        for i in path.drain(..) {
+            on_path[i] = false;
+            roots[i] = Some(root);
+        }
+    }
+
+    roots
+        .into_iter()
+        .map(|root| root.unwrap_or_default())
+        .collect()
+}
+
+/// Converts an OTLP trace export request into transaction events.
+///
+/// Spans are grouped per resource by their local root. A span is a local root if its parent is not
+/// contained in the same resource, which is the case for the root span of a trace and for spans
+/// continuing a trace from another service.
+pub fn convert_traces(request: ExportTraceServiceRequest) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    for resource_spans in request.resource_spans {
+        let resource = ResourceInfo::new(resource_spans.resource);
+        let spans: Vec<proto::Span> = resource_spans
+            .scope_spans
+            .into_iter()
+            .flat_map(|scope_spans| scope_spans.spans)
+            .collect();
+
+        let roots = resolve_roots(&spans);
+
+        let mut groups = BTreeMap::<usize, Vec<proto::Span>>::new();
+        let mut root_spans = BTreeMap::new();
+        for (i, span) in spans.into_iter().enumerate() {
+            if roots[i] == i {
+                root_spans.insert(i, span);
+            } else {
+                groups.entry(roots[i]).or_default().push(span);
+            }
+        }
+
+        for (i, root) in root_spans {
+            let children = groups.remove(&i).unwrap_or_default();
+            events.push(create_transaction(&resource, root, children));
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use relay_general::protocol::ContextInner;
+
+    fn get_trace_context(event: &Event) -> &TraceContext {
+        let contexts = event.contexts.value().unwrap();
+        match contexts.get("trace").and_then(Annotated::value) {
+            Some(ContextInner(Context::Trace(trace))) => trace,
+            _ => panic!("missing trace context"),
+        }
+    }
+
+    #[test]
+    fn test_convert_trace_json() {
+        let json = r#"{
+  "resourceSpans": [{
+    "resource": {
+      "attributes": [
+        {"key": "service.name", "value": {"stringValue": "checkout"}},
+        {"key": "service.version", "value": {"stringValue": "1.2.3"}},
+        {"key": "deployment.environment", "value": {"stringValue": "production"}},
+        {"key": "telemetry.sdk.name", "value": {"stringValue": "opentelemetry"}},
+        {"key": "telemetry.sdk.version", "value": {"stringValue": "1.0.0"}}
+      ]
+    },
+    "scopeSpans": [{
+      "spans": [
+        {
+          "traceId": "4c79f60c11214eb38604f4ae0781bfb2",
+          "spanId": "fa90fdead5f74052",
+          "name": "GET /checkout",
+          "kind": 2,
+          "startTimeUnixNano": "1597976300000000000",
+          "endTimeUnixNano": "1597976302000000000",
+          "attributes": [
+            {"key": "http.method", "value": {"stringValue": "GET"}},
+            {"key": "http.url", "value": {"stringValue": "https://example.com/checkout"}},
+            {"key": "http.status_code", "value": {"intValue": "200"}}
+          ]
+        },
+        {
+          "traceId": "4c79f60c11214eb38604f4ae0781bfb2",
+          "spanId": "fa90fdead5f74053",
+          "parentSpanId": "fa90fdead5f74052",
+          "name": "SELECT",
+          "kind": 3,
+          "startTimeUnixNano": 1597976300500000000,
+          "endTimeUnixNano": 1597976301000000000,
+          "attributes": [
+            {"key": "db.system", "value": {"stringValue": "postgresql"}},
+            {"key": "db.statement", "value": {"stringValue": "SELECT * FROM orders"}}
+          ],
+          "status": {"code": 2}
+        }
+      ]
+    }]
+  }]
+}"#;
+
+        let events = convert_traces(decode_json(json.as_bytes()).unwrap());
+        assert_eq!(events.len(), 1);
+
+        let event = &events[0];
+        assert_eq!(event.ty.value(), Some(&EventType::Transaction));
+        assert_eq!(event.transaction.as_str(), Some("GET /checkout"));
+        assert_eq!(event.release.as_str(), Some("1.2.3"));
+        assert_eq!(event.environment.as_str(), Some("production"));
+        assert_eq!(
+            event.start_timestamp.value().unwrap().timestamp(),
+            1_597_976_300
+        );
+        assert_eq!(event.timestamp.value().unwrap().timestamp(), 1_597_976_302);
+
+        let request = event.request.value().unwrap();
+        assert_eq!(request.method.as_str(), Some("GET"));
+        assert_eq!(request.url.as_str(), Some("https://example.com/checkout"));
+
+        let tag = event.tags.value().unwrap()[0].value().unwrap();
+        assert_eq!(tag.0.as_str(), Some("service.name"));
+        assert_eq!(tag.1.as_str(), Some("checkout"));
+
+        let sdk = event.client_sdk.value().unwrap();
+        assert_eq!(sdk.name.as_str(), Some("opentelemetry"));
+        assert_eq!(sdk.version.as_str(), Some("1.0.0"));
+
+        let trace = get_trace_context(event);
+        assert_eq!(
+            trace.trace_id.value().unwrap().0,
+            "4c79f60c11214eb38604f4ae0781bfb2"
+        );
+        assert_eq!(trace.span_id.value().unwrap().0, "fa90fdead5f74052");
+        assert!(trace.parent_span_id.value().is_none());
+        assert_eq!(trace.op.as_str(), Some("http.server"));
+        assert_eq!(trace.status.value(), Some(&SpanStatus::Ok));
+
+        let data = match trace.other.get("data").and_then(Annotated::value) {
+            Some(Value::Object(data)) => data,
+            _ => panic!("missing trace data"),
+        };
+        assert_eq!(
+            data.get("http.status_code"),
+            Some(&Annotated::new(Value::I64(200)))
+        );
+
+        let spans = event.spans.value().unwrap();
+        assert_eq!(spans.len(), 1);
+
+        let span = spans[0].value().unwrap();
+        assert_eq!(span.description.as_str(), Some("SELECT * FROM orders"));
+        assert_eq!(span.op.as_str(), Some("db.postgresql"));
+        assert_eq!(span.parent_span_id.value().unwrap().0, "fa90fdead5f74052");
+        assert_eq!(span.status.value(), Some(&SpanStatus::Unknown));
+        assert_eq!(
+            span.start_timestamp
+                .value()
+                .unwrap()
+                .timestamp_subsec_millis(),
+            500
+        );
+    }
+    #[test]
+    fn test_convert_trace_protobuf() {
+        let span = proto::Span {
+            trace_id: vec![0xab; 16],
+            span_id: vec![0xcd; 8],
+            name: "process".to_owned(),
+            kind: SpanKind::Consumer as i32,
+            start_time_unix_nano: 1_597_976_300_000_000_000,
+            end_time_unix_nano: 1_597_976_301_000_000_000,
+            attributes: vec![KeyValue {
+                key: "messaging.system".to_owned(),
+                value: Some(AnyValue {
+                    value: Some(ProtoValue::StringValue("kafka".to_owned())),
+                }),
+            }],
+            ..Default::default()
+        };
+
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![proto::ResourceSpans {
+                resource: None,
+                scope_spans: vec![proto::ScopeSpans { spans: vec![span] }],
+            }],
+        };
+
+        let mut data = Vec::new();
+        prost::Message::encode(&request, &mut data).unwrap();
+        let decoded = decode_protobuf(&data).unwrap();
+        assert_eq!(decoded, request);
+
+        let events = convert_traces(decoded);
+        assert_eq!(events.len(), 1);
+
+        let event = &events[0];
+        assert_eq!(event.transaction.as_str(), Some("process"));
+
+        let trace = get_trace_context(event);
+        assert_eq!(
+            trace.trace_id.value().unwrap().0,
+            "abababababababababababababababab"
+        );
+        assert_eq!(trace.span_id.value().unwrap().0, "cdcdcdcdcdcdcdcd");
+        assert_eq!(trace.op.as_str(), Some("queue.process"));
+        assert_eq!(trace.status.value(), Some(&SpanStatus::Ok));
+    }
+
+    #[test]
+    fn test_group_local_roots() {
+        let json = r#"{
+  "resourceSpans": [{
+    "scopeSpans": [
+      {
+        "spans": [
+          {"traceId": "4c79f60c11214eb38604f4ae0781bfb2", "spanId": "0000000000000003", "parentSpanId": "0000000000000002", "name": "grandchild"},
+          {"traceId": "4c79f60c11214eb38604f4ae0781bfb2", "spanId": "0000000000000001", "name": "root"}
+        ]
+      },
+      {
+        "spans": [
+          {"traceId": "4c79f60c11214eb38604f4ae0781bfb2", "spanId": "0000000000000002", "parentSpanId": "0000000000000001", "name": "child"},
+          {"traceId": "4c79f60c11214eb38604f4ae0781bfb2", "spanId": "0000000000000004", "parentSpanId": "00000000000000ff", "name": "remote child"},
+          {"traceId": "1c79f60c11214eb38604f4ae0781bfb2", "spanId": "0000000000000002", "parentSpanId": "0000000000000001", "name": "other trace"}
+        ]
+      }
+    ]
+  }]
+}"#;
+
+        let events = convert_traces(decode_json(json.as_bytes()).unwrap());
+        let summary: Vec<(&str, Vec<&str>)> = events
+            .iter()
+            .map(|event| {
+                let spans = event
+                    .spans
+                    .value()
+                    .unwrap()
+                    .iter()
+                    .map(|span| span.value().unwrap().description.as_str().unwrap())
+                    .collect();
+                (event.transaction.as_str().unwrap(), spans)
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            vec![
+                ("root", vec!["grandchild", "child"]),
+                ("remote child", vec![]),
+                ("other trace", vec![]),
+            ]
+        );
+    }
+
+    fn test_span(id: u8, parent: Option<u8>) -> proto::Span {
+        proto::Span {
+            trace_id: vec![0xab; 16],
+            span_id: vec![id; 8],
+            parent_span_id: parent.map(|parent| vec![parent; 8]).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_roots() {
+        // 0 <- 1 <- 2 <- 3, with the spans in reverse order.
+        let spans = vec![
+            test_span(3, Some(2)),
+            test_span(2, Some(1)),
+            test_span(1, Some(0)),
+            test_span(0, None),
+        ];
+        assert_eq!(resolve_roots(&spans), vec![3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_resolve_roots_cycle() {
+        // A <-> B form a cycle, C is a child of A, and D is a child of C.
+        let spans = vec![
+            test_span(0xa, Some(0xb)),
+            test_span(0xb, Some(0xa)),
+            test_span(0xc, Some(0xa)),
+            test_span(0xd, Some(0xc)),
+        ];
+        assert_eq!(resolve_roots(&spans), vec![0, 1, 0, 0]);
+
+        // Entering the cycle from below yields the same result.
+        let spans = vec![
+            test_span(0xd, Some(0xc)),
+            test_span(0xc, Some(0xa)),
+            test_span(0xa, Some(0xb)),
+            test_span(0xb, Some(0xa)),
+        ];
+        assert_eq!(resolve_roots(&spans), vec![2, 2, 2, 3]);
+
+        // A span that is its own parent is a root.
+        assert_eq!(resolve_roots(&[test_span(1, Some(1))]), vec![0]);
+    }
+
+    #[test]
+    fn test_count_spans() {
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![
+                proto::ResourceSpans {
+                    resource: None,
+                    scope_spans: vec![
+                        proto::ScopeSpans {
+                            spans: vec![test_span(1, None), test_span(2, Some(1))],
+                        },
+                        proto::ScopeSpans {
+                            spans: vec![test_span(3, Some(1))],
+                        },
+                    ],
+                },
+                proto::ResourceSpans {
+                    resource: None,
+                    scope_spans: vec![proto::ScopeSpans {
+                        spans: vec![test_span(4, None)],
+                    }],
+                },
+            ],
+        };
+
+        assert_eq!(count_spans(&request), 4);
+    }
+
+    #[test]
+    fn test_span_status() {
+        let mut attributes = Object::new();
+        assert_eq!(span_status(None, &attributes), SpanStatus::Ok);
+
+        let error = proto::Status {
+            code: StatusCode::Error as i32,
+            message: String::new(),
+        };
+        assert_eq!(span_status(Some(&error), &attributes), SpanStatus::Unknown);
+
+        attributes.insert(
+            "http.status_code".to_owned(),
+            Annotated::new(Value::I64(404)),
+        );
+        assert_eq!(span_status(None, &attributes), SpanStatus::NotFound);
+        assert_eq!(span_status(Some(&error), &attributes), SpanStatus::NotFound);
+    }
+
+    #[test]
+    fn test_invalid_hex_id() {
+        let json = r#"{"resourceSpans": [{"scopeSpans": [{"spans": [{"traceId": "xyz"}]}]}]}"#;
+        assert!(decode_json(json.as_bytes()).is_err());
+    }
+}
@@ -5,6 +5,8 @@ use relay_quotas::{
     Scoping,
 };
 
+use relay_general::protocol::SessionAggregates;
+
 use crate::envelope::{Envelope, Item, ItemType};
 
 /// Name of the rate limits header.
@@ -88,11 +90,41 @@ fn infer_event_category(item: &Item) -> Option<DataCategory> {
         ItemType::Attachment if item.creates_event() => Some(DataCategory::Error),
         ItemType::Attachment => None,
         ItemType::Session => None,
+        ItemType::Sessions => None,
+        ItemType::Metrics => None,
+        ItemType::ClientReport => None,
+        ItemType::Profile => None,
+        ItemType::CheckIn => None,
         ItemType::FormData => None,
         ItemType::UserReport => None,
     }
 }
 
+/// Counts the non-empty lines in a metrics item.
+///
+/// Each line may contain multiple values of a metric. Quotas are counted per line, which avoids
+/// parsing the payload before rate limiting.
+fn count_metric_lines(item: &Item) -> usize {
+    item.payload()
+        .split(|&byte| byte == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .count()
+}
+
+/// Counts the sessions in a session item.
+///
+/// A session update counts as one session, while session aggregates count each aggregated session.
+/// This charges quotas equally, regardless of whether SDKs aggregate sessions. Invalid aggregates
+/// count as one session, since they are dropped during processing.
+fn count_sessions(item: &Item) -> usize {
+    match item.ty() {
+        ItemType::Sessions => SessionAggregates::parse(&item.payload())
+            .map(|aggregates| aggregates.num_sessions() as usize)
+            .unwrap_or(1),
+        _ => 1,
+    }
+}
+
 /// A summary of `Envelope` contents.
 ///
 /// Summarizes the contained event, size of attachments, session updates, and whether there are
@@ -106,9 +138,18 @@ pub struct EnvelopeSummary {
     /// The quantity of all attachments combined in bytes.
     pub attachment_quantity: usize,
 
-    /// The number of all session updates.
+    /// The number of all sessions, including each session in aggregates.
     pub session_quantity: usize,
 
+    /// The number of all custom metric lines.
+    pub metric_quantity: usize,
+
+    /// The number of all profiles.
+    pub profile_quantity: usize,
+
+    /// The number of all monitor check-ins.
+    pub check_in_quantity: usize,
+
     /// Indicates that the envelope contains regular attachments that do not create event payloads.
     pub has_plain_attachments: bool,
 }
@@ -139,7 +180,12 @@ impl EnvelopeSummary {
 
             match item.ty() {
                 ItemType::Attachment => summary.attachment_quantity += item.len().max(1),
-                ItemType::Session => summary.session_quantity += 1,
+                ItemType::Session | ItemType::Sessions => {
+                    summary.session_quantity += count_sessions(item)
+                }
+                ItemType::Metrics => summary.metric_quantity += count_metric_lines(item),
+                ItemType::Profile => summary.profile_quantity += 1,
+                ItemType::CheckIn => summary.check_in_quantity += 1,
                 _ => (),
             }
         }
@@ -162,17 +208,24 @@ impl EnvelopeSummary {
 ///  - Once for a single event, if present in the envelope.
 ///  - Once for all comprised attachments, unless the event was rate limited.
 ///  - Once for all comprised sessions.
+///  - Once for all comprised metric lines.
+///  - Once for all comprised profiles, unless the event was rate limited.
+///  - Once for all comprised check-ins.
 ///
 /// Items violating the rate limit are removed from the envelope. This follows a set of rules:
-///  - If the event is removed, all items depending on the event are removed (e.g. attachments).
+///  - If the event is removed, all items depending on the event are removed (e.g. attachments
+///    and profiles).
 ///  - Attachments are not removed if they create events (e.g. minidumps).
-///  - Sessions are handled separate to all of the above.
+///  - Sessions, metrics, and check-ins are handled separate to all of the above.
 pub struct EnvelopeLimiter<F> {
     check: F,
     event_category: Option<DataCategory>,
     remove_event: bool,
     remove_attachments: bool,
     remove_sessions: bool,
+    remove_metrics: bool,
+    remove_profiles: bool,
+    remove_check_ins: bool,
 }
 
 impl<E, F> EnvelopeLimiter<F>
@@ -187,6 +240,9 @@ where
             remove_event: false,
             remove_attachments: false,
             remove_sessions: false,
+            remove_metrics: false,
+            remove_profiles: false,
+            remove_check_ins: false,
         }
     }
 
@@ -195,7 +251,6 @@ where
     /// This ensures that rate limits for the given data category are checked even if there is no
     /// matching item in the envelope. Other items are handled according to the rules as if the
     /// event item were present.
-    #[cfg(feature = "processing")]
     pub fn assume_event(&mut self, category: DataCategory) {
         self.event_category = Some(category);
     }
@@ -241,6 +296,27 @@ where
             rate_limits.merge(session_limits);
         }
 
+        if summary.metric_quantity > 0 {
+            let item_scoping = scoping.item(DataCategory::Metric);
+            let metric_limits = (&mut self.check)(item_scoping, summary.metric_quantity)?;
+            self.remove_metrics = metric_limits.is_limited();
+            rate_limits.merge(metric_limits);
+        }
+
+        if !self.remove_event && summary.profile_quantity > 0 {
+            let item_scoping = scoping.item(DataCategory::Profile);
+            let profile_limits = (&mut self.check)(item_scoping, summary.profile_quantity)?;
+            self.remove_profiles = profile_limits.is_limited();
+            rate_limits.merge(profile_limits);
+        }
+
+        if summary.check_in_quantity > 0 {
+            let item_scoping = scoping.item(DataCategory::Monitor);
+            let check_in_limits = (&mut self.check)(item_scoping, summary.check_in_quantity)?;
+            self.remove_check_ins = check_in_limits.is_limited();
+            rate_limits.merge(check_in_limits);
+        }
+
         Ok(rate_limits)
     }
 
@@ -261,7 +337,22 @@ where
         }
 
         // Remove sessions independently of events
-        if self.remove_sessions && item.ty() == ItemType::Session {
+        if self.remove_sessions && matches!(item.ty(), ItemType::Session | ItemType::Sessions) {
+            return false;
+        }
+
+        // Remove metrics independently of events
+        if self.remove_metrics && item.ty() == ItemType::Metrics {
+            return false;
+        }
+
+        // Remove profiles without affecting the transaction they belong to
+        if self.remove_profiles && item.ty() == ItemType::Profile {
+            return false;
+        }
+
+        // Remove check-ins independently of events
+        if self.remove_check_ins && item.ty() == ItemType::CheckIn {
             return false;
         }
 
@@ -276,6 +367,9 @@ impl<F> fmt::Debug for EnvelopeLimiter<F> {
             .field("remove_event", &self.remove_event)
             .field("remove_attachments", &self.remove_attachments)
             .field("remove_sessions", &self.remove_sessions)
+            .field("remove_metrics", &self.remove_metrics)
+            .field("remove_profiles", &self.remove_profiles)
+            .field("remove_check_ins", &self.remove_check_ins)
             .finish()
     }
 }
@@ -619,7 +713,108 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature = "processing")]
+    fn test_enforce_limit_session_aggregates() {
+        let mut envelope = envelope![Sessions, Session, Event];
+
+        let mut mock = MockLimiter::default().deny(DataCategory::Session);
+        let limits = EnvelopeLimiter::new(|s, q| mock.check(s, q))
+            .enforce(&mut envelope, &scoping())
+            .unwrap();
+
+        assert!(limits.is_limited());
+        assert_eq!(envelope.len(), 1);
+        mock.assert_call(DataCategory::Error, Some(1));
+        mock.assert_call(DataCategory::Attachment, None);
+        mock.assert_call(DataCategory::Session, Some(2));
+    }
+
+    #[test]
+    fn test_enforce_session_aggregates_quantity() {
+        let mut envelope = envelope![Session];
+        let mut item = Item::new(ItemType::Sessions);
+        item.set_payload(
+            ContentType::Json,
+            r#"{
+                "aggregates": [
+                    {"started": "2020-02-07T14:16:00Z", "exited": 10, "errored": 2},
+                    {"started": "2020-02-07T14:17:00Z", "crashed": 1, "abnormal": 1}
+                ],
+                "attrs": {"release": "sentry-test@1.0.0"}
+            }"#,
+        );
+        envelope.add_item(item);
+
+        let mut mock = MockLimiter::default();
+        EnvelopeLimiter::new(|s, q| mock.check(s, q))
+            .enforce(&mut envelope, &scoping())
+            .unwrap();
+
+        assert_eq!(envelope.len(), 2);
+        mock.assert_call(DataCategory::Session, Some(15));
+    }
+
+    #[test]
+    fn test_enforce_limit_metrics() {
+        let mut envelope = envelope![Metrics, Metrics, Event];
+
+        let mut mock = MockLimiter::default().deny(DataCategory::Metric);
+        let limits = EnvelopeLimiter::new(|s, q| mock.check(s, q))
+            .enforce(&mut envelope, &scoping())
+            .unwrap();
+
+        assert!(limits.is_limited());
+        assert_eq!(envelope.len(), 1);
+        mock.assert_call(DataCategory::Error, Some(1));
+        mock.assert_call(DataCategory::Session, None);
+        mock.assert_call(DataCategory::Metric, Some(2));
+    }
+
+    #[test]
+    fn test_enforce_limit_profiles() {
+        let mut envelope = envelope![Profile, Transaction];
+
+        let mut mock = MockLimiter::default().deny(DataCategory::Profile);
+        let limits = EnvelopeLimiter::new(|s, q| mock.check(s, q))
+            .enforce(&mut envelope, &scoping())
+            .unwrap();
+
+        assert!(limits.is_limited());
+        assert_eq!(envelope.len(), 1);
+        mock.assert_call(DataCategory::Transaction, Some(1));
+        mock.assert_call(DataCategory::Profile, Some(1));
+    }
+
+    #[test]
+    fn test_enforce_limit_profiles_with_transaction() {
+        let mut envelope = envelope![Profile, Transaction];
+
+        let mut mock = MockLimiter::default().deny(DataCategory::Transaction);
+        let limits = EnvelopeLimiter::new(|s, q| mock.check(s, q))
+            .enforce(&mut envelope, &scoping())
+            .unwrap();
+
+        assert!(limits.is_limited());
+        assert_eq!(envelope.len(), 0);
+        mock.assert_call(DataCategory::Transaction, Some(1));
+        mock.assert_call(DataCategory::Profile, None);
+    }
+
+    #[test]
+    fn test_enforce_limit_check_ins() {
+        let mut envelope = envelope![CheckIn, CheckIn, Event];
+
+        let mut mock = MockLimiter::default().deny(DataCategory::Monitor);
+        let limits = EnvelopeLimiter::new(|s, q| mock.check(s, q))
+            .enforce(&mut envelope, &scoping())
+            .unwrap();
+
+        assert!(limits.is_limited());
+        assert_eq!(envelope.len(), 1);
+        mock.assert_call(DataCategory::Error, Some(1));
+        mock.assert_call(DataCategory::Monitor, Some(2));
+    }
+
+    #[test]
     fn test_enforce_limit_assumed_event() {
         let mut envelope = envelope![];
 
@@ -636,7 +831,6 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature = "processing")]
     fn test_enforce_limit_assumed_attachments() {
         let mut envelope = envelope![Attachment, Attachment];
 
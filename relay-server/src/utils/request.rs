@@ -1,4 +1,7 @@
-use actix_web::{http::header, HttpMessage};
+use std::cell::Cell;
+use std::rc::Rc;
+
+use actix_web::{http::header, HttpMessage, HttpRequest};
 
 // Resolve the content length from HTTP request headers.
 pub fn get_content_length<T>(req: &T) -> Option<usize>
@@ -10,3 +13,33 @@ where
         .and_then(|h| h.to_str().ok())
         .and_then(|s| s.parse().ok())
 }
+
+/// Counts the request body bytes read from the payload of a request.
+///
+/// The counter is shared through the request extensions, so that all readers of the payload add
+/// to the same total. Unlike the `Content-Length` header, this also covers chunked requests.
+#[derive(Clone, Debug, Default)]
+pub struct RequestBodySize(Rc<Cell<u64>>);
+
+impl RequestBodySize {
+    /// Returns the counter of the given request, inserting a new one if needed.
+    pub fn of<S>(req: &HttpRequest<S>) -> Self {
+        if let Some(size) = req.extensions().get::<Self>() {
+            return size.clone();
+        }
+
+        let size = Self::default();
+        req.extensions_mut().insert(size.clone());
+        size
+    }
+
+    /// Adds the length of a chunk read from the payload.
+    pub fn add(&self, len: usize) {
+        self.0.set(self.0.get() + len as u64);
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+}
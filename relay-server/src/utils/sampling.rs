@@ -0,0 +1,518 @@
+//! Rule-based sampling of transactions.
+//!
+//! The sampling configuration of a project contains an ordered list of rules. The first rule whose
+//! condition matches an event determines its sample rate. Events that match no rule are kept. Only
+//! transactions are sampled; all other events are always kept.
+//!
+//! The sampling decision is deterministic for a trace: it is derived from the trace ID, so that all
+//! transactions of a trace are either kept or dropped together, even across Relays.
+//!
+//! If the SDK sends the trace context in the envelope headers, transactions are sampled before
+//! their payload is parsed with [`sample_envelope`]. Otherwise, they are sampled after the event has
+//! been extracted with [`sample_event`].
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use relay_common::{glob_match, EventType, GlobOptions};
+use relay_general::protocol::{Context, Event};
+use relay_general::types::Value;
+
+use crate::envelope::{Envelope, ItemType, TraceContext};
+
+/// The identifier of a sampling rule.
+///
+/// The rule ID is reported in the outcomes of dropped events.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct RuleId(pub u32);
+
+impl fmt::Display for RuleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The condition under which a sampling rule applies.
+///
+/// All specified fields must match for the condition to match. Empty lists match any value. Values
+/// in `releases`, `environments`, `transactions`, and `user_segments` are glob patterns, of which
+/// at least one must match.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RuleCondition {
+    /// Glob patterns matching the release of the event.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub releases: Vec<String>,
+    /// Glob patterns matching the environment of the event.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<String>,
+    /// Glob patterns matching the transaction name of the event.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
+    /// The event types to which the rule applies.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub types: Vec<EventType>,
+    /// Glob patterns matching the segment of the user.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub user_segments: Vec<String>,
+}
+
+/// Matches a value against a list of glob patterns.
+///
+/// An empty list of patterns matches any value, including a missing value.
+fn matches_any(patterns: &[String], value: Option<&str>) -> bool {
+    if patterns.is_empty() {
+        return true;
+    }
+
+    match value {
+        Some(value) => patterns
+            .iter()
+            .any(|pattern| glob_match(value, pattern, GlobOptions::default())),
+        None => false,
+    }
+}
+
+impl RuleCondition {
+    /// Returns `true` if the condition matches the given event attributes.
+    pub fn matches(
+        &self,
+        ty: EventType,
+        release: Option<&str>,
+        environment: Option<&str>,
+        transaction: Option<&str>,
+        user_segment: Option<&str>,
+    ) -> bool {
+        (self.types.is_empty() || self.types.contains(&ty))
+            && matches_any(&self.releases, release)
+            && matches_any(&self.environments, environment)
+            && matches_any(&self.transactions, transaction)
+            && matches_any(&self.user_segments, user_segment)
+    }
+}
+
+/// A sampling rule.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamplingRule {
+    /// The unique identifier of this rule.
+    pub id: RuleId,
+    /// The condition under which this rule applies.
+    #[serde(default)]
+    pub condition: RuleCondition,
+    /// The fraction of matching events to keep, between `0.0` and `1.0`.
+    pub sample_rate: f64,
+}
+
+/// The sampling configuration of a project.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SamplingConfig {
+    /// Ordered sampling rules. The first matching rule applies.
+    pub rules: Vec<SamplingRule>,
+}
+
+impl SamplingConfig {
+    /// Returns `true` if there are no sampling rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns the first rule that matches the given event attributes.
+    pub fn get_matching_rule(
+        &self,
+        ty: EventType,
+        release: Option<&str>,
+        environment: Option<&str>,
+        transaction: Option<&str>,
+        user_segment: Option<&str>,
+    ) -> Option<&SamplingRule> {
+        self.rules.iter().find(|rule| {
+            rule.condition
+                .matches(ty, release, environment, transaction, user_segment)
+        })
+    }
+}
+
+/// The result of a sampling decision.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SamplingResult {
+    /// The event is kept.
+    Keep,
+    /// The event is dropped due to the given rule.
+    Drop(RuleId),
+}
+
+/// Computes a number in the range `[0, 1)` from a trace ID.
+///
+/// Trace IDs are random 128-bit hex strings, so the leading bits are used as uniformly distributed
+/// value. Only 53 bits are used, which can be represented exactly in a float. Returns `None` if the
+/// trace ID is not a valid hex string.
+fn trace_sample_value(trace_id: &str) -> Option<f64> {
+    let leading = trace_id.get(..16)?;
+    let value = u64::from_str_radix(leading, 16).ok()? >> 11;
+    Some(value as f64 / (1u64 << 53) as f64)
+}
+
+/// Decides whether to keep an event with the given trace ID and matching rule.
+///
+/// Events without a valid trace ID are kept.
+pub fn sample_trace(rule: Option<&SamplingRule>, trace_id: Option<&str>) -> SamplingResult {
+    let rule = match rule {
+        Some(rule) => rule,
+        None => return SamplingResult::Keep,
+    };
+
+    match trace_id.and_then(trace_sample_value) {
+        Some(value) if value >= rule.sample_rate => SamplingResult::Drop(rule.id),
+        _ => SamplingResult::Keep,
+    }
+}
+
+/// Returns the trace ID from the trace context of an event.
+fn get_trace_id(event: &Event) -> Option<&str> {
+    let contexts = event.contexts.value()?;
+    match contexts.get("trace")?.value()?.0 {
+        Context::Trace(ref trace) => Some(trace.trace_id.value()?.0.as_str()),
+        _ => None,
+    }
+}
+
+/// Returns the user segment from the user interface of an event.
+fn get_user_segment(event: &Event) -> Option<&str> {
+    match event.user.value()?.other.get("segment")?.value()? {
+        Value::String(segment) => Some(segment.as_str()),
+        _ => None,
+    }
+}
+
+/// Decides whether to keep an event based on the project's sampling configuration.
+///
+/// Only transactions are sampled. Error events are always kept, even if they carry a trace context
+/// and a rule without a condition on the event type would match them.
+pub fn sample_event(config: &SamplingConfig, event: &Event) -> SamplingResult {
+    if config.is_empty() || event.ty.value() != Some(&EventType::Transaction) {
+        return SamplingResult::Keep;
+    }
+
+    let rule = config.get_matching_rule(
+        EventType::Transaction,
+        event.release.as_str(),
+        event.environment.as_str(),
+        event.transaction.as_str(),
+        get_user_segment(event),
+    );
+
+    sample_trace(rule, get_trace_id(event))
+}
+
+/// Decides whether to keep a transaction based on the trace context of its envelope.
+///
+/// The trace context does not contain the transaction name. If the first rule that could match
+/// has a condition on the transaction, no decision is made here and the event is kept, so that it
+/// can be sampled after parsing.
+fn sample_trace_context(config: &SamplingConfig, trace: &TraceContext) -> SamplingResult {
+    let user_segment = trace.user.as_ref().and_then(|user| user.segment.as_deref());
+
+    for rule in &config.rules {
+        if !rule.condition.transactions.is_empty() {
+            return SamplingResult::Keep;
+        }
+
+        let matches = rule.condition.matches(
+            EventType::Transaction,
+            trace.release.as_deref(),
+            trace.environment.as_deref(),
+            None,
+            user_segment,
+        );
+
+        if matches {
+            return sample_trace(Some(rule), Some(&trace.trace_id));
+        }
+    }
+
+    SamplingResult::Keep
+}
+
+/// Decides whether to keep an envelope based on the trace context in its headers.
+///
+/// This only applies to envelopes containing a transaction. Envelopes without trace context are
+/// kept and sampled after their event has been parsed.
+pub fn sample_envelope(config: &SamplingConfig, envelope: &Envelope) -> SamplingResult {
+    if config.is_empty() {
+        return SamplingResult::Keep;
+    }
+
+    let trace = match envelope.trace_context() {
+        Some(trace) => trace,
+        None => return SamplingResult::Keep,
+    };
+
+    if envelope
+        .get_item_by(|item| item.ty() == ItemType::Transaction)
+        .is_none()
+    {
+        return SamplingResult::Keep;
+    }
+
+    sample_trace_context(config, trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use relay_general::protocol::{Contexts, LenientString, TraceContext, TraceId};
+    use relay_general::types::Annotated;
+
+    fn create_event(trace_id: &str) -> Event {
+        let mut contexts = Contexts::new();
+        contexts.add(Context::Trace(Box::new(TraceContext {
+            trace_id: Annotated::new(TraceId(trace_id.to_owned())),
+            ..Default::default()
+        })));
+
+        Event {
+            ty: Annotated::new(EventType::Transaction),
+            release: Annotated::new(LenientString("1.2.3".to_owned())),
+            environment: Annotated::new("production".to_owned()),
+            transaction: Annotated::new("/api/orders/".to_owned()),
+            contexts: Annotated::new(contexts),
+            ..Default::default()
+        }
+    }
+
+    fn create_envelope(trace: &str, item_type: &str) -> Envelope {
+        let bytes = format!(
+            "{{\"dsn\":\"https://e12d836b15bb49d7bbf99e64295d995b:@sentry.io/42\",\"trace\":{}}}\n{{\"type\":\"{}\"}}\n{{}}\n",
+            trace, item_type
+        );
+        Envelope::parse_bytes(bytes.into()).unwrap()
+    }
+
+    fn create_config(condition: RuleCondition, sample_rate: f64) -> SamplingConfig {
+        SamplingConfig {
+            rules: vec![SamplingRule {
+                id: RuleId(1),
+                condition,
+                sample_rate,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_deserialize_config() {
+        let json = r#"{
+            "rules": [
+                {
+                    "id": 1,
+                    "condition": {
+                        "releases": ["1.*"],
+                        "environments": ["production"],
+                        "transactions": ["/api/*"],
+                        "types": ["transaction"]
+                    },
+                    "sampleRate": 0.5
+                },
+                {"id": 2, "sampleRate": 0.1}
+            ]
+        }"#;
+
+        let config: SamplingConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(
+            config.rules[0].condition.types,
+            vec![EventType::Transaction]
+        );
+        assert_eq!(config.rules[1].condition, RuleCondition::default());
+    }
+
+    #[test]
+    fn test_first_matching_rule() {
+        let config = SamplingConfig {
+            rules: vec![
+                SamplingRule {
+                    id: RuleId(1),
+                    condition: RuleCondition {
+                        environments: vec!["staging".to_owned()],
+                        ..Default::default()
+                    },
+                    sample_rate: 0.0,
+                },
+                SamplingRule {
+                    id: RuleId(2),
+                    condition: RuleCondition {
+                        releases: vec!["1.*".to_owned()],
+                        transactions: vec!["/api/*".to_owned()],
+                        ..Default::default()
+                    },
+                    sample_rate: 0.0,
+                },
+                SamplingRule {
+                    id: RuleId(3),
+                    condition: RuleCondition::default(),
+                    sample_rate: 0.0,
+                },
+            ],
+        };
+
+        let rule = config.get_matching_rule(
+            EventType::Transaction,
+            Some("1.2.3"),
+            Some("production"),
+            Some("/api/orders/"),
+            None,
+        );
+        assert_eq!(rule.map(|rule| rule.id), Some(RuleId(2)));
+
+        let rule = config.get_matching_rule(EventType::Transaction, None, None, None, None);
+        assert_eq!(rule.map(|rule| rule.id), Some(RuleId(3)));
+    }
+
+    #[test]
+    fn test_condition_types() {
+        let condition = RuleCondition {
+            types: vec![EventType::Transaction],
+            ..Default::default()
+        };
+
+        assert!(condition.matches(EventType::Transaction, None, None, None, None));
+        assert!(!condition.matches(EventType::Error, None, None, None, None));
+    }
+
+    #[test]
+    fn test_sample_deterministic() {
+        let config = create_config(RuleCondition::default(), 0.5);
+
+        // The leading bits of the trace ID determine the decision.
+        let low = create_event("1000000000000000ffffffffffffffff");
+        let high = create_event("f000000000000000ffffffffffffffff");
+
+        assert_eq!(sample_event(&config, &low), SamplingResult::Keep);
+        assert_eq!(
+            sample_event(&config, &high),
+            SamplingResult::Drop(RuleId(1))
+        );
+
+        // The same trace ID always yields the same result.
+        for _ in 0..10 {
+            assert_eq!(
+                sample_event(&config, &high),
+                SamplingResult::Drop(RuleId(1))
+            );
+        }
+    }
+
+    #[test]
+    fn test_sample_rate_bounds() {
+        let event = create_event("ffffffffffffffffffffffffffffffff");
+        let keep_all = create_config(RuleCondition::default(), 1.0);
+        assert_eq!(sample_event(&keep_all, &event), SamplingResult::Keep);
+
+        let event = create_event("00000000000000000000000000000001");
+        let drop_all = create_config(RuleCondition::default(), 0.0);
+        assert_eq!(
+            sample_event(&drop_all, &event),
+            SamplingResult::Drop(RuleId(1))
+        );
+    }
+
+    #[test]
+    fn test_no_matching_rule() {
+        let condition = RuleCondition {
+            releases: vec!["2.*".to_owned()],
+            ..Default::default()
+        };
+        let config = create_config(condition, 0.0);
+        let event = create_event("f000000000000000ffffffffffffffff");
+        assert_eq!(sample_event(&config, &event), SamplingResult::Keep);
+    }
+
+    #[test]
+    fn test_missing_trace_id() {
+        let config = create_config(RuleCondition::default(), 0.0);
+        let event = Event {
+            ty: Annotated::new(EventType::Transaction),
+            ..Default::default()
+        };
+        assert_eq!(sample_event(&config, &event), SamplingResult::Keep);
+    }
+
+    #[test]
+    fn test_keep_errors() {
+        let config = create_config(RuleCondition::default(), 0.0);
+        let transaction = create_event("f000000000000000ffffffffffffffff");
+        assert_eq!(
+            sample_event(&config, &transaction),
+            SamplingResult::Drop(RuleId(1))
+        );
+
+        // Error events with the same trace context are never sampled.
+        let error = Event {
+            ty: Annotated::new(EventType::Error),
+            ..transaction.clone()
+        };
+        assert_eq!(sample_event(&config, &error), SamplingResult::Keep);
+
+        let default = Event {
+            ty: Annotated::empty(),
+            ..transaction
+        };
+        assert_eq!(sample_event(&config, &default), SamplingResult::Keep);
+    }
+
+    #[test]
+    fn test_sample_envelope() {
+        let config = create_config(
+            RuleCondition {
+                environments: vec!["production".to_owned()],
+                user_segments: vec!["free".to_owned()],
+                ..Default::default()
+            },
+            0.0,
+        );
+
+        let trace = r#"{"trace_id":"f000000000000000ffffffffffffffff","public_key":"e12d836b15bb49d7bbf99e64295d995b","environment":"production","user":{"segment":"free"}}"#;
+        let envelope = create_envelope(trace, "transaction");
+        assert_eq!(
+            sample_envelope(&config, &envelope),
+            SamplingResult::Drop(RuleId(1))
+        );
+
+        // Only envelopes with transactions are sampled.
+        let envelope = create_envelope(trace, "event");
+        assert_eq!(sample_envelope(&config, &envelope), SamplingResult::Keep);
+
+        let trace = r#"{"trace_id":"f000000000000000ffffffffffffffff","public_key":"e12d836b15bb49d7bbf99e64295d995b","environment":"production","user":{"segment":"paid"}}"#;
+        let envelope = create_envelope(trace, "transaction");
+        assert_eq!(sample_envelope(&config, &envelope), SamplingResult::Keep);
+    }
+
+    #[test]
+    fn test_sample_envelope_transaction_condition() {
+        let config = SamplingConfig {
+            rules: vec![
+                SamplingRule {
+                    id: RuleId(1),
+                    condition: RuleCondition {
+                        transactions: vec!["/health".to_owned()],
+                        ..Default::default()
+                    },
+                    sample_rate: 1.0,
+                },
+                SamplingRule {
+                    id: RuleId(2),
+                    condition: RuleCondition::default(),
+                    sample_rate: 0.0,
+                },
+            ],
+        };
+
+        // The first rule requires the transaction name, so the decision is deferred.
+        let trace = r#"{"trace_id":"f000000000000000ffffffffffffffff","public_key":"e12d836b15bb49d7bbf99e64295d995b"}"#;
+        let envelope = create_envelope(trace, "transaction");
+        assert_eq!(sample_envelope(&config, &envelope), SamplingResult::Keep);
+    }
+}
@@ -0,0 +1,366 @@
+//! Aggregation of session updates into pre-rolled buckets.
+//!
+//! Session updates and pre-aggregated sessions sent by SDKs are rolled up per organization,
+//! project, release, environment, status, and the minute in which the sessions started. Each bucket
+//! counts sessions and records duration quantiles of the updates it contains.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+
+use relay_common::ProjectId;
+use relay_general::protocol::{SessionAggregates, SessionStatus, SessionUpdate};
+use relay_quotas::Scoping;
+
+/// The length of the time window of a bucket in seconds.
+const BUCKET_INTERVAL: i64 = 60;
+
+/// Quantiles of session durations reported for every bucket.
+const DURATION_QUANTILES: &[f64] = &[0.5, 0.75, 0.9, 0.95, 0.99, 1.0];
+
+/// The key that identifies a bucket of sessions.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct SessionBucketKey {
+    org_id: u64,
+    project_id: ProjectId,
+    release: String,
+    environment: Option<String>,
+    status: SessionStatus,
+    started: i64,
+}
+
+/// Counters and durations of a bucket that has not been flushed yet.
+#[derive(Debug, Default)]
+struct SessionBucket {
+    retention_days: u16,
+    quantity: u32,
+    started: u32,
+    errored: u32,
+    durations: Vec<f64>,
+}
+
+/// An aggregate of sessions with the same attributes and status, started in the same minute.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionAggregate {
+    /// The organization that owns the project.
+    pub org_id: u64,
+    /// The project of the sessions.
+    pub project_id: ProjectId,
+    /// The release version string.
+    pub release: String,
+    /// The environment identifier.
+    pub environment: Option<String>,
+    /// The status of the sessions.
+    pub status: SessionStatus,
+    /// The start of the minute in which the sessions started as UNIX timestamp.
+    pub started: i64,
+    /// The number of session updates or aggregated sessions in this bucket.
+    pub quantity: u32,
+    /// The number of sessions that were started, based on the `init` flag of updates.
+    pub started_count: u32,
+    /// The number of sessions that reported errors or crashed.
+    pub errored: u32,
+    /// Pairs of quantiles and the corresponding session durations in seconds.
+    ///
+    /// This is empty if none of the updates reported a duration.
+    pub duration_quantiles: Vec<(f64, f64)>,
+    /// Data retention of the project in days.
+    pub retention_days: u16,
+}
+
+/// Computes the quantiles of the given durations.
+fn duration_quantiles(mut durations: Vec<f64>) -> Vec<(f64, f64)> {
+    if durations.is_empty() {
+        return Vec::new();
+    }
+
+    durations.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let max_index = durations.len() - 1;
+
+    DURATION_QUANTILES
+        .iter()
+        .map(|&quantile| {
+            let index = (quantile * max_index as f64).round() as usize;
+            (quantile, durations[index.min(max_index)])
+        })
+        .collect()
+}
+
+/// Rounds a session start time down to the beginning of its bucket.
+fn bucket_start(started: DateTime<Utc>) -> i64 {
+    let timestamp = started.timestamp();
+    timestamp - timestamp.rem_euclid(BUCKET_INTERVAL)
+}
+
+/// Aggregates session updates into buckets until they are flushed.
+#[derive(Debug)]
+pub struct SessionAggregator {
+    buckets: BTreeMap<SessionBucketKey, SessionBucket>,
+    max_buckets: usize,
+}
+
+impl SessionAggregator {
+    /// Creates an empty session aggregator.
+    ///
+    /// The aggregator is considered full once it holds `max_buckets` buckets. This is a soft
+    /// limit: adding pre-aggregated sessions may exceed it, and the caller is expected to flush
+    /// once `is_full` returns `true`.
+    pub fn new(max_buckets: usize) -> Self {
+        Self {
+            buckets: BTreeMap::new(),
+            max_buckets,
+        }
+    }
+
+    /// Returns the bucket for the given key, creating it if necessary.
+    fn bucket(
+        &mut self,
+        scoping: &Scoping,
+        retention_days: u16,
+        release: &str,
+        environment: Option<&str>,
+        status: SessionStatus,
+        started: DateTime<Utc>,
+    ) -> &mut SessionBucket {
+        let key = SessionBucketKey {
+            org_id: scoping.organization_id,
+            project_id: scoping.project_id,
+            release: release.to_owned(),
+            environment: environment.map(str::to_owned),
+            status,
+            started: bucket_start(started),
+        };
+
+        let bucket = self.buckets.entry(key).or_default();
+        bucket.retention_days = retention_days;
+        bucket
+    }
+
+    /// Adds a single session update.
+    pub fn add_update(&mut self, scoping: &Scoping, retention_days: u16, update: &SessionUpdate) {
+        let attributes = &update.attributes;
+        let bucket = self.bucket(
+            scoping,
+            retention_days,
+            &attributes.release,
+            attributes.environment.as_deref(),
+            update.status,
+            update.started,
+        );
+
+        bucket.quantity += 1;
+        if update.init {
+            bucket.started += 1;
+        }
+        if update.errors > 0 || update.status == SessionStatus::Crashed {
+            bucket.errored += 1;
+        }
+        if let Some(duration) = update.duration {
+            bucket.durations.push(duration);
+        }
+    }
+
+    /// Adds pre-aggregated sessions.
+    ///
+    /// Pre-aggregated sessions are complete, so all of them count as started. Errored sessions are
+    /// counted as exited sessions with errors.
+    pub fn add_aggregates(
+        &mut self,
+        scoping: &Scoping,
+        retention_days: u16,
+        aggregates: &SessionAggregates,
+    ) {
+        let attributes = &aggregates.attributes;
+        let release = &attributes.release;
+        let environment = attributes.environment.as_deref();
+
+        for aggregate in &aggregates.aggregates {
+            let counts = [
+                (SessionStatus::Exited, aggregate.exited, 0),
+                (SessionStatus::Exited, aggregate.errored, aggregate.errored),
+                (SessionStatus::Abnormal, aggregate.abnormal, 0),
+                (SessionStatus::Crashed, aggregate.crashed, aggregate.crashed),
+            ];
+
+            for &(status, quantity, errored) in &counts {
+                if quantity == 0 {
+                    continue;
+                }
+
+                let bucket = self.bucket(
+                    scoping,
+                    retention_days,
+                    release,
+                    environment,
+                    status,
+                    aggregate.started,
+                );
+
+                bucket.quantity += quantity;
+                bucket.started += quantity;
+                bucket.errored += errored;
+            }
+        }
+    }
+
+    /// Returns `true` if there are no buckets to flush.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Returns `true` if the maximum number of buckets has been reached and the aggregator should
+    /// be flushed.
+    pub fn is_full(&self) -> bool {
+        self.buckets.len() >= self.max_buckets
+    }
+
+    /// Removes all buckets and returns their aggregates.
+    pub fn take(&mut self) -> Vec<SessionAggregate> {
+        std::mem::replace(&mut self.buckets, BTreeMap::new())
+            .into_iter()
+            .map(|(key, bucket)| SessionAggregate {
+                org_id: key.org_id,
+                project_id: key.project_id,
+                release: key.release,
+                environment: key.environment,
+                status: key.status,
+                started: key.started,
+                quantity: bucket.quantity,
+                started_count: bucket.started,
+                errored: bucket.errored,
+                duration_quantiles: duration_quantiles(bucket.durations),
+                retention_days: bucket.retention_days,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scoping() -> Scoping {
+        Scoping {
+            organization_id: 42,
+            project_id: ProjectId::new(21),
+            public_key: "e12d836b15bb49d7bbf99e64295d995b".to_owned(),
+            key_id: Some(17),
+        }
+    }
+
+    fn update(json: &str) -> SessionUpdate {
+        SessionUpdate::parse(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_aggregate_updates() {
+        let mut aggregator = SessionAggregator::new(100);
+        let scoping = scoping();
+
+        aggregator.add_update(
+            &scoping,
+            90,
+            &update(r#"{"init":true,"started":"2020-02-07T14:16:10Z","attrs":{"release":"1.0"}}"#),
+        );
+        aggregator.add_update(
+            &scoping,
+            90,
+            &update(
+                r#"{"started":"2020-02-07T14:16:50Z","status":"exited","duration":10,"attrs":{"release":"1.0"}}"#,
+            ),
+        );
+        aggregator.add_update(
+            &scoping,
+            90,
+            &update(
+                r#"{"started":"2020-02-07T14:16:20Z","status":"exited","duration":20,"errors":1,"attrs":{"release":"1.0"}}"#,
+            ),
+        );
+        aggregator.add_update(
+            &scoping,
+            90,
+            &update(
+                r#"{"started":"2020-02-07T14:17:00Z","status":"exited","attrs":{"release":"1.0"}}"#,
+            ),
+        );
+
+        let aggregates = aggregator.take();
+        assert!(aggregator.is_empty());
+        assert_eq!(aggregates.len(), 3);
+
+        let exited = &aggregates[1];
+        assert_eq!(exited.status, SessionStatus::Exited);
+        assert_eq!(exited.started, 1_581_084_960);
+        assert_eq!(exited.quantity, 2);
+        assert_eq!(exited.started_count, 0);
+        assert_eq!(exited.errored, 1);
+        assert_eq!(exited.duration_quantiles[0], (0.5, 20.0));
+        assert_eq!(exited.duration_quantiles[5], (1.0, 20.0));
+
+        let ok = &aggregates[0];
+        assert_eq!(ok.status, SessionStatus::Ok);
+        assert_eq!(ok.started_count, 1);
+        assert!(ok.duration_quantiles.is_empty());
+
+        assert_eq!(aggregates[2].started, 1_581_085_020);
+    }
+
+    #[test]
+    fn test_aggregate_pre_aggregated() {
+        let mut aggregator = SessionAggregator::new(100);
+        let aggregates = SessionAggregates::parse(
+            br#"{
+                "aggregates": [
+                    {"started": "2020-02-07T14:16:00Z", "exited": 5, "errored": 2, "crashed": 1},
+                    {"started": "2020-02-07T14:16:30Z", "exited": 1}
+                ],
+                "attrs": {"release": "1.0", "environment": "prod"}
+            }"#,
+        )
+        .unwrap();
+
+        aggregator.add_aggregates(&scoping(), 90, &aggregates);
+        let aggregates = aggregator.take();
+        assert_eq!(aggregates.len(), 2);
+
+        let exited = &aggregates[0];
+        assert_eq!(exited.status, SessionStatus::Exited);
+        assert_eq!(exited.environment.as_deref(), Some("prod"));
+        assert_eq!(exited.quantity, 8);
+        assert_eq!(exited.started_count, 8);
+        assert_eq!(exited.errored, 2);
+
+        let crashed = &aggregates[1];
+        assert_eq!(crashed.status, SessionStatus::Crashed);
+        assert_eq!(crashed.quantity, 1);
+        assert_eq!(crashed.errored, 1);
+    }
+
+    #[test]
+    fn test_aggregate_full() {
+        let mut aggregator = SessionAggregator::new(2);
+        let scoping = scoping();
+
+        aggregator.add_update(
+            &scoping,
+            90,
+            &update(r#"{"started":"2020-02-07T14:16:10Z","attrs":{"release":"1.0"}}"#),
+        );
+        aggregator.add_update(
+            &scoping,
+            90,
+            &update(r#"{"started":"2020-02-07T14:16:20Z","attrs":{"release":"1.0"}}"#),
+        );
+        assert!(!aggregator.is_full());
+
+        aggregator.add_update(
+            &scoping,
+            90,
+            &update(r#"{"started":"2020-02-07T14:16:10Z","attrs":{"release":"2.0"}}"#),
+        );
+        assert!(aggregator.is_full());
+
+        assert_eq!(aggregator.take().len(), 2);
+        assert!(!aggregator.is_full());
+    }
+}
@@ -0,0 +1,475 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use actix::prelude::*;
+use bytes::Bytes;
+use failure::Fail;
+use parking_lot::Mutex;
+
+use relay_common::UnixTimestamp;
+
+use crate::envelope::{Envelope, EnvelopeError};
+
+/// File extension of spooled envelopes.
+const SPOOL_EXTENSION: &str = "envelope";
+
+#[derive(Debug, Fail)]
+pub enum SpoolError {
+    #[fail(display = "spool is full (max_disk_size reached)")]
+    Full,
+
+    #[fail(display = "failed to access spool file")]
+    Io(#[cause] io::Error),
+
+    #[fail(display = "failed to serialize spooled envelope")]
+    Envelope(#[cause] EnvelopeError),
+}
+
+/// An envelope read back from the spool.
+#[derive(Debug)]
+pub struct SpooledEnvelope {
+    /// The envelope as it was originally queued.
+    pub envelope: Envelope,
+    /// The time at which the envelope was originally received.
+    pub start_time: Instant,
+}
+
+/// Bookkeeping for a single envelope file in the spool directory.
+#[derive(Debug)]
+struct SpoolEntry {
+    path: PathBuf,
+    sequence: u64,
+    received_at: SystemTime,
+    size: usize,
+}
+
+impl SpoolEntry {
+    /// Parses the file name of a spooled envelope.
+    ///
+    /// File names are `<sequence>-<received millis>.envelope` in zero-padded hex notation, so that
+    /// sorting them lexicographically yields the order in which they were written, regardless of
+    /// the system clock.
+    fn from_path(path: PathBuf, size: usize) -> Option<Self> {
+        if path.extension()? != SPOOL_EXTENSION {
+            return None;
+        }
+
+        let stem = path.file_stem()?.to_str()?;
+        let mut parts = stem.splitn(2, '-');
+        let sequence = u64::from_str_radix(parts.next()?, 16).ok()?;
+        let millis = u64::from_str_radix(parts.next()?, 16).ok()?;
+        let received_at = SystemTime::UNIX_EPOCH + Duration::from_millis(millis);
+
+        Some(Self {
+            path,
+            sequence,
+            received_at,
+            size,
+        })
+    }
+
+    /// Returns the time elapsed since the envelope was received.
+    fn age(&self) -> Duration {
+        self.received_at.elapsed().unwrap_or_default()
+    }
+}
+
+/// A persistent, size-bounded FIFO queue of envelopes on disk.
+///
+/// Each envelope is written to its own file using the regular envelope serialization format. The
+/// time at which the envelope was received is encoded in the file name, which allows to restore
+/// the spool in order after a restart and to expire envelopes without reading them.
+///
+/// All operations are blocking file system operations. To use the spool from async code, start it
+/// as synchronous actor in its own thread and send [`SpoolEnvelope`] and [`ReplaySpool`] messages.
+///
+/// [`SpoolEnvelope`]: struct.SpoolEnvelope.html
+/// [`ReplaySpool`]: struct.ReplaySpool.html
+#[derive(Debug)]
+pub struct EnvelopeSpool {
+    path: PathBuf,
+    max_size: usize,
+    max_age: Duration,
+    entries: VecDeque<SpoolEntry>,
+    size: usize,
+    /// The sequence number of the next spooled envelope.
+    sequence: u64,
+}
+
+impl EnvelopeSpool {
+    /// Opens the spool in the given directory, creating it if necessary.
+    ///
+    /// Envelopes that were spooled by a previous instance are restored in order.
+    pub fn open(path: &Path, max_size: usize, max_age: Duration) -> Result<Self, SpoolError> {
+        fs::create_dir_all(path).map_err(SpoolError::Io)?;
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path).map_err(SpoolError::Io)? {
+            let entry = entry.map_err(SpoolError::Io)?;
+            let metadata = entry.metadata().map_err(SpoolError::Io)?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            match SpoolEntry::from_path(entry.path(), metadata.len() as usize) {
+                Some(spool_entry) => entries.push(spool_entry),
+                None => log::warn!("skipping {:?}, not a spooled envelope", entry.path()),
+            }
+        }
+
+        entries.sort_by_key(|entry| entry.sequence);
+        let size = entries.iter().map(|entry| entry.size).sum();
+        let sequence = entries.last().map_or(0, |entry| entry.sequence + 1);
+
+        if !entries.is_empty() {
+            log::info!("restored {} envelopes from spool", entries.len());
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            max_size,
+            max_age,
+            entries: entries.into(),
+            size,
+            sequence,
+        })
+    }
+
+    /// Returns the number of envelopes in the spool.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no envelopes in the spool.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the combined size of all spooled envelopes in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Writes an envelope to the end of the spool.
+    ///
+    /// Returns `SpoolError::Full` if the envelope would exceed the maximum size of the spool.
+    pub fn push(&mut self, envelope: &Envelope, start_time: Instant) -> Result<(), SpoolError> {
+        let data = envelope.to_vec().map_err(SpoolError::Envelope)?;
+        if self.size + data.len() > self.max_size {
+            return Err(SpoolError::Full);
+        }
+
+        let millis = UnixTimestamp::from_instant(start_time).as_millis() as u64;
+
+        // Never overwrite existing files, for instance if they were created by another process.
+        let (path, mut file) = loop {
+            let sequence = self.sequence;
+            self.sequence += 1;
+
+            let file_name = format!("{:016x}-{:016x}.{}", sequence, millis, SPOOL_EXTENSION);
+            let path = self.path.join(file_name);
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => break (path, file),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(SpoolError::Io(error)),
+            }
+        };
+
+        if let Err(error) = file.write_all(&data) {
+            fs::remove_file(&path).ok();
+            return Err(SpoolError::Io(error));
+        }
+
+        let entry = SpoolEntry::from_path(path, data.len()).ok_or_else(|| {
+            SpoolError::Io(io::Error::new(io::ErrorKind::Other, "invalid spool file"))
+        })?;
+
+        self.size += entry.size;
+        self.entries.push_back(entry);
+        Ok(())
+    }
+
+    /// Removes and returns the oldest envelope from the spool.
+    ///
+    /// Envelopes that cannot be read or parsed are discarded. This does not check for expiry, use
+    /// `evict_expired` before popping envelopes.
+    pub fn pop(&mut self) -> Option<Result<SpooledEnvelope, SpoolError>> {
+        let entry = self.entries.pop_front()?;
+        self.size -= entry.size;
+        Some(self.read_entry(entry))
+    }
+
+    /// Removes all envelopes that exceeded the maximum age from the spool.
+    ///
+    /// Expired envelopes are returned so that outcomes can be emitted for them. Since envelopes
+    /// can be spooled again after a failed replay, all envelopes are checked, not just the oldest.
+    pub fn evict_expired(&mut self) -> Vec<Envelope> {
+        let max_age = self.max_age;
+        let (expired_entries, entries) = self
+            .entries
+            .drain(..)
+            .partition::<VecDeque<_>, _>(|entry| entry.age() >= max_age);
+
+        self.entries = entries;
+
+        let mut expired = Vec::new();
+        for entry in expired_entries {
+            self.size -= entry.size;
+
+            match self.read_entry(entry) {
+                Ok(spooled) => expired.push(spooled.envelope),
+                Err(error) => log::error!("failed to read expired envelope: {}", error),
+            }
+        }
+
+        expired
+    }
+
+    fn read_entry(&self, entry: SpoolEntry) -> Result<SpooledEnvelope, SpoolError> {
+        let data = fs::read(&entry.path).map_err(SpoolError::Io);
+        fs::remove_file(&entry.path).ok();
+
+        let envelope = Envelope::parse_bytes(Bytes::from(data?)).map_err(SpoolError::Envelope)?;
+        let start_time = Instant::now()
+            .checked_sub(entry.age())
+            .unwrap_or_else(Instant::now);
+
+        Ok(SpooledEnvelope {
+            envelope,
+            start_time,
+        })
+    }
+}
+
+impl EnvelopeSpool {
+    /// Moves the spool into a dedicated worker thread and returns the address of its actor.
+    pub fn start_sync(self) -> Addr<Self> {
+        let spool = Mutex::new(Some(self));
+        SyncArbiter::start(1, move || {
+            spool
+                .lock()
+                .take()
+                .expect("spool worker must not be restarted")
+        })
+    }
+}
+
+impl Actor for EnvelopeSpool {
+    type Context = SyncContext<Self>;
+}
+
+/// Writes an envelope to the end of the spool.
+#[derive(Debug)]
+pub struct SpoolEnvelope {
+    pub envelope: Envelope,
+    pub start_time: Instant,
+}
+
+impl Message for SpoolEnvelope {
+    type Result = Result<(), SpoolError>;
+}
+
+impl Handler<SpoolEnvelope> for EnvelopeSpool {
+    type Result = Result<(), SpoolError>;
+
+    fn handle(&mut self, message: SpoolEnvelope, _context: &mut Self::Context) -> Self::Result {
+        self.push(&message.envelope, message.start_time)
+    }
+}
+
+/// Evicts expired envelopes and reads up to `max_count` envelopes from the front of the spool.
+#[derive(Debug)]
+pub struct ReplaySpool {
+    pub max_count: usize,
+}
+
+/// The result of [`ReplaySpool`].
+///
+/// [`ReplaySpool`]: struct.ReplaySpool.html
+#[derive(Debug)]
+pub struct SpoolReplay {
+    /// Envelopes that exceeded the maximum age.
+    pub expired: Vec<Envelope>,
+    /// Envelopes to replay, in the order in which they were spooled.
+    pub replayed: Vec<SpooledEnvelope>,
+    /// The number of envelopes removed from the spool, including unreadable ones.
+    pub removed: usize,
+    /// The combined size of the remaining envelopes in bytes.
+    pub size: usize,
+}
+
+impl Message for ReplaySpool {
+    type Result = SpoolReplay;
+}
+
+impl Handler<ReplaySpool> for EnvelopeSpool {
+    type Result = MessageResult<ReplaySpool>;
+
+    fn handle(&mut self, message: ReplaySpool, _context: &mut Self::Context) -> Self::Result {
+        let len = self.len();
+        let expired = self.evict_expired();
+
+        let mut replayed = Vec::new();
+        while replayed.len() < message.max_count {
+            match self.pop() {
+                Some(Ok(spooled)) => replayed.push(spooled),
+                Some(Err(error)) => log::error!("failed to read spooled envelope: {}", error),
+                None => break,
+            }
+        }
+
+        MessageResult(SpoolReplay {
+            expired,
+            replayed,
+            removed: len - self.len(),
+            size: self.size(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use relay_general::protocol::EventId;
+
+    use crate::envelope::{ContentType, Item, ItemType};
+    use crate::extractors::RequestMeta;
+
+    fn spool_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("relay-spool-{}", EventId::new()))
+    }
+
+    fn envelope(payload: &'static str) -> Envelope {
+        let dsn = "https://e12d836b15bb49d7bbf99e64295d995b:@sentry.io/42"
+            .parse()
+            .unwrap();
+
+        let mut envelope = Envelope::from_request(Some(EventId::new()), RequestMeta::new(dsn));
+        let mut item = Item::new(ItemType::Event);
+        item.set_payload(ContentType::Json, payload);
+        envelope.add_item(item);
+        envelope
+    }
+
+    fn payload(spooled: SpooledEnvelope) -> Bytes {
+        spooled.envelope.items().next().unwrap().payload()
+    }
+
+    #[test]
+    fn test_spool_fifo() {
+        let dir = spool_dir();
+        let mut spool = EnvelopeSpool::open(&dir, 1024 * 1024, Duration::from_secs(60)).unwrap();
+
+        spool.push(&envelope("{\"a\":1}"), Instant::now()).unwrap();
+        spool.push(&envelope("{\"b\":2}"), Instant::now()).unwrap();
+        assert_eq!(spool.len(), 2);
+
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"a\":1}");
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"b\":2}");
+        assert!(spool.pop().is_none());
+        assert_eq!(spool.size(), 0);
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_spool_restore() {
+        let dir = spool_dir();
+
+        {
+            let mut spool =
+                EnvelopeSpool::open(&dir, 1024 * 1024, Duration::from_secs(60)).unwrap();
+            spool.push(&envelope("{\"a\":1}"), Instant::now()).unwrap();
+            spool.push(&envelope("{\"b\":2}"), Instant::now()).unwrap();
+        }
+
+        let mut spool = EnvelopeSpool::open(&dir, 1024 * 1024, Duration::from_secs(60)).unwrap();
+        assert_eq!(spool.len(), 2);
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"a\":1}");
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_spool_full() {
+        let dir = spool_dir();
+        let mut spool = EnvelopeSpool::open(&dir, 10, Duration::from_secs(60)).unwrap();
+
+        let result = spool.push(&envelope("{\"a\":1}"), Instant::now());
+        assert!(matches!(result, Err(SpoolError::Full)));
+        assert!(spool.is_empty());
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_spool_expired() {
+        let dir = spool_dir();
+        let mut spool = EnvelopeSpool::open(&dir, 1024 * 1024, Duration::from_secs(60)).unwrap();
+
+        let old = Instant::now() - Duration::from_secs(120);
+        spool.push(&envelope("{\"a\":1}"), old).unwrap();
+        spool.push(&envelope("{\"b\":2}"), Instant::now()).unwrap();
+
+        let expired = spool.evict_expired();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(spool.len(), 1);
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"b\":2}");
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_spool_expired_not_first() {
+        let dir = spool_dir();
+        let mut spool = EnvelopeSpool::open(&dir, 1024 * 1024, Duration::from_secs(60)).unwrap();
+
+        // Envelopes spooled again after a failed replay can be older than envelopes before them.
+        let old = Instant::now() - Duration::from_secs(120);
+        spool.push(&envelope("{\"a\":1}"), Instant::now()).unwrap();
+        spool.push(&envelope("{\"b\":2}"), old).unwrap();
+        spool.push(&envelope("{\"c\":3}"), Instant::now()).unwrap();
+
+        assert_eq!(spool.evict_expired().len(), 1);
+        assert_eq!(spool.len(), 2);
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"a\":1}");
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"c\":3}");
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_spool_no_overwrite() {
+        let dir = spool_dir();
+
+        {
+            let mut spool =
+                EnvelopeSpool::open(&dir, 1024 * 1024, Duration::from_secs(60)).unwrap();
+            spool.push(&envelope("{\"a\":1}"), Instant::now()).unwrap();
+        }
+
+        // A restarted spool continues after the last sequence, even with the same timestamp.
+        let mut spool = EnvelopeSpool::open(&dir, 1024 * 1024, Duration::from_secs(60)).unwrap();
+        spool.push(&envelope("{\"b\":2}"), Instant::now()).unwrap();
+
+        // Files created by others are skipped.
+        let file_name = format!("{:016x}-{:016x}.envelope", spool.sequence, 0);
+        fs::write(dir.join(file_name), "").unwrap();
+        spool.push(&envelope("{\"c\":3}"), Instant::now()).unwrap();
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"a\":1}");
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"b\":2}");
+        assert_eq!(payload(spool.pop().unwrap().unwrap()), "{\"c\":3}");
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -9,7 +9,7 @@ use failure::{err_msg, Error};
 
 use relay_common::{LogError, Uuid};
 use relay_config::{Config, Credentials, MinimalConfig, OverridableConfig, RelayMode};
-use relay_general::pii::{PiiConfig, PiiProcessor};
+use relay_general::pii::{decrypt_value, EncryptionKey, PiiConfig, PiiProcessor};
 use relay_general::processor::{process_value, ProcessingState};
 use relay_general::protocol::Event;
 use relay_general::store::{StoreConfig, StoreProcessor};
@@ -44,6 +44,8 @@ pub fn execute() -> Result<(), Error> {
         }
     } else if let Some(matches) = matches.subcommand_matches("generate-completions") {
         return generate_completions(&matches);
+    } else if let Some(matches) = matches.subcommand_matches("pii") {
+        return manage_pii(&matches);
     } else if let Some(matches) = matches.subcommand_matches("process-event") {
         return process_event(&matches);
     } else if let Some(_matches) = matches.subcommand_matches("event-json-schema") {
@@ -187,6 +189,7 @@ pub fn manage_credentials<'a>(mut config: Config, matches: &ArgMatches<'a>) -> R
                     }
                 }
             },
+            next: None,
         }))?;
         if !changed {
             println!("Nothing was changed");
@@ -210,6 +213,39 @@ pub fn manage_credentials<'a>(mut config: Config, matches: &ArgMatches<'a>) -> R
         } else {
             println!("No credentials");
         }
+    } else if let Some(matches) = matches.subcommand_matches("rotate") {
+        let mut credentials = config
+            .credentials()
+            .cloned()
+            .ok_or_else(|| err_msg("no stored credentials"))?;
+
+        if matches.is_present("complete") {
+            if !credentials.complete_rotation() {
+                return Err(err_msg("no key rotation in progress"));
+            }
+            config.replace_credentials(Some(credentials))?;
+            println!("Completed key rotation:");
+            setup::dump_credentials(&config);
+        } else if matches.is_present("abort") {
+            if credentials.next.take().is_none() {
+                return Err(err_msg("no key rotation in progress"));
+            }
+            config.replace_credentials(Some(credentials))?;
+            println!("Aborted key rotation");
+        } else {
+            if credentials.next.is_some() && !matches.is_present("overwrite") {
+                return Err(err_msg(
+                    "aborting because a key rotation is in progress. Pass --overwrite to force.",
+                ));
+            }
+            credentials.start_rotation();
+            config.replace_credentials(Some(credentials))?;
+            println!("Generated next key pair:");
+            setup::dump_credentials(&config);
+            println!();
+            println!("Trust the next public key upstream, then reload or restart the relay.");
+            println!("Run `relay credentials rotate --complete` once the rotation has finished.");
+        }
     } else if let Some(..) = matches.subcommand_matches("show") {
         if !config.has_credentials() {
             return Err(err_msg("no stored credentials"));
@@ -362,10 +398,42 @@ pub fn generate_completions<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
     Ok(())
 }
 
+pub fn manage_pii<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
+    if let Some(matches) = matches.subcommand_matches("decrypt") {
+        decrypt_values(&matches)
+    } else {
+        unreachable!();
+    }
+}
+
+pub fn decrypt_values<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
+    let keys_json = fs::read_to_string(matches.value_of("keys").unwrap())?;
+    let keys: Vec<EncryptionKey> = serde_json::from_str(&keys_json)?;
+
+    let values: Vec<String> = match matches.values_of("values") {
+        Some(values) => values.map(str::to_owned).collect(),
+        None => {
+            let mut input = String::new();
+            io::stdin().lock().read_to_string(&mut input)?;
+            input.lines().map(str::to_owned).collect()
+        }
+    };
+
+    for value in values.iter().map(|value| value.trim()) {
+        if !value.is_empty() {
+            println!("{}", decrypt_value(&keys, value)?);
+        }
+    }
+
+    Ok(())
+}
+
 pub fn process_event<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
     let pii_config = if let Some(pii_config) = matches.value_of("pii_config") {
         let json_config = fs::read_to_string(&pii_config)?;
-        Some(PiiConfig::from_json(&json_config)?)
+        let pii_config = PiiConfig::from_json(&json_config)?;
+        pii_config.validate()?;
+        Some(pii_config)
     } else {
         None
     };
@@ -376,6 +444,21 @@ pub fn process_event<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
     let mut event = EventV8::from_json_bytes(&event_json[..])?;
     if let Some(ref pii_config) = pii_config {
         let compiled = pii_config.compiled();
+
+        if matches.is_present("pii_audit") {
+            let mut processor = PiiProcessor::audit(&compiled);
+            process_value(&mut event, &mut processor, ProcessingState::root())?;
+
+            let pii_matches = processor.into_matches();
+            if matches.is_present("pretty") {
+                println!("{}", serde_json::to_string_pretty(&pii_matches)?);
+            } else {
+                println!("{}", serde_json::to_string(&pii_matches)?);
+            }
+
+            return Ok(());
+        }
+
         let mut processor = PiiProcessor::new(&compiled);
         process_value(&mut event, &mut processor, ProcessingState::root())?;
     };
@@ -408,7 +491,7 @@ pub fn event_json_schema<'a>(_matches: &ArgMatches<'a>) -> Result<(), Error> {
 pub fn run<'a>(config: Config, _matches: &ArgMatches<'a>) -> Result<(), Error> {
     setup::dump_spawn_infos(&config);
     setup::check_config(&config)?;
-    setup::init_metrics(&config)?;
+    relay_server::init_metrics(&config)?;
     relay_server::run(config)?;
     Ok(())
 }
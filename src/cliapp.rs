@@ -204,6 +204,37 @@ pub fn make_app() -> App<'static, 'static> {
                                 .value_name("RELAY_ID")
                                 .help("The relay ID to set"),
                         ),
+                )
+                .subcommand(
+                    App::new("rotate")
+                        .about("Rotate the key pair of the credentials")
+                        .after_help(
+                            "This generates the next key pair for the relay and stores it \
+                             next to the current one.  After a reload or restart, the relay \
+                             registers the next public key with its upstreams and signs \
+                             requests with it once all upstreams have acknowledged it.  \
+                             Until then, the current key pair remains in use.\n\
+                             \n\
+                             Once the next public key is trusted everywhere, run this \
+                             command with '--complete' to replace the current key pair.",
+                        )
+                        .arg(
+                            Arg::with_name("overwrite")
+                                .long("overwrite")
+                                .help("Replace the key pair of a pending rotation"),
+                        )
+                        .arg(
+                            Arg::with_name("complete")
+                                .long("complete")
+                                .conflicts_with_all(&["abort", "overwrite"])
+                                .help("Replace the current key pair with the next one"),
+                        )
+                        .arg(
+                            Arg::with_name("abort")
+                                .long("abort")
+                                .conflicts_with("overwrite")
+                                .help("Discard the next key pair"),
+                        ),
                 ),
         )
         .subcommand(
@@ -246,6 +277,36 @@ pub fn make_app() -> App<'static, 'static> {
                         ),
                 ),
         )
+        .subcommand(
+            App::new("pii")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .about("Work with scrubbed PII")
+                .subcommand(
+                    App::new("decrypt")
+                        .about("Decrypt values scrubbed with the encrypt redaction")
+                        .after_help(
+                            "This decrypts values that were replaced by an encrypt \
+                             redaction during PII scrubbing.  The keys are read from a \
+                             JSON file containing a list of objects with an `id` and a \
+                             base64 encoded 32 byte `secret`.  Each value is decrypted with the key that matches \
+                             its key ID.  If no values are given, they are read from stdin, \
+                             one per line.",
+                        )
+                        .arg(
+                            Arg::with_name("keys")
+                                .long("keys")
+                                .value_name("PATH")
+                                .required(true)
+                                .help("The path to a JSON file with encryption keys"),
+                        )
+                        .arg(
+                            Arg::with_name("values")
+                                .value_name("VALUE")
+                                .multiple(true)
+                                .help("The encrypted values"),
+                        ),
+                ),
+        )
         .subcommand(
             App::new("process-event")
                 .setting(AppSettings::Hidden)
@@ -272,6 +333,15 @@ pub fn make_app() -> App<'static, 'static> {
                         .value_name("PATH")
                         .help("The path to a PII processing config"),
                 )
+                .arg(
+                    Arg::with_name("pii_audit")
+                        .long("pii-audit")
+                        .requires("pii_config")
+                        .help(
+                            "Apply the PII config in audit mode and print matches instead \
+                             of the event",
+                        ),
+                )
                 .arg(
                     Arg::with_name("store")
                         .long("store")
@@ -5,12 +5,28 @@ use std::mem;
 
 use chrono::{DateTime, Utc};
 use failure::{err_msg, Error};
-use log::{Level, LevelFilter};
+use log::Level;
 use serde::{Deserialize, Serialize};
 
-use relay_common::metrics;
 use relay_config::{Config, LogFormat, RelayMode};
 
+/// Log filters used unless overridden by `RUST_LOG`.
+///
+/// Relay's own crates log at all levels. The level configured in `logging.level` limits this
+/// further at runtime.
+const LOG_FILTERS: &str = "INFO,\
+                           trust_dns_proto=WARN,\
+                           actix_web::pipeline=DEBUG,\
+                           relay_auth=TRACE,\
+                           relay_common=TRACE,\
+                           relay_config=TRACE,\
+                           relay_filter=TRACE,\
+                           relay_general=TRACE,\
+                           relay_quotas=TRACE,\
+                           relay_redis=TRACE,\
+                           relay_server=TRACE,\
+                           relay=TRACE";
+
 pub fn check_config(config: &Config) -> Result<(), Error> {
     if config.relay_mode() == RelayMode::Managed && config.credentials().is_none() {
         return Err(err_msg(
@@ -51,6 +67,9 @@ pub fn dump_credentials(config: &Config) {
         Some(key) => println!("  public key: {}", key),
         None => println!("  public key: -"),
     };
+    if let Some(key) = config.next_public_key() {
+        println!("  next public key: {}", key);
+    }
 }
 
 /// Initialize the logging system.
@@ -81,49 +100,6 @@ pub fn init_logging(config: &Config) {
         env::set_var("RUST_BACKTRACE", "1");
     }
 
-    if env::var("RUST_LOG").is_err() {
-        let log = match config.log_level_filter() {
-            LevelFilter::Off => "",
-            LevelFilter::Error => "ERROR",
-            LevelFilter::Warn => "WARN",
-            LevelFilter::Info => {
-                "INFO,\
-                 trust_dns_proto=WARN"
-            }
-            LevelFilter::Debug => {
-                "INFO,\
-                 trust_dns_proto=WARN,\
-                 actix_web::pipeline=DEBUG,\
-                 relay_auth=DEBUG,\
-                 relay_common=DEBUG,\
-                 relay_config=DEBUG,\
-                 relay_filter=DEBUG,\
-                 relay_general=DEBUG,\
-                 relay_quotas=DEBUG,\
-                 relay_redis=DEBUG,\
-                 relay_server=DEBUG,\
-                 relay=DEBUG"
-            }
-            LevelFilter::Trace => {
-                "INFO,\
-                 trust_dns_proto=WARN,\
-                 actix_web::pipeline=DEBUG,\
-                 relay_auth=TRACE,\
-                 relay_common=TRACE,\
-                 relay_config=TRACE,\
-                 relay_filter=TRACE,\
-                 relay_general=TRACE,\
-                 relay_quotas=TRACE,\
-                 relay_redis=TRACE,\
-                 relay_server=TRACE,\
-                 relay=TRACE"
-            }
-        }
-        .to_string();
-
-        env::set_var("RUST_LOG", log);
-    }
-
     let mut log_builder = {
         match (config.log_format(), console::user_attended()) {
             (LogFormat::Auto, true) | (LogFormat::Pretty, _) => {
@@ -151,6 +127,8 @@ pub fn init_logging(config: &Config) {
                     level: Level,
                     logger: &'a str,
                     message: String,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    access_log: Option<serde_json::Value>,
                     module_path: Option<&'a str>,
                     filename: Option<&'a str>,
                     lineno: Option<u32>,
@@ -158,13 +136,25 @@ pub fn init_logging(config: &Config) {
 
                 let mut builder = env_logger::Builder::new();
                 builder.format(|mut buf, record| -> io::Result<()> {
+                    let mut message = record.args().to_string();
+
+                    // Access log records carry a JSON object, which is emitted as structured data.
+                    let mut access_log = None;
+                    if record.target() == relay_server::ACCESS_LOG_TARGET {
+                        if let Ok(value) = serde_json::from_str(&message) {
+                            access_log = Some(value);
+                            message = "access log".to_owned();
+                        }
+                    }
+
                     serde_json::to_writer(
                         &mut buf,
                         &LogRecord {
                             timestamp: Utc::now(),
                             level: record.level(),
                             logger: record.target(),
-                            message: record.args().to_string(),
+                            message,
+                            access_log,
                             module_path: record.module_path(),
                             filename: record.file(),
                             lineno: record.line(),
@@ -181,7 +171,7 @@ pub fn init_logging(config: &Config) {
 
     match env::var("RUST_LOG") {
         Ok(rust_log) => log_builder.parse_filters(&rust_log),
-        Err(_) => log_builder.filter_level(config.log_level_filter()),
+        Err(_) => log_builder.parse_filters(LOG_FILTERS),
     };
 
     let log = Box::new(log_builder.build());
@@ -196,23 +186,11 @@ pub fn init_logging(config: &Config) {
         },
     );
 
-    sentry::integrations::panic::register_panic_handler();
-}
-
-/// Initialize the metric system.
-pub fn init_metrics(config: &Config) -> Result<(), Error> {
-    let addrs = config.statsd_addrs()?;
-    if addrs.is_empty() {
-        return Ok(());
+    // Without `RUST_LOG`, the filters above admit all of Relay's logs and the configured level is
+    // applied as global maximum. This allows to change it when the config is reloaded.
+    if env::var_os("RUST_LOG").is_none() {
+        log::set_max_level(config.log_level_filter());
     }
 
-    let mut default_tags = config.metrics_default_tags().clone();
-    if let Some(hostname_tag) = config.metrics_hostname_tag() {
-        if let Some(hostname) = hostname::get().ok().and_then(|s| s.into_string().ok()) {
-            default_tags.insert(hostname_tag.to_owned(), hostname);
-        }
-    }
-    metrics::configure_statsd(config.metrics_prefix(), &addrs[..], default_tags);
-
-    Ok(())
+    sentry::integrations::panic::register_panic_handler();
 }